@@ -31,11 +31,23 @@ module Server =
   let deserialize<'t> (token: JToken) = token.ToObject<'t>(jsonRpcFormatter.JsonSerializer)
   let serialize<'t> (o: 't) = JToken.FromObject(o, jsonRpcFormatter.JsonSerializer)
 
-  let requestHandling<'param, 'result> (run: 'param -> AsyncLspResult<'result>) : Delegate =
+  /// Wraps the handling of every request and notification, e.g. to time the handlers.
+  type IRequestTracer =
+    abstract Trace<'r> : methodName: string -> handling: Async<'r> -> Async<'r>
+
+  let noTracer =
+    { new IRequestTracer with
+        member _.Trace _ handling = handling }
+
+  let tracedRequestHandling<'param, 'result>
+    (tracer: IRequestTracer)
+    (methodName: string)
+    (run: 'param -> AsyncLspResult<'result>)
+    : Delegate =
     let runAsTask param ct =
       // Execute non-async portion of `run` before forking the async portion into a task.
       // This is needed to avoid reordering of messages from a client.
-      let asyncLspResult = run param
+      let asyncLspResult = run param |> tracer.Trace methodName
 
       let asyncContinuation =
         async {
@@ -55,6 +67,9 @@ module Server =
 
     Func<'param, CancellationToken, Task<'result>>(runAsTask) :> Delegate
 
+  let requestHandling<'param, 'result> (run: 'param -> AsyncLspResult<'result>) : Delegate =
+    tracedRequestHandling noTracer "" run
+
   /// Notifications don't generate a response or error, but to unify things we consider them as always successful.
   /// They will still not send any response because their ID is null.
   let private notificationSuccess (response: Async<unit>) =
@@ -184,12 +199,12 @@ module Server =
     | _ -> LspCloseReason.ErrorStreamClosed
 
   type ServerRequestHandling<'server when 'server :> Ionide.LanguageServerProtocol.ILspServer> =
-    { Run: 'server -> Delegate }
+    { Run: IRequestTracer -> string -> 'server -> Delegate }
 
   let serverRequestHandling<'server, 'param, 'result when 'server :> Ionide.LanguageServerProtocol.ILspServer>
     (run: 'server -> 'param -> AsyncLspResult<'result>)
     : ServerRequestHandling<'server> =
-    { Run = fun s -> requestHandling (run s) }
+    { Run = fun tracer methodName s -> tracedRequestHandling tracer methodName (run s) }
 
   let defaultRequestHandlings () : Map<string, ServerRequestHandling<'server>> =
     let requestHandling = serverRequestHandling
@@ -253,6 +268,7 @@ module Server =
     |> Map.ofList
 
   let start<'client, 'server when 'client :> Ionide.LanguageServerProtocol.ILspClient and 'server :> Ionide.LanguageServerProtocol.ILspServer>
+    (tracer: IRequestTracer)
     (requestHandlings: Map<string, ServerRequestHandling<'server>>)
     (input: Stream)
     (output: Stream)
@@ -263,7 +279,7 @@ module Server =
       let server = serverCreator client
 
       requestHandlings
-      |> Map.map (fun _ requestHandling -> requestHandling.Run server)
+      |> Map.map (fun methodName requestHandling -> requestHandling.Run tracer methodName server)
 
     startWithSetup requestHandlingSetup input output clientCreator
 
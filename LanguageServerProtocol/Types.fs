@@ -111,6 +111,76 @@ type DocumentUri = string
 /// Represents a location inside a resource, such as a line inside a text file.
 type Location = { Uri: DocumentUri; Range: Range }
 
+/// A token the client or the server uses to tell progress reports apart.
+///
+/// @since 3.15.0
+type ProgressToken = U2<int, string>
+
+/// Starts reporting progress of a long running operation.
+type WorkDoneProgressBegin =
+  { /// Always "begin".
+    Kind: string
+
+    /// Mandatory title of the progress operation, e.g. "Indexing".
+    Title: string
+
+    /// Whether the client should show a button to cancel the operation.
+    Cancellable: bool option
+
+    /// Optional, more detailed progress message, e.g. "3/25 files".
+    Message: string option
+
+    /// Optional progress percentage in the range [0, 100].
+    Percentage: uint32 option }
+  static member Create(title: string, ?message: string, ?percentage: uint32) =
+    { Kind = "begin"
+      Title = title
+      Cancellable = None
+      Message = message
+      Percentage = percentage }
+
+/// Reports progress of an operation after `WorkDoneProgressBegin`.
+type WorkDoneProgressReport =
+  { /// Always "report".
+    Kind: string
+
+    /// Whether the client should show a button to cancel the operation.
+    Cancellable: bool option
+
+    /// Optional, more detailed progress message.
+    Message: string option
+
+    /// Optional progress percentage in the range [0, 100].
+    Percentage: uint32 option }
+  static member Create(?message: string, ?percentage: uint32) =
+    { Kind = "report"
+      Cancellable = None
+      Message = message
+      Percentage = percentage }
+
+/// Signals the end of an operation.
+type WorkDoneProgressEnd =
+  { /// Always "end".
+    Kind: string
+
+    /// Optional final message, e.g. the outcome of the operation.
+    Message: string option }
+  static member Create(?message: string) = { Kind = "end"; Message = message }
+
+/// Parameters of the `window/workDoneProgress/create` request.
+type WorkDoneProgressCreateParams =
+  { /// The token to report progress with.
+    Token: ProgressToken }
+
+/// Parameters of the `$/progress` notification.
+type ProgressParams<'T> =
+  { /// The token given by the client or created by the server with
+    /// `window/workDoneProgress/create`.
+    Token: ProgressToken
+
+    /// The progress data.
+    Value: 'T }
+
 type ITextDocumentIdentifier =
   /// Warning: normalize this member by UrlDecoding it before use
   abstract member Uri: DocumentUri
@@ -214,6 +284,31 @@ type DocumentSymbol =
     /// Children of this symbol, e.g. properties of a class.
     Children: DocumentSymbol [] option }
 
+/// Additional information that describes document changes.
+type ChangeAnnotation =
+  { /// A human-readable string describing the actual change. The string is rendered prominent in
+    /// the user interface.
+    Label: string
+    /// A flag which indicates that user confirmation is needed before applying the change.
+    NeedsConfirmation: bool option
+    /// A human-readable string which is rendered less prominent in the user interface.
+    Description: string option }
+
+// Annotated edits are declared before the plain ones, so that records with the fields of both are
+// inferred to be plain ones unless annotated otherwise
+
+/// A special text edit with an additional change annotation.
+type AnnotatedTextEdit =
+  { Range: Range
+    NewText: string
+    /// The identifier of the annotation in `WorkspaceEdit.changeAnnotations`.
+    AnnotationId: string }
+
+/// A `TextDocumentEdit` whose edits are annotated.
+type AnnotatedTextDocumentEdit =
+  { TextDocument: VersionedTextDocumentIdentifier
+    Edits: AnnotatedTextEdit [] }
+
 /// A textual edit applicable to a text document.
 type TextEdit =
   { /// The range of the text document to be manipulated. To insert
@@ -236,6 +331,33 @@ type TextDocumentEdit =
     /// The edits to be applied.
     Edits: TextEdit [] }
 
+/// Delete file options
+type DeleteFileOptions =
+  { /// Delete the content recursively if a folder is denoted.
+    Recursive: bool option
+    /// Ignore the operation if the file doesn't exist.
+    IgnoreIfNotExists: bool option }
+
+/// Delete file operation
+type DeleteFile =
+  { /// A delete
+    Kind: string
+    /// The file to delete.
+    Uri: DocumentUri
+    /// Delete options.
+    Options: DeleteFileOptions option }
+  static member Create(uri: DocumentUri) = { Kind = "delete"; Uri = uri; Options = None }
+
+/// An element of `WorkspaceEdit.documentChanges`: an edit of a text document or an operation on a
+/// file. Whether a client supports file operations is expressed via
+/// `WorkspaceEditCapabilities.resourceOperations`.
+[<ErasedUnion>]
+[<RequireQualifiedAccess>]
+type DocumentChange =
+  | Edit of TextDocumentEdit
+  | AnnotatedEdit of AnnotatedTextDocumentEdit
+  | Delete of DeleteFile
+
 type TraceSetting =
   | Off = 0
   | Messages = 1
@@ -361,6 +483,11 @@ type WorkspaceClientCapabilities =
     /// Capabilities specific to the `workspace/didChangeWatchedFiles` notification.
     DidChangeWatchedFiles: DynamicCapabilities option
 
+    /// The client supports `workspace/configuration` requests.
+    ///
+    /// @since 3.6.0
+    Configuration: bool option
+
     /// Capabilities specific to the `workspace/symbol` request.
     Symbol: SymbolCapabilities option
 
@@ -806,6 +933,22 @@ type TextDocumentClientCapabilities =
     /// @since 3.17.0
     InlayHint: InlayHintClientCapabilities option }
 
+/// General client capabilities.
+type GeneralClientCapabilities =
+  { /// The position encodings supported by the client, in the order of preference: "utf-8",
+    /// "utf-16" or "utf-32". UTF-16 is always supported, even when it's not listed.
+    ///
+    /// @since 3.17.0
+    PositionEncodings: string [] option }
+
+/// Window specific client capabilities.
+type WindowClientCapabilities =
+  { /// Whether the client supports server initiated progress using the
+    /// `window/workDoneProgress/create` request.
+    ///
+    /// @since 3.15.0
+    WorkDoneProgress: bool option }
+
 type ClientCapabilities =
   { /// Workspace specific client capabilities.
     Workspace: WorkspaceClientCapabilities option
@@ -813,6 +956,14 @@ type ClientCapabilities =
     /// Text document specific client capabilities.
     TextDocument: TextDocumentClientCapabilities option
 
+    /// Window specific client capabilities.
+    Window: WindowClientCapabilities option
+
+    /// General client capabilities.
+    ///
+    /// @since 3.16.0
+    General: GeneralClientCapabilities option
+
     /// Experimental client capabilities.
     Experimental: JToken option }
 
@@ -840,7 +991,11 @@ type InitializeParams =
     /// This property is only available if the client supports workspace folders.
     /// It can be `null` if the client supports workspace folders but none are configured.
     /// @since 3.6.0
-    WorkspaceFolders: WorkspaceFolder [] option }
+    WorkspaceFolders: WorkspaceFolder [] option
+
+    /// A token the server can report the progress of the initialization with.
+    /// @since 3.15.0
+    WorkDoneToken: ProgressToken option }
 
 type InitializedParams() =
   override _.Equals(o) = o :? InitializedParams
@@ -1104,6 +1259,12 @@ type ServerCapabilities =
     /// Workspace specific server capabilities.
     Workspace: WorkspaceServerCapabilities option
 
+    /// The position encoding the server picked from the encodings offered by the client, UTF-16
+    /// when omitted.
+    ///
+    /// @since 3.17.0
+    PositionEncoding: string option
+
    }
   static member Default =
     { HoverProvider = None
@@ -1130,7 +1291,8 @@ type ServerCapabilities =
       SelectionRangeProvider = None
       SemanticTokensProvider = None
       InlayHintProvider = None
-      Workspace = None }
+      Workspace = None
+      PositionEncoding = None }
 
 type InitializeResult =
   { Capabilities: ServerCapabilities }
@@ -1147,7 +1309,12 @@ type WorkspaceEdit =
     /// where each text document edit addresses a specific version of a text document.
     /// Whether a client supports versioned document edits is expressed via
     /// `WorkspaceClientCapabilities.workspaceEdit.documentChanges`.
-    DocumentChanges: TextDocumentEdit [] option }
+    DocumentChanges: DocumentChange [] option
+
+    /// A map of change annotations that can be referenced in `AnnotatedTextEdit`s or create, rename
+    /// and delete file / folder operations. Whether clients honor this property depends on the
+    /// client capability `workspace.changeAnnotationSupport`.
+    ChangeAnnotations: Map<string, ChangeAnnotation> option }
   static member DocumentChangesToChanges(edits: TextDocumentEdit []) =
     edits
     |> Array.map (fun edit -> edit.TextDocument.Uri.ToString(), edit.Edits)
@@ -1160,10 +1327,13 @@ type WorkspaceEdit =
 
   static member Create(edits: TextDocumentEdit [], capabilities: ClientCapabilities) =
     if WorkspaceEdit.CanUseDocumentChanges(capabilities) then
-      { Changes = None; DocumentChanges = Some edits }
+      { Changes = None
+        DocumentChanges = Some(Array.map DocumentChange.Edit edits)
+        ChangeAnnotations = None }
     else
       { Changes = Some(WorkspaceEdit.DocumentChangesToChanges edits)
-        DocumentChanges = None }
+        DocumentChanges = None
+        ChangeAnnotations = None }
 
 type MessageType =
   | Error = 1
@@ -1264,7 +1434,10 @@ type ConfigurationParams = { items: ConfigurationItem [] }
 /// The parameters of a Workspace Symbol Request.
 type WorkspaceSymbolParams =
   { /// A non-empty query string
-    Query: string }
+    Query: string
+    /// An optional token that a server can use to report partial results (e.g. streaming) to
+    /// the client.
+    PartialResultToken: ProgressToken option }
 
 type ExecuteCommandParams =
   { /// The identifier of the actual command handler.
@@ -1401,7 +1574,10 @@ type ReferenceParams =
     TextDocument: TextDocumentIdentifier
     /// The position inside the text document.
     Position: Position
-    Context: ReferenceContext }
+    Context: ReferenceContext
+    /// An optional token that a server can use to report partial results (e.g. streaming) to
+    /// the client.
+    PartialResultToken: ProgressToken option }
   interface ITextDocumentPositionParams with
     member this.TextDocument = this.TextDocument
     member this.Position = this.Position
@@ -2119,7 +2295,14 @@ type SelectionRange =
     /// The parent selection range containing this range. Therefore `parent.range` must contain `this.range`.
     Parent: SelectionRange option }
 
-type SemanticTokensParams = { TextDocument: TextDocumentIdentifier }
+type SemanticTokensParams =
+  { TextDocument: TextDocumentIdentifier
+    /// An optional token that a server can use to report partial results (e.g. streaming) to
+    /// the client.
+    PartialResultToken: ProgressToken option }
+
+/// A chunk of the tokens reported via `$/progress`; the chunks are appended to each other.
+type SemanticTokensPartialResult = { Data: uint32 [] }
 
 type SemanticTokensDeltaParams =
   { TextDocument: TextDocumentIdentifier
@@ -0,0 +1,277 @@
+/// Local non-markdown files referenced from documents, e.g. images.
+module Marksman.Assets
+
+open System
+open System.IO
+open System.Text.RegularExpressions
+
+open Marksman.Cst
+open Marksman.Index
+open Marksman.Misc
+open Marksman.Refs
+open Marksman.Workspace
+
+let imageExtensions = [| "png"; "jpg"; "jpeg"; "gif"; "svg"; "webp"; "bmp"; "avif" |]
+
+let isImagePath (path: string) =
+    let ext = Path.GetExtension(path).TrimStart('.').ToLowerInvariant()
+    Array.contains ext imageExtensions
+
+// Files notes typically embed or link to, beside images
+let attachmentExtensions =
+    Array.append
+        imageExtensions
+        [| "pdf"; "mp3"; "wav"; "ogg"; "m4a"; "flac"; "mp4"; "webm"; "mov"; "mkv"; "excalidraw" |]
+
+let isAttachmentPath (path: string) =
+    let ext = Path.GetExtension(path).TrimStart('.').ToLowerInvariant()
+    Array.contains ext attachmentExtensions
+
+/// Absolute local path of the asset at `url`, which is relative to the document or to the
+/// folder root when it starts with '/'. Remote and data URLs aren't local assets.
+let tryResolve (folder: Folder) (srcDoc: Doc) (url: string) : option<string> =
+    let url =
+        match url.IndexOfAny([| '?'; '#' |]) with
+        | -1 -> url
+        | idx -> url.Substring(0, idx)
+
+    if url.IsWhitespace()
+       || url.StartsWith("data:")
+       || Uri.IsWellFormedUriString(url, UriKind.Absolute) then
+        None
+    else
+        let root = Folder.rootPath folder
+
+        InternName.tryResolveToRootPath root (Doc.path srcDoc) url
+        |> Option.map (fun relPath -> Path.Join((RootPath.path root).LocalPath, relPath))
+
+// The assets under the configured asset directories, all of them when there are none
+let private inAssetDirs (folder: Folder) : Set<string> =
+    let config = Folder.configOrDefault folder
+    let rootPath = (RootPath.path (Folder.rootPath folder)).LocalPath
+    let assets = Folder.assets folder
+
+    match config.ComplAssetDirs() with
+    | [||] -> assets
+    | dirs ->
+        let dirs =
+            dirs
+            |> Array.map (fun dir ->
+                Path.GetRelativePath(rootPath, Path.GetFullPath(dir, rootPath)).Replace('\\', '/'))
+
+        let isInDir (path: string) =
+            dirs |> Array.exists (fun dir -> dir = "." || path.StartsWith(dir + "/"))
+
+        assets |> Set.filter isInDir
+
+/// Non-markdown files under the configured asset directories, or under the whole folder when none
+/// are configured, as paths relative to the root. Hidden and ignored files are skipped, see
+/// `Folder.assets`.
+let files (folder: Folder) : array<string> =
+    inAssetDirs folder |> Seq.truncate 1000 |> Array.ofSeq
+
+let imageFiles (folder: Folder) : array<string> =
+    inAssetDirs folder |> Seq.filter isImagePath |> Seq.truncate 1000 |> Array.ofSeq
+
+// `src` of raw HTML, e.g. `<img src="img/a.png">`
+let private htmlSrc = Regex(@"<\w+[^>]*?\ssrc\s*=\s*[""']([^""']+)[""']", RegexOptions.Compiled)
+
+// Values of the frontmatter that look like file names, e.g. `cover: img/a.png`
+let private frontMatterPath = Regex(@"[^\s""'\[\](){},:|]+\.\w+", RegexOptions.Compiled)
+
+/// Attachments that no note links to or embeds, as paths relative to the root, without a limit
+/// on their number: the files under the configured asset directories or, when there are none,
+/// the files of the folder with an attachment extension, e.g. images and PDFs. Wiki links are
+/// taken to name a file by its path or name, like `![[image.png]]`; `src` of raw HTML and file
+/// names in the frontmatter count as links too. Bibliographies and templates are never unused.
+let unused (folder: Folder) : array<string> =
+    let config = Folder.configOrDefault folder
+    let rootPath = (RootPath.path (Folder.rootPath folder)).LocalPath
+    let fromRoot (path: string) = Path.GetRelativePath(rootPath, path).Replace('\\', '/')
+
+    let urls (doc: Doc) =
+        let index = Doc.index doc
+
+        let inlineUrls =
+            Index.mdLinks index
+            |> Seq.choose (function
+                | { data = MdLink.IL (_, Some url, _) } -> (Url.ofUrlNode url).url
+                | _ -> None)
+            |> Seq.map Node.text
+
+        let defUrls =
+            Index.linkDefs index |> Seq.map (fun { data = def } -> (MdLinkDef.url def).text)
+
+        let wikiNames =
+            Index.wikiLinks index
+            |> Seq.choose (fun { data = wl } -> wl.doc |> Option.map Node.text)
+
+        let htmlUrls =
+            htmlSrc.Matches((Doc.text doc).content) |> Seq.map (fun m -> m.Groups[1].Value)
+
+        let frontMatterUrls =
+            index.yamlFrontMatter
+            |> Option.map (fun yml ->
+                frontMatterPath.Matches(yml.text) |> Seq.map (fun m -> m.Value))
+            |> Option.defaultValue Seq.empty
+
+        Seq.concat [ inlineUrls; defUrls; wikiNames; htmlUrls; frontMatterUrls ]
+
+    let linked =
+        Folder.docs folder
+        |> Seq.collect (fun doc -> urls doc |> Seq.map (fun url -> doc, url))
+        |> Array.ofSeq
+
+    let paths =
+        linked
+        |> Seq.choose (fun (doc, url) -> tryResolve folder doc url |> Option.map fromRoot)
+        |> Set.ofSeq
+
+    let names = linked |> Seq.map (fun (_, url) -> url.UrlDecode()) |> Set.ofSeq
+
+    let bibliographies =
+        config.CoreBibliography()
+        |> Array.map (fun path -> fromRoot (Path.GetFullPath(path, rootPath)))
+        |> Set.ofArray
+
+    let isTemplate (path: string) =
+        match config.ComplCreateNoteTemplateDir().Trim([| '/'; '\\' |]) with
+        | "" -> false
+        | dir -> path.StartsWith(dir + "/")
+
+    let candidates =
+        if Array.isEmpty (config.ComplAssetDirs()) then
+            inAssetDirs folder |> Set.filter isAttachmentPath
+        else
+            inAssetDirs folder
+
+    candidates
+    |> Seq.filter (fun path ->
+        not (Set.contains path bibliographies || isTemplate path)
+        && not (Set.contains path paths || Set.contains (Path.GetFileName(path)) names))
+    |> Array.ofSeq
+
+/// Url-encoded link from `srcDoc` to the file at `path`, relative to the doc's directory, e.g.
+/// `../img/a%20b.png`. Both paths are relative to the root.
+let relativeLink (srcDoc: Doc) (path: string) : string =
+    let srcParts = (Doc.pathFromRoot srcDoc).Replace('\\', '/').TrimStart('/').Split('/')
+    let srcDir = srcParts[.. srcParts.Length - 2]
+    let target = path.TrimStart('/').Split('/')
+
+    let common =
+        Seq.zip srcDir target
+        |> Seq.takeWhile (fun (a, b) -> a = b)
+        |> Seq.length
+
+    // The last component of the target is always kept, even when it's named like a directory
+    let common = min common (target.Length - 1)
+
+    Array.append (Array.create (srcDir.Length - common) "..") target[common..]
+    |> Array.map (fun part -> part.UrlEncode())
+    |> String.concat "/"
+
+module private Bytes =
+    let be16 (bytes: array<byte>) idx = (int bytes[idx] <<< 8) ||| int bytes[idx + 1]
+
+    let be32 (bytes: array<byte>) idx = (be16 bytes idx <<< 16) ||| be16 bytes (idx + 2)
+
+    let le16 (bytes: array<byte>) idx = int bytes[idx] ||| (int bytes[idx + 1] <<< 8)
+
+    let le32 (bytes: array<byte>) idx = le16 bytes idx ||| (le16 bytes (idx + 2) <<< 16)
+
+let private jpegDimensions (stream: Stream) : option<int * int> =
+    let reader = new BinaryReader(stream)
+    let mutable result = None
+    let mutable fin = false
+    stream.Position <- 2L
+
+    while not fin && stream.Position + 9L < stream.Length do
+        if reader.ReadByte() <> 0xFFuy then
+            fin <- true
+        else
+            let marker = reader.ReadByte()
+            let segment = reader.ReadBytes(2)
+            let length = Bytes.be16 segment 0
+
+            // SOFn markers, except DHT (C4), JPG (C8) and DAC (CC), carry the frame size
+            if marker >= 0xC0uy
+               && marker <= 0xCFuy
+               && marker <> 0xC4uy
+               && marker <> 0xC8uy
+               && marker <> 0xCCuy then
+                let frame = reader.ReadBytes(5)
+                result <- Some(Bytes.be16 frame 3, Bytes.be16 frame 1)
+                fin <- true
+            else
+                stream.Position <- stream.Position + int64 (length - 2)
+
+    result
+
+let private pngSignature = [| 0x89uy; 0x50uy; 0x4Euy; 0x47uy; 0x0Duy; 0x0Auy; 0x1Auy; 0x0Auy |]
+
+/// Width and height of PNG, GIF, BMP and JPEG images.
+let tryReadDimensions (path: string) : option<int * int> =
+    try
+        use stream = File.OpenRead(path)
+        let header = Array.zeroCreate<byte> 26
+        let read = stream.Read(header, 0, header.Length)
+
+        if read >= 24 && header[0..7] = pngSignature then
+            Some(Bytes.be32 header 16, Bytes.be32 header 20)
+        elif read >= 10 && header[0..3] = "GIF8"B then
+            Some(Bytes.le16 header 6, Bytes.le16 header 8)
+        elif read >= 26 && header[0..1] = "BM"B then
+            Some(Bytes.le32 header 18, abs (Bytes.le32 header 22))
+        elif read >= 2 && header[0] = 0xFFuy && header[1] = 0xD8uy then
+            jpegDimensions stream
+        else
+            None
+    with
+    | :? IOException
+    | :? UnauthorizedAccessException -> None
+
+let private humanSize (bytes: int64) =
+    if bytes < 1024L then $"{bytes} B"
+    elif bytes < 1024L * 1024L then $"{float bytes / 1024.0:F1} KiB"
+    else $"{float bytes / (1024.0 * 1024.0):F1} MiB"
+
+/// Markdown summary of an asset for hover: its size and, for images, dimensions.
+let describe (path: string) : option<string> =
+    let info = FileInfo(path)
+
+    if not info.Exists then
+        None
+    else
+        let size = $"Size: {humanSize info.Length}"
+
+        let dimensions =
+            if isImagePath path then
+                tryReadDimensions path
+                |> Option.map (fun (width, height) -> $"Dimensions: {width}×{height}")
+                |> Option.toList
+            else
+                []
+
+        Some(String.Join("\n\n", $"`{info.Name}`" :: size :: dimensions))
+
+/// Description of the local file an image link points to.
+let describeImageLink (folder: Folder) (srcDoc: Doc) (link: Node<MdLink>) : option<string> =
+    match link.data with
+    | MdLink.IL (_, Some url, _) when MdLink.isImage link ->
+        (Url.ofUrlNode url).url
+        |> Option.bind (fun path -> tryResolve folder srcDoc path.text)
+        |> Option.bind describe
+    | _ -> None
+
+/// Description of the local file a wiki embed names by its path from the root or by its name,
+/// like `![[image.png]]`.
+let describeEmbed (folder: Folder) (embed: Node<WikiLink>) : option<string> =
+    let rootPath = (RootPath.path (Folder.rootPath folder)).LocalPath
+
+    embed.data.doc
+    |> Option.bind (fun name ->
+        let name = name.text.UrlDecode()
+
+        Folder.assets folder
+        |> Seq.tryFind (fun path -> path = name || Path.GetFileName(path) = name))
+    |> Option.bind (fun path -> describe (Path.Join(rootPath, path)))
@@ -0,0 +1,95 @@
+/// A "## Backlinks" section at the bottom of a note listing the notes linking to it, for reading
+/// the notes outside the editor. `marksman.updateBacklinks` refreshes it, and so does saving the
+/// note when `core.backlinks_section` is on.
+module Marksman.Backlinks
+
+open Ionide.LanguageServerProtocol.Types
+
+open Marksman.CodeActions
+open Marksman.Cst
+open Marksman.Index
+open Marksman.Misc
+open Marksman.Refs
+open Marksman.Workspace
+
+let title = "Backlinks"
+
+/// The range of the section, from its heading to the end of the note. A section followed by
+/// another one of the same level or above isn't generated, so it's left alone.
+let sectionRange (doc: Doc) : option<Range> =
+    let headings = Index.headings (Doc.index doc)
+
+    let isLast index =
+        headings[index + 1 ..] |> Array.forall (fun { data = heading } -> heading.level > 2)
+
+    headings
+    |> Array.tryFindIndexBack (fun { data = heading } ->
+        heading.level = 2 && Heading.name heading = title)
+    |> Option.filter isLast
+    |> Option.map (fun index ->
+        Range.Mk(headings[index].range.Start, (Doc.text doc).EndRange().End))
+
+/// The other notes of the folder linking to `doc`, by name. Links in their own backlinks sections
+/// don't count, or notes listing each other would keep doing so after the actual links are gone.
+let linkingDocs (folder: Folder) (doc: Doc) : array<Doc> =
+    let linksTo (srcDoc: Doc) =
+        let section = sectionRange srcDoc
+
+        Dest.resolveLinks folder srcDoc
+        |> Map.exists (fun link dests ->
+            let inSection =
+                section
+                |> Option.exists (fun range -> range.ContainsInclusive(Element.rangeStart link))
+
+            not inSection && dests |> Array.exists (fun dest -> Dest.doc dest = doc))
+
+    Folder.docsLinkingTo doc folder
+    |> Seq.filter (fun srcDoc -> srcDoc <> doc && linksTo srcDoc)
+    |> Seq.sortBy (fun srcDoc -> Doc.name srcDoc, Doc.pathFromRoot srcDoc)
+    |> Array.ofSeq
+
+let render (doc: Doc) (linking: array<Doc>) : string =
+    let items =
+        linking
+        |> Array.map (fun srcDoc ->
+            let url = Refactor.docUrl doc srcDoc ""
+            $"- [{Doc.name srcDoc}]({url})")
+
+    String.concat "\n" (Array.append [| $"## {title}"; "" |] items) + "\n"
+
+/// The action creating, refreshing or removing the section, unless it's up to date. Notes no other
+/// note links to get no section.
+let update (folder: Folder) (doc: Doc) : option<DocumentAction> =
+    let text = Doc.text doc
+
+    let rendered =
+        match linkingDocs folder doc with
+        | [||] -> None
+        | linking -> Some(render doc linking)
+
+    match sectionRange doc, rendered with
+    | None, None -> None
+    | Some range, Some rendered when text.Substring(range) = rendered -> None
+    | Some range, Some rendered ->
+        Some { name = "Update the backlinks"; newText = rendered; edit = range }
+    | Some range, None ->
+        // The blank lines separating the section go with it
+        let mutable line = range.Start.Line
+
+        while line > 0 && text.LineContent(line - 1).IsWhitespace() do
+            line <- line - 1
+
+        Some
+            { name = "Remove the backlinks"
+              newText = ""
+              edit = Range.Mk(Position.Mk(line, 0), range.End) }
+    | None, Some rendered ->
+        let separator =
+            if text.content = "" || text.content.EndsWith("\n\n") then ""
+            elif text.content.EndsWith("\n") then "\n"
+            else "\n\n"
+
+        Some
+            { name = "Create the backlinks"
+              newText = separator + rendered
+              edit = text.EndRange() }
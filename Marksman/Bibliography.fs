@@ -0,0 +1,347 @@
+/// Bibliography entries referenced by citations, loaded from BibTeX or CSL-JSON files.
+module Marksman.Bibliography
+
+open System
+open System.Collections.Concurrent
+open System.IO
+open System.Text
+open System.Text.Json
+open Ionide.LanguageServerProtocol.Logging
+
+open Marksman.Misc
+open Marksman.Workspace
+
+type Entry =
+    { key: string
+      kind: string
+      title: option<string>
+      authors: list<string>
+      year: option<string>
+      container: option<string> }
+
+module Entry =
+    /// Markdown rendering of the reference, e.g. for hover.
+    let fmt (entry: Entry) : string =
+        let authors =
+            match entry.authors with
+            | [] -> None
+            | authors -> Some(String.Join(", ", authors))
+
+        let year = entry.year |> Option.map (fun year -> $"({year})")
+        let byline = [ authors; year ] |> List.choose id
+
+        let parts =
+            [ if not byline.IsEmpty then
+                  yield String.Join(" ", byline)
+              yield! entry.title |> Option.map (fun title -> $"*{title}*") |> Option.toList
+              yield! entry.container |> Option.toList ]
+
+        let reference =
+            if parts.IsEmpty then
+                ""
+            else
+                "\n\n" + String.Join(". ", parts) + "."
+
+        $"`@{entry.key}` ({entry.kind}){reference}"
+
+    // "Doe, John" and "John Doe" are both Doe
+    let private surname (author: string) =
+        match author.IndexOf(',') with
+        | -1 -> author.Split(' ', StringSplitOptions.RemoveEmptyEntries) |> Array.last
+        | idx -> author.Substring(0, idx)
+
+    /// One line summary, e.g. `Doe and Roe (1999) The Markdown Handbook`, for completion details.
+    let summary (entry: Entry) : string =
+        let authors =
+            match entry.authors with
+            | [] -> None
+            | [ author ] -> Some(surname author)
+            | [ first; second ] -> Some $"{surname first} and {surname second}"
+            | first :: _ -> Some $"{surname first} et al."
+
+        let year = entry.year |> Option.map (fun year -> $"({year})")
+
+        match [ authors; year; entry.title ] |> List.choose id with
+        | [] -> entry.kind
+        | parts -> String.Join(" ", parts)
+
+module BibTeX =
+    let private normalize (value: string) =
+        let sb = StringBuilder()
+        let mutable wsSeen = false
+
+        for char in value do
+            if Char.IsWhiteSpace(char) then
+                wsSeen <- true
+            elif char <> '{' && char <> '}' then
+                if wsSeen && sb.Length > 0 then sb.Append(' ') |> ignore
+                wsSeen <- false
+                sb.Append(char) |> ignore
+
+        sb.ToString()
+
+    let private ignoredKinds = [ "preamble"; "comment" ]
+
+    // ISO dates of biblatex, e.g. `date = {2020-05-01}`
+    let private dateYear (date: string) = date.Split('-')[0]
+
+    /// Entries of a `.bib` file. `@string` macros are expanded in the values of the entries after
+    /// them, `@preamble` and `@comment` are skipped, malformed entries are skipped up to the next
+    /// '@'.
+    let parse (content: string) : array<Entry> =
+        let mutable pos = 0
+        let entries = ResizeArray()
+        // Macro names are case-insensitive
+        let mutable macros = Map.empty
+
+        let peek () = if pos < content.Length then content[pos] else '\000'
+
+        let skipWs () =
+            while pos < content.Length && Char.IsWhiteSpace(content[pos]) do
+                pos <- pos + 1
+
+        let readWhile (pred: char -> bool) =
+            let start = pos
+
+            while pos < content.Length && pred content[pos] do
+                pos <- pos + 1
+
+            content.Substring(start, pos - start)
+
+        // Reads a braced value; `pos` is at the opening brace
+        let readBraced () =
+            let start = pos + 1
+            let mutable depth = 0
+            let mutable fin = false
+
+            while not fin && pos < content.Length do
+                match content[pos] with
+                | '{' -> depth <- depth + 1
+                | '}' ->
+                    depth <- depth - 1
+                    fin <- depth = 0
+                | _ -> ()
+
+                pos <- pos + 1
+
+            content.Substring(start, max 0 (pos - start - 1))
+
+        let readQuoted () =
+            pos <- pos + 1
+            let mutable depth = 0
+            let start = pos
+
+            while pos < content.Length && (content[pos] <> '"' || depth > 0) do
+                match content[pos] with
+                | '{' -> depth <- depth + 1
+                | '}' -> depth <- depth - 1
+                | _ -> ()
+
+                pos <- pos + 1
+
+            let value = content.Substring(start, pos - start)
+            pos <- pos + 1
+            value
+
+        let isBareValueChar (c: char) =
+            c <> ',' && c <> '}' && c <> '#' && not (Char.IsWhiteSpace c)
+
+        // Values can be concatenated with '#', e.g. `title = "Part " # {One}`. Bare values name
+        // macros, numbers and unknown names stand for themselves
+        let rec readValue () =
+            skipWs ()
+
+            let part =
+                match peek () with
+                | '{' -> readBraced ()
+                | '"' -> readQuoted ()
+                | _ ->
+                    let name = readWhile isBareValueChar
+
+                    Map.tryFind (name.ToLowerInvariant()) macros
+                    |> Option.defaultValue name
+
+            skipWs ()
+
+            if peek () = '#' then
+                pos <- pos + 1
+                part + readValue ()
+            else
+                part
+
+        let readFields () =
+            let mutable fields = Map.empty
+            let mutable fin = false
+
+            while not fin do
+                skipWs ()
+
+                match peek () with
+                | ',' -> pos <- pos + 1
+                | '}'
+                | ')' ->
+                    pos <- pos + 1
+                    fin <- true
+                | '\000' -> fin <- true
+                | _ ->
+                    let name = readWhile (fun c -> c <> '=' && c <> '}' && c <> ',')
+                    let name = name.Trim().ToLowerInvariant()
+
+                    if peek () = '=' then
+                        pos <- pos + 1
+                        fields <- Map.add name (normalize (readValue ())) fields
+
+            fields
+
+        while pos < content.Length do
+            match content.IndexOf('@', pos) with
+            | -1 -> pos <- content.Length
+            | at ->
+                pos <- at + 1
+                let kind = readWhile Char.IsLetter
+                skipWs ()
+
+                let kind = kind.ToLowerInvariant()
+                let isOpening = peek () = '{' || peek () = '('
+
+                if isOpening && kind = "string" then
+                    pos <- pos + 1
+
+                    for KeyValue (name, value) in readFields () do
+                        macros <- Map.add name value macros
+                elif isOpening && kind <> "" && not (List.contains kind ignoredKinds) then
+                    pos <- pos + 1
+                    skipWs ()
+                    let key = readWhile isBareValueChar
+                    let fields = readFields ()
+                    let field name = Map.tryFind name fields
+
+                    let authors =
+                        field "author"
+                        |> Option.orElse (field "editor")
+                        |> Option.map (fun authors ->
+                            authors.Split(" and ", StringSplitOptions.TrimEntries) |> List.ofArray)
+                        |> Option.defaultValue []
+
+                    if key <> "" then
+                        entries.Add(
+                            { key = key
+                              kind = kind
+                              title = field "title"
+                              authors = authors
+                              year =
+                                field "year"
+                                |> Option.orElse (field "date" |> Option.map dateYear)
+                              container =
+                                field "journal"
+                                |> Option.orElse (field "booktitle")
+                                |> Option.orElse (field "publisher") }
+                        )
+
+        entries.ToArray()
+
+module CslJson =
+    let private tryString (el: JsonElement) (name: string) =
+        match el.TryGetProperty(name) with
+        | true, value when value.ValueKind = JsonValueKind.String -> Some(value.GetString())
+        | true, value when value.ValueKind = JsonValueKind.Number -> Some(value.GetRawText())
+        | _ -> None
+
+    let private authors (el: JsonElement) =
+        match el.TryGetProperty("author") with
+        | true, authors when authors.ValueKind = JsonValueKind.Array ->
+            [ for author in authors.EnumerateArray() do
+                  match tryString author "literal", tryString author "family" with
+                  | Some literal, _ -> yield literal
+                  | None, Some family ->
+                      match tryString author "given" with
+                      | Some given -> yield $"{family}, {given}"
+                      | None -> yield family
+                  | None, None -> () ]
+        | _ -> []
+
+    let private year (el: JsonElement) =
+        match el.TryGetProperty("issued") with
+        | true, issued when issued.ValueKind = JsonValueKind.Object ->
+            match issued.TryGetProperty("date-parts") with
+            | true, parts when
+                parts.ValueKind = JsonValueKind.Array
+                && parts.GetArrayLength() > 0
+                && parts[0].ValueKind = JsonValueKind.Array
+                && parts[0].GetArrayLength() > 0
+                ->
+                Some(parts[0][0].ToString())
+            | _ -> tryString issued "raw"
+        | _ -> None
+
+    /// Entries of a CSL-JSON file, i.e. an array of items with an `id`.
+    let parse (content: string) : array<Entry> =
+        use json = JsonDocument.Parse(content)
+
+        if json.RootElement.ValueKind <> JsonValueKind.Array then
+            [||]
+        else
+            [| for item in json.RootElement.EnumerateArray() do
+                   if item.ValueKind = JsonValueKind.Object then
+                       match tryString item "id" with
+                       | Some key ->
+                           yield
+                               { key = key
+                                 kind = tryString item "type" |> Option.defaultValue "entry"
+                                 title = tryString item "title"
+                                 authors = authors item
+                                 year = year item
+                                 container = tryString item "container-title" }
+                       | None -> () |]
+
+let private logger = LogProvider.getLoggerByName "Bibliography"
+
+let private cache = ConcurrentDictionary<string, DateTime * array<Entry>>()
+
+/// Entries of the bibliography file at `path`. Files are re-read only when they change.
+let load (path: string) : array<Entry> =
+    try
+        let modified = File.GetLastWriteTimeUtc(path)
+
+        match cache.TryGetValue(path) with
+        | true, (cachedAt, entries) when cachedAt = modified -> entries
+        | _ ->
+            let content = File.ReadAllText(path)
+
+            let entries =
+                if Path.GetExtension(path).ToLowerInvariant() = ".json" then
+                    CslJson.parse content
+                else
+                    BibTeX.parse content
+
+            if Caches.enabled () then
+                cache[path] <- (modified, entries)
+            else
+                cache.Clear()
+
+            entries
+    with
+    | :? IOException
+    | :? UnauthorizedAccessException
+    | :? JsonException as exn ->
+        logger.warn (
+            Log.setMessage "Failed to load bibliography"
+            >> Log.addContext "path" path
+            >> Log.addException exn
+        )
+
+        [||]
+
+/// Absolute paths of the bibliography files configured for the folder.
+let files (folder: Folder) : array<string> =
+    let root = (RootPath.path (Folder.rootPath folder)).LocalPath
+
+    (Folder.configOrDefault folder).CoreBibliography()
+    |> Array.map (fun path -> Path.GetFullPath(path, root))
+
+let isConfigured (folder: Folder) : bool = not (Array.isEmpty (files folder))
+
+let entries (folder: Folder) : array<Entry> = files folder |> Array.collect load
+
+let tryFind (folder: Folder) (key: string) : option<Entry> =
+    entries folder |> Array.tryFind (fun entry -> entry.key = key)
@@ -0,0 +1,34 @@
+/// Whether derived data, e.g. parses of notes and completion candidates, may be kept around to be
+/// reused. Keeping it is turned off while the managed heap is over `core.memory_limit_mb`, which
+/// trades speed for memory.
+module Marksman.Caches
+
+open System
+
+let mutable private isEnabled = true
+
+/// Whether caches may hold on to what they computed.
+let enabled () : bool = isEnabled
+
+let heapBytes () : int64 = GC.GetTotalMemory(false)
+
+// Caches come back once the heap is this far under the limit, so that they don't flip on and off
+// with every collection
+let private resumeRatio = 0.8
+
+/// Turns the caches off when `heap` is over `limitMb`, and on again once it's well under it. A
+/// limit of 0 means there is none. Returns whether the caches were turned on or off.
+let applyLimit (limitMb: int) (heap: int64) : bool =
+    let wasEnabled = isEnabled
+
+    if limitMb <= 0 then
+        isEnabled <- true
+    else
+        let limit = int64 limitMb * 1024L * 1024L
+
+        if isEnabled && heap > limit then
+            isEnabled <- false
+        elif not isEnabled && float heap < float limit * resumeRatio then
+            isEnabled <- true
+
+    isEnabled <> wasEnabled
@@ -0,0 +1,165 @@
+/// Headless checks of a workspace, e.g. `marksman check docs/` in CI.
+module Marksman.Check
+
+open System
+open System.IO
+open Newtonsoft.Json
+open Newtonsoft.Json.Linq
+open Ionide.LanguageServerProtocol.Types
+
+open Marksman.Config
+open Marksman.Diag
+open Marksman.Misc
+open Marksman.Workspace
+
+[<RequireQualifiedAccess>]
+type Format =
+    | Human
+    | Json
+    | Sarif
+
+module Format =
+    let ofString (input: string) : option<Format> =
+        match input.ToLowerInvariant() with
+        | "human" -> Some Format.Human
+        | "json" -> Some Format.Json
+        | "sarif" -> Some Format.Sarif
+        | _ -> None
+
+/// The folder at `dir` with its config merged over the user config, if any.
+let tryLoadFolder (dir: string) : option<Folder> =
+    let dir = Path.GetFullPath(dir)
+
+    // The config directory itself may not exist, e.g. in CI
+    let userConfig =
+        if File.Exists(Config.userConfigFile) then
+            Config.read Config.userConfigFile
+        else
+            None
+
+    let root = RootPath.ofPath (PathUri.ofString dir)
+    Folder.tryLoad userConfig (Path.GetFileName(dir)) root
+
+let private severityName (severity: option<DiagnosticSeverity>) =
+    match severity with
+    | Some DiagnosticSeverity.Error -> "error"
+    | Some DiagnosticSeverity.Warning -> "warning"
+    | Some DiagnosticSeverity.Information -> "info"
+    | _ -> "hint"
+
+let private relPath (root: string) (uri: string) =
+    Path.GetRelativePath(root, (PathUri.ofString uri).LocalPath).Replace('\\', '/')
+
+/// One line per diagnostic, `path:line:col: severity: message [code]`, followed by the totals.
+let fmtHuman (root: string) (report: Report) : string =
+    let lines =
+        [ for doc in report.documents do
+              for diag in doc.diagnostics do
+                  let start = diag.Range.Start
+                  let code = diag.Code |> Option.map (fun code -> $" [{code}]")
+
+                  yield
+                      $"{relPath root doc.uri}:{start.Line + 1}:{start.Character + 1}: "
+                      + $"{severityName diag.Severity}: {diag.Message}"
+                      + Option.defaultValue "" code
+
+          yield
+              $"{report.errors} errors, {report.warnings} warnings, "
+              + $"{report.information + report.hints} notes" ]
+
+    String.Join(Environment.NewLine, lines)
+
+let fmtJson (report: Report) : string =
+    (Ionide.LanguageServerProtocol.Server.serialize report).ToString(Formatting.Indented)
+
+let private sarifLevel (severity: option<DiagnosticSeverity>) =
+    match severity with
+    | Some DiagnosticSeverity.Error -> "error"
+    | Some DiagnosticSeverity.Warning -> "warning"
+    | _ -> "note"
+
+/// The report as a SARIF 2.1.0 log, which code scanning tools understand.
+let fmtSarif (root: string) (report: Report) : string =
+    let result (uri: string) (diag: Diagnostic) =
+        let range = diag.Range
+
+        let region =
+            JObject(
+                JProperty("startLine", range.Start.Line + 1),
+                JProperty("startColumn", range.Start.Character + 1),
+                JProperty("endLine", range.End.Line + 1),
+                JProperty("endColumn", range.End.Character + 1)
+            )
+
+        let location =
+            JObject(
+                JProperty(
+                    "physicalLocation",
+                    JObject(
+                        JProperty("artifactLocation", JObject(JProperty("uri", relPath root uri))),
+                        JProperty("region", region)
+                    )
+                )
+            )
+
+        JObject(
+            JProperty("ruleId", Option.defaultValue "marksman" diag.Code),
+            JProperty("level", sarifLevel diag.Severity),
+            JProperty("message", JObject(JProperty("text", diag.Message))),
+            JProperty("locations", JArray(location))
+        )
+
+    let results =
+        [ for doc in report.documents do
+              for diag in doc.diagnostics do
+                  yield result doc.uri diag ]
+
+    let rules =
+        results
+        |> List.map (fun result -> result.Value<string>("ruleId"))
+        |> List.distinct
+        |> List.map (fun id -> JObject(JProperty("id", id)))
+
+    let driver =
+        JObject(
+            JProperty("name", "marksman"),
+            JProperty("informationUri", "https://github.com/artempyanykh/marksman"),
+            JProperty("rules", JArray(rules))
+        )
+
+    let run =
+        JObject(
+            JProperty("tool", JObject(JProperty("driver", driver))),
+            JProperty("results", JArray(results))
+        )
+
+    let log =
+        JObject(
+            JProperty("$schema", "https://json.schemastore.org/sarif-2.1.0.json"),
+            JProperty("version", "2.1.0"),
+            JProperty("runs", JArray(run))
+        )
+
+    log.ToString(Formatting.Indented)
+
+/// Checks the folder at `dir` and prints the report. The exit code is non-zero when there are
+/// errors, or when the folder can't be read.
+let run (dir: string) (format: Format) : int =
+    match tryLoadFolder dir with
+    | None ->
+        eprintfn $"Can't read the folder: {dir}"
+        2
+    | Some folder ->
+        let root = (RootPath.path (Folder.rootPath folder)).LocalPath
+
+        let report =
+            Map.ofList [ Folder.id folder, FolderDiag.mk folder ] |> Report.ofWorkspaceDiag
+
+        let output =
+            match format with
+            | Format.Human -> fmtHuman root report
+            | Format.Json -> fmtJson report
+            | Format.Sarif -> fmtSarif root report
+
+        printfn "%s" output
+        if report.errors > 0 then 1 else 0
@@ -1,8 +1,13 @@
 module Marksman.CodeActions
 
+open System.IO
 open Ionide.LanguageServerProtocol.Types
 open Ionide.LanguageServerProtocol.Logging
 
+open Marksman.Config
+open Marksman.Cst
+open Marksman.Index
+open Marksman.Refs
 open Marksman.Toc
 open Marksman.Workspace
 open Marksman.Misc
@@ -18,10 +23,13 @@ let documentEdit range text documentUri : WorkspaceEdit =
 
     let workspaceChanges = Map.ofList [ documentUri, [| textEdit |] ]
 
-    { Changes = Some workspaceChanges; DocumentChanges = None }
+    { Changes = Some workspaceChanges
+      DocumentChanges = None
+      ChangeAnnotations = None }
 
-let tableOfContentsInner (doc: Doc) : DocumentAction option =
-    match TableOfContents.mk (Doc.index doc) with
+/// The action inserting or refreshing the table of contents of headings up to `maxLevel`.
+let tableOfContentsUpTo (maxLevel: int) (doc: Doc) : DocumentAction option =
+    match TableOfContents.mk (Doc.index doc) |> Option.bind (TableOfContents.upTo maxLevel) with
     | Some toc ->
         let rendered = TableOfContents.render toc
         let existingRange = TableOfContents.detect (Doc.text doc)
@@ -93,9 +101,328 @@ let tableOfContentsInner (doc: Doc) : DocumentAction option =
     | _ -> None
 
 
+let tableOfContentsInner (doc: Doc) : DocumentAction option =
+    tableOfContentsUpTo (Config.Default.CaTocMaxLevel()) doc
+
 let tableOfContents
+    (maxLevel: int)
     (_range: Range)
     (_context: CodeActionContext)
     (doc: Doc)
     : DocumentAction option =
-    tableOfContentsInner doc
+    tableOfContentsUpTo maxLevel doc
+
+let private rowCells (table: Table) (rowOffset: int) =
+    match rowOffset with
+    | offset when offset >= 2 && offset - 2 < table.rows.Length -> table.rows[offset - 2]
+    | _ -> table.header
+
+/// Table edits at the cursor: re-aligning the table, inserting a row below the cursor's row and
+/// inserting a column after the cursor's column.
+let tableActions (range: Range) (_context: CodeActionContext) (doc: Doc) : array<DocumentAction> =
+    let pos = range.Start
+
+    let table =
+        Doc.index doc
+        |> Index.tables
+        |> Array.tryFind (fun { range = tableRange } -> tableRange.ContainsInclusive(pos))
+
+    match table with
+    | None -> [||]
+    | Some { data = table; text = tableText; range = tableRange } ->
+        let grid = Tables.Grid.ofTable table
+
+        let render = Tables.Grid.renderNested table.linePrefix
+
+        let action name grid =
+            { name = name; newText = render grid; edit = tableRange }
+
+        let rowOffset = pos.Line - tableRange.Start.Line
+
+        let column =
+            rowCells table rowOffset
+            |> Array.tryFindIndexBack (fun cell -> cell.range.Start.Character <= pos.Character)
+            |> Option.defaultValue 0
+
+        let format =
+            if render grid = tableText then
+                None
+            else
+                Some(action "Format table" grid)
+
+        // Inserting below the header (or the delimiter row) makes the new row the first one
+        let insertRow =
+            action "Insert table row below" (Tables.Grid.insertRow (max 0 (rowOffset - 1)) grid)
+
+        let insertColumn =
+            action "Insert table column after" (Tables.Grid.insertColumn (column + 1) grid)
+
+        Array.append (Option.toArray format) [| insertRow; insertColumn |]
+
+/// Edit appending the line at the end of the doc. Link definitions can't interrupt a paragraph, so
+/// the line is separated from a preceding paragraph by an empty line.
+let private appendLinkDefLine (doc: Doc) (line: string) : DocumentAction =
+    let text = Doc.text doc
+    let endRange = text.EndRange()
+    let lastLine = endRange.Start.Line - 1
+
+    let isDef lineNum =
+        Doc.index doc
+        |> Index.linkDefs
+        |> Array.exists (fun { range = range } ->
+            range.Start.Line <= lineNum && lineNum <= range.End.Line)
+
+    let isEmpty lineNum = text.LineContent(lineNum).IsWhitespace()
+
+    let before =
+        if text.content.Length = 0 then ""
+        elif not (text.content.EndsWith('\n')) then
+            if isDef lastLine then NewLine else NewLine + NewLine
+        elif isEmpty lastLine || isDef lastLine then ""
+        else NewLine
+
+    { name = ""; newText = before + line + NewLine; edit = endRange }
+
+/// Quick fixes for the diagnostics about link definitions in the `context`: removing footnotes and
+/// link definitions nobody references, and creating the missing definitions of footnote and
+/// reference links.
+let linkDefFixes (context: CodeActionContext) (doc: Doc) : array<DocumentAction * Diagnostic> =
+    let index = Doc.index doc
+
+    // The whole lines of the definition are removed
+    let remove (diag: Diagnostic) what =
+        Index.linkDefs index
+        |> Array.tryFind (fun { range = range } -> range = diag.Range)
+        |> Option.map (fun { data = def; range = range } ->
+            { name = $"Remove unused {what} [{MdLinkDef.name def}]"
+              newText = ""
+              edit = Range.Mk(range.Start.Line, 0, range.End.Line + 1, 0) })
+
+    let create (diag: Diagnostic) =
+        match Index.linkAtPos diag.Range.Start index with
+        | Some (ML { data = link }) ->
+            match MdLink.referenceLabel link with
+            | Some label when label.text.StartsWith('^') ->
+                let action = appendLinkDefLine doc $"[{label.text}]: "
+                Some { action with name = $"Create footnote [{label.text}]" }
+            | Some label ->
+                let action = appendLinkDefLine doc $"[{label.text}]: <>"
+                Some { action with name = $"Create link definition [{label.text}]" }
+            | None -> None
+        | _ -> None
+
+    context.Diagnostics
+    |> Array.choose (fun diag ->
+        let action =
+            match diag.Code with
+            | Some code when code = Diag.Rule.unusedFootnote -> remove diag "footnote"
+            | Some code when code = Diag.Rule.unusedLinkDef -> remove diag "link definition"
+            | Some code when code = Diag.Rule.brokenLink -> create diag
+            | _ -> None
+
+        action |> Option.map (fun action -> action, diag))
+
+/// Fixes of diagnostics: edits of the document with the diagnostic, or notes to create.
+type QuickFix =
+    | EditDoc of DocumentAction
+    | CreateNote of name: string * path: string * content: string
+
+/// The command creating a note, with the note's path and content as arguments.
+let createNoteCommand = Compl.NewNote.createCommandName
+
+// Links are fixed only to targets that are a few typos away from what's typed
+let private isNearMatch (distance: int) (typed: string) = distance <= max 1 (typed.Length / 3)
+
+/// Replacements of the `typed` text among the `candidates`, which are pairs of the replacement and
+/// the names it's known by. The nearest ones are listed first, at most 3 of them.
+let private nearestReplacements
+    (typed: string)
+    (candidates: seq<string * list<string>>)
+    : list<string * int> =
+    candidates
+    |> Seq.map (fun (replacement, names) ->
+        replacement, names |> List.map (Fuzzy.editDistance typed) |> List.min)
+    |> Seq.filter (fun (replacement, distance) ->
+        replacement <> typed && isNearMatch distance typed)
+    |> Seq.sortBy (fun (replacement, distance) -> distance, replacement)
+    |> Seq.distinctBy fst
+    |> Seq.truncate 3
+    |> List.ofSeq
+
+let private replacementFix (range: Range) (replacement: string, distance: int) =
+    let name =
+        if distance = 0 then
+            $"Fix casing to '{replacement}'"
+        else
+            $"Replace with '{replacement}'"
+
+    EditDoc { name = name; newText = replacement; edit = range }
+
+let private docFixes (folder: Folder) (srcDoc: Doc) (link: Element) (name: InternNameNode) =
+    let config = Folder.configFor srcDoc folder
+    let typed = name.text
+
+    let candidates =
+        Folder.docs folder
+        |> Seq.filter (fun doc -> doc <> srcDoc)
+        |> Seq.map (fun doc ->
+            let path = Doc.pathFromRoot doc
+            let stem = Path.GetFileNameWithoutExtension(path)
+
+            match link with
+            | WL _ ->
+                let style = config.ComplWikiStyle()
+                let replacement = Compl.CompletionHelpers.wikiTargetLink style doc
+                replacement, [ replacement; Doc.name doc; stem ]
+            | _ ->
+                let replacement = Assets.relativeLink srcDoc path
+                replacement, [ replacement; path ])
+
+    let replacements =
+        nearestReplacements typed candidates |> List.map (replacementFix name.range)
+
+    let notePath =
+        match link with
+        | WL _ -> Compl.NewNote.tryPath folder typed |> Option.map (fun path -> typed, path)
+        | _ ->
+            InternName.tryResolveToRootPath (Folder.rootPath folder) (Doc.path srcDoc) typed
+            |> Option.map (fun relPath ->
+                let root = (RootPath.path (Folder.rootPath folder)).LocalPath
+                Path.GetFileNameWithoutExtension(relPath), Path.Join(root, relPath))
+
+    let createNote =
+        notePath
+        |> Option.map (fun (title, path) ->
+            CreateNote($"Create note '{title}'", path, Compl.NewNote.content folder title))
+
+    replacements @ Option.toList createNote
+
+let private headingFixes
+    (folder: Folder)
+    (srcDoc: Doc)
+    (link: Element)
+    (uref: Uref)
+    (heading: TextNode)
+    =
+    let destDoc =
+        match uref with
+        | Uref.Heading (Some docName, _) ->
+            Dest.tryResolveUref (Uref.Doc docName) srcDoc folder
+            |> Seq.tryHead
+            |> Option.map Dest.doc
+        | _ -> Some srcDoc
+
+    let candidates =
+        destDoc
+        |> Option.map (Doc.index >> Index.headings)
+        |> Option.defaultValue [||]
+        |> Seq.map (fun { data = h } ->
+            let name = Heading.name h
+
+            let replacement =
+                match link with
+                | WL _ -> Slug.str name
+                | _ -> GitHubSlug.ofString name
+
+            replacement, [ replacement; name ])
+
+    nearestReplacements (Node.text heading) candidates
+    |> List.map (replacementFix (Node.range heading))
+
+/// Quick fixes of broken links in the `context`: replacing the target with the nearest existing
+/// note or heading, fixing its casing, or creating the missing note.
+let brokenLinkFixes
+    (folder: Folder)
+    (context: CodeActionContext)
+    (doc: Doc)
+    : array<QuickFix * Diagnostic> =
+    let configuredExts = (Folder.configOrDefault folder).CoreMarkdownFileExtensions()
+
+    context.Diagnostics
+    |> Array.filter (fun diag ->
+        diag.Code = Some Diag.Rule.brokenLink || diag.Code = Some Diag.Rule.brokenAnchor)
+    |> Array.collect (fun diag ->
+        let fixes (link: Element) =
+            match Uref.ofElement configuredExts link with
+            | Some (Uref.Doc name) -> docFixes folder doc link name
+            // Links to headings in docs that don't exist are broken links rather than anchors
+            | Some (Uref.Heading (Some name, _)) when diag.Code = Some Diag.Rule.brokenLink ->
+                docFixes folder doc link name
+            | Some (Uref.Heading (_, heading) as uref) -> headingFixes folder doc link uref heading
+            | Some (Uref.LinkDef _)
+            | None -> []
+
+        Doc.index doc
+        |> Index.linkAtPos diag.Range.Start
+        |> Option.map fixes
+        |> Option.defaultValue []
+        |> List.map (fun fix -> fix, diag)
+        |> Array.ofList)
+
+/// Quick fixes of links that break on some filesystems: respelling the target with '/' separators
+/// or with the case of the note it links to.
+let nonPortableLinkFixes
+    (folder: Folder)
+    (context: CodeActionContext)
+    (doc: Doc)
+    : array<DocumentAction * Diagnostic> =
+    let configuredExts = (Folder.configOrDefault folder).CoreMarkdownFileExtensions()
+
+    context.Diagnostics
+    |> Array.filter (fun diag -> diag.Code = Some Diag.Rule.nonPortableLink)
+    |> Array.choose (fun diag ->
+        let docName =
+            Doc.index doc
+            |> Index.linkAtPos diag.Range.Start
+            |> Option.bind (Uref.ofElement configuredExts)
+            |> Option.bind (function
+                | Uref.Doc name
+                | Uref.Heading (Some name, _) -> Some name
+                | Uref.Heading (None, _)
+                | Uref.LinkDef _ -> None)
+
+        docName
+        |> Option.bind (fun name ->
+            Diag.tryPortableName folder doc name
+            |> Option.map (fun (portable, _) ->
+                { name = $"Change link to '{portable}'"; newText = portable; edit = name.range },
+                diag)))
+
+/// Quick fixes of tags that don't follow the naming convention: renaming the tag to follow it, when
+/// the convention is a preset.
+let tagNameFixes
+    (config: Config)
+    (context: CodeActionContext)
+    (doc: Doc)
+    : array<DocumentAction * Diagnostic> =
+    let convention = config.DiagTagConvention()
+
+    context.Diagnostics
+    |> Array.filter (fun diag -> diag.Code = Some Diag.Rule.tagName)
+    |> Array.choose (fun diag ->
+        Doc.index doc
+        |> Index.tags
+        |> Array.tryFind (fun tag -> tag.range = diag.Range)
+        |> Option.bind (fun tag ->
+            let name = tag.data.name
+
+            Diag.TagName.tryRespell convention name.text
+            |> Option.map (fun respelled ->
+                { name = $"Rename to '#{respelled}'"; newText = respelled; edit = name.range },
+                diag)))
+
+/// Fixes of the diagnostics listed in the `context` of a code action request.
+let quickFixes
+    (folder: Folder)
+    (context: CodeActionContext)
+    (doc: Doc)
+    : array<QuickFix * Diagnostic> =
+    let config = Folder.configFor doc folder
+
+    let docEdits =
+        [| yield! linkDefFixes context doc
+           yield! nonPortableLinkFixes folder context doc
+           yield! tagNameFixes config context doc |]
+        |> Array.map (fun (action, diag) -> EditDoc action, diag)
+
+    Array.append docEdits (brokenLinkFixes folder context doc)
@@ -0,0 +1,541 @@
+/// Commands of the server for features that don't fit a standard request. Clients invoke them by
+/// name via `workspace/executeCommand`, and every registered command is advertised at initialize.
+module Marksman.Commands
+
+open System
+open System.IO
+open System.Text.RegularExpressions
+open Ionide.LanguageServerProtocol.Types
+open Ionide.LanguageServerProtocol.Server
+open Newtonsoft.Json.Linq
+
+open Marksman.Diag
+open Marksman.Misc
+open Marksman.State
+open Marksman.Workspace
+
+/// What the server does on behalf of a command once it ran.
+type Effect =
+    /// Publish the diagnostics right away instead of after the usual debounce
+    | PublishDiag of WorkspaceDiag
+    /// Ask the client to apply the edit, so that it works for notes open in the editor too
+    | ApplyEdit of label: string * WorkspaceEdit
+
+/// Commands run on a snapshot of the state, so that slow ones don't hold up other requests.
+/// Changes of the state are applied afterwards by `update`, which gets the state of that moment.
+type Outcome =
+    { result: JToken
+      update: option<State -> option<State>>
+      effects: list<Effect> }
+
+module Outcome =
+    let empty: Outcome = { result = JValue.CreateNull(); update = None; effects = [] }
+
+    let result (result: JToken) : Outcome = { empty with result = result }
+
+    let effect (effect: Effect) : Outcome = { empty with effects = [ effect ] }
+
+type Command =
+    { name: string
+      run: State -> array<JToken> -> Result<Outcome, string> }
+
+let private stringArg (arg: JToken) : option<string> =
+    if arg.Type = JTokenType.String then Some(arg.Value<string>()) else None
+
+let private intArg (arg: JToken) : option<int> =
+    if arg.Type = JTokenType.Integer then Some(arg.Value<int>()) else None
+
+let private boolArg (arg: JToken) : option<bool> =
+    if arg.Type = JTokenType.Boolean then Some(arg.Value<bool>()) else None
+
+let createNote: Command =
+    let isWithin (folder: Folder) (path: string) =
+        let root = (RootPath.path (Folder.rootPath folder)).LocalPath
+        let relPath = Path.GetRelativePath(root, Path.GetFullPath(path))
+        not (Path.IsPathRooted(relPath) || relPath.Split([| '/'; '\\' |])[0] = "..")
+
+    let run state (args: array<JToken>) =
+        match Array.map stringArg args with
+        | [| Some path; Some content |] ->
+            let docUri = PathUri.ofString path
+
+            // Nothing is written where the server has no business writing
+            match State.tryFindFolderEnclosing docUri state with
+            | Some folder when isWithin folder docUri.LocalPath ->
+                if File.Exists(docUri.LocalPath) then
+                    Error $"The note exists already: {path}"
+                else
+                    Compl.NewNote.create docUri.LocalPath content
+
+                    // Clients may not report files created by the server, so it's added here
+                    let update state =
+                        State.tryFindFolderEnclosing docUri state
+                        |> Option.bind (fun folder ->
+                            Folder.tryLoadDoc docUri folder
+                            |> Option.map (fun doc -> Folder.withDoc doc folder))
+                        |> Option.map (fun folder -> State.updateFolder folder state)
+
+                    Ok { Outcome.empty with update = Some update }
+            | _ -> Error $"The note is outside of the workspace folders: {path}"
+        | _ -> Error "Expected the path and the content of the note"
+
+    { name = CodeActions.createNoteCommand; run = run }
+
+let checkWorkspace: Command =
+    let run state _ =
+        let diag = WorkspaceDiag.mk (State.workspace state)
+        let report = Report.ofWorkspaceDiag diag
+
+        Ok
+            { Outcome.empty with
+                result = serialize report
+                effects = [ PublishDiag diag ] }
+
+    { name = checkWorkspaceCommand; run = run }
+
+let updateTocCommand = "marksman.updateToc"
+
+let updateToc: Command =
+    let run state (args: array<JToken>) =
+        match Array.map stringArg args with
+        | [| Some uri |] ->
+            match State.tryFindFolderAndDoc (PathUri.ofString uri) state with
+            | None -> Error $"Unknown document: {uri}"
+            | Some (folder, doc) ->
+                let maxLevel = (Folder.configFor doc folder).CaTocMaxLevel()
+
+                match CodeActions.tableOfContentsUpTo maxLevel doc with
+                | None -> Ok Outcome.empty
+                | Some toc ->
+                    let edit = CodeActions.documentEdit toc.edit toc.newText uri
+
+                    // Clients that can't apply edits on request get the edit to apply themselves
+                    if (State.client state).SupportsApplyEdit then
+                        Ok(Outcome.effect (ApplyEdit(toc.name, edit)))
+                    else
+                        Ok(Outcome.result (serialize edit))
+        | _ -> Error "Expected the URI of the document"
+
+    { name = updateTocCommand; run = run }
+
+let updateBacklinksCommand = "marksman.updateBacklinks"
+
+/// Creates or refreshes the backlinks section of the document, see `Backlinks.update`.
+let updateBacklinks: Command =
+    let run state (args: array<JToken>) =
+        match Array.map stringArg args with
+        | [| Some uri |] ->
+            match State.tryFindFolderAndDoc (PathUri.ofString uri) state with
+            | None -> Error $"Unknown document: {uri}"
+            | Some (folder, doc) ->
+                match Backlinks.update folder doc with
+                | None -> Ok Outcome.empty
+                | Some action ->
+                    let edit = CodeActions.documentEdit action.edit action.newText uri
+
+                    if (State.client state).SupportsApplyEdit then
+                        Ok(Outcome.effect (ApplyEdit(action.name, edit)))
+                    else
+                        Ok(Outcome.result (serialize edit))
+        | _ -> Error "Expected the URI of the document"
+
+    { name = updateBacklinksCommand; run = run }
+
+let dumpProfileCommand = "marksman.dumpProfile"
+
+/// Writes the recent spans of `Trace` to the given path, or to a new file in the temp directory.
+/// The result has the path of the file, to attach to a report of slowness.
+let dumpProfile: Command =
+    let run _ (args: array<JToken>) =
+        let path =
+            match Array.map stringArg args with
+            | [||] ->
+                let time = DateTime.Now.ToString("yyyyMMdd-HHmmss")
+                Ok(Path.Combine(Path.GetTempPath(), $"marksman-profile-{time}.json"))
+            | [| Some path |] -> Ok path
+            | _ -> Error "Expected the path of the file to write the profile to"
+
+        path
+        |> Result.bind (fun path ->
+            try
+                let spans = Trace.dump path
+                Ok(Outcome.result (JObject(JProperty("path", path), JProperty("spans", spans))))
+            with
+            | :? IOException
+            | :? UnauthorizedAccessException as exn ->
+                Error $"Can't write the profile: {exn.Message}")
+
+    { name = dumpProfileCommand; run = run }
+
+let mergeNotesCommand = "marksman.mergeNotes"
+
+/// Merges the note with the first URI into the one with the second URI, see `Refactor.mergeNotes`.
+/// The edit deletes a file, so it needs a client that can apply such edits.
+let mergeNotes: Command =
+    let run state (args: array<JToken>) =
+        match Array.map stringArg args with
+        | [| Some srcUri; Some destUri |] ->
+            let src = State.tryFindFolderAndDoc (PathUri.ofString srcUri) state
+            let dest = State.tryFindDoc (PathUri.ofString destUri) state
+            let client = State.client state
+
+            let sameFolder (folder: Folder) (doc: Doc) =
+                State.tryFindFolderEnclosing (Doc.path doc) state
+                |> Option.exists (fun other -> Folder.id other = Folder.id folder)
+
+            match src, dest with
+            | None, _ -> Error $"Unknown document: {srcUri}"
+            | _, None -> Error $"Unknown document: {destUri}"
+            | Some (folder, _), Some dest when not (sameFolder folder dest) ->
+                Error "Can't merge notes of different folders"
+            | _ when not client.SupportsDeleteFile ->
+                Error "The client can't apply workspace edits that delete files"
+            | Some (folder, src), Some dest ->
+                Refactor.mergeNotes folder src dest
+                |> Result.map (fun edit ->
+                    if client.SupportsApplyEdit then
+                        Outcome.effect (ApplyEdit("Merge notes", edit))
+                    else
+                        Outcome.result (serialize edit))
+        | _ -> Error "Expected the URIs of the note to merge and of the note to merge it into"
+
+    { name = mergeNotesCommand; run = run }
+
+let convertLinksCommand = "marksman.convertLinks"
+
+/// Rewrites the links of the note with the given URI, or of all the notes of the workspace when
+/// there's none, in the given style: `wiki` or `markdown`, see `Refactor.convertLinks`.
+let convertLinks: Command =
+    let run state (args: array<JToken>) =
+        let ws = State.workspace state
+
+        let docs =
+            match Array.map stringArg args with
+            | [| Some style |] ->
+                let docs =
+                    Workspace.folders ws
+                    |> Seq.collect (fun folder ->
+                        Folder.docs folder |> Seq.map (fun doc -> folder, doc))
+
+                Ok(style, List.ofSeq docs)
+            | [| Some style; Some uri |] ->
+                match State.tryFindFolderAndDoc (PathUri.ofString uri) state with
+                | Some (folder, doc) -> Ok(style, [ folder, doc ])
+                | None -> Error $"Unknown document: {uri}"
+            | _ -> Error "Expected the style of the links and optionally the URI of the document"
+
+        let client = State.client state
+
+        docs
+        |> Result.bind (fun (style, docs) ->
+            match Refactor.LinkStyle.ofString style with
+            | None -> Error $"Unknown style of links: {style}; expected wiki or markdown"
+            | Some style ->
+                let edits =
+                    docs
+                    |> List.choose (fun (folder, doc) ->
+                        match Refactor.convertLinks folder doc style with
+                        | [||] -> None
+                        | edits ->
+                            Some
+                                { TextDocument = { Uri = Doc.uri doc; Version = Doc.version doc }
+                                  Edits = edits })
+                    |> Array.ofList
+
+                if Array.isEmpty edits then
+                    Ok Outcome.empty
+                else
+                    let edit = Refactor.mkWorkspaceEdit client.SupportsDocumentEdit edits
+
+                    if client.SupportsApplyEdit then
+                        Ok(Outcome.effect (ApplyEdit("Convert links", edit)))
+                    else
+                        Ok(Outcome.result (serialize edit)))
+
+    { name = convertLinksCommand; run = run }
+
+let openDailyNoteCommand = "marksman.openDailyNote"
+
+/// Opens the daily note `days` from today (0 when not given) of the folder enclosing the given
+/// URI, or of the first folder. A missing note is created from the template of created notes.
+/// The result is the location of the note for the client to show.
+let openDailyNote: Command =
+    let run state (args: array<JToken>) =
+        let ws = State.workspace state
+
+        let target =
+            match args with
+            | [||] -> Ok(None, 0)
+            | [| arg |] ->
+                match stringArg arg, intArg arg with
+                | Some uri, _ -> Ok(Some uri, 0)
+                | _, Some days -> Ok(None, days)
+                | _ -> Error()
+            | [| uri; days |] ->
+                match stringArg uri, intArg days with
+                | Some uri, Some days -> Ok(Some uri, days)
+                | _ -> Error()
+            | _ -> Error()
+
+        let location (doc: Doc) : JToken =
+            serialize ({ Uri = Doc.uri doc; Range = Range.Mk(0, 0, 0, 0) }: Location)
+
+        match target with
+        | Error () -> Error "Expected the URI of a document or folder and the offset in days"
+        | Ok (uri, days) ->
+            let folder =
+                match uri with
+                | Some uri -> Workspace.tryFindFolderEnclosing (PathUri.ofString uri) ws
+                | None -> Workspace.folders ws |> Seq.tryHead
+
+            match folder with
+            | None -> Error "No folder to open the daily note in"
+            | Some folder ->
+                let date, name = Compl.DailyNote.ofOffset folder DateTime.Today days
+
+                match Compl.DailyNote.tryFindDoc folder name with
+                | Some doc -> Ok(Outcome.result (location doc))
+                | None ->
+                    let doc =
+                        Compl.DailyNote.tryCreate folder name date
+                        |> Option.bind (fun path ->
+                            Folder.tryLoadDoc (PathUri.ofString path) folder)
+
+                    match doc with
+                    | None -> Error $"Can't create the daily note {name}"
+                    | Some doc ->
+                        // Like in createNote, the client may not report the new file
+                        let update state =
+                            State.tryFindFolderEnclosing (Doc.path doc) state
+                            |> Option.map (fun folder ->
+                                State.updateFolder (Folder.withDoc doc folder) state)
+
+                        Ok
+                            { Outcome.empty with
+                                result = location doc
+                                update = Some update }
+
+    { name = openDailyNoteCommand; run = run }
+
+let createZettelCommand = "marksman.createZettel"
+
+/// Creates a note named after a new ID in `core.zettel_id.format` followed by the given title, at
+/// the root of the folder enclosing the given URI or of the first folder. The result is the
+/// location of the note.
+let createZettel: Command =
+    let run state (args: array<JToken>) =
+        let ws = State.workspace state
+
+        let target =
+            match Array.map stringArg args with
+            | [| Some title |] -> Ok(title, Workspace.folders ws |> Seq.tryHead)
+            | [| Some title; Some uri |] ->
+                Ok(title, Workspace.tryFindFolderEnclosing (PathUri.ofString uri) ws)
+            | _ -> Error "Expected the title of the note and optionally the URI of a document"
+
+        target
+        |> Result.bind (fun (title, folder) ->
+            match folder with
+            | None -> Error "No folder to create the note in"
+            | Some folder ->
+                let format = (Folder.configOrDefault folder).CoreZettelIdFormat()
+                let name = Zettel.noteName (Zettel.newId format DateTime.Now) title
+                let title = if title.IsWhitespace() then name else title.Trim()
+
+                let doc =
+                    Compl.NewNote.tryPath folder name
+                    |> Option.filter (fun path -> not (File.Exists(path)))
+                    |> Option.bind (fun path ->
+                        Compl.NewNote.create path (Compl.NewNote.content folder title)
+                        Folder.tryLoadDoc (PathUri.ofString path) folder)
+
+                match doc with
+                | None -> Error $"Can't create the note {name}"
+                | Some doc ->
+                    let update state =
+                        State.tryFindFolderEnclosing (Doc.path doc) state
+                        |> Option.map (fun folder ->
+                            State.updateFolder (Folder.withDoc doc folder) state)
+
+                    let location: Location = { Uri = Doc.uri doc; Range = Range.Mk(0, 0, 0, 0) }
+
+                    Ok
+                        { Outcome.empty with
+                            result = serialize location
+                            update = Some update })
+
+    { name = createZettelCommand; run = run }
+
+let unusedAttachmentsCommand = "marksman.unusedAttachments"
+
+/// Lists the URIs of the files in asset directories that no note links to, see `Assets.unused`,
+/// for the folder enclosing the given URI or for all folders. With `true` after the URI, the
+/// client is also asked to delete them.
+let unusedAttachments: Command =
+    let run state (args: array<JToken>) =
+        let ws = State.workspace state
+
+        let folderOf uri =
+            match Workspace.tryFindFolderEnclosing (PathUri.ofString uri) ws with
+            | Some folder -> Ok [ folder ]
+            | None -> Error $"Unknown folder: {uri}"
+
+        let target =
+            match args |> Array.map (fun arg -> stringArg arg, boolArg arg) with
+            | [||] -> Ok(List.ofSeq (Workspace.folders ws), false)
+            | [| Some uri, _ |] -> folderOf uri |> Result.map (fun folders -> folders, false)
+            | [| Some uri, _; _, Some delete |] ->
+                folderOf uri |> Result.map (fun folders -> folders, delete)
+            | _ -> Error "Expected the URI of a document or folder and whether to delete the files"
+
+        let client = State.client state
+
+        target
+        |> Result.bind (fun (folders, delete) ->
+            let uris =
+                folders
+                |> Seq.collect (fun folder ->
+                    let root = (RootPath.path (Folder.rootPath folder)).LocalPath
+
+                    Assets.unused folder
+                    |> Seq.map (fun path -> (PathUri.ofString (Path.Join(root, path))).DocumentUri))
+                |> Array.ofSeq
+
+            let result = JArray(uris)
+
+            if not delete || Array.isEmpty uris then
+                Ok(Outcome.result result)
+            elif not client.SupportsDeleteFile || not client.SupportsApplyEdit then
+                Error "The client can't apply workspace edits that delete files"
+            else
+                let edit =
+                    { Changes = None
+                      DocumentChanges =
+                        Some(uris |> Array.map (DeleteFile.Create >> DocumentChange.Delete))
+                      ChangeAnnotations = None }
+
+                Ok
+                    { Outcome.empty with
+                        result = result
+                        effects = [ ApplyEdit("Delete unused attachments", edit) ] })
+
+    { name = unusedAttachmentsCommand; run = run }
+
+let findAndReplaceCommand = "marksman.findAndReplace"
+
+/// Replaces the given text with the replacement in all the notes of the workspace, or the matches
+/// of a regex when `true` comes after them, see `Refactor.replaceEdits`. The edits come as one
+/// annotated workspace edit, so that clients can preview them and ask for confirmation.
+let findAndReplace: Command =
+    let run state (args: array<JToken>) =
+        let pattern =
+            match args |> Array.map (fun arg -> stringArg arg, boolArg arg) with
+            | [| Some find, _; Some replace, _ |]
+            | [| Some find, _; Some replace, _; _, Some false |] ->
+                Ok(Refactor.Pattern.Literal find, find, replace)
+            | [| Some find, _; Some replace, _; _, Some true |] ->
+                try
+                    let regex = Regex(find, RegexOptions.Multiline, TimeSpan.FromSeconds(1))
+                    Ok(Refactor.Pattern.Regex regex, find, replace)
+                with :? ArgumentException as exn ->
+                    Error $"Invalid regex: {exn.Message}"
+            | _ -> Error "Expected the text to find, its replacement and whether it's a regex"
+
+        let client = State.client state
+
+        pattern
+        |> Result.bind (fun (pattern, find, replace) ->
+            let docEdits () =
+                State.workspace state
+                |> Workspace.folders
+                |> Seq.collect Folder.docs
+                |> Seq.choose (fun doc ->
+                    match Refactor.replaceEdits pattern replace doc with
+                    | [||] -> None
+                    | edits ->
+                        Some
+                            { TextDocument = { Uri = Doc.uri doc; Version = Doc.version doc }
+                              Edits = edits })
+                |> Array.ofSeq
+
+            let docEdits =
+                try
+                    Ok(docEdits ())
+                with :? RegexMatchTimeoutException ->
+                    Error $"The regex takes too long to match: {find}"
+
+            docEdits
+            |> Result.map (fun docEdits ->
+                if Array.isEmpty docEdits then
+                    Outcome.empty
+                else
+                    let label = $"Replace {find} with {replace}"
+                    let matches = docEdits |> Array.sumBy (fun docEdit -> docEdit.Edits.Length)
+
+                    let annotation: ChangeAnnotation =
+                        { Label = label
+                          NeedsConfirmation = Some true
+                          Description = Some $"{matches} matches in {docEdits.Length} notes" }
+
+                    let edit = Refactor.mkAnnotatedWorkspaceEdit client annotation docEdits
+
+                    if client.SupportsApplyEdit then
+                        Outcome.effect (ApplyEdit(label, edit))
+                    else
+                        Outcome.result (serialize edit)))
+
+    { name = findAndReplaceCommand; run = run }
+
+let renumberCommand = "marksman.renumber"
+
+/// Renumbers the ordered lists of the document with the given URI, and its headings as well when
+/// `true` comes after the URI, see `Numbering.edits`.
+let renumber: Command =
+    let run state (args: array<JToken>) =
+        let target =
+            match args |> Array.map (fun arg -> stringArg arg, boolArg arg) with
+            | [| Some uri, _ |] -> Ok(uri, false)
+            | [| Some uri, _; _, Some headings |] -> Ok(uri, headings)
+            | _ -> Error "Expected the URI of the document and whether to number the headings"
+
+        target
+        |> Result.bind (fun (uri, headings) ->
+            match State.tryFindDoc (PathUri.ofString uri) state with
+            | None -> Error $"Unknown document: {uri}"
+            | Some doc ->
+                match Numbering.edits headings doc with
+                | [||] -> Ok Outcome.empty
+                | edits ->
+                    let client = State.client state
+                    let lspDoc = { Uri = Doc.uri doc; Version = Doc.version doc }
+
+                    let edit =
+                        Refactor.mkWorkspaceEdit
+                            client.SupportsDocumentEdit
+                            [| { TextDocument = lspDoc; Edits = edits } |]
+
+                    if client.SupportsApplyEdit then
+                        Ok(Outcome.effect (ApplyEdit("Renumber", edit)))
+                    else
+                        Ok(Outcome.result (serialize edit)))
+
+    { name = renumberCommand; run = run }
+
+let all: array<Command> =
+    [| createNote
+       checkWorkspace
+       updateToc
+       updateBacklinks
+       dumpProfile
+       openDailyNote
+       createZettel
+       mergeNotes
+       convertLinks
+       unusedAttachments
+       findAndReplace
+       renumber |]
+
+let names: array<string> = all |> Array.map (fun command -> command.name)
+
+let tryFind (name: string) : option<Command> =
+    all |> Array.tryFind (fun command -> command.name = name)
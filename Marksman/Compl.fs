@@ -2,9 +2,11 @@
 
 open System
 
+open System.Globalization
 open System.IO
 open Ionide.LanguageServerProtocol.Logging
 open Ionide.LanguageServerProtocol.Types
+open Newtonsoft.Json.Linq
 
 open FSharpPlus.GenericBuilders
 
@@ -242,30 +244,56 @@ module Completable =
         | E _ -> false
         | PE _ -> true
 
+    let range =
+        function
+        | E el -> Element.range el
+        | PE pel -> PartialElement.range pel
+
 type Prompt =
     | WikiDoc of input: string
     | WikiHeadingInSrcDoc of input: string
     | WikiHeadingInOtherDoc of destPart: string * headingPart: string
+    | WikiBlockInOtherDoc of destPart: string * blockPart: string
     | Reference of input: string
+    | Footnote of input: string
     | InlineDoc of input: string
     | InlineAnchorInSrcDoc of input: string
     | InlineAnchorInOtherDoc of pathPart: string * anchorPart: string
     | Tag of input: string
+    | Citation of input: string
 
 module Prompt =
-    let ofCompletable (pos: Position) (compl: Completable) : option<Prompt> =
-        let elementRange =
-            match compl with
-            | E el -> Element.range el
-            | PE pel -> PartialElement.range pel
-
-        if not (elementRange.ContainsInclusive(pos)) then
+    // Inputs are NFC-normalized once, rather than every time they're matched against a candidate
+    let private normalized =
+        function
+        | WikiDoc input -> WikiDoc(input.Normalize())
+        | WikiHeadingInSrcDoc input -> WikiHeadingInSrcDoc(input.Normalize())
+        | WikiHeadingInOtherDoc (destPart, headingPart) ->
+            WikiHeadingInOtherDoc(destPart.Normalize(), headingPart.Normalize())
+        | WikiBlockInOtherDoc (destPart, blockPart) ->
+            WikiBlockInOtherDoc(destPart.Normalize(), blockPart.Normalize())
+        | Reference input -> Reference(input.Normalize())
+        | Footnote input -> Footnote(input.Normalize())
+        | InlineDoc input -> InlineDoc(input.Normalize())
+        | InlineAnchorInSrcDoc input -> InlineAnchorInSrcDoc(input.Normalize())
+        | InlineAnchorInOtherDoc (pathPart, anchorPart) ->
+            InlineAnchorInOtherDoc(pathPart.Normalize(), anchorPart.Normalize())
+        | Tag input -> Tag(input.Normalize())
+        | Citation input -> Citation(input.Normalize())
+
+    let private ofCompletableAsTyped (pos: Position) (compl: Completable) : option<Prompt> =
+        if not ((Completable.range compl).ContainsInclusive(pos)) then
             None
         else
             match compl with
             // No completion
             | E (H _)
             | E (MLD _)
+            | E (BA _)
+            | E (C _)
+            | E (TK _)
+            | E (TB _)
+            | E (CO _)
             | E (YML _) -> None
             // Wiki link
             | E (WL { data = { doc = doc; heading = None } }) ->
@@ -275,9 +303,13 @@ module Prompt =
             | E (WL { data = { doc = Some doc; heading = Some heading } }) ->
                 if doc.range.ContainsInclusive(pos) then
                     Some(WikiDoc doc.text)
+                elif heading.text.StartsWith('^') then
+                    Some(WikiBlockInOtherDoc(doc.text, heading.text.Substring(1)))
                 else
                     Some(WikiHeadingInOtherDoc(doc.text, heading.text))
             // Markdown link
+            | E (ML { data = MdLink.RS label }) when label.text.StartsWith('^') ->
+                Some(Footnote(label.text.Substring(1)))
             | E (ML { data = MdLink.RF (_, label) })
             | E (ML { data = MdLink.RC label })
             | E (ML { data = MdLink.RS label }) -> Some(Reference label.text)
@@ -297,6 +329,10 @@ module Prompt =
                 Some(WikiDoc(Node.textOpt doc String.Empty))
             | PE (PartialElement.WikiLink (None, heading, _)) ->
                 Some(WikiHeadingInSrcDoc(Node.textOpt heading String.Empty))
+            | PE (PartialElement.WikiLink (Some dest, Some heading, _)) when
+                heading.text.StartsWith('^')
+                ->
+                Some(WikiBlockInOtherDoc(dest.text, heading.text.Substring(1)))
             | PE (PartialElement.WikiLink (Some dest, Some heading, _)) ->
                 Some(WikiHeadingInOtherDoc(dest.text, heading.text))
             // Partial markdown link
@@ -306,11 +342,118 @@ module Prompt =
                 Some(InlineAnchorInSrcDoc(Node.textOpt anchor String.Empty))
             | PE (PartialElement.InlineLink (_, Some path, Some anchor, _)) ->
                 Some(InlineAnchorInOtherDoc(path.text, anchor.text))
+            | PE (PartialElement.ReferenceLink (Some label, _)) when label.text.StartsWith('@') ->
+                Some(Citation(label.text.Substring(1)))
+            | PE (PartialElement.ReferenceLink (Some label, _)) when label.text.StartsWith('^') ->
+                Some(Footnote(label.text.Substring(1)))
             | PE (PartialElement.ReferenceLink (label, _)) ->
                 Some(Reference(Node.textOpt label String.Empty))
             // Tags
             | E (T { data = { name = name } }) -> Some(Tag name.text)
             | PE (PartialElement.TagOpening _) -> Some(Tag String.Empty)
+            // Citations
+            | E (CT { data = { key = key } }) -> Some(Citation key.text)
+
+    let ofCompletable (pos: Position) (compl: Completable) : option<Prompt> =
+        ofCompletableAsTyped pos compl |> Option.map normalized
+
+    /// The part of the prompt candidates are matched against.
+    let input =
+        function
+        | WikiDoc input
+        | WikiHeadingInSrcDoc input
+        | WikiHeadingInOtherDoc (_, input)
+        | WikiBlockInOtherDoc (_, input)
+        | Reference input
+        | Footnote input
+        | InlineDoc input
+        | InlineAnchorInSrcDoc input
+        | InlineAnchorInOtherDoc (_, input)
+        | Tag input
+        | Citation input -> input
+
+    /// Characters that bring up completion as they are typed, at the start of links, headings,
+    /// tags and citations. '|' separates a wiki link target from its label, '^' starts footnotes
+    /// and block ids.
+    let triggerCharacters = [| '['; '('; '#'; '@'; '|'; '^' |]
+
+    /// Whether the `prompt` is worth completing after typing `trigger`, e.g. '(' only brings up
+    /// inline link targets and not headings.
+    let isTriggeredBy (trigger: char) (prompt: Prompt) : bool =
+        match trigger, prompt with
+        | '[', (WikiDoc _ | Reference _ | Footnote _ | Citation _) -> true
+        | '(', (InlineDoc _ | InlineAnchorInSrcDoc _ | InlineAnchorInOtherDoc _) -> true
+        | '#',
+          (WikiHeadingInSrcDoc _
+          | WikiHeadingInOtherDoc _
+          | InlineAnchorInSrcDoc _
+          | InlineAnchorInOtherDoc _
+          | Tag _) -> true
+        | '@', Citation _ -> true
+        | '^', (WikiHeadingInSrcDoc _ | WikiBlockInOtherDoc _ | Footnote _) -> true
+        | '|',
+          (WikiDoc _
+          | WikiHeadingInSrcDoc _
+          | WikiHeadingInOtherDoc _
+          | WikiBlockInOtherDoc _) -> true
+        | _ -> false
+
+/// Completions of partially typed links insert the whole link, hence they are snippets: e.g. the
+/// text of a new inline link becomes a placeholder.
+module Snippet =
+    let escape (text: string) : string =
+        text.Replace("\\", "\\\\").Replace("$", "\\$").Replace("}", "\\}")
+
+    let placeholder (idx: int) (text: string) : string = $"${{{idx}:{escape text}}}"
+
+    /// The text a snippet inserts when tab stops are ignored: placeholders are replaced with
+    /// their default text.
+    let toPlainText (snippet: string) : string =
+        let sb = System.Text.StringBuilder()
+        let mutable i = 0
+
+        let skipDigits () =
+            while i < snippet.Length && Char.IsDigit(snippet[i]) do
+                i <- i + 1
+
+        while i < snippet.Length do
+            match snippet[i] with
+            | '\\' when i + 1 < snippet.Length ->
+                sb.Append(snippet[i + 1]) |> ignore
+                i <- i + 2
+            | '$' when i + 1 < snippet.Length && Char.IsDigit(snippet[i + 1]) ->
+                i <- i + 1
+                skipDigits ()
+            | '$' when i + 1 < snippet.Length && snippet[i + 1] = '{' ->
+                i <- i + 2
+                skipDigits ()
+                if i < snippet.Length && snippet[i] = ':' then i <- i + 1
+            | '}' -> i <- i + 1
+            | char ->
+                sb.Append(char) |> ignore
+                i <- i + 1
+
+        sb.ToString()
+
+    let ofItem (item: CompletionItem) : CompletionItem =
+        match item.InsertTextFormat with
+        | Some InsertTextFormat.Snippet -> item
+        | _ ->
+            { item with
+                InsertTextFormat = Some InsertTextFormat.Snippet
+                TextEdit =
+                    item.TextEdit
+                    |> Option.map (fun edit -> { edit with NewText = escape edit.NewText }) }
+
+    let toPlainItem (item: CompletionItem) : CompletionItem =
+        match item.InsertTextFormat with
+        | Some InsertTextFormat.Snippet ->
+            { item with
+                InsertTextFormat = Some InsertTextFormat.PlainText
+                TextEdit =
+                    item.TextEdit
+                    |> Option.map (fun edit -> { edit with NewText = toPlainText edit.NewText }) }
+        | _ -> item
 
 module CompletionHelpers =
     let wikiTargetLink (style: ComplWikiStyle) (doc: Doc) =
@@ -326,15 +469,250 @@ module CompletionHelpers =
             let name = docPath.TrimSuffix(extension)
             name.EncodePathForWiki()
 
+    let docLabel (label: ComplDocLabel) (doc: Doc) =
+        let fileName = Path.GetFileName(Doc.pathFromRoot doc)
+
+        match label with
+        | ComplDocLabel.Title -> Doc.name doc
+        | ComplDocLabel.FileName -> fileName
+        | ComplDocLabel.TitleAndFileName -> $"{Doc.name doc} ({fileName})"
+
+/// Data attached to completion items to be picked up by `completionItem/resolve`.
+module ItemData =
+    let docUriKey = "docUri"
+
+    let citationKey = "citation"
+
+    let mk (key: string) (value: string) : JToken = JObject(JProperty(key, value))
+
+    let tryGet (key: string) (item: CompletionItem) : option<string> =
+        match item.Data with
+        | Some (:? JObject as data) ->
+            match data.TryGetValue(key) with
+            | true, value -> Some(value.Value<string>())
+            | _ -> None
+        | _ -> None
+
+    let ofDoc (doc: Doc) : JToken = mk docUriKey (Doc.uri doc)
+
+/// Notes that don't exist yet, linked to from completion and created when the item is accepted.
+module NewNote =
+    /// Whether a note is linked to as `name` already, by its title, file name or path.
+    let exists (folder: Folder) (name: string) : bool =
+        let slug = Slug.ofString name
+
+        Folder.docs folder
+        |> Seq.exists (fun doc ->
+            let path = (Doc.pathFromRoot doc).Replace('\\', '/')
+            let stem = path.TrimSuffix(Path.GetExtension(path))
+
+            [ Doc.name doc; Path.GetFileName(stem); stem ]
+            |> List.exists (fun name -> Slug.ofString name = slug))
+
+    /// Where the note `name`, which may include directories, is created relative to the root of
+    /// the folder. Names escaping the folder can't be created.
+    let tryPath (folder: Folder) (name: string) : option<string> =
+        let root = (RootPath.path (Folder.rootPath folder)).LocalPath
+
+        let ext =
+            (Folder.configOrDefault folder).CoreMarkdownFileExtensions()
+            |> Array.tryHead
+            |> Option.map normalizeExt
+            |> Option.defaultValue "md"
+
+        let path = Path.GetFullPath(Path.Join(root, $"{name.TrimStart('/')}.{ext}"))
+        let relPath = Path.GetRelativePath(root, path)
+
+        if Path.IsPathRooted(relPath) || relPath.Split([| '/'; '\\' |])[0] = ".." then
+            None
+        else
+            Some path
+
+    /// The path of the doc relative to the root, with '/' and without the extension.
+    let stemFromRoot (doc: Doc) : string =
+        let path = (Doc.pathFromRoot doc).Replace('\\', '/').TrimStart('/')
+        path.TrimSuffix(Path.GetExtension(path))
+
+    /// Template of notes that have no template of their own.
+    let defaultTemplate = "default"
+
+    /// The content of the template `name` in `completion.create_note.template_dir`. Templates are
+    /// kept by the folder, so that edits not saved yet are taken into account.
+    let tryFindTemplate (folder: Folder) (name: string) : option<string> =
+        let config = Folder.configOrDefault folder
+
+        match config.ComplCreateNoteTemplateDir().Trim([| '/'; '\\' |]) with
+        | "" -> None
+        | dir ->
+            Folder.templates folder
+            |> Seq.tryFind (fun doc -> stemFromRoot doc = $"{dir}/{name}")
+            |> Option.map (fun doc -> (Doc.text doc).content)
+
+    /// Variables of the templates of the note `title` for `date`.
+    let variables (folder: Folder) (title: string) (date: DateTime) : Map<string, string> =
+        let config = Folder.configOrDefault folder
+
+        let author =
+            match config.ComplCreateNoteAuthor() with
+            | "" -> Environment.UserName
+            | author -> author
+
+        Map.ofList
+            [ "title", title
+              "date", date.ToString(config.ComplDailyNoteFormat(), CultureInfo.InvariantCulture)
+              "author", author ]
+
+    /// Content of the note `title` for `date` from the template `template`. Without it, the default
+    /// template is used, and without that `completion.create_note.template`.
+    let contentOf (folder: Folder) (template: string) (title: string) (date: DateTime) : string =
+        let vars = variables folder title date
+
+        let fromDir =
+            List.distinct [ template; defaultTemplate ]
+            |> List.tryPick (tryFindTemplate folder)
+
+        match fromDir with
+        | Some content -> Template.render vars content
+        | None ->
+            let content = (Folder.configOrDefault folder).ComplCreateNoteTemplate()
+            (Template.render vars content).Replace("{title}", title)
+
+    /// Content of the note `title` created today.
+    let content (folder: Folder) (title: string) : string =
+        contentOf folder defaultTemplate title DateTime.Today
+
+    let createCommandName = "marksman.createNote"
+
+    /// The command creating the note at `path`, which clients run once the item is accepted.
+    /// Resolving the item can't create it: clients resolve items that are only highlighted.
+    let createCommand (path: string) (content: string) : Command =
+        { Title = "Create note"
+          Command = createCommandName
+          Arguments = Some [| JValue(path) :> JToken; JValue(content) |] }
+
+    /// Creates the note at `path`, with its directories, unless it exists already.
+    let create (path: string) (content: string) : unit =
+        if not (File.Exists(path)) then
+            try
+                Directory.CreateDirectory(Path.GetDirectoryName(path)) |> ignore
+                File.WriteAllText(path, content)
+            with
+            | :? IOException
+            | :? UnauthorizedAccessException as exn ->
+                logger.warn (
+                    Log.setMessage "Failed to create note"
+                    >> Log.addContext "path" path
+                    >> Log.addException exn
+                )
+
+/// Notes named after their date, e.g. '2024-05-01.md', linked with keywords like `[[today`.
+module DailyNote =
+    let private keywords = [ "today", 0.0; "tomorrow", 1.0; "yesterday", -1.0 ]
+
+    /// Expands into a date placeholder to be edited in place.
+    let dateKeyword = "date"
+
+    let name (format: string) (date: DateTime) : string =
+        date.ToString(format, CultureInfo.InvariantCulture)
+
+    let matchesKeyword (keyword: string) (input: string) =
+        not (input.IsEmpty())
+        && keyword.StartsWith(input, StringComparison.OrdinalIgnoreCase)
+
+    /// Keywords starting with `input` together with their dates, and the input itself when it's a
+    /// date already.
+    let dates (format: string) (today: DateTime) (input: string) : list<string * DateTime> =
+        let byKeyword =
+            keywords
+            |> List.filter (fun (keyword, _) -> matchesKeyword keyword input)
+            |> List.map (fun (keyword, days) -> keyword, today.AddDays(days))
+
+        let isDate, date =
+            DateTime.TryParseExact(input, format, CultureInfo.InvariantCulture, DateTimeStyles.None)
+
+        let typed = if isDate then [ input, date ] else []
+
+        byKeyword @ typed
+
+    /// The daily note `name` relative to the root, i.e. inside `completion.daily_note.dir`.
+    let pathFromRoot (folder: Folder) (name: string) : string =
+        match (Folder.configOrDefault folder).ComplDailyNoteDir().Trim([| '/'; '\\' |]) with
+        | "" -> name
+        | dir -> $"{dir}/{name}"
+
+    /// The daily note `name`. Notes in the directory of daily notes come first, so that a note
+    /// elsewhere that happens to share the name doesn't shadow it.
+    let tryFindDoc (folder: Folder) (name: string) : option<Doc> =
+        let docs = Folder.docs folder |> List.ofSeq
+
+        docs
+        |> List.tryFind (fun doc -> NewNote.stemFromRoot doc = pathFromRoot folder name)
+        |> Option.orElse (
+            docs
+            |> List.tryFind (fun doc ->
+                Path.GetFileNameWithoutExtension(Doc.pathFromRoot doc) = name
+                || Doc.name doc = name)
+        )
+
+    /// The date `days` from `today` and the name of its daily note.
+    let ofOffset (folder: Folder) (today: DateTime) (days: int) : DateTime * string =
+        let date = today.AddDays(float days)
+        date, name ((Folder.configOrDefault folder).ComplDailyNoteFormat()) date
+
+    /// Template of daily notes, the default template when there is none.
+    let template = "daily"
+
+    /// Content of the daily note `name` for `date`.
+    let content (folder: Folder) (name: string) (date: DateTime) : string =
+        NewNote.contentOf folder template name date
+
+    /// Creates the missing daily note `name` for `date` from its template. The result is the path
+    /// of the note, unless it's outside of the folder.
+    let tryCreate (folder: Folder) (name: string) (date: DateTime) : option<string> =
+        NewNote.tryPath folder (pathFromRoot folder name)
+        |> Option.map (fun path ->
+            NewNote.create path (content folder name date)
+            path)
+
+/// A target for heading completion: a heading by its title or explicit id, or a block anchor.
+[<RequireQualifiedAccess>]
+type AnchorCandidate =
+    | Title of string
+    | Id of string
+    | Block of string
+
+module AnchorCandidate =
+    let label =
+        function
+        | AnchorCandidate.Title title -> title
+        | AnchorCandidate.Id id -> $"{{#{id}}}"
+        | AnchorCandidate.Block id -> $"^{id}"
+
+    /// Titles are linked via their slugs; explicit ids and block anchors are linked as is.
+    let link =
+        function
+        | AnchorCandidate.Title title -> Slug.str title
+        | AnchorCandidate.Id id -> id
+        | AnchorCandidate.Block id -> $"^{id}"
+
+    /// Like `link` but for inline links, where titles get GitHub-style anchors so that the link
+    /// also works when the document is rendered by GitHub.
+    let inlineLink =
+        function
+        | AnchorCandidate.Title title -> GitHubSlug.ofString title
+        | other -> link other
+
 module Completions =
     let wikiDoc
         (style: ComplWikiStyle)
+        (label: ComplDocLabel)
         (pos: Position)
         (compl: Completable)
         (doc: Doc)
         : option<CompletionItem> =
         let targetName = (Doc.name doc)
         let targetLink = CompletionHelpers.wikiTargetLink style doc
+        let label = CompletionHelpers.docLabel label doc
 
         match compl with
         | E (WL { data = { doc = input; heading = heading }; range = range })
@@ -356,27 +734,185 @@ module Completions =
                 let textEdit = { Range = range; NewText = newText }
 
                 Some
-                    { CompletionItem.Create(targetName) with
+                    { CompletionItem.Create(label) with
                         Detail = Some(Doc.pathFromRoot doc)
                         TextEdit = Some textEdit
-                        FilterText = Some filterText }
+                        FilterText = Some filterText
+                        Data = Some(ItemData.ofDoc doc) }
             | Some _ ->
                 let newText = targetLink
                 let range = inputRange
                 let textEdit = { Range = range; NewText = newText }
 
                 Some
-                    { CompletionItem.Create(targetName) with
+                    { CompletionItem.Create(label) with
                         Detail = Some(Doc.pathFromRoot doc)
                         TextEdit = Some textEdit
-                        FilterText = Some targetName }
+                        FilterText = Some targetName
+                        Data = Some(ItemData.ofDoc doc) }
+        | _ -> None
+
+    // The range to replace with a doc: the whole partial link or the doc part of a wiki link
+    let private wikiDocRange (pos: Position) (compl: Completable) : option<Range> =
+        match compl with
+        | E (WL { data = { doc = input; heading = None } }) ->
+            input
+            |> Option.map Node.range
+            |> Option.defaultValue (Range.Mk(pos, pos))
+            |> Some
+        | PE (PartialElement.WikiLink (_, None, range)) -> Some range
+        | _ -> None
+
+    /// Completes the doc part of a wiki link with the daily note of `date`, offered under
+    /// `keyword`. Missing notes are linked to anyway and created on accept when `create` is set.
+    let dailyNote
+        (folder: Folder)
+        (style: ComplWikiStyle)
+        (create: bool)
+        (pos: Position)
+        (compl: Completable)
+        (keyword: string, date: DateTime)
+        : option<CompletionItem> =
+        let name = DailyNote.name ((Folder.configOrDefault folder).ComplDailyNoteFormat()) date
+
+        let filterText =
+            WikiLink.render (Some keyword) None (Completable.isPartial compl)
+
+        match DailyNote.tryFindDoc folder name with
+        | Some doc ->
+            wikiDoc style ComplDocLabel.Title pos compl doc
+            |> Option.map (fun item ->
+                { item with
+                    Label = keyword
+                    Detail = Some name
+                    FilterText = Some filterText })
+        | None ->
+            let newText =
+                WikiLink.render (Some(name.EncodeForWiki())) None (Completable.isPartial compl)
+
+            let command =
+                if create then
+                    NewNote.tryPath folder (DailyNote.pathFromRoot folder name)
+                    |> Option.map (fun path ->
+                        NewNote.createCommand path (DailyNote.content folder name date))
+                else
+                    None
+
+            wikiDocRange pos compl
+            |> Option.map (fun range ->
+                { CompletionItem.Create(keyword) with
+                    Detail = Some $"{name} (new note)"
+                    TextEdit = Some { Range = range; NewText = newText }
+                    FilterText = Some filterText
+                    Command = command })
+
+    /// A snippet with `name` as a placeholder to be edited into another date.
+    let dailyNoteDate (pos: Position) (compl: Completable) (name: string) : option<CompletionItem> =
+        let isPartial = Completable.isPartial compl
+        let newText = WikiLink.render (Some(Snippet.placeholder 1 name)) None isPartial
+        let filterText = WikiLink.render (Some DailyNote.dateKeyword) None isPartial
+
+        wikiDocRange pos compl
+        |> Option.map (fun range ->
+            { CompletionItem.Create(DailyNote.dateKeyword) with
+                Detail = Some name
+                InsertTextFormat = Some InsertTextFormat.Snippet
+                TextEdit = Some { Range = range; NewText = newText }
+                FilterText = Some filterText })
+
+    /// Daily notes for the wiki link `input` as of `today`.
+    let dailyNotes
+        (folder: Folder)
+        (today: DateTime)
+        (pos: Position)
+        (compl: Completable)
+        (input: string)
+        : array<CompletionItem> =
+        let config = Folder.configOrDefault folder
+        let format = config.ComplDailyNoteFormat()
+        let style = config.ComplWikiStyle()
+
+        let byDate =
+            DailyNote.dates format today input
+            |> List.choose (dailyNote folder style (config.ComplDailyNoteCreate()) pos compl)
+
+        let date =
+            if DailyNote.matchesKeyword DailyNote.dateKeyword input then
+                dailyNoteDate pos compl (DailyNote.name format today) |> Option.toList
+            else
+                []
+
+        Array.ofList (byDate @ date)
+
+    let private rootRelative (folder: Folder) (path: string) =
+        let rootPath = (RootPath.path (Folder.rootPath folder)).LocalPath
+        Path.GetRelativePath(rootPath, path).Replace('\\', '/')
+
+    // Sorted after everything else: linking to an existing note is what's wanted most of the time
+    let private newNoteItem (folder: Folder) (name: string) (path: string) (item: CompletionItem) =
+        { item with
+            Label = $"Create note '{name}'"
+            Kind = Some CompletionItemKind.File
+            Detail = Some $"{rootRelative folder path} (new note)"
+            SortText = Some "~"
+            Command = Some(NewNote.createCommand path (NewNote.content folder name)) }
+
+    /// Completes the doc part of a wiki link with `input` as is and creates the note on accept.
+    let wikiNewNote
+        (folder: Folder)
+        (pos: Position)
+        (compl: Completable)
+        (input: string)
+        : option<CompletionItem> =
+        let name = input.Trim()
+        let link = WikiLink.render (Some name) None (Completable.isPartial compl)
+
+        match wikiDocRange pos compl, NewNote.tryPath folder name with
+        | Some range, Some path when not (name.IsWhitespace() || NewNote.exists folder name) ->
+            { CompletionItem.Create(name) with
+                TextEdit = Some { Range = range; NewText = link }
+                FilterText = Some link }
+            |> newNoteItem folder name path
+            |> Some
+        | _ -> None
+
+    let private folderItem (dirPath: string) (noteCount: int) =
+        let notes = if noteCount = 1 then "1 note" else $"{noteCount} notes"
+
+        { CompletionItem.Create(dirPath) with
+            Kind = Some CompletionItemKind.Folder
+            Detail = Some notes }
+
+    /// Completes the doc part of a wiki link with a folder, e.g. `[[proj` becomes `[[projects/`.
+    /// Partial links are left open so that the path can be continued.
+    let wikiFolder (pos: Position) (compl: Completable) (dirPath: string, noteCount: int) =
+        let dirLink = dirPath.EncodePathForWiki()
+
+        match compl with
+        | E (WL { data = { doc = input; heading = None } }) ->
+            let range =
+                input
+                |> Option.map Node.range
+                |> Option.defaultValue (Range.Mk(pos, pos))
+
+            Some
+                { folderItem dirPath noteCount with
+                    TextEdit = Some { Range = range; NewText = dirLink }
+                    FilterText = Some dirPath }
+        | PE (PartialElement.WikiLink (_, None, range)) ->
+            let newText = $"[[{dirLink}"
+
+            Some
+                { folderItem dirPath noteCount with
+                    TextEdit = Some { Range = range; NewText = newText }
+                    FilterText = Some $"[[{dirPath}" }
         | _ -> None
 
     let wikiHeadingInSrcDoc
         (_style: ComplWikiStyle)
         (_pos: Position)
         (compl: Completable)
-        (completionHeading: string)
+        (completionHeading: AnchorCandidate)
         : option<CompletionItem> =
         match compl with
         | E (WL { data = { doc = None; heading = Some input }; range = range })
@@ -384,14 +920,14 @@ module Completions =
             let newText =
                 WikiLink.render
                     None
-                    (Slug.str completionHeading |> Some)
+                    (AnchorCandidate.link completionHeading |> Some)
                     (Completable.isPartial compl)
 
             let range = if Completable.isPartial compl then range else input.range
             let textEdit = { Range = range; NewText = newText }
 
             Some
-                { CompletionItem.Create(completionHeading) with
+                { CompletionItem.Create(AnchorCandidate.label completionHeading) with
                     TextEdit = Some textEdit
                     FilterText = Some newText }
         | _ -> None
@@ -400,9 +936,9 @@ module Completions =
         (style: ComplWikiStyle)
         (_pos: Position)
         (compl: Completable)
-        (doc: Doc, heading: string)
+        (doc: Doc, heading: AnchorCandidate)
         : option<CompletionItem> =
-        let label = $"{Doc.name doc} / {heading}"
+        let label = $"{Doc.name doc} / {AnchorCandidate.label heading}"
 
         match compl with
         | E (WL { data = { doc = Some destPart; heading = Some headingPart }
@@ -413,12 +949,15 @@ module Completions =
             let newText =
                 WikiLink.render
                     (targetLink |> Some)
-                    (Slug.str heading |> Some)
+                    (AnchorCandidate.link heading |> Some)
                     (Completable.isPartial compl)
 
 
             let filterText =
-                WikiLink.render (Some targetLink) (Some heading) (Completable.isPartial compl)
+                WikiLink.render
+                    (Some targetLink)
+                    (Some(AnchorCandidate.label heading))
+                    (Completable.isPartial compl)
 
             let range =
                 if Completable.isPartial compl then
@@ -435,6 +974,67 @@ module Completions =
                     FilterText = Some filterText }
         | _ -> None
 
+    /// Like `wikiHeadingInOtherDoc` but for a block id, documented with the text of the block.
+    let wikiBlockInOtherDoc
+        (style: ComplWikiStyle)
+        (pos: Position)
+        (compl: Completable)
+        (doc: Doc, block: Node<BlockAnchor>)
+        : option<CompletionItem> =
+        let id = BlockAnchor.id block.data
+        let content = (Doc.text doc).Substring(block.data.scope).Replace($"^{id}", "").Trim()
+
+        wikiHeadingInOtherDoc style pos compl (doc, AnchorCandidate.Block id)
+        |> Option.map (fun item ->
+            { item with
+                Kind = Some CompletionItemKind.Reference
+                Documentation = Some(Documentation.String content) })
+
+    /// A heading of another doc offered for a link that doesn't name a doc yet, e.g. `[[#head`
+    /// becomes `[[doc#heading]]`.
+    let wikiHeadingInAnyDoc
+        (style: ComplWikiStyle)
+        (_pos: Position)
+        (compl: Completable)
+        (doc: Doc, heading: AnchorCandidate)
+        : option<CompletionItem> =
+        let label = $"{Doc.name doc} / {AnchorCandidate.label heading}"
+
+        match compl with
+        | E (WL { data = { doc = None; heading = Some input }; range = range })
+        | PE (PartialElement.WikiLink (None, Some input, range)) ->
+            let targetLink = CompletionHelpers.wikiTargetLink style doc
+
+            let newText =
+                WikiLink.render
+                    (Some targetLink)
+                    (AnchorCandidate.link heading |> Some)
+                    (Completable.isPartial compl)
+
+            // Matched against the typed text, which doesn't contain the doc yet
+            let filterText =
+                WikiLink.render
+                    None
+                    (Some(AnchorCandidate.label heading))
+                    (Completable.isPartial compl)
+
+            // Without braces the edit replaces the heading together with the preceding '#'
+            let range =
+                if Completable.isPartial compl then
+                    range
+                else
+                    let hashPos = input.range.Start
+                    Range.Mk(Position.Mk(hashPos.Line, hashPos.Character - 1), input.range.End)
+
+            let textEdit = { Range = range; NewText = newText }
+
+            Some
+                { CompletionItem.Create(label) with
+                    Detail = Some(Doc.pathFromRoot doc)
+                    TextEdit = Some textEdit
+                    FilterText = Some filterText }
+        | _ -> None
+
     let reference (pos: Position) (compl: Completable) (def: MdLinkDef) : option<CompletionItem> =
         let data =
             match compl with
@@ -470,26 +1070,99 @@ module Completions =
                     TextEdit = Some textEdit
                     FilterText = Some newText }
 
-    let inlineDoc (pos: Position) (compl: Completable) (doc: Doc) : option<CompletionItem> =
-        let targetPath = (Doc.pathFromRoot doc)
-        let targetPathEncoded = targetPath.AbsPathUrlEncode()
+    /// Like `reference` but for a footnote, which is documented with its text.
+    let footnote (pos: Position) (compl: Completable) (def: MdLinkDef) : option<CompletionItem> =
+        reference pos compl def
+        |> Option.map (fun item ->
+            { item with
+                Kind = Some CompletionItemKind.Reference
+                Detail = Some "footnote" })
 
-        let detail =
-            Some(Doc.name doc) |> Option.filter (fun x -> x <> targetPath)
+    // Where text appended to the doc goes: the end of the last line
+    let private endOfDoc (text: Text) : Position =
+        let numLines = text.lineMap.NumLines
 
+        if numLines = 0 || text.content.EndsWith('\n') then
+            Position.Mk(numLines, 0)
+        else
+            text.LineContentRange(numLines - 1).End
+
+    /// Completes a partial reference link, e.g. `[doc`, with a link to `doc` in the configured
+    /// `linkStyle`. Reference style adds a link definition at the end of `srcDoc` unless there
+    /// is one for the title already.
+    let linkToDoc
+        (linkStyle: ComplLinkStyle)
+        (wikiStyle: ComplWikiStyle)
+        (label: ComplDocLabel)
+        (srcDoc: Doc)
+        (compl: Completable)
+        (doc: Doc)
+        : option<CompletionItem> =
+        let title = Doc.name doc
+        let path = (Doc.pathFromRoot doc).AbsPathUrlEncode()
+
+        let hasLinkDef () =
+            Doc.index srcDoc
+            |> Index.filterLinkDefs (fun label -> label = LinkLabel.ofString title)
+            |> Seq.isEmpty
+            |> not
+
+        let linkDefEdit () =
+            let text = Doc.text srcDoc
+            let endPos = endOfDoc text
+            let separator = if endPos.Character = 0 then "\n" else "\n\n"
+
+            { Range = Range.Mk(endPos, endPos)
+              NewText = $"{separator}[{title}]: {path}\n" }
+
+        let link =
+            match linkStyle with
+            | ComplLinkStyle.Auto -> None
+            | ComplLinkStyle.Wiki ->
+                let target = CompletionHelpers.wikiTargetLink wikiStyle doc
+                Some(WikiLink.render (Some target) None true, None)
+            | ComplLinkStyle.Markdown ->
+                Some(MdLink.renderInline (Some title) (Some path) None, None)
+            | ComplLinkStyle.Reference ->
+                let additionalEdits = if hasLinkDef () then None else Some [| linkDefEdit () |]
+                Some($"[{title}]", additionalEdits)
+
+        match compl, link with
+        | PE (PartialElement.ReferenceLink (_, range)), Some (newText, additionalEdits) ->
+            Some
+                { CompletionItem.Create(CompletionHelpers.docLabel label doc) with
+                    Detail = Some(Doc.pathFromRoot doc)
+                    TextEdit = Some { Range = range; NewText = newText }
+                    AdditionalTextEdits = additionalEdits
+                    FilterText = Some $"[{title}"
+                    Data = Some(ItemData.ofDoc doc) }
+        | _ -> None
+
+    /// Completes the path of an inline link with `targetPathEncoded`, a link to `targetPath`,
+    /// which is relative to the root. When the link has no text yet, `defaultText` becomes a
+    /// placeholder for it.
+    let inlinePath
+        (pos: Position)
+        (compl: Completable)
+        (defaultText: string)
+        (targetPathEncoded: string)
+        (targetPath: string, detail: option<string>)
+        : option<CompletionItem> =
         match compl with
         | E (ML { data = MdLink.IL (_, None, _) }) ->
             Some
                 { CompletionItem.Create(targetPath) with
                     Detail = detail
                     TextEdit = Some { Range = Range.Mk(pos, pos); NewText = targetPathEncoded } }
-        | E (ML { data = MdLink.IL (_, Some url, _) }) ->
+        | E (ML ({ data = MdLink.IL (_, Some url, _) } as link)) ->
             match Url.ofUrlNode url with
             | { url = Some url } ->
+                let newText = Url.toDestination (MdLink.isAngled link) targetPathEncoded
+
                 Some
                     { CompletionItem.Create(targetPath) with
                         Detail = detail
-                        TextEdit = Some { Range = url.range; NewText = targetPathEncoded } }
+                        TextEdit = Some { Range = url.range; NewText = newText } }
             | _ -> None
         | PE (PartialElement.InlineLink (Some _text, path, Some _anchor, _range)) ->
             let range =
@@ -502,22 +1175,104 @@ module Completions =
                     Detail = detail
                     TextEdit = Some { Range = range; NewText = targetPathEncoded } }
         | PE (PartialElement.InlineLink (Some text, _path, None, range)) ->
-            let newText =
+            let filterText =
                 MdLink.renderInline (Node.text text |> Some) (Some targetPathEncoded) None
 
+            let textSnippet =
+                if text.text.IsEmpty() then
+                    Snippet.placeholder 1 defaultText
+                else
+                    Snippet.escape text.text
+
+            let newText =
+                MdLink.renderInline (Some textSnippet) (Some(Snippet.escape targetPathEncoded)) None
+
             Some
                 { CompletionItem.Create(targetPath) with
                     Detail = detail
+                    InsertTextFormat = Some InsertTextFormat.Snippet
+                    TextEdit = Some { Range = range; NewText = newText }
+                    FilterText = Some filterText }
+        | _ -> None
+
+    /// Like `wikiFolder` but for the path of an inline link.
+    let inlineFolder (pos: Position) (compl: Completable) (dirPath: string, noteCount: int) =
+        let dirPathEncoded = dirPath.AbsPathUrlEncode()
+
+        match compl with
+        | E (ML { data = MdLink.IL (_, None, _) }) ->
+            Some
+                { folderItem dirPath noteCount with
+                    TextEdit = Some { Range = Range.Mk(pos, pos); NewText = dirPathEncoded } }
+        | E (ML ({ data = MdLink.IL (_, Some url, _) } as link)) ->
+            match Url.ofUrlNode url with
+            | { url = Some url; anchor = None } ->
+                let newText = Url.toDestination (MdLink.isAngled link) dirPathEncoded
+
+                Some
+                    { folderItem dirPath noteCount with
+                        TextEdit = Some { Range = url.range; NewText = newText } }
+            | _ -> None
+        | PE (PartialElement.InlineLink (Some text, _path, None, range)) ->
+            let newText = $"[{text.text}]({dirPathEncoded}"
+
+            Some
+                { folderItem dirPath noteCount with
                     TextEdit = Some { Range = range; NewText = newText }
                     FilterText = Some newText }
         | _ -> None
 
+    let inlineDoc (pos: Position) (compl: Completable) (doc: Doc) : option<CompletionItem> =
+        let targetPath = (Doc.pathFromRoot doc)
+
+        let detail =
+            Some(Doc.name doc) |> Option.filter (fun x -> x <> targetPath)
+
+        inlinePath pos compl (Doc.name doc) (targetPath.AbsPathUrlEncode()) (targetPath, detail)
+        |> Option.map (fun item -> { item with Data = Some(ItemData.ofDoc doc) })
+
+    /// Like `wikiNewNote` but for the path of an inline link, e.g. `[](notes/idea` creates
+    /// 'notes/idea.md'.
+    let inlineNewNote
+        (folder: Folder)
+        (pos: Position)
+        (compl: Completable)
+        (input: string)
+        : option<CompletionItem> =
+        let exts = (Folder.configOrDefault folder).CoreMarkdownFileExtensions()
+        let input = input.Trim().UrlDecode()
+
+        let name =
+            if isMarkdownFile exts input then
+                input.TrimSuffix(Path.GetExtension(input))
+            else
+                input
+
+        NewNote.tryPath folder name
+        |> Option.filter (fun _ -> not (name.IsWhitespace() || NewNote.exists folder name))
+        |> Option.bind (fun path ->
+            let targetPath = rootRelative folder path
+            let defaultText = Path.GetFileName(name)
+
+            inlinePath pos compl defaultText (targetPath.AbsPathUrlEncode()) (targetPath, None)
+            |> Option.map (newNoteItem folder name path))
+
+    /// Completes an image or a file link with `link`, which points to the asset at `path`.
+    let inlineAsset
+        (pos: Position)
+        (compl: Completable)
+        (link: string, path: string)
+        : option<CompletionItem> =
+        inlinePath pos compl (Path.GetFileNameWithoutExtension(path)) link (path, None)
+        |> Option.map (fun item -> { item with Kind = Some CompletionItemKind.File })
+
     let inlineAnchorInSrcDoc
         (_pos: Position)
         (compl: Completable)
-        (completionHeading: string)
+        (completionHeading: AnchorCandidate)
         : option<CompletionItem> =
-        let headingSlug = Slug.str completionHeading
+        let headingSlug = AnchorCandidate.inlineLink completionHeading |> Url.encodeAnchor
+        let label = AnchorCandidate.label completionHeading
 
         match compl with
         | E (ML { data = MdLink.IL (_, Some url, _) }) ->
@@ -528,7 +1283,7 @@ module Completions =
                 let newText = headingSlug
 
                 Some
-                    { CompletionItem.Create(completionHeading) with
+                    { CompletionItem.Create(label) with
                         TextEdit = Some { Range = anchor.range; NewText = newText }
                         FilterText = Some newText }
             | _ -> None
@@ -536,7 +1291,7 @@ module Completions =
             let newText = $"[{text.text}](#{headingSlug})"
 
             Some
-                { CompletionItem.Create(completionHeading) with
+                { CompletionItem.Create(label) with
                     TextEdit = Some { Range = range; NewText = newText }
                     FilterText = Some newText }
         | _ -> None
@@ -544,22 +1299,25 @@ module Completions =
     let inlineAnchorInOtherDoc
         (_pos: Position)
         (compl: Completable)
-        (targetDoc: Doc, targetHeading: string)
+        (targetDoc: Doc, targetAnchor: AnchorCandidate)
         : option<CompletionItem> =
         let targetPath = Doc.pathFromRoot targetDoc
         let targetPathEncoded = targetPath.AbsPathUrlEncode()
+        let targetHeading = AnchorCandidate.label targetAnchor
+        let targetLink = AnchorCandidate.inlineLink targetAnchor |> Url.encodeAnchor
         let label = $"{targetPath} / {targetHeading}"
 
         let detail =
             Some(Doc.name targetDoc) |> Option.filter (fun x -> x <> targetPath)
 
         match compl with
-        | E (ML { data = MdLink.IL (_, Some url, _) }) ->
+        | E (ML ({ data = MdLink.IL (_, Some url, _) } as link)) ->
             let url = Url.ofUrlNode url
 
             match url.url, url.anchor with
             | Some url, Some anchor ->
-                let newText = $"{targetPathEncoded}#{Slug.str targetHeading}"
+                let path = Url.toDestination (MdLink.isAngled link) targetPathEncoded
+                let newText = $"{path}#{targetLink}"
                 let newRange = Range.Mk(url.range.Start, anchor.range.End)
                 let filterText = $"{targetPathEncoded}#{targetHeading}"
 
@@ -570,8 +1328,7 @@ module Completions =
                         FilterText = Some filterText }
             | _, _ -> None
         | PE (PartialElement.InlineLink (Some text, Some _path, Some _anchor, range)) ->
-            let newText =
-                $"[{text.text}]({targetPathEncoded}#{Slug.str targetHeading})"
+            let newText = $"[{text.text}]({targetPathEncoded}#{targetLink})"
 
             let filterText = $"[{text.text}]({targetPathEncoded}#{targetHeading})"
 
@@ -598,16 +1355,56 @@ module Completions =
         | None -> None
         | Some range ->
             let label = tagName
-            let detail = $"{numUsages} usages"
 
-            // IDEA: since we have numUsages we could provide sort text that would sort based on usages.
+            let detail =
+                if numUsages = 1 then
+                    "1 usage"
+                else
+                    $"{numUsages} usages"
+
+            // More frequently used tags should come first. Editors sort by sortText lexicographically,
+            // hence the inverted and zero-padded usage count.
+            let sortText = $"{(Int32.MaxValue - numUsages):D10}{tagName}"
+
             Some
                 { CompletionItem.Create(label) with
                     Detail = Some detail
+                    SortText = Some sortText
                     // Use input as filter text to avoid any extra filtering on the editor's side
                     FilterText = Some input
                     TextEdit = Some { Range = range; NewText = label } }
 
+    /// Completes a citation key; the formatted reference is added on resolve.
+    let citation
+        (srcDoc: Doc)
+        (_pos: Position)
+        (compl: Completable)
+        (entry: Bibliography.Entry)
+        : option<CompletionItem> =
+        let range =
+            match compl with
+            | E (CT { range = range }) -> Some range
+            | PE (PartialElement.ReferenceLink (Some label, _)) -> Some label.range
+            | _ -> None
+
+        range
+        |> Option.map (fun range ->
+            let newText = $"@{entry.key}"
+
+            // The doc is only there to find the folder, and with it the bibliography, on resolve
+            let data =
+                JObject(
+                    JProperty(ItemData.docUriKey, Doc.uri srcDoc),
+                    JProperty(ItemData.citationKey, entry.key)
+                )
+
+            { CompletionItem.Create(entry.key) with
+                Kind = Some CompletionItemKind.Reference
+                Detail = Some(Bibliography.Entry.summary entry)
+                FilterText = Some newText
+                TextEdit = Some { Range = range; NewText = newText }
+                Data = Some data })
+
 module Candidates =
     let findDocCandidates
         (folder: Folder)
@@ -621,35 +1418,86 @@ module Candidates =
 
         candidates |> Seq.filter (fun d -> d <> srcDoc) |> Array.ofSeq
 
-    let findHeadingCandidates
-        (folder: Folder)
-        (srcDoc: Doc)
-        (destPart: option<InternName>)
-        (headingPart: string)
-        : array<Doc * string> =
-        let targetDocs =
-            destPart
-            |> Option.map (FileLink.filterFuzzyMatchingDocs folder srcDoc)
-            |> Option.defaultValue [ srcDoc ]
+    /// Folders one level below the directory part of `input` that contain docs, with trailing '/'
+    /// and the number of docs in them. The last segment of `input` filters the folders.
+    let findFolderCandidates (folder: Folder) (input: string) : array<string * int> =
+        let input = input.TrimStart('/').UrlDecode()
 
-        let targetDocs =
-            if destPart.IsSome then
-                targetDocs |> Seq.filter (fun d -> d <> srcDoc)
+        let dirPrefix, segment =
+            match input.LastIndexOf('/') with
+            | -1 -> "", input
+            | idx -> input.Substring(0, idx + 1), input.Substring(idx + 1)
+
+        Folder.docs folder
+        |> Seq.choose (fun doc ->
+            let path = (Doc.pathFromRoot doc).Replace('\\', '/')
+
+            if path.StartsWith(dirPrefix) then
+                match path.IndexOf('/', dirPrefix.Length) with
+                | -1 -> None
+                | idx -> Some(path.Substring(0, idx + 1))
             else
-                targetDocs
+                None)
+        |> Seq.countBy id
+        |> Seq.filter (fun (dir, _) -> segment.IsSubSequenceOf(dir.Substring(dirPrefix.Length)))
+        |> Seq.sortBy fst
+        |> Array.ofSeq
+
+    /// Links to the `assets` matching `input`. Links are relative to `srcDoc` unless the input
+    /// starts with '/', in which case they are relative to the root like the input.
+    let findAssetCandidates
+        (srcDoc: Doc)
+        (input: string)
+        (assets: array<string>)
+        : array<string * string> =
+        let input = input.UrlDecode()
+
+        if input.StartsWith('/') then
+            let input = input.TrimStart('/')
 
+            assets
+            |> Array.filter (fun path -> input.IsSubSequenceOf(path))
+            |> Array.map (fun path -> path.AbsPathUrlEncode(), path)
+        else
+            assets
+            |> Array.map (fun path -> Assets.relativeLink srcDoc path, path)
+            |> Array.filter (fun (link, _) -> input.IsSubSequenceOf(link.UrlDecode()))
+
+    let private headingCandidatesIn
+        (targetDocs: seq<Doc>)
+        (headingPart: string)
+        : array<Doc * AnchorCandidate> =
         let inputSlug = Slug.ofString headingPart
 
         let matchingHeadings destDoc =
             Doc.index >> Index.headings <| destDoc
             // We are not interested in completing titles as headings
             |> Seq.filter (fun { data = h } -> Heading.isTitle h |> not)
-            |> Seq.map (fun { data = h } -> Heading.name h)
-            |> Seq.filter (fun h -> Slug.isSubSequence inputSlug (Slug.ofString h))
+            |> Seq.collect (fun { data = h } ->
+                let byTitle = AnchorCandidate.Title(Heading.name h)
+                let byId = h.id |> Option.map (Node.text >> AnchorCandidate.Id) |> Option.toList
+                byTitle :: byId)
+            |> Seq.filter (fun cand ->
+                Slug.isSubSequence inputSlug (Slug.ofString (AnchorCandidate.link cand)))
             // There may be several headings with the same name.
             // Remove duplicates in completion candidates
             |> Set.ofSeq
 
+        let matchingBlocks destDoc =
+            let inputId = headingPart.Substring(1)
+
+            Doc.index >> Index.blockAnchors <| destDoc
+            |> Seq.map (fun { data = b } -> BlockAnchor.id b)
+            |> Seq.filter (fun id -> inputId.IsSubSequenceOf(id))
+            |> Seq.map AnchorCandidate.Block
+            |> Set.ofSeq
+
+        let matchingHeadings =
+            if headingPart.StartsWith('^') then
+                matchingBlocks
+            else
+                matchingHeadings
+
         let prepareForDoc d =
             let headings = matchingHeadings d
             let docWithHeadings = headings |> Seq.map (fun h -> d, h)
@@ -657,6 +1505,50 @@ module Candidates =
 
         targetDocs |> Seq.collect prepareForDoc |> Array.ofSeq
 
+    let findHeadingCandidates
+        (folder: Folder)
+        (srcDoc: Doc)
+        (destPart: option<InternName>)
+        (headingPart: string)
+        : array<Doc * AnchorCandidate> =
+        let targetDocs =
+            destPart
+            |> Option.map (FileLink.filterFuzzyMatchingDocs folder srcDoc)
+            |> Option.defaultValue [ srcDoc ]
+
+        let targetDocs =
+            if destPart.IsSome then
+                targetDocs |> Seq.filter (fun d -> d <> srcDoc)
+            else
+                targetDocs
+
+        headingCandidatesIn targetDocs headingPart
+
+    /// Block anchors in docs matching `destPart` whose ids match `blockPart`.
+    let findBlockCandidates
+        (folder: Folder)
+        (srcDoc: Doc)
+        (destPart: InternName)
+        (blockPart: string)
+        : array<Doc * Node<BlockAnchor>> =
+        FileLink.filterFuzzyMatchingDocs folder srcDoc destPart
+        |> Seq.filter (fun d -> d <> srcDoc)
+        |> Seq.collect (fun doc ->
+            Doc.index doc
+            |> Index.blockAnchors
+            |> Seq.filter (fun { data = b } -> blockPart.IsSubSequenceOf(BlockAnchor.id b))
+            |> Seq.map (fun block -> doc, block))
+        |> Array.ofSeq
+
+    /// Headings of all docs but `srcDoc`, for links that don't name the target doc.
+    let findHeadingCandidatesInOtherDocs
+        (folder: Folder)
+        (srcDoc: Doc)
+        (headingPart: string)
+        : array<Doc * AnchorCandidate> =
+        let otherDocs = Folder.docs folder |> Seq.filter (fun d -> d <> srcDoc)
+        headingCandidatesIn otherDocs headingPart
+
     let findLinkDefCandidates (_folder: Folder) (srcDoc: Doc) (input: string) : array<MdLinkDef> =
         Index.filterLinkDefs
             (LinkLabel.isSubSequenceOf (LinkLabel.ofString input))
@@ -664,6 +1556,19 @@ module Candidates =
         |> Seq.map Node.data
         |> Array.ofSeq
 
+    /// Footnote definitions of `srcDoc` whose names, without the leading '^', match `input`.
+    let findFootnoteCandidates (srcDoc: Doc) (input: string) : array<MdLinkDef> =
+        Index.linkDefs (Doc.index srcDoc)
+        |> Seq.map Node.data
+        |> Seq.filter (fun def ->
+            MdLinkDef.isFootnote def
+            && input.IsSubSequenceOf((MdLinkDef.name def).Substring(1)))
+        |> Array.ofSeq
+
+    let findCitationCandidates (folder: Folder) (input: string) : array<Bibliography.Entry> =
+        Bibliography.entries folder
+        |> Array.filter (fun entry -> input.IsSubSequenceOf(entry.key))
+
     let findTagCandidates (folder: Folder) (_srcDoc: Doc) (input: string) : array<string * int> =
         let matchingTags =
             seq {
@@ -681,6 +1586,144 @@ module Candidates =
 
         matchingTags |> Array.ofSeq
 
+/// Compact previews of docs for completion items: the beginning of a doc without its front
+/// matter, and the number of links to it.
+module Preview =
+    let private isFrontMatterFence (line: string) = line.Trim() = "---"
+
+    // Lines are cut out as they're consumed, so that previews of long docs don't copy all of them
+    let private lines (content: string) : seq<string> =
+        0
+        |> Seq.unfold (fun start ->
+            if start > content.Length then
+                None
+            else
+                let end_ =
+                    match content.IndexOf('\n', start) with
+                    | -1 -> content.Length
+                    | idx -> idx
+
+                Some(content.Substring(start, end_ - start).TrimEnd('\r'), end_ + 1))
+
+    /// The first `maxLines` lines, but at most `maxChars` characters, of `content` skipping YAML
+    /// front matter and leading blank lines. Truncated text ends with '…'.
+    let summarize (maxLines: int) (maxChars: int) (content: string) : string =
+        let lines = lines content
+
+        let body =
+            match Seq.tryHead lines with
+            | Some first when isFrontMatterFence first ->
+                let rest = Seq.tail lines
+
+                match Seq.tryFindIndex isFrontMatterFence rest with
+                | Some closingIdx -> Seq.skip (closingIdx + 1) rest
+                | None -> lines
+            | _ -> lines
+
+        // One line more than shown tells whether the text is truncated
+        let body =
+            body
+            |> Seq.skipWhile String.IsNullOrWhiteSpace
+            |> Seq.truncate (maxLines + 1)
+            |> Array.ofSeq
+
+        let text = String.Join("\n", Array.truncate maxLines body)
+
+        if text.Length > maxChars then
+            text.Substring(0, maxChars).TrimEnd() + "…"
+        elif body.Length > maxLines then
+            text.TrimEnd() + "…"
+        else
+            text.TrimEnd()
+
+    /// Number of the other docs of the folder linking to `doc` or into it, as found by the link
+    /// index of the folder, so that no links are resolved while completing.
+    let backlinkCount (folder: Folder) (doc: Doc) : int =
+        Folder.docsLinkingTo doc folder |> Seq.filter (fun srcDoc -> srcDoc <> doc) |> Seq.length
+
+    let ofDoc (folder: Folder) (doc: Doc) : string =
+        let config = Folder.configOrDefault folder
+
+        let summary =
+            summarize
+                (config.ComplPreviewMaxLines())
+                (config.ComplPreviewMaxChars())
+                (Doc.text doc).content
+
+        let backlinks =
+            match backlinkCount folder doc with
+            | 1 -> "1 backlink"
+            | count -> $"{count} backlinks"
+
+        $"{summary}\n\n---\n\n{backlinks}"
+
+    /// Adds the preview of the doc the `item` completes to, or the reference it cites, if any.
+    let resolve (folder: Folder) (item: CompletionItem) : CompletionItem =
+        let withDocumentation content =
+            { item with Documentation = Some(Documentation.Markup(markdown content)) }
+
+        match ItemData.tryGet ItemData.citationKey item with
+        | Some key ->
+            Bibliography.tryFind folder key
+            |> Option.map (Bibliography.Entry.fmt >> withDocumentation)
+            |> Option.defaultValue item
+        | None ->
+            ItemData.tryGet ItemData.docUriKey item
+            |> Option.bind (fun uri -> Folder.tryFindDocByPath (PathUri.ofString uri) folder)
+            |> Option.map (ofDoc folder >> withDocumentation)
+            |> Option.defaultValue item
+
+/// Candidates of the link being completed, reused as the user keeps typing it. A longer input
+/// only narrows the candidates down, so they are filtered instead of looked up in the whole
+/// folder on every keystroke.
+module Session =
+    // The folder is replaced on every edit, so the key is the link in the folder and the set of
+    // docs, which edits of the docs don't change
+    type Key =
+        { folder: FolderId
+          docSet: int64
+          srcDoc: PathUri
+          start: Position }
+
+    /// Candidates of one kind found for the last link.
+    type Cache<'a>() =
+        let mutable last: option<Key * string * array<'a>> = None
+
+        /// Candidates for `input` of the link `compl` in `srcDoc`. When the link was completed
+        /// for a prefix of `input` last time, the candidates found then are filtered with
+        /// `narrow`; otherwise they are looked up anew.
+        member this.Find
+            (
+                folder: Folder,
+                srcDoc: Doc,
+                compl: Completable,
+                input: string,
+                lookup: unit -> array<'a>,
+                narrow: 'a -> bool
+            ) : array<'a> =
+            let key =
+                { folder = Folder.id folder
+                  docSet = Folder.docSetVersion folder
+                  srcDoc = Doc.path srcDoc
+                  start = (Completable.range compl).Start }
+
+            lock this (fun () ->
+                let candidates =
+                    match last with
+                    | Some (lastKey, lastInput, candidates) when
+                        lastKey = key && input.StartsWith(lastInput)
+                        ->
+                        candidates |> Array.filter narrow
+                    | _ -> lookup ()
+
+                // Over the memory limit the candidates aren't kept
+                last <- if Caches.enabled () then Some(key, input, candidates) else None
+                candidates)
+
+    /// The caches of one server, so that servers, and tests, don't share candidates.
+    type Store() =
+        member val Docs = Cache<Doc>()
+
 let findCompletableAtPos (doc: Doc) (pos: Position) : option<Completable> =
     let link () = Doc.index doc |> Index.linkAtPos pos |> Option.map E
 
@@ -691,82 +1734,268 @@ let findCompletableAtPos (doc: Doc) (pos: Position) : option<Completable> =
         |> Array.tryFind (fun { data = { name = name } } -> (Node.range name).ContainsInclusive(pos))
         |> Option.map (T >> E)
 
-    let partialElement () = PartialElement.inText (Doc.text doc) pos |> Option.map PE
+    let citation () =
+        Doc.index doc |> Index.citationAtPos pos |> Option.map (CT >> E)
 
-    link () |> Option.orElseWith tag |> Option.orElseWith partialElement
+    let partialElement () =
+        // Text inside code looks like markdown, but it isn't
+        if Doc.index doc |> Index.isInCode pos then
+            None
+        else
+            PartialElement.inText (Doc.text doc) pos |> Option.map PE
+
+    link ()
+    |> Option.orElseWith tag
+    |> Option.orElseWith citation
+    |> Option.orElseWith partialElement
 
 
+let private isImageCompletable (doc: Doc) (compl: Completable) =
+    match compl with
+    | E (ML link) -> MdLink.isImage link
+    | PE (PartialElement.InlineLink (_, _, _, range)) when range.Start.Character > 0 ->
+        let line = (Doc.text doc).LineContent(range.Start.Line)
+        let bangIdx = range.Start.Character - 1
+        bangIdx < line.Length && line[bangIdx] = '!'
+    | _ -> false
+
 let findCandidatesForCompl
+    (session: Session.Store)
     (folder: Folder)
     (srcDoc: Doc)
     (pos: Position)
     (compl: Completable)
     : array<CompletionItem> =
-    let config = Folder.configOrDefault folder
+    let config = Folder.configFor srcDoc folder
+    let prompt = Prompt.ofCompletable pos compl
+
+    let candidates =
+        match prompt with
+        | None -> [||]
+        | Some (InlineDoc input) when isImageCompletable srcDoc compl ->
+            Assets.imageFiles folder
+            |> Candidates.findAssetCandidates srcDoc input
+            |> Array.choose (Completions.inlineAsset pos compl)
+        | Some (WikiDoc input) ->
+            let style = config.ComplWikiStyle()
+            let destPart = InternName input
+
+            let cand =
+                session.Docs.Find(
+                    folder,
+                    srcDoc,
+                    compl,
+                    input,
+                    (fun () -> Candidates.findDocCandidates folder srcDoc (Some destPart)),
+                    FileLink.isFuzzyMatchDoc folder srcDoc destPart
+                )
+
+            let docs =
+                cand
+                |> Array.choose (Completions.wikiDoc style (config.ComplDocLabel()) pos compl)
+
+            // Only path-style links can be completed folder by folder
+            let folders =
+                match style with
+                | FilePathStem ->
+                    Candidates.findFolderCandidates folder input
+                    |> Array.choose (Completions.wikiFolder pos compl)
+                | TitleSlug
+                | FileStem -> [||]
+
+            let dailyNotes = Completions.dailyNotes folder DateTime.Today pos compl input
+
+            let newNote =
+                if config.ComplCreateNoteEnable() then
+                    Completions.wikiNewNote folder pos compl input |> Option.toArray
+                else
+                    [||]
+
+            Array.concat [ docs; folders; dailyNotes; newNote ]
+        | Some (WikiHeadingInSrcDoc input) ->
+            let style = config.ComplWikiStyle()
+            let cand = Candidates.findHeadingCandidates folder srcDoc None input
+
+            let inSrcDoc =
+                cand
+                |> Array.map snd
+                |> Array.choose (Completions.wikiHeadingInSrcDoc style pos compl)
+
+            // Search headings across the folder; an empty input would list every heading there is
+            let inOtherDocs =
+                if input.IsEmpty() then
+                    [||]
+                else
+                    Candidates.findHeadingCandidatesInOtherDocs folder srcDoc input
+                    |> Array.choose (Completions.wikiHeadingInAnyDoc style pos compl)
+
+            Array.append inSrcDoc inOtherDocs
+        | Some (WikiHeadingInOtherDoc (destPart, headingPart)) ->
+            let destPart = Some(InternName destPart)
+
+            let cand =
+                Candidates.findHeadingCandidates folder srcDoc destPart headingPart
+
+            cand
+            |> Array.choose (Completions.wikiHeadingInOtherDoc (config.ComplWikiStyle()) pos compl)
+        | Some (WikiBlockInOtherDoc (destPart, blockPart)) ->
+            Candidates.findBlockCandidates folder srcDoc (InternName destPart) blockPart
+            |> Array.choose (Completions.wikiBlockInOtherDoc (config.ComplWikiStyle()) pos compl)
+        | Some (Footnote input) ->
+            Candidates.findFootnoteCandidates srcDoc input
+            |> Array.choose (Completions.footnote pos compl)
+        | Some (Reference input) ->
+            let cand = Candidates.findLinkDefCandidates folder srcDoc input
+            let linkDefs = cand |> Array.choose (Completions.reference pos compl)
+
+            // With an explicit link style docs are completed after a single '[' too
+            let docs =
+                match config.ComplLinkStyle() with
+                | ComplLinkStyle.Auto -> [||]
+                | linkStyle ->
+                    let destPart = if input.IsEmpty() then None else Some(InternName input)
+
+                    let toItem =
+                        Completions.linkToDoc
+                            linkStyle
+                            (config.ComplWikiStyle())
+                            (config.ComplDocLabel())
+                            srcDoc
+                            compl
+
+                    Candidates.findDocCandidates folder srcDoc destPart |> Array.choose toItem
+
+            Array.append linkDefs docs
+        | Some (InlineDoc input) ->
+            let destPart = InternName.ofUrl (config.CoreMarkdownFileExtensions()) input
+
+            let lookup () =
+                match destPart with
+                | None when input.IsEmpty() -> Candidates.findDocCandidates folder srcDoc None
+                | None -> [||]
+                | Some destPart -> Candidates.findDocCandidates folder srcDoc (Some destPart)
+
+            let narrow doc =
+                match destPart with
+                | None -> input.IsEmpty()
+                | Some destPart -> FileLink.isFuzzyMatchDoc folder srcDoc destPart doc
+
+            let cand = session.Docs.Find(folder, srcDoc, compl, input, lookup, narrow)
+            let docs = cand |> Array.choose (Completions.inlineDoc pos compl)
+
+            let folders =
+                Candidates.findFolderCandidates folder input
+                |> Array.choose (Completions.inlineFolder pos compl)
+
+            // Any file can be linked to but listing them all up front would bury the docs
+            let assets =
+                if input.IsEmpty() then
+                    [||]
+                else
+                    Assets.files folder
+                    |> Candidates.findAssetCandidates srcDoc input
+                    |> Array.choose (Completions.inlineAsset pos compl)
+
+            let newNote =
+                if config.ComplCreateNoteEnable() then
+                    Completions.inlineNewNote folder pos compl input |> Option.toArray
+                else
+                    [||]
+
+            Array.concat [ docs; folders; assets; newNote ]
+        | Some (InlineAnchorInSrcDoc input) ->
+            let cand = Candidates.findHeadingCandidates folder srcDoc None input
+
+            cand
+            |> Array.map snd
+            |> Array.choose (Completions.inlineAnchorInSrcDoc pos compl)
+        | Some (InlineAnchorInOtherDoc (pathPart, anchorPart)) ->
+            let cand =
+                match InternName.ofUrl (config.CoreMarkdownFileExtensions()) pathPart with
+                | None -> [||]
+                | Some destPart ->
+                    Candidates.findHeadingCandidates folder srcDoc (Some destPart) anchorPart
+
+            cand |> Array.choose (Completions.inlineAnchorInOtherDoc pos compl)
+        | Some (Tag input) ->
+            let cand = Candidates.findTagCandidates folder srcDoc input
+            cand |> Array.choose (Completions.tag pos compl input)
+        | Some (Citation input) ->
+            let cand = Candidates.findCitationCandidates folder input
+            cand |> Array.choose (Completions.citation srcDoc pos compl)
+
+    // Better fuzzy matches come first unless candidates define their own order, e.g. tags
+    let rank (input: string) (item: CompletionItem) =
+        match item.SortText with
+        | Some _ -> item
+        | None ->
+            let score =
+                [ Some item.Label; item.FilterText ]
+                |> List.choose (Option.bind (Fuzzy.score input))
+                |> List.fold max 0
+
+            { item with SortText = Some(Fuzzy.sortText score item.Label) }
+
+    let ranked =
+        match prompt with
+        | Some prompt -> candidates |> Array.map (rank (Prompt.input prompt))
+        | None -> candidates
+
+    // Only the best matches are sent, the rest come up as the input grows
+    let maxItems = config.ComplMaxItems()
+
+    if ranked.Length > maxItems then
+        ranked
+        |> Array.sortBy (fun item -> item.SortText |> Option.defaultValue item.Label)
+        |> Array.truncate maxItems
+    else
+        ranked
+
+/// Candidates at `pos`. When completion was brought up by typing a `trigger` character, only
+/// the prompts it starts are completed.
+let findCandidatesInDoc
+    (session: Session.Store)
+    (folder: Folder)
+    (doc: Doc)
+    (pos: Position)
+    (trigger: option<char>)
+    : array<CompletionItem> =
+    let isTriggered compl =
+        match trigger with
+        | None -> true
+        | Some trigger ->
+            Prompt.ofCompletable pos compl
+            |> Option.exists (Prompt.isTriggeredBy trigger)
 
-    match Prompt.ofCompletable pos compl with
-    | None -> [||]
-    | Some (WikiDoc input) ->
-        let destPart = Some(InternName input)
-        let cand = Candidates.findDocCandidates folder srcDoc destPart
-
-        cand
-        |> Array.choose (Completions.wikiDoc (config.ComplWikiStyle()) pos compl)
-    | Some (WikiHeadingInSrcDoc input) ->
-        let cand = Candidates.findHeadingCandidates folder srcDoc None input
-
-        cand
-        |> Array.map snd
-        |> Array.choose (Completions.wikiHeadingInSrcDoc (config.ComplWikiStyle()) pos compl)
-    | Some (WikiHeadingInOtherDoc (destPart, headingPart)) ->
-        let destPart = Some(InternName destPart)
-
-        let cand =
-            Candidates.findHeadingCandidates folder srcDoc destPart headingPart
-
-        cand
-        |> Array.choose (Completions.wikiHeadingInOtherDoc (config.ComplWikiStyle()) pos compl)
-    | Some (Reference input) ->
-        let cand = Candidates.findLinkDefCandidates folder srcDoc input
-        cand |> Array.choose (Completions.reference pos compl)
-    | Some (InlineDoc input) ->
-        let cand =
-            match InternName.ofUrl (config.CoreMarkdownFileExtensions()) input with
-            | None when input.IsEmpty() -> Candidates.findDocCandidates folder srcDoc None
-            | None -> [||]
-            | Some destPart -> Candidates.findDocCandidates folder srcDoc (Some destPart)
-
-        cand |> Array.choose (Completions.inlineDoc pos compl)
-    | Some (InlineAnchorInSrcDoc input) ->
-        let cand = Candidates.findHeadingCandidates folder srcDoc None input
-
-        cand
-        |> Array.map snd
-        |> Array.choose (Completions.inlineAnchorInSrcDoc pos compl)
-    | Some (InlineAnchorInOtherDoc (pathPart, anchorPart)) ->
-        let cand =
-            match InternName.ofUrl (config.CoreMarkdownFileExtensions()) pathPart with
-            | None -> [||]
-            | Some destPart ->
-                Candidates.findHeadingCandidates folder srcDoc (Some destPart) anchorPart
-
-        cand |> Array.choose (Completions.inlineAnchorInOtherDoc pos compl)
-    | Some (Tag input) ->
-        let cand = Candidates.findTagCandidates folder srcDoc input
-        cand |> Array.choose (Completions.tag pos compl input)
-
-let findCandidatesInDoc (folder: Folder) (doc: Doc) (pos: Position) : array<CompletionItem> =
     match findCompletableAtPos doc pos with
     | None ->
         logger.trace (Log.setMessage "No completion point found")
         [||]
+    | Some compl when not (isTriggered compl) ->
+        logger.trace (Log.setMessage "Completion point isn't started by the trigger character")
+        [||]
     | Some compl ->
         logger.trace (Log.setMessage "Found completion point" >> Log.addContext "comp" compl)
-        findCandidatesForCompl folder doc pos compl
+        let candidates = findCandidatesForCompl session folder doc pos compl
 
-let findCandidates (folder: Folder) (docUri: PathUri) (pos: Position) : array<CompletionItem> =
+        if Completable.isPartial compl then
+            Array.map Snippet.ofItem candidates
+        else
+            candidates
+
+let findTriggeredCandidates
+    (session: Session.Store)
+    (folder: Folder)
+    (docUri: PathUri)
+    (pos: Position)
+    (trigger: option<char>)
+    : array<CompletionItem> =
     let doc = Folder.tryFindDocByPath docUri folder
 
     match doc with
     | None -> [||]
-    | Some doc -> findCandidatesInDoc folder doc pos
+    | Some doc -> findCandidatesInDoc session folder doc pos trigger
+
+/// Candidates at `pos` looked up anew, without the caches of a session.
+let findCandidates (folder: Folder) (docUri: PathUri) (pos: Position) : array<CompletionItem> =
+    findTriggeredCandidates (Session.Store()) folder docUri pos None
@@ -1,8 +1,10 @@
 module Marksman.Config
 
 open System.IO
+open System.Text.RegularExpressions
 open FSharpPlus
 open Ionide.LanguageServerProtocol.Logging
+open Newtonsoft.Json.Linq
 open Tomlyn
 open Tomlyn.Model
 
@@ -82,6 +84,29 @@ let private lookupAsOpt =
 let private getFromTableOpt<'R> table revSeenPath remPath : Result<option<'R>, LookupError> =
     getFromTable table revSeenPath remPath |> lookupAsOpt
 
+/// How a link to a note name shared by several notes is resolved by go to definition and hover.
+[<RequireQualifiedAccess>]
+type CoreLinkResolution =
+    /// Definition lists every note ordered by path, hover shows the first one
+    | All
+    /// The note sharing most directories with the linking one
+    | Nearest
+    /// The note with the fewest directories in its path
+    | ShortestPath
+
+module CoreLinkResolution =
+    let ofString (input: string) : Result<CoreLinkResolution, string> =
+        match input.ToLower() with
+        | "all" -> Ok CoreLinkResolution.All
+        | "nearest" -> Ok CoreLinkResolution.Nearest
+        | "shortest-path" -> Ok CoreLinkResolution.ShortestPath
+        | other -> Error $"Unknown CoreLinkResolution: {other}"
+
+    let ofStringOpt input =
+        match ofString input with
+        | Ok x -> Some x
+        | Error _ -> None
+
 type ComplWikiStyle =
     /// Document title's slug, e.g. "A B C" -> "a-b-c"
     | TitleSlug
@@ -103,66 +128,822 @@ module ComplWikiStyle =
         | Ok x -> Some x
         | Error _ -> None
 
+[<RequireQualifiedAccess>]
+type ComplLinkStyle =
+    /// Docs are only completed in links whose syntax is already typed, e.g. "[[" or "[](".
+    | Auto
+    /// "[doc" is completed to a wiki link "[[doc]]"
+    | Wiki
+    /// "[doc" is completed to an inline link "[Doc](/doc.md)"
+    | Markdown
+    /// "[doc" is completed to a shortcut reference "[Doc]" with a link definition at the end
+    | Reference
+
+module ComplLinkStyle =
+    let ofString (input: string) : Result<ComplLinkStyle, string> =
+        match input.ToLower() with
+        | "auto" -> Ok ComplLinkStyle.Auto
+        | "wiki" -> Ok ComplLinkStyle.Wiki
+        | "markdown" -> Ok ComplLinkStyle.Markdown
+        | "reference" -> Ok ComplLinkStyle.Reference
+        | other -> Error $"Unknown ComplLinkStyle: {other}"
+
+    let ofStringOpt input =
+        match ofString input with
+        | Ok x -> Some x
+        | Error _ -> None
+
+/// What completion items for docs are labelled with.
+[<RequireQualifiedAccess>]
+type ComplDocLabel =
+    /// Document title, e.g. "Doc"
+    | Title
+    /// File name, e.g. "path/to/doc.md" -> "doc.md"
+    | FileName
+    /// Both, e.g. "Doc (doc.md)"
+    | TitleAndFileName
+
+module ComplDocLabel =
+    let ofString (input: string) : Result<ComplDocLabel, string> =
+        match input.ToLower() with
+        | "title" -> Ok ComplDocLabel.Title
+        | "file-name" -> Ok ComplDocLabel.FileName
+        | "title-and-file-name" -> Ok ComplDocLabel.TitleAndFileName
+        | other -> Error $"Unknown ComplDocLabel: {other}"
+
+    let ofStringOpt input =
+        match ofString input with
+        | Ok x -> Some x
+        | Error _ -> None
+
+/// Severity of the diagnostics of a rule, or `Off` to disable the rule.
+[<RequireQualifiedAccess>]
+type DiagSeverity =
+    | Error
+    | Warning
+    | Information
+    | Hint
+    | Off
+
+module DiagSeverity =
+    let ofString (input: string) : Result<DiagSeverity, string> =
+        match input.ToLower() with
+        | "error" -> Ok DiagSeverity.Error
+        | "warn"
+        | "warning" -> Ok DiagSeverity.Warning
+        | "info"
+        | "information" -> Ok DiagSeverity.Information
+        | "hint" -> Ok DiagSeverity.Hint
+        | "off" -> Ok DiagSeverity.Off
+        | other -> Error $"Unknown DiagSeverity: {other}"
+
+    let ofStringOpt input =
+        match ofString input with
+        | Ok x -> Some x
+        | Error _ -> None
+
+    /// Severities by rule ID, e.g. `broken-link = "error"`. Unknown severities are skipped.
+    let ofTable (table: TomlTable) : Map<string, DiagSeverity> =
+        seq {
+            for KeyValue (rule, value) in table do
+                match value with
+                | :? string as value ->
+                    match ofStringOpt value with
+                    | Some severity -> yield rule, severity
+                    | None -> ()
+                | _ -> ()
+        }
+        |> Map.ofSeq
+
+/// Naming convention of tags: one of the presets or a regular expression whole tags must match.
+[<RequireQualifiedAccess>]
+type TagConvention =
+    | Any
+    | KebabCase
+    | SnakeCase
+    | CamelCase
+    | Pattern of string
+
+module TagConvention =
+    let ofString (input: string) : Result<TagConvention, string> =
+        match input with
+        | "any" -> Ok TagConvention.Any
+        | "kebab-case" -> Ok TagConvention.KebabCase
+        | "snake_case" -> Ok TagConvention.SnakeCase
+        | "camelCase" -> Ok TagConvention.CamelCase
+        | pattern ->
+            try
+                Regex(pattern) |> ignore
+                Ok(TagConvention.Pattern pattern)
+            with :? System.ArgumentException ->
+                Error $"Invalid tag pattern: {pattern}"
+
+    let ofStringOpt input =
+        match ofString input with
+        | Ok x -> Some x
+        | Error _ -> None
+
+/// Type of a frontmatter field in a frontmatter schema.
+[<RequireQualifiedAccess>]
+type FieldType =
+    | String
+    | Number
+    | Bool
+    | Date
+    | List of FieldType
+
+module FieldType =
+    /// Parses "string", "number", "bool", "date" and lists of these, e.g. "string[]".
+    let rec ofString (input: string) : Result<FieldType, string> =
+        let input = input.Trim().ToLower()
+
+        if input.EndsWith("[]") then
+            ofString (input.Substring(0, input.Length - 2)) |> Result.map FieldType.List
+        else
+            match input with
+            | "string" -> Ok FieldType.String
+            | "number" -> Ok FieldType.Number
+            | "bool"
+            | "boolean" -> Ok FieldType.Bool
+            | "date" -> Ok FieldType.Date
+            | other -> Error $"Unknown FieldType: {other}"
+
+    let ofStringOpt input =
+        match ofString input with
+        | Ok x -> Some x
+        | Error _ -> None
+
+    let rec fmt (fieldType: FieldType) : string =
+        match fieldType with
+        | FieldType.String -> "string"
+        | FieldType.Number -> "number"
+        | FieldType.Bool -> "bool"
+        | FieldType.Date -> "date"
+        | FieldType.List elType -> fmt elType + "[]"
+
+    /// Types by field name, e.g. `tags = "string[]"`. Unknown types are skipped.
+    let ofTable (table: TomlTable) : Map<string, FieldType> =
+        seq {
+            for KeyValue (field, value) in table do
+                match value with
+                | :? string as value ->
+                    match ofStringOpt value with
+                    | Some fieldType -> yield field, fieldType
+                    | None -> ()
+                | _ -> ()
+        }
+        |> Map.ofSeq
+
+/// Fields the frontmatter of a note must have and the types of its fields. Fields without a type
+/// can have any value.
+type FrontmatterSchema =
+    { required: option<array<string>>
+      types: Map<string, FieldType> }
+
+module FrontmatterSchema =
+    let private ofTable (table: TomlTable) : FrontmatterSchema =
+        let required =
+            match table.TryGetValue("required") with
+            | true, (:? TomlArray as fields) ->
+                Some [| for field in fields do
+                            match field with
+                            | :? string as field -> yield field
+                            | _ -> () |]
+            | _ -> None
+
+        let types =
+            match table.TryGetValue("types") with
+            | true, (:? TomlTable as types) -> FieldType.ofTable types
+            | _ -> Map.empty
+
+        { required = required; types = types }
+
+    /// Schemas by directory relative to the root, e.g. `[diagnostics.frontmatter.dirs.journal]`.
+    let ofDirsTable (table: TomlTable) : Map<string, FrontmatterSchema> =
+        seq {
+            for KeyValue (dir, value) in table do
+                match value with
+                | :? TomlTable as schema -> yield dir.Replace('\\', '/').Trim('/'), ofTable schema
+                | _ -> ()
+        }
+        |> Map.ofSeq
+
 /// Configuration knobs for the Marksman LSP.
 ///
 /// Note: all config options are laid out flat to make working with the config
 /// without lenses manageable.
 type Config =
     { caTocEnable: option<bool>
+      caTocMaxLevel: option<int>
+      caTableEnable: option<bool>
+      caSectionEnable: option<bool>
       coreMarkdownFileExtensions: option<array<string>>
-      complWikiStyle: option<ComplWikiStyle> }
+      coreBibliography: option<array<string>>
+      coreIgnore: option<array<string>>
+      coreMaxFileSizeKb: option<int>
+      coreMemoryLimitMb: option<int>
+      coreFollowSymlinks: option<bool>
+      coreLinkResolution: option<CoreLinkResolution>
+      coreBacklinksSection: option<bool>
+      coreZettelIdEnable: option<bool>
+      coreZettelIdFormat: option<string>
+      complWikiStyle: option<ComplWikiStyle>
+      complLinkStyle: option<ComplLinkStyle>
+      complDocLabel: option<ComplDocLabel>
+      complAssetDirs: option<array<string>>
+      complDailyNoteFormat: option<string>
+      complDailyNoteCreate: option<bool>
+      complDailyNoteDir: option<string>
+      complCreateNoteEnable: option<bool>
+      complCreateNoteTemplate: option<string>
+      complCreateNoteTemplateDir: option<string>
+      complCreateNoteAuthor: option<string>
+      complPreviewMaxLines: option<int>
+      complPreviewMaxChars: option<int>
+      complMaxItems: option<int>
+      diagOrphanEnable: option<bool>
+      diagRules: option<Map<string, DiagSeverity>>
+      diagUrlCheckEnable: option<bool>
+      diagUrlCheckTtlMinutes: option<int>
+      gitEnable: option<bool>
+      diagFrontmatterRequired: option<array<string>>
+      diagFrontmatterTypes: option<Map<string, FieldType>>
+      diagFrontmatterDirs: option<Map<string, FrontmatterSchema>>
+      diagDebounceMs: option<int>
+      diagMissingTitleEnable: option<bool>
+      diagHeadingJumpEnable: option<bool>
+      diagTagConvention: option<TagConvention> }
 
     static member Default =
         { caTocEnable = Some true
+          caTocMaxLevel = Some 6
+          caTableEnable = Some true
+          caSectionEnable = Some true
           coreMarkdownFileExtensions = Some [| "md"; "markdown" |]
-          complWikiStyle = Some TitleSlug }
+          coreBibliography = Some [||]
+          coreIgnore = Some [||]
+          coreMaxFileSizeKb = Some 1024
+          coreMemoryLimitMb = Some 0
+          coreFollowSymlinks = Some false
+          coreLinkResolution = Some CoreLinkResolution.All
+          coreBacklinksSection = Some false
+          coreZettelIdEnable = Some false
+          coreZettelIdFormat = Some "yyyyMMddHHmm"
+          complWikiStyle = Some TitleSlug
+          complLinkStyle = Some ComplLinkStyle.Auto
+          complDocLabel = Some ComplDocLabel.Title
+          complAssetDirs = Some [||]
+          complDailyNoteFormat = Some "yyyy-MM-dd"
+          complDailyNoteCreate = Some false
+          complDailyNoteDir = Some ""
+          complCreateNoteEnable = Some false
+          complCreateNoteTemplate = Some "# {title}\n"
+          complCreateNoteTemplateDir = Some "templates"
+          complCreateNoteAuthor = Some ""
+          complPreviewMaxLines = Some 10
+          complPreviewMaxChars = Some 500
+          complMaxItems = Some 200
+          diagOrphanEnable = Some false
+          diagRules = Some Map.empty
+          diagUrlCheckEnable = Some false
+          diagUrlCheckTtlMinutes = Some 60
+          gitEnable = Some false
+          diagFrontmatterRequired = Some [||]
+          diagFrontmatterTypes = Some Map.empty
+          diagFrontmatterDirs = Some Map.empty
+          diagDebounceMs = Some 200
+          diagMissingTitleEnable = Some false
+          diagHeadingJumpEnable = Some false
+          diagTagConvention = Some TagConvention.Any }
 
     static member Empty =
         { caTocEnable = None
+          caTocMaxLevel = None
+          caTableEnable = None
+          caSectionEnable = None
           coreMarkdownFileExtensions = None
-          complWikiStyle = None }
+          coreBibliography = None
+          coreIgnore = None
+          coreMaxFileSizeKb = None
+          coreMemoryLimitMb = None
+          coreFollowSymlinks = None
+          coreLinkResolution = None
+          coreBacklinksSection = None
+          coreZettelIdEnable = None
+          coreZettelIdFormat = None
+          complWikiStyle = None
+          complLinkStyle = None
+          complDocLabel = None
+          complAssetDirs = None
+          complDailyNoteFormat = None
+          complDailyNoteCreate = None
+          complDailyNoteDir = None
+          complCreateNoteEnable = None
+          complCreateNoteTemplate = None
+          complCreateNoteTemplateDir = None
+          complCreateNoteAuthor = None
+          complPreviewMaxLines = None
+          complPreviewMaxChars = None
+          complMaxItems = None
+          diagOrphanEnable = None
+          diagRules = None
+          diagUrlCheckEnable = None
+          diagUrlCheckTtlMinutes = None
+          gitEnable = None
+          diagFrontmatterRequired = None
+          diagFrontmatterTypes = None
+          diagFrontmatterDirs = None
+          diagDebounceMs = None
+          diagMissingTitleEnable = None
+          diagHeadingJumpEnable = None
+          diagTagConvention = None }
 
     member this.CaTocEnable() =
         this.caTocEnable
         |> Option.orElse Config.Default.caTocEnable
         |> Option.get
 
+    member this.CaTocMaxLevel() =
+        this.caTocMaxLevel
+        |> Option.orElse Config.Default.caTocMaxLevel
+        |> Option.get
+
+    member this.CaTableEnable() =
+        this.caTableEnable
+        |> Option.orElse Config.Default.caTableEnable
+        |> Option.get
+
+    member this.CaSectionEnable() =
+        this.caSectionEnable
+        |> Option.orElse Config.Default.caSectionEnable
+        |> Option.get
+
     member this.CoreMarkdownFileExtensions() =
         this.coreMarkdownFileExtensions
         |> Option.orElse Config.Default.coreMarkdownFileExtensions
         |> Option.get
 
+    member this.CoreBibliography() =
+        this.coreBibliography
+        |> Option.orElse Config.Default.coreBibliography
+        |> Option.get
+
+    member this.CoreIgnore() =
+        this.coreIgnore
+        |> Option.orElse Config.Default.coreIgnore
+        |> Option.get
+
+    member this.CoreMaxFileSizeKb() =
+        this.coreMaxFileSizeKb
+        |> Option.orElse Config.Default.coreMaxFileSizeKb
+        |> Option.get
+
+    member this.CoreMemoryLimitMb() =
+        this.coreMemoryLimitMb
+        |> Option.orElse Config.Default.coreMemoryLimitMb
+        |> Option.get
+
+    member this.CoreFollowSymlinks() =
+        this.coreFollowSymlinks
+        |> Option.orElse Config.Default.coreFollowSymlinks
+        |> Option.get
+
+    member this.CoreLinkResolution() =
+        this.coreLinkResolution
+        |> Option.orElse Config.Default.coreLinkResolution
+        |> Option.get
+
+    member this.CoreBacklinksSection() =
+        this.coreBacklinksSection
+        |> Option.orElse Config.Default.coreBacklinksSection
+        |> Option.get
+
+    member this.CoreZettelIdEnable() =
+        this.coreZettelIdEnable
+        |> Option.orElse Config.Default.coreZettelIdEnable
+        |> Option.get
+
+    member this.CoreZettelIdFormat() =
+        this.coreZettelIdFormat
+        |> Option.orElse Config.Default.coreZettelIdFormat
+        |> Option.get
+
     member this.ComplWikiStyle() =
         this.complWikiStyle
         |> Option.orElse Config.Default.complWikiStyle
         |> Option.get
 
+    member this.ComplLinkStyle() =
+        this.complLinkStyle
+        |> Option.orElse Config.Default.complLinkStyle
+        |> Option.get
+
+    member this.ComplDocLabel() =
+        this.complDocLabel
+        |> Option.orElse Config.Default.complDocLabel
+        |> Option.get
+
+    member this.ComplAssetDirs() =
+        this.complAssetDirs
+        |> Option.orElse Config.Default.complAssetDirs
+        |> Option.get
+
+    member this.ComplDailyNoteFormat() =
+        this.complDailyNoteFormat
+        |> Option.orElse Config.Default.complDailyNoteFormat
+        |> Option.get
+
+    member this.ComplDailyNoteCreate() =
+        this.complDailyNoteCreate
+        |> Option.orElse Config.Default.complDailyNoteCreate
+        |> Option.get
+
+    member this.ComplDailyNoteDir() =
+        this.complDailyNoteDir
+        |> Option.orElse Config.Default.complDailyNoteDir
+        |> Option.get
+
+    member this.ComplCreateNoteEnable() =
+        this.complCreateNoteEnable
+        |> Option.orElse Config.Default.complCreateNoteEnable
+        |> Option.get
+
+    member this.ComplCreateNoteTemplate() =
+        this.complCreateNoteTemplate
+        |> Option.orElse Config.Default.complCreateNoteTemplate
+        |> Option.get
+
+    member this.ComplCreateNoteTemplateDir() =
+        this.complCreateNoteTemplateDir
+        |> Option.orElse Config.Default.complCreateNoteTemplateDir
+        |> Option.get
+
+    member this.ComplCreateNoteAuthor() =
+        this.complCreateNoteAuthor
+        |> Option.orElse Config.Default.complCreateNoteAuthor
+        |> Option.get
+
+    member this.ComplPreviewMaxLines() =
+        this.complPreviewMaxLines
+        |> Option.orElse Config.Default.complPreviewMaxLines
+        |> Option.get
+
+    member this.ComplPreviewMaxChars() =
+        this.complPreviewMaxChars
+        |> Option.orElse Config.Default.complPreviewMaxChars
+        |> Option.get
+
+    member this.ComplMaxItems() =
+        this.complMaxItems
+        |> Option.orElse Config.Default.complMaxItems
+        |> Option.get
+
+    member this.DiagOrphanEnable() =
+        this.diagOrphanEnable
+        |> Option.orElse Config.Default.diagOrphanEnable
+        |> Option.get
+
+    member this.DiagRules() =
+        this.diagRules
+        |> Option.orElse Config.Default.diagRules
+        |> Option.get
+
+    /// Severity configured for the rule, if any.
+    member this.DiagRuleSeverity(rule: string) : option<DiagSeverity> =
+        this.DiagRules() |> Map.tryFind rule
+
+    member this.DiagUrlCheckEnable() =
+        this.diagUrlCheckEnable
+        |> Option.orElse Config.Default.diagUrlCheckEnable
+        |> Option.get
+
+    member this.DiagUrlCheckTtlMinutes() =
+        this.diagUrlCheckTtlMinutes
+        |> Option.orElse Config.Default.diagUrlCheckTtlMinutes
+        |> Option.get
+
+    member this.GitEnable() =
+        this.gitEnable
+        |> Option.orElse Config.Default.gitEnable
+        |> Option.get
+
+    member this.DiagFrontmatterRequired() =
+        this.diagFrontmatterRequired
+        |> Option.orElse Config.Default.diagFrontmatterRequired
+        |> Option.get
+
+    member this.DiagFrontmatterTypes() =
+        this.diagFrontmatterTypes
+        |> Option.orElse Config.Default.diagFrontmatterTypes
+        |> Option.get
+
+    member this.DiagFrontmatterDirs() =
+        this.diagFrontmatterDirs
+        |> Option.orElse Config.Default.diagFrontmatterDirs
+        |> Option.get
+
+    /// Frontmatter schema of the doc at `pathFromRoot`. The schema of the longest directory
+    /// containing the doc overrides field types of the workspace schema and, when it lists them,
+    /// its required fields.
+    member this.DiagFrontmatterSchema(pathFromRoot: string) : FrontmatterSchema =
+        let path = pathFromRoot.Replace('\\', '/').TrimStart('/')
+
+        let baseSchema =
+            { required = Some(this.DiagFrontmatterRequired())
+              types = this.DiagFrontmatterTypes() }
+
+        let dirSchema =
+            this.DiagFrontmatterDirs()
+            |> Map.toSeq
+            |> Seq.filter (fun (dir, _) -> path.StartsWith(dir + "/"))
+            |> Seq.sortByDescending (fun (dir, _) -> dir.Length)
+            |> Seq.tryHead
+
+        match dirSchema with
+        | None -> baseSchema
+        | Some (_, dirSchema) ->
+            let types =
+                Map.fold (fun acc field ty -> Map.add field ty acc) baseSchema.types dirSchema.types
+
+            { required = dirSchema.required |> Option.orElse baseSchema.required
+              types = types }
+
+    member this.DiagDebounceMs() =
+        this.diagDebounceMs
+        |> Option.orElse Config.Default.diagDebounceMs
+        |> Option.get
+
+    member this.DiagMissingTitleEnable() =
+        this.diagMissingTitleEnable
+        |> Option.orElse Config.Default.diagMissingTitleEnable
+        |> Option.get
+
+    member this.DiagHeadingJumpEnable() =
+        this.diagHeadingJumpEnable
+        |> Option.orElse Config.Default.diagHeadingJumpEnable
+        |> Option.get
+
+    member this.DiagTagConvention() =
+        this.diagTagConvention
+        |> Option.orElse Config.Default.diagTagConvention
+        |> Option.get
+
+// Integers out of range are ignored like unknown values of the other settings, so that the
+// defaults apply. TOML integers are 64-bit
+let private inRange (min: int64) (max: int64) (value: option<int64>) : option<int> =
+    value |> Option.filter (fun value -> min <= value && value <= max) |> Option.map int
+
+// Integers out of range are taken for the nearest bound instead, e.g. of durations
+let private clamped (min: int64) (max: int64) (value: option<int64>) : option<int> =
+    value |> Option.map (fun value -> int (System.Math.Clamp(value, min, max)))
+
 let private configOfTable (table: TomlTable) : LookupResult<Config> =
     monad {
         let! caTocEnable = getFromTableOpt<bool> table [] [ "code_action"; "toc"; "enable" ]
 
+        let! caTocMaxLevel =
+            getFromTableOpt<int64> table [] [ "code_action"; "toc"; "max_level" ]
+
+        let! caTableEnable = getFromTableOpt<bool> table [] [ "code_action"; "table"; "enable" ]
+
+        let! caSectionEnable =
+            getFromTableOpt<bool> table [] [ "code_action"; "section"; "enable" ]
+
         let! coreMarkdownFileExtensions =
             getFromTableOpt<array<string>> table [] [ "core"; "markdown"; "file_extensions" ]
 
+        let! coreBibliography =
+            getFromTableOpt<array<string>> table [] [ "core"; "bibliography" ]
+
+        let! coreIgnore = getFromTableOpt<array<string>> table [] [ "core"; "ignore" ]
+
+        let! coreMaxFileSizeKb =
+            getFromTableOpt<int64> table [] [ "core"; "max_file_size_kb" ]
+
+        let! coreMemoryLimitMb =
+            getFromTableOpt<int64> table [] [ "core"; "memory_limit_mb" ]
+
+        let! coreFollowSymlinks = getFromTableOpt<bool> table [] [ "core"; "follow_symlinks" ]
+
+        let! coreLinkResolution =
+            getFromTableOpt<string> table [] [ "core"; "link_resolution" ]
+
+        let coreLinkResolution =
+            coreLinkResolution |> Option.bind CoreLinkResolution.ofStringOpt
+
+        let! coreBacklinksSection =
+            getFromTableOpt<bool> table [] [ "core"; "backlinks_section" ]
+
+        let! coreZettelIdEnable =
+            getFromTableOpt<bool> table [] [ "core"; "zettel_id"; "enable" ]
+
+        let! coreZettelIdFormat =
+            getFromTableOpt<string> table [] [ "core"; "zettel_id"; "format" ]
+
         let! complWikiStyle = getFromTableOpt<string> table [] [ "completion"; "wiki"; "style" ]
 
         let complWikiStyle =
             complWikiStyle |> Option.bind ComplWikiStyle.ofStringOpt
 
+        let! complLinkStyle = getFromTableOpt<string> table [] [ "completion"; "link_style" ]
+
+        let complLinkStyle =
+            complLinkStyle |> Option.bind ComplLinkStyle.ofStringOpt
+
+        let! complDocLabel = getFromTableOpt<string> table [] [ "completion"; "label" ]
+        let complDocLabel = complDocLabel |> Option.bind ComplDocLabel.ofStringOpt
+
+        let! complAssetDirs = getFromTableOpt<array<string>> table [] [ "completion"; "asset_dirs" ]
+
+        let! complDailyNoteFormat =
+            getFromTableOpt<string> table [] [ "completion"; "daily_note"; "format" ]
+
+        let! complDailyNoteCreate =
+            getFromTableOpt<bool> table [] [ "completion"; "daily_note"; "create" ]
+
+        let! complDailyNoteDir =
+            getFromTableOpt<string> table [] [ "completion"; "daily_note"; "dir" ]
+
+        let! complCreateNoteEnable =
+            getFromTableOpt<bool> table [] [ "completion"; "create_note"; "enable" ]
+
+        let! complCreateNoteTemplate =
+            getFromTableOpt<string> table [] [ "completion"; "create_note"; "template" ]
+
+        let! complCreateNoteTemplateDir =
+            getFromTableOpt<string> table [] [ "completion"; "create_note"; "template_dir" ]
+
+        let! complCreateNoteAuthor =
+            getFromTableOpt<string> table [] [ "completion"; "create_note"; "author" ]
+
+        // TOML integers are 64-bit
+        let! complPreviewMaxLines =
+            getFromTableOpt<int64> table [] [ "completion"; "preview"; "max_lines" ]
+
+        let! complPreviewMaxChars =
+            getFromTableOpt<int64> table [] [ "completion"; "preview"; "max_chars" ]
+
+        let! complMaxItems = getFromTableOpt<int64> table [] [ "completion"; "max_items" ]
+
+        let! diagOrphanEnable = getFromTableOpt<bool> table [] [ "diagnostics"; "orphan"; "enable" ]
+
+        let! diagRules = getFromTableOpt<TomlTable> table [] [ "diagnostics"; "rules" ]
+
+        let! diagUrlCheckEnable =
+            getFromTableOpt<bool> table [] [ "diagnostics"; "url_check"; "enable" ]
+
+        let! diagUrlCheckTtlMinutes =
+            getFromTableOpt<int64> table [] [ "diagnostics"; "url_check"; "ttl_minutes" ]
+
+        let! gitEnable = getFromTableOpt<bool> table [] [ "git"; "enable" ]
+
+        let! diagFrontmatterRequired =
+            getFromTableOpt<array<string>> table [] [ "diagnostics"; "frontmatter"; "required" ]
+
+        let! diagFrontmatterTypes =
+            getFromTableOpt<TomlTable> table [] [ "diagnostics"; "frontmatter"; "types" ]
+
+        let! diagFrontmatterDirs =
+            getFromTableOpt<TomlTable> table [] [ "diagnostics"; "frontmatter"; "dirs" ]
+
+        let! diagDebounceMs = getFromTableOpt<int64> table [] [ "diagnostics"; "debounce_ms" ]
+
+        let! diagMissingTitleEnable =
+            getFromTableOpt<bool> table [] [ "diagnostics"; "missing_title"; "enable" ]
+
+        let! diagHeadingJumpEnable =
+            getFromTableOpt<bool> table [] [ "diagnostics"; "heading_jump"; "enable" ]
+
+        let! diagTagConvention =
+            getFromTableOpt<string> table [] [ "diagnostics"; "tag_convention" ]
+
+        let diagTagConvention =
+            diagTagConvention |> Option.bind TagConvention.ofStringOpt
+
         { caTocEnable = caTocEnable
+          caTocMaxLevel = caTocMaxLevel |> inRange 1L 6L
+          caTableEnable = caTableEnable
+          caSectionEnable = caSectionEnable
           coreMarkdownFileExtensions = coreMarkdownFileExtensions
-          complWikiStyle = complWikiStyle }
+          coreBibliography = coreBibliography
+          coreIgnore = coreIgnore
+          coreMaxFileSizeKb = coreMaxFileSizeKb |> inRange 1L (int64 System.Int32.MaxValue)
+          coreMemoryLimitMb = coreMemoryLimitMb |> inRange 0L (int64 System.Int32.MaxValue)
+          coreFollowSymlinks = coreFollowSymlinks
+          coreLinkResolution = coreLinkResolution
+          coreBacklinksSection = coreBacklinksSection
+          coreZettelIdEnable = coreZettelIdEnable
+          coreZettelIdFormat = coreZettelIdFormat
+          complWikiStyle = complWikiStyle
+          complLinkStyle = complLinkStyle
+          complDocLabel = complDocLabel
+          complAssetDirs = complAssetDirs
+          complDailyNoteFormat = complDailyNoteFormat
+          complDailyNoteCreate = complDailyNoteCreate
+          complDailyNoteDir = complDailyNoteDir
+          complCreateNoteEnable = complCreateNoteEnable
+          complCreateNoteTemplate = complCreateNoteTemplate
+          complCreateNoteTemplateDir = complCreateNoteTemplateDir
+          complCreateNoteAuthor = complCreateNoteAuthor
+          complPreviewMaxLines = complPreviewMaxLines |> inRange 0L (int64 System.Int32.MaxValue)
+          complPreviewMaxChars = complPreviewMaxChars |> inRange 0L (int64 System.Int32.MaxValue)
+          complMaxItems = complMaxItems |> inRange 1L (int64 System.Int32.MaxValue)
+          diagOrphanEnable = diagOrphanEnable
+          diagRules = diagRules |> Option.map DiagSeverity.ofTable
+          diagUrlCheckEnable = diagUrlCheckEnable
+          diagUrlCheckTtlMinutes =
+            diagUrlCheckTtlMinutes |> inRange 0L (int64 System.Int32.MaxValue)
+          gitEnable = gitEnable
+          diagFrontmatterRequired = diagFrontmatterRequired
+          diagFrontmatterTypes = diagFrontmatterTypes |> Option.map FieldType.ofTable
+          diagFrontmatterDirs =
+            diagFrontmatterDirs
+            |> Option.map FrontmatterSchema.ofDirsTable
+          diagDebounceMs = diagDebounceMs |> clamped 0L 10000L
+          diagMissingTitleEnable = diagMissingTitleEnable
+          diagHeadingJumpEnable = diagHeadingJumpEnable
+          diagTagConvention = diagTagConvention }
     }
 
 module Config =
     let logger = LogProvider.getLoggerByName "Config"
 
+    let private mergeMaps (hi: option<Map<'K, 'V>>) (low: option<Map<'K, 'V>>) =
+        match hi, low with
+        | Some hi, Some low -> Some(Map.fold (fun acc key value -> Map.add key value acc) low hi)
+        | hi, low -> hi |> Option.orElse low
+
     let merge hi low =
         { caTocEnable = hi.caTocEnable |> Option.orElse low.caTocEnable
+          caTocMaxLevel = hi.caTocMaxLevel |> Option.orElse low.caTocMaxLevel
+          caTableEnable = hi.caTableEnable |> Option.orElse low.caTableEnable
+          caSectionEnable = hi.caSectionEnable |> Option.orElse low.caSectionEnable
           coreMarkdownFileExtensions =
             hi.coreMarkdownFileExtensions
             |> Option.orElse low.coreMarkdownFileExtensions
-          complWikiStyle = hi.complWikiStyle |> Option.orElse low.complWikiStyle }
+          coreBibliography = hi.coreBibliography |> Option.orElse low.coreBibliography
+          coreIgnore = hi.coreIgnore |> Option.orElse low.coreIgnore
+          coreMaxFileSizeKb = hi.coreMaxFileSizeKb |> Option.orElse low.coreMaxFileSizeKb
+          coreMemoryLimitMb = hi.coreMemoryLimitMb |> Option.orElse low.coreMemoryLimitMb
+          coreFollowSymlinks = hi.coreFollowSymlinks |> Option.orElse low.coreFollowSymlinks
+          coreLinkResolution = hi.coreLinkResolution |> Option.orElse low.coreLinkResolution
+          coreBacklinksSection =
+            hi.coreBacklinksSection |> Option.orElse low.coreBacklinksSection
+          coreZettelIdEnable = hi.coreZettelIdEnable |> Option.orElse low.coreZettelIdEnable
+          coreZettelIdFormat = hi.coreZettelIdFormat |> Option.orElse low.coreZettelIdFormat
+          complWikiStyle = hi.complWikiStyle |> Option.orElse low.complWikiStyle
+          complLinkStyle = hi.complLinkStyle |> Option.orElse low.complLinkStyle
+          complDocLabel = hi.complDocLabel |> Option.orElse low.complDocLabel
+          complAssetDirs = hi.complAssetDirs |> Option.orElse low.complAssetDirs
+          complDailyNoteFormat =
+            hi.complDailyNoteFormat
+            |> Option.orElse low.complDailyNoteFormat
+          complDailyNoteCreate =
+            hi.complDailyNoteCreate
+            |> Option.orElse low.complDailyNoteCreate
+          complDailyNoteDir =
+            hi.complDailyNoteDir
+            |> Option.orElse low.complDailyNoteDir
+          complCreateNoteEnable =
+            hi.complCreateNoteEnable
+            |> Option.orElse low.complCreateNoteEnable
+          complCreateNoteTemplate =
+            hi.complCreateNoteTemplate
+            |> Option.orElse low.complCreateNoteTemplate
+          complCreateNoteTemplateDir =
+            hi.complCreateNoteTemplateDir
+            |> Option.orElse low.complCreateNoteTemplateDir
+          complCreateNoteAuthor =
+            hi.complCreateNoteAuthor
+            |> Option.orElse low.complCreateNoteAuthor
+          complPreviewMaxLines =
+            hi.complPreviewMaxLines
+            |> Option.orElse low.complPreviewMaxLines
+          complPreviewMaxChars =
+            hi.complPreviewMaxChars
+            |> Option.orElse low.complPreviewMaxChars
+          complMaxItems = hi.complMaxItems |> Option.orElse low.complMaxItems
+          diagOrphanEnable = hi.diagOrphanEnable |> Option.orElse low.diagOrphanEnable
+          // Severities are merged rule by rule, the ones in `hi` take precedence
+          diagRules = mergeMaps hi.diagRules low.diagRules
+          diagUrlCheckEnable = hi.diagUrlCheckEnable |> Option.orElse low.diagUrlCheckEnable
+          diagUrlCheckTtlMinutes =
+            hi.diagUrlCheckTtlMinutes
+            |> Option.orElse low.diagUrlCheckTtlMinutes
+          gitEnable = hi.gitEnable |> Option.orElse low.gitEnable
+          diagFrontmatterRequired =
+            hi.diagFrontmatterRequired
+            |> Option.orElse low.diagFrontmatterRequired
+          diagFrontmatterTypes = mergeMaps hi.diagFrontmatterTypes low.diagFrontmatterTypes
+          diagFrontmatterDirs = mergeMaps hi.diagFrontmatterDirs low.diagFrontmatterDirs
+          diagDebounceMs = hi.diagDebounceMs |> Option.orElse low.diagDebounceMs
+          diagMissingTitleEnable =
+            hi.diagMissingTitleEnable
+            |> Option.orElse low.diagMissingTitleEnable
+          diagHeadingJumpEnable =
+            hi.diagHeadingJumpEnable
+            |> Option.orElse low.diagHeadingJumpEnable
+          diagTagConvention = hi.diagTagConvention |> Option.orElse low.diagTagConvention }
 
     let mergeOpt hi low =
         match low with
@@ -186,6 +967,44 @@ module Config =
             logger.trace (Log.setMessage "Parsing as TOML failed")
             None
 
+    // Client settings mirror the layout of the config file, so they are read as if they were TOML
+    let rec private tomlOfJson (json: JToken) : option<obj> =
+        match json with
+        | :? JObject as json ->
+            let table = TomlTable()
+
+            for prop in json.Properties() do
+                tomlOfJson prop.Value |> Option.iter (fun value -> table[prop.Name] <- value)
+
+            Some(box table)
+        | :? JArray as json ->
+            let array = TomlArray()
+
+            for item in json do
+                tomlOfJson item |> Option.iter array.Add
+
+            Some(box array)
+        | _ ->
+            match json.Type with
+            | JTokenType.Boolean -> Some(box (json.Value<bool>()))
+            | JTokenType.Integer -> Some(box (json.Value<int64>()))
+            | JTokenType.Float -> Some(box (json.Value<double>()))
+            | JTokenType.String -> Some(box (json.Value<string>()))
+            | _ -> None
+
+    /// Config of the settings sent by the client, e.g. `{ "completion": { "link_style": ... } }`.
+    let ofJson (json: JToken) : option<Config> =
+        match tomlOfJson json with
+        | Some (:? TomlTable as table) ->
+            match configOfTable table with
+            | Ok parsed -> Some parsed
+            | Error err ->
+                logger.warn (
+                    Log.setMessage "Malformed client settings" >> Log.addContext "error" err
+                )
+                None
+        | _ -> None
+
     let read (filepath: string) =
         try
             let content = using (new StreamReader(filepath)) (fun f -> f.ReadToEnd())
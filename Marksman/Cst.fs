@@ -24,8 +24,15 @@ module Node =
 
     let fmtOptText (node: option<TextNode>) : string = fmtOption fmtText node
 
+/// Wiki link, e.g. `[[doc#heading]]`.
+/// Embeds (transclusions), e.g. `![[doc#heading]]`, are wiki links with `embed` set.
 [<RequireQualifiedAccess>]
-type WikiLink = { doc: option<TextNode>; heading: option<TextNode> }
+type WikiLink =
+    { doc: option<TextNode>
+      heading: option<TextNode>
+      /// The text shown instead of the target, e.g. `[[doc|alias]]`
+      alias: option<TextNode>
+      embed: bool }
 
 module WikiLink =
     let destDoc (dest: WikiLink) : option<string> = dest.doc |> Option.map Node.text
@@ -41,6 +48,11 @@ module WikiLink =
         wl.heading
         |> Option.iter (fun h -> $"head={h.text}; {h.range}" |> lines.Add)
 
+        wl.alias
+        |> Option.iter (fun a -> $"alias={a.text}; {a.range}" |> lines.Add)
+
+        if wl.embed then lines.Add("embed")
+
         String.Join(Environment.NewLine, lines)
 
     let render (doc: option<string>) (heading: option<string>) (includeBraces: bool) : string =
@@ -106,27 +118,53 @@ module Url =
             { url = None; anchor = Some anchor }
         else
             let docText = url.text.Substring(0, offsetHash)
+            // Columns are in the position encoding, e.g. UTF-8 bytes, rather than chars
+            let hashColumn = url.range.Start.Character + Text.PositionEncoding.length docText
 
             let docRange =
-                { url.range with
-                    End = Position.Mk(url.range.Start.Line, url.range.Start.Character + offsetHash) }
+                { url.range with End = Position.Mk(url.range.Start.Line, hashColumn) }
 
             let docUrl = Node.mkText docText docRange
 
             let anchorText = url.text.Substring(offsetHash + 1)
 
             let anchorRange =
-                { url.range with
-                    Start =
-                        Position.Mk(
-                            url.range.Start.Line,
-                            url.range.Start.Character + offsetHash + 1
-                        ) }
+                { url.range with Start = Position.Mk(url.range.Start.Line, hashColumn + 1) }
 
             let anchor = Node.mkText anchorText anchorRange
 
             { url = Some docUrl; anchor = Some anchor }
 
+    /// `encoded`, a percent-encoded url, as the destination of an inline link. In angle brackets,
+    /// e.g. `<my note.md>`, it's written decoded, except for '%' that links are decoded by and
+    /// the brackets and line breaks that can't be there.
+    let toDestination (angled: bool) (encoded: string) : string =
+        if angled then
+            encoded
+                .UrlDecode()
+                .Replace("%", "%25")
+                .Replace("<", "%3C")
+                .Replace(">", "%3E")
+                .Replace("\n", "%0A")
+        else
+            encoded
+
+    /// The anchor, e.g. a heading id, in the url of an inline link. Letters are kept as they are,
+    /// like in GitHub-style anchors; '%' and the chars that can't be in a destination are
+    /// percent-encoded.
+    let encodeAnchor (anchor: string) : string =
+        anchor
+        |> Seq.map (fun char ->
+            match char with
+            | '%'
+            | ' '
+            | '('
+            | ')'
+            | '<'
+            | '>' -> "%" + (int char).ToString("X2")
+            | char -> string char)
+        |> String.concat ""
+
 module MdLink =
     let fmt (ml: MdLink) : string =
         match ml with
@@ -146,6 +184,12 @@ module MdLink =
             let fmtLabel = Node.fmtText label
             $"RS: label={fmtLabel}"
 
+    /// Whether the destination of the inline link is in angle brackets, e.g. `[](<my note.md>)`.
+    let isAngled (link: Node<MdLink>) : bool =
+        match link.data with
+        | MdLink.IL (_, Some _, _) -> link.text.Contains("](<")
+        | _ -> false
+
     let referenceLabel =
         function
         | MdLink.RF (_, label)
@@ -153,6 +197,17 @@ module MdLink =
         | MdLink.RS label -> Some label
         | MdLink.IL _ -> None
 
+    /// Footnote references, e.g. `[^1]`, are parsed as shortcut reference links.
+    let isFootnote =
+        function
+        | MdLink.RS label -> label.text.StartsWith('^')
+        | MdLink.IL _
+        | MdLink.RF _
+        | MdLink.RC _ -> false
+
+    /// Images, e.g. `![alt](path)`, are parsed as inline links whose source starts with '!'.
+    let isImage (link: Node<MdLink>) = link.text.StartsWith('!')
+
     let renderInline text path anchor =
         let text = text |> Option.defaultValue String.Empty
         let path = path |> Option.defaultValue String.Empty
@@ -172,6 +227,7 @@ module MdLinkDef =
     let normalizedLabel t = LinkLabel.ofString (Node.text t.label)
 
     let label t = t.label
+    let url t = t.url
     let labelContent t = Node.text t.label
 
     let titleContent t = t.title |> Option.map Node.text
@@ -189,22 +245,137 @@ module MdLinkDef =
 
     let name (mld: MdLinkDef) = mld.label |> Node.text
 
+    /// Footnote definitions, e.g. `[^1]: text`, are represented as link definitions with the
+    /// footnote's text in place of the URL.
+    let isFootnote (mld: MdLinkDef) = mld.label.text.StartsWith('^')
+
 type Tag = { name: TextNode }
 
 module Tag =
     let fmt (t: Tag) = $"name={t.name.text}; range={t.name.range}"
 
+/// Block anchor, i.e. `^block-id` at the end of a paragraph. The paragraph can then be referenced
+/// as `[[doc#^block-id]]`.
+type BlockAnchor = { id: TextNode; scope: Range }
+
+module BlockAnchor =
+    let fmt (b: BlockAnchor) = $"id={b.id.text}; range={b.id.range}; scope={b.scope}"
+
+    let id (b: BlockAnchor) = b.id.text
+
+/// Code block (fenced or indented), inline code span or math (`$...$` inline, `$$...$$` display).
+/// Content of code is never parsed for other elements.
+[<RequireQualifiedAccess>]
+type Code =
+    | Block of info: option<string>
+    | Inline
+    | Math of display: bool
+    /// Raw HTML block or an inline `<!-- ... -->` comment.
+    | Html
+
+module Code =
+    let fmt =
+        function
+        | Code.Block info -> $"block; info={fmtOption id info}"
+        | Code.Inline -> "inline"
+        | Code.Math display -> if display then "math; display" else "math; inline"
+        | Code.Html -> "html"
+
+/// Task list item, i.e. `- [ ] text` or `- [x] text`. The element spans only the `[ ]` marker,
+/// `text` is the rest of the item's first line.
+type Task = { completed: bool; text: TextNode }
+
+module Task =
+    let fmt (t: Task) =
+        let status = if t.completed then "done" else "open"
+        $"{status}; text={Node.fmtText t.text}"
+
+[<RequireQualifiedAccess>]
+type ColumnAlign =
+    | Default
+    | Left
+    | Center
+    | Right
+
+/// GFM (pipe) table. Cells hold trimmed cell content with their own ranges; the delimiter row is
+/// represented by per-column alignments.
+type Table =
+    { header: array<TextNode>
+      alignments: array<ColumnAlign>
+      rows: array<array<TextNode>>
+      /// What the lines after the first start with, e.g. `> ` for a table in a block quote. The
+      /// first line starts where the table does.
+      linePrefix: string }
+
+module Table =
+    let fmt (t: Table) =
+        let fmtCells cells =
+            cells |> Array.map Node.fmtText |> String.concat "; "
+
+        let fmtAlign =
+            function
+            | ColumnAlign.Default -> "default"
+            | ColumnAlign.Left -> "left"
+            | ColumnAlign.Center -> "center"
+            | ColumnAlign.Right -> "right"
+
+        let header = $"header={fmtCells t.header}"
+        let align = t.alignments |> Array.map fmtAlign |> String.concat "; "
+        let rows = t.rows |> Array.map (fun row -> $"row={fmtCells row}")
+        String.Join(Environment.NewLine, Array.append [| header; $"align={align}" |] rows)
+
+    let columnCount (t: Table) =
+        t.rows
+        |> Array.map Array.length
+        |> Array.fold max (max t.header.Length t.alignments.Length)
+
+/// Pandoc-style citation, i.e. `@key` inside brackets, e.g. `[@doe99]` or `[see @doe99, p. 3]`.
+/// The element spans `@key`.
+type Citation = { key: TextNode }
+
+module Citation =
+    let fmt (c: Citation) = $"key={c.key.text}; range={c.key.range}"
+
+    let key (c: Citation) = c.key.text
+
+/// Obsidian/GitHub-style callout, i.e. a block quote starting with a `[!KIND]` marker and an
+/// optional title, e.g. `> [!NOTE] Read this`. The element spans the marker, `scope` is the quote.
+type Callout =
+    { kind: TextNode
+      title: option<TextNode>
+      scope: Range }
+
+module Callout =
+    let fmt (c: Callout) =
+        $"kind={c.kind.text}; title={Node.fmtOptText c.title}; scope={c.scope}"
+
+    let kind (c: Callout) = c.kind.text
+
+    /// The title or, when there's none, the kind, e.g. `NOTE`.
+    let name (c: Callout) =
+        match c.title with
+        | Some title -> title.text
+        | None -> c.kind.text
+
 type Element =
     | H of Node<Heading>
     | WL of Node<WikiLink>
     | ML of Node<MdLink>
     | MLD of Node<MdLinkDef>
     | T of Node<Tag>
+    | BA of Node<BlockAnchor>
+    | C of Node<Code>
+    | TK of Node<Task>
+    | TB of Node<Table>
+    | CT of Node<Citation>
+    | CO of Node<Callout>
     | YML of TextNode
 
 and Heading =
     { level: int
       title: TextNode
+      /// Explicit id set via a trailing attribute, e.g. `## Heading {#custom-id}`
+      id: option<TextNode>
       scope: Range
       children: array<Element> }
 
@@ -215,6 +386,12 @@ let rec private fmtElement =
     | ML l -> fmtMdLink l
     | MLD r -> fmtMdLinkDef r
     | T t -> fmtTag t
+    | BA b -> fmtBlockAnchor b
+    | C c -> fmtCode c
+    | TK t -> fmtTask t
+    | TB t -> fmtTable t
+    | CT c -> fmtCitation c
+    | CO c -> fmtCallout c
     | YML y -> Node.fmtText y
 
 and private fmtHeading node =
@@ -226,9 +403,14 @@ and private fmtHeading node =
 
     let l3 = $"  title=`{inner.title.text}` @ {inner.title.range}"
 
+    let l4 =
+        inner.id
+        |> Option.map (fun id -> $"  id=`{id.text}` @ {id.range}")
+        |> Option.toArray
+
     let rest = Array.map (indentFmt fmtElement) inner.children
 
-    String.Join(Environment.NewLine, Array.concat [ [| l1; l2; l3 |]; rest ])
+    String.Join(Environment.NewLine, Array.concat [ [| l1; l2; l3 |]; l4; rest ])
 
 and private fmtWikiLink node =
     let first = $"WL: {node.text}; {node.range}"
@@ -247,6 +429,21 @@ and private fmtMdLinkDef node =
 
 and private fmtTag node = $"T: {Tag.fmt node.data} @ {node.range}"
 
+and private fmtBlockAnchor node = $"BA: {BlockAnchor.fmt node.data} @ {node.range}"
+
+and private fmtCode node = $"C: {Code.fmt node.data} @ {node.range}"
+
+and private fmtTask node = $"TK: {Task.fmt node.data} @ {node.range}"
+
+and private fmtCitation node = $"CT: {Citation.fmt node.data} @ {node.range}"
+
+and private fmtCallout node = $"CO: {Callout.fmt node.data} @ {node.range}"
+
+and private fmtTable node =
+    let first = $"TB: {node.range}"
+    let rest = (indentFmt Table.fmt) node.data
+    String.Join(Environment.NewLine, [ first; rest ])
+
 module Heading =
     let fmt = fmtHeading
 
@@ -254,6 +451,13 @@ module Heading =
 
     let slug (heading: Heading) : Slug = name heading |> Slug.ofString
 
+    let idSlug (heading: Heading) : option<Slug> =
+        heading.id |> Option.map (Node.text >> Slug.ofString)
+
+    /// Checks whether the anchor targets the heading by its explicit id rather than by its title.
+    let isTargetedById (anchor: string) (heading: Heading) : bool =
+        idSlug heading = Some(Slug.ofString anchor) && slug heading <> Slug.ofString anchor
+
     let isTitle (heading: Heading) = heading.level <= 1
 
     let range (heading: Heading) : Range = heading.title.range
@@ -270,6 +474,12 @@ module Element =
         | ML n -> n.range
         | MLD n -> n.range
         | T n -> n.range
+        | BA n -> n.range
+        | C n -> n.range
+        | TK n -> n.range
+        | TB n -> n.range
+        | CT n -> n.range
+        | CO n -> n.range
         | YML n -> n.range
 
     let rangeStart el = (range el).Start
@@ -281,6 +491,12 @@ module Element =
         | ML n -> n.text
         | MLD n -> n.text
         | T n -> n.text
+        | BA n -> n.text
+        | C n -> n.text
+        | TK n -> n.text
+        | TB n -> n.text
+        | CT n -> n.text
+        | CO n -> n.text
         | YML n -> n.text
 
     let asHeading =
@@ -305,10 +521,16 @@ module Element =
         function
         | WL _
         | ML _
-        | T _ -> false
+        | T _
+        | C _
+        | TK _
+        | TB _
+        | CT _
+        | CO _ -> false
         | YML _
         | H _
-        | MLD _ -> true
+        | MLD _
+        | BA _ -> true
 
     let isLink =
         function
@@ -317,12 +539,95 @@ module Element =
         | H _
         | MLD _
         | T _
+        | BA _
+        | C _
+        | TK _
+        | TB _
+        | CT _
+        | CO _
         | YML _ -> false
 
     let isTitle el =
         asHeading el |>> Node.data |>> Heading.isTitle
         |> Option.defaultValue false
 
+    let private shiftRange (delta: int) (range: Range) : Range =
+        { Start = { range.Start with Line = range.Start.Line + delta }
+          End = { range.End with Line = range.End.Line + delta } }
+
+    let private shiftNode (delta: int) (node: Node<'A>) : Node<'A> =
+        { node with range = shiftRange delta node.range }
+
+    /// The element moved `delta` lines down, or up when `delta` is negative.
+    let rec shiftLines (delta: int) (el: Element) : Element =
+        let text (node: TextNode) = shiftNode delta node
+        let textOpt = Option.map text
+
+        match el with
+        | H h ->
+            let heading =
+                { h.data with
+                    title = text h.data.title
+                    id = textOpt h.data.id
+                    scope = shiftRange delta h.data.scope
+                    children = Array.map (shiftLines delta) h.data.children }
+
+            H { shiftNode delta h with data = heading }
+        | WL wl ->
+            let link =
+                { wl.data with
+                    doc = textOpt wl.data.doc
+                    heading = textOpt wl.data.heading
+                    alias = textOpt wl.data.alias }
+            WL { shiftNode delta wl with data = link }
+        | ML ml ->
+            let link =
+                match ml.data with
+                | MdLink.IL (label, url, title) -> MdLink.IL(text label, textOpt url, textOpt title)
+                | MdLink.RF (label, ref) -> MdLink.RF(text label, text ref)
+                | MdLink.RC label -> MdLink.RC(text label)
+                | MdLink.RS label -> MdLink.RS(text label)
+
+            ML { shiftNode delta ml with data = link }
+        | MLD mld ->
+            let def: MdLinkDef =
+                { label = text mld.data.label
+                  url = text mld.data.url
+                  title = textOpt mld.data.title }
+
+            MLD { shiftNode delta mld with data = def }
+        | T tag ->
+            let data: Tag = { name = text tag.data.name }
+            T { shiftNode delta tag with data = data }
+        | BA ba ->
+            let anchor: BlockAnchor =
+                { id = text ba.data.id; scope = shiftRange delta ba.data.scope }
+
+            BA { shiftNode delta ba with data = anchor }
+        | C code -> C(shiftNode delta code)
+        | TK task ->
+            let data = { task.data with text = text task.data.text }
+            TK { shiftNode delta task with data = data }
+        | TB table ->
+            let data =
+                { table.data with
+                    header = Array.map text table.data.header
+                    rows = Array.map (Array.map text) table.data.rows }
+
+            TB { shiftNode delta table with data = data }
+        | CT cit ->
+            let data: Citation = { key = text cit.data.key }
+            CT { shiftNode delta cit with data = data }
+        | CO co ->
+            let callout =
+                { co.data with
+                    kind = text co.data.kind
+                    title = textOpt co.data.title
+                    scope = shiftRange delta co.data.scope }
+
+            CO { shiftNode delta co with data = callout }
+        | YML yml -> YML(shiftNode delta yml)
+
 type Cst = array<Element>
 
 module Cst =
@@ -336,6 +641,12 @@ module Cst =
                     | H h -> yield! collect h.data.children
                     | YML _
                     | T _
+                    | BA _
+                    | C _
+                    | TK _
+                    | TB _
+                    | CT _
+                    | CO _
                     | WL _
                     | ML _
                     | MLD _ -> ()
@@ -344,5 +655,14 @@ module Cst =
         collect cst
 
     let elementAtPos (pos: Position) (cst: Cst) : option<Element> =
+        let contains el = (Element.range el).ContainsInclusive(pos)
+
+        // Tables contain other elements, e.g. links inside cells, which are more specific
+        let isTable =
+            function
+            | TB _ -> true
+            | _ -> false
+
         elementsAll cst
-        |> Seq.tryFind (fun el -> (Element.range el).ContainsInclusive(pos))
+        |> Seq.tryFind (fun el -> not (isTable el) && contains el)
+        |> Option.orElseWith (fun () -> elementsAll cst |> Seq.tryFind contains)
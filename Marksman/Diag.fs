@@ -1,47 +1,221 @@
 module Marksman.Diag
 
+open System.Globalization
 open System.IO
+open System.Text.RegularExpressions
+open System.Threading
 open Ionide.LanguageServerProtocol.Types
 open Marksman.Workspace
 
 module Lsp = Ionide.LanguageServerProtocol.Types
 
+open Marksman.Config
 open Marksman.Misc
 open Marksman.Cst
 open Marksman.Index
 open Marksman.Refs
 
+type LinkSyntaxError =
+    | UnclosedWikiLink
+    | EmptyTarget
+    | EmptyAnchor
+    | EmptyCitationKey
+
+/// Why a link that works on some filesystems breaks on others.
+type PortabilityIssue =
+    | CaseMismatch
+    | BackslashSeparator
+
+type FrontmatterError =
+    | MissingField of field: string
+    | WrongType of field: string * expected: FieldType
+    | WrongItemType of field: string * expected: FieldType
+
 type Entry =
     | AmbiguousLink of Element * Uref * array<Dest>
     | BrokenLink of Element * Uref
+    | BrokenAnchor of Element * Uref * anchor: TextNode
+    | NonPortableLink of Element * InternNameNode * portable: string * PortabilityIssue
     | NonBreakableWhitespace of Lsp.Range
+    | UnusedFootnote of Node<MdLinkDef>
+    | UnusedLinkDef of Node<MdLinkDef>
+    | MissingImage of Node<MdLink> * path: string
+    | UnknownCitation of Node<Citation>
+    | DuplicateHeading of Doc * Node<Heading> * others: array<Node<Heading>>
+    | DuplicateTitle of Doc * others: array<Doc>
+    | CircularEmbed of Node<WikiLink> * cycle: list<Doc>
+    | MissingTitle of Doc
+    | HeadingLevelJump of Node<Heading> * prevLevel: int
+    | UnconventionalTag of Node<Tag> * TagConvention
+    | OrphanNote of Doc
+    | MalformedLink of Lsp.Range * LinkSyntaxError
+    | DeadUrl of TextNode * reason: string
+    | FrontmatterViolation of Lsp.Range * FrontmatterError
+
+/// IDs of the rules producing diagnostics. They are reported in `Diagnostic.code` and are the keys
+/// of the `diagnostics.rules` config, which sets the severity of each rule or turns it off.
+module Rule =
+    let ambiguousLink = "ambiguous-link"
+    let brokenLink = "broken-link"
+    let brokenAnchor = "broken-anchor"
+    let nonPortableLink = "non-portable-link"
+    let nonBreakingWhitespace = "non-breaking-whitespace"
+    let unusedFootnote = "unused-footnote"
+    let unusedLinkDef = "unused-link-def"
+    let missingImage = "missing-image"
+    let unknownCitation = "unknown-citation"
+    let dupHeading = "dup-heading"
+    let dupTitle = "dup-title"
+    let circularEmbed = "circular-embed"
+    let missingTitle = "missing-title"
+    let headingJump = "heading-jump"
+    let tagName = "tag-name"
+    let orphan = "orphan"
+    let malformedLink = "malformed-link"
+    let deadUrl = "dead-url"
+    let frontmatter = "frontmatter"
+
+    let isOff (config: Config) (rule: string) =
+        config.DiagRuleSeverity(rule) = Some DiagSeverity.Off
+
+    /// Whether an opt-in rule is on, either explicitly or by configuring its severity.
+    let isOptedIn (config: Config) (rule: string) (enabled: bool) =
+        enabled
+        || (config.DiagRuleSeverity(rule)
+            |> Option.exists (fun severity -> severity <> DiagSeverity.Off))
 
 let code: Entry -> string =
     function
-    | AmbiguousLink _ -> "1"
-    | BrokenLink _ -> "2"
-    | NonBreakableWhitespace _ -> "3"
+    | AmbiguousLink _ -> Rule.ambiguousLink
+    | BrokenLink _ -> Rule.brokenLink
+    | NonBreakableWhitespace _ -> Rule.nonBreakingWhitespace
+    | UnusedFootnote _ -> Rule.unusedFootnote
+    | UnusedLinkDef _ -> Rule.unusedLinkDef
+    | MissingImage _ -> Rule.missingImage
+    | UnknownCitation _ -> Rule.unknownCitation
+    | BrokenAnchor _ -> Rule.brokenAnchor
+    | NonPortableLink _ -> Rule.nonPortableLink
+    | DuplicateHeading _ -> Rule.dupHeading
+    | DuplicateTitle _ -> Rule.dupTitle
+    | CircularEmbed _ -> Rule.circularEmbed
+    | MissingTitle _ -> Rule.missingTitle
+    | HeadingLevelJump _ -> Rule.headingJump
+    | UnconventionalTag _ -> Rule.tagName
+    | OrphanNote _ -> Rule.orphan
+    | MalformedLink _ -> Rule.malformedLink
+    | DeadUrl _ -> Rule.deadUrl
+    | FrontmatterViolation _ -> Rule.frontmatter
 
 let checkNonBreakingWhitespace (doc: Doc) =
     let nonBreakingWhitespace = "\u00a0"
 
     let headings = [ 1..7 ] |> List.map (fun n -> (String.replicate n "#"))
 
-    [ 0 .. (Doc.text doc).lineMap.NumLines ]
-    |> List.collect (fun x ->
-        let line = (Doc.text doc).LineContent x
+    // Most notes have no such whitespace at all, and then their lines aren't cut out one by one
+    if not ((Doc.text doc).content.Contains(nonBreakingWhitespace)) then
+        []
+    else
+        [ 0 .. (Doc.text doc).lineMap.NumLines ]
+        |> List.collect (fun x ->
+            let line = (Doc.text doc).LineContent x
+
+            let headingLike =
+                headings
+                |> List.tryFind (fun (h: string) -> line.StartsWith(h + nonBreakingWhitespace))
+
+            let isInCode () =
+                Doc.index doc |> Index.isInCode (Position.Mk(x, 0))
+
+            match headingLike with
+            | None -> []
+            | Some _ when isInCode () -> []
+            | Some heading ->
+                let whitespaceRange: Lsp.Range =
+                    { Start = { Line = x; Character = heading.Length }
+                      End = { Line = x; Character = heading.Length + 1 } }
+
+                [ NonBreakableWhitespace(whitespaceRange) ])
+
+let private emptyInlineTargetPat = Regex(@"(?<!\\)\]\(\s*\)")
+
+let private emptyCitationPat = Regex(@"(?<!\\)\[-?@(?=[\s;\]]|$)")
+
+/// Link syntax the parser gives up on, so that it ends up as plain text: unclosed wiki links,
+/// links with nothing to link to, and '#' or '@' with nothing following.
+let checkLinkSyntax (doc: Doc) : seq<Entry> =
+    let text = Doc.text doc
+    let isInCode line char = Doc.index doc |> Index.isInCode (Position.Mk(line, char))
+
+    // Only the lines with something looking like a link are cut out of the text to be checked
+    let mayHaveLinks (line: int) =
+        let start, end_ = text.LineContentOffsets(line)
+
+        let contains (value: string) =
+            text.content.IndexOf(value, start, end_ - start, System.StringComparison.Ordinal) >= 0
+
+        contains "[[" || contains "](" || contains "@"
+
+    let checkLine (line: int) =
+        let content = text.LineContent line
+        let lineStart, _ = text.LineContentOffsets(line)
+        let errors = ResizeArray()
+
+        // Chars of the line are counted in the position encoding by the line map
+        let add (start: int) (end_: int) error =
+            let range: Lsp.Range =
+                { Start = text.lineMap.FindPosition(lineStart + start)
+                  End = text.lineMap.FindPosition(lineStart + end_) }
 
-        let headingLike =
-            List.tryFind (fun (h: string) -> line.StartsWith(h + nonBreakingWhitespace)) headings
+            errors.Add(range, error)
 
-        match headingLike with
-        | None -> []
-        | Some heading ->
-            let whitespaceRange: Lsp.Range =
-                { Start = { Line = x; Character = heading.Length }
-                  End = { Line = x; Character = heading.Length + 1 } }
+        let mutable pos = content.IndexOf("[[")
 
-            [ NonBreakableWhitespace(whitespaceRange) ])
+        while pos >= 0 do
+            let next =
+                if pos > 0 && content[pos - 1] = '\\' then
+                    pos + 2
+                else
+                    let nextOpen = content.IndexOf("[[", pos + 2)
+
+                    match content.IndexOf("]]", pos + 2) with
+                    | close when close = -1 || (nextOpen >= 0 && nextOpen < close) ->
+                        // The link runs until the next one starts or until the end of the line
+                        let end_ = if nextOpen >= 0 then nextOpen else content.Length
+                        add pos (content.Substring(0, end_).TrimEnd().Length) UnclosedWikiLink
+                        end_
+                    | close ->
+                        let inner = content.Substring(pos + 2, close - pos - 2)
+
+                        let target =
+                            match inner.IndexOf('|') with
+                            | -1 -> inner
+                            | idx -> inner.Substring(0, idx)
+
+                        if target.Trim() = "" then
+                            add pos (close + 2) EmptyTarget
+                        elif target.TrimEnd().EndsWith('#') then
+                            let hash = pos + 2 + target.TrimEnd().Length - 1
+                            add hash (hash + 1) EmptyAnchor
+
+                        close + 2
+
+            pos <- if next >= content.Length then -1 else content.IndexOf("[[", next)
+
+        for m in emptyInlineTargetPat.Matches(content) do
+            add (m.Index + 1) (m.Index + m.Length) EmptyTarget
+
+        for m in emptyCitationPat.Matches(content) do
+            add (m.Index + m.Length - 1) (m.Index + m.Length) EmptyCitationKey
+
+        errors
+        |> Seq.filter (fun (range, _) -> not (isInCode line range.Start.Character))
+        |> Seq.sortBy (fun (range, _) -> range.Start.Character)
+        |> Seq.map MalformedLink
+        |> List.ofSeq
+
+    [ 0 .. text.lineMap.NumLines ]
+    |> Seq.filter mayHaveLinks
+    |> Seq.collect checkLine
 
 let isCrossFileLink uref =
     match uref with
@@ -50,6 +224,61 @@ let isCrossFileLink uref =
     | Uref.Heading(doc = None) -> false
     | Uref.LinkDef _ -> false
 
+// Leading components of a link that are kept as typed, e.g. `../` in `../notes/a.md`
+let private relativePrefixPat = Regex(@"^(\.{1,2}/|/)*")
+
+/// Spelling of the doc part of a link that works on any filesystem when the typed one doesn't:
+/// `\` separators are replaced with `/`, and a link matching a doc only when ignoring case gets
+/// the case of the doc's path.
+let tryPortableName
+    (folder: Folder)
+    (srcDoc: Doc)
+    (name: InternNameNode)
+    : option<string * PortabilityIssue> =
+    let typed = name.text
+
+    if typed.Contains('\\') then
+        Some(typed.Replace('\\', '/'), BackslashSeparator)
+    else
+        let resolved =
+            InternName.tryResolveToRootPath (Folder.rootPath folder) (Doc.path srcDoc) typed
+
+        let prefix = relativePrefixPat.Match(typed).Value
+        let tail = typed.Substring(prefix.Length)
+        let ignoreCase = System.StringComparison.OrdinalIgnoreCase
+        let sameIgnoringCase (a: string) (b: string) = a.Equals(b, ignoreCase)
+
+        // The typed prefix is kept, the rest takes the case of the matching path
+        let respell (target: string) =
+            if target.Length >= tail.Length && target.EndsWith(tail, ignoreCase) then
+                Some(prefix + target.Substring(target.Length - tail.Length))
+            else
+                None
+
+        let tryMatch (doc: Doc) =
+            let path = (Doc.pathFromRoot doc).Replace('\\', '/')
+            let pathNoExt = Path.ChangeExtension(path, null)
+
+            let byPath =
+                resolved
+                |> Option.bind (fun resolved ->
+                    [ path; pathNoExt ] |> List.tryFind (sameIgnoringCase resolved))
+
+            // Links by file name resolve anywhere in the folder
+            let byName () =
+                if tail.Contains('/') then
+                    None
+                else
+                    [ Path.GetFileName(path); Path.GetFileNameWithoutExtension(path) ]
+                    |> List.tryFind (sameIgnoringCase tail)
+
+            byPath |> Option.orElseWith byName |> Option.bind respell
+
+        Folder.docs folder
+        |> Seq.tryPick tryMatch
+        |> Option.filter (fun portable -> portable <> typed)
+        |> Option.map (fun portable -> portable, CaseMismatch)
+
 let checkLink (folder: Folder) (doc: Doc) (link: Element) : seq<Entry> =
     let configuredExts =
         (Folder.configOrDefault folder).CoreMarkdownFileExtensions()
@@ -61,12 +290,51 @@ let checkLink (folder: Folder) (doc: Doc) (link: Element) : seq<Entry> =
     | Some uref ->
         let refs = Dest.tryResolveUref uref doc folder |> Array.ofSeq
 
+        // The linked doc is there, only the heading or block in it isn't
+        let brokenAnchor =
+            lazy
+                (match uref with
+                 | Uref.Heading (None, anchor) -> Some anchor
+                 | Uref.Heading (Some docName, anchor) ->
+                     let docRefs = Dest.tryResolveUref (Uref.Doc docName) doc folder
+
+                     if Seq.isEmpty docRefs then None else Some anchor
+                 | Uref.Doc _
+                 | Uref.LinkDef _ -> None)
+
+        let docName =
+            match uref with
+            | Uref.Doc name
+            | Uref.Heading (Some name, _) -> Some name
+            | Uref.Heading (None, _)
+            | Uref.LinkDef _ -> None
+
+        let nonPortable =
+            lazy
+                (docName
+                 |> Option.bind (fun name ->
+                     tryPortableName folder doc name
+                     |> Option.map (fun (portable, issue) ->
+                         NonPortableLink(link, name, portable, issue))))
+
+        // Links with '\\' resolve on Windows only, so they are reported whether they resolve or not
+        let hasBackslash =
+            docName |> Option.exists (fun name -> name.text.Contains('\\'))
+
         if Folder.isSingleFile folder && isCrossFileLink uref then
             []
+        else if hasBackslash then
+            Option.toList nonPortable.Value
         else if refs.Length = 1 then
             []
+        else if refs.Length = 0 && nonPortable.Value.IsSome then
+            Option.toList nonPortable.Value
+        else if refs.Length = 0 && brokenAnchor.Value.IsSome then
+            [ BrokenAnchor(link, uref, Option.get brokenAnchor.Value) ]
         else if refs.Length = 0 then
             match link with
+            // Footnote references look like shortcut links but are never a part of regular text.
+            | ML { data = MdLink.RS _ as ml } when MdLink.isFootnote ml -> [ BrokenLink(link, uref) ]
             // Inline shortcut links often are a part of regular text.
             // Raising diagnostics on them would be noisy.
             | ML { data = MdLink.RS _ } -> []
@@ -88,47 +356,580 @@ let checkLinks (folder: Folder) (doc: Doc) : seq<Entry> =
     let links = Doc.index >> Index.links <| doc
     links |> Seq.collect (checkLink folder doc)
 
-let checkFolder (folder: Folder) : seq<PathUri * list<Entry>> =
+/// Footnotes and link definitions that no link of the doc references.
+let checkLinkDefs (doc: Doc) : seq<Entry> =
+    let index = Doc.index doc
+
+    let referencedLabels =
+        Index.mdLinks index
+        |> Seq.choose (fun { data = ml } -> MdLink.referenceLabel ml)
+        |> Seq.map (Node.text >> LinkLabel.ofString)
+        |> Set.ofSeq
+
+    Index.linkDefs index
+    |> Seq.filter (fun { data = ld } ->
+        not (Set.contains (MdLinkDef.normalizedLabel ld) referencedLabels))
+    |> Seq.map (fun ld ->
+        if MdLinkDef.isFootnote ld.data then
+            UnusedFootnote ld
+        else
+            UnusedLinkDef ld)
+
+let checkImages (folder: Folder) (doc: Doc) : seq<Entry> =
+    Doc.index doc
+    |> Index.mdLinks
+    |> Seq.filter MdLink.isImage
+    |> Seq.collect (fun link ->
+        match link.data with
+        | MdLink.IL (_, Some url, _) ->
+            match (Url.ofUrlNode url).url with
+            // Without a workspace root there's nothing for root-relative paths to resolve against
+            | Some path when Folder.isSingleFile folder && path.text.StartsWith('/') -> []
+            | Some path ->
+                match Assets.tryResolve folder doc path.text with
+                | Some absPath when not (File.Exists(absPath)) -> [ MissingImage(link, path.text) ]
+                | _ -> []
+            | None -> []
+        | _ -> [])
+
+let checkCitations (bibKeys: Set<string>) (doc: Doc) : seq<Entry> =
+    Doc.index doc
+    |> Index.citations
+    |> Seq.filter (fun { data = citation } -> not (bibKeys.Contains(Citation.key citation)))
+    |> Seq.map UnknownCitation
+
+/// Headings sharing a slug with another heading of the doc, which makes links to them ambiguous.
+let checkHeadings (doc: Doc) : seq<Entry> =
+    Doc.index doc
+    |> Index.headings
+    |> Array.groupBy (fun { data = heading } -> Heading.slug heading)
+    |> Seq.collect (fun (_, headings) ->
+        if headings.Length < 2 then
+            []
+        else
+            [ for heading in headings do
+                  let others = headings |> Array.filter (fun other -> other <> heading)
+                  yield DuplicateHeading(doc, heading, others) ])
+
+/// Docs of the folder by the other docs sharing their name, i.e. the title or the file name when
+/// there's no title. Links by name can't tell such docs apart.
+let findDuplicateTitles (folder: Folder) : Map<PathUri, array<Doc>> =
+    Folder.docs folder
+    |> Seq.groupBy Doc.slug
+    |> Seq.collect (fun (_, docs) ->
+        let docs = Array.ofSeq docs
+
+        if docs.Length < 2 then
+            []
+        else
+            [ for doc in docs do
+                  Doc.path doc, docs |> Array.filter (fun other -> other <> doc) ])
+    |> Map.ofSeq
+
+/// Embeds that end up embedding themselves, e.g. when A embeds B that embeds A, by the path of
+/// their doc, along with the docs of the cycle. Only the embeds within the embedded part of a doc,
+/// e.g. the section of a heading, are followed.
+let findEmbedCycles (folder: Folder) : Map<PathUri, list<Node<WikiLink> * list<Doc>>> =
+    // Ambiguous embeds follow the first of their destinations
+    let embedsIn (doc: Doc) (scope: Range) =
+        Index.wikiLinks (Doc.index doc)
+        |> Seq.filter (fun wl -> wl.data.embed && scope.ContainsInclusive(wl.range.Start))
+        |> Seq.choose (fun wl ->
+            Dest.tryResolveElement folder doc (WL wl)
+            |> Seq.tryHead
+            |> Option.map (fun dest -> wl, dest))
+        |> List.ofSeq
+
+    let tryFindCycle (srcDoc: Doc) (embed: Node<WikiLink>) (dest: Dest) =
+        let visited = System.Collections.Generic.HashSet<string * Range>()
+
+        // `path` has the docs embedding `dest` so far, the last one first
+        let rec go (dest: Dest) (path: list<Doc>) =
+            let doc = Dest.doc dest
+            let path = doc :: path
+            let scope = Dest.scope dest
+
+            if Doc.path doc = Doc.path srcDoc && scope.ContainsInclusive(embed.range.Start) then
+                Some(List.rev path)
+            else
+                embedsIn doc scope
+                |> List.tryPick (fun (wl, next) ->
+                    if visited.Add((Doc.pathFromRoot doc, wl.range)) then
+                        go next path
+                    else
+                        None)
+
+        go dest [ srcDoc ]
+
+    Folder.docs folder
+    |> Seq.choose (fun doc ->
+        let cycles =
+            embedsIn doc ((Doc.text doc).FullRange())
+            |> List.choose (fun (wl, dest) ->
+                tryFindCycle doc wl dest |> Option.map (fun cycle -> wl, cycle))
+
+        if cycles.IsEmpty then None else Some(Doc.path doc, cycles))
+    |> Map.ofSeq
+
+/// Style checks of the headings, both opt-in: a note should have a title, since it's the identity
+/// of the note, and heading levels shouldn't skip a level, e.g. from H1 to H3.
+let checkHeadingStyle (config: Config) (doc: Doc) : seq<Entry> =
+    let headings = Doc.index doc |> Index.headings
+
+    let missingTitle =
+        if Rule.isOptedIn config Rule.missingTitle (config.DiagMissingTitleEnable())
+           && Option.isNone (Doc.title doc) then
+            [ MissingTitle doc ]
+        else
+            []
+
+    let jumps =
+        if Rule.isOptedIn config Rule.headingJump (config.DiagHeadingJumpEnable()) then
+            headings
+            |> Seq.pairwise
+            |> Seq.filter (fun (prev, heading) -> heading.data.level > prev.data.level + 1)
+            |> Seq.map (fun (prev, heading) -> HeadingLevelJump(heading, prev.data.level))
+            |> List.ofSeq
+        else
+            []
+
+    missingTitle @ jumps
+
+module TagName =
+    let private kebabCasePat = Regex(@"^[\p{Ll}\p{Lo}\d]+(-[\p{Ll}\p{Lo}\d]+)*$")
+    let private snakeCasePat = Regex(@"^[\p{Ll}\p{Lo}\d]+(_[\p{Ll}\p{Lo}\d]+)*$")
+    let private camelCasePat = Regex(@"^[\p{Ll}\p{Lo}\d]+(\p{Lu}[\p{Ll}\p{Lo}\d]*)*$")
+
+    // Words of a tag in any of the presets, e.g. `myTag`, `my-tag` and `My_Tag` are "my" and "tag"
+    let private words (name: string) =
+        Regex.Replace(name, @"(?<=[\p{Ll}\d])(?=\p{Lu})", "-").Split('-', '_')
+        |> Array.filter (fun word -> word <> "")
+        |> Array.map (fun word -> word.ToLowerInvariant())
+
+    let isConventional (convention: TagConvention) (name: string) : bool =
+        match convention with
+        | TagConvention.Any -> true
+        | TagConvention.KebabCase -> kebabCasePat.IsMatch(name)
+        | TagConvention.SnakeCase -> snakeCasePat.IsMatch(name)
+        | TagConvention.CamelCase -> camelCasePat.IsMatch(name)
+        | TagConvention.Pattern pattern -> Regex.IsMatch(name, $"^(?:{pattern})$")
+
+    /// The tag `name` respelled to follow a preset convention. Patterns can't be followed
+    /// automatically.
+    let tryRespell (convention: TagConvention) (name: string) : option<string> =
+        let words = words name
+
+        let capitalize (word: string) =
+            string (System.Char.ToUpperInvariant(word[0])) + word.Substring(1)
+
+        let respelled =
+            match convention with
+            | TagConvention.KebabCase -> Some(String.concat "-" words)
+            | TagConvention.SnakeCase -> Some(String.concat "_" words)
+            | TagConvention.CamelCase ->
+                words
+                |> Array.mapi (fun idx word -> if idx = 0 then word else capitalize word)
+                |> String.concat ""
+                |> Some
+            | TagConvention.Any
+            | TagConvention.Pattern _ -> None
+
+        respelled |> Option.filter (fun respelled -> respelled <> "" && respelled <> name)
+
+    let fmtConvention (convention: TagConvention) : string =
+        match convention with
+        | TagConvention.Any -> "any"
+        | TagConvention.KebabCase -> "kebab-case"
+        | TagConvention.SnakeCase -> "snake_case"
+        | TagConvention.CamelCase -> "camelCase"
+        | TagConvention.Pattern pattern -> $"'{pattern}'"
+
+/// Tags whose names don't follow the naming convention configured for the workspace.
+let checkTags (config: Config) (doc: Doc) : seq<Entry> =
+    let convention = config.DiagTagConvention()
+
+    Doc.index doc
+    |> Index.tags
+    |> Seq.filter (fun tag -> not (TagName.isConventional convention tag.data.name.text))
+    |> Seq.map (fun tag -> UnconventionalTag(tag, convention))
+
+/// Paths of docs that no other doc of the folder links to, or links into.
+// The other docs the links of `srcDoc` resolve to, by their path from the root
+let private linkedFrom (folder: Folder) (srcDoc: Doc) : Set<string> =
+    Dest.resolveLinks folder srcDoc
+    |> Map.values
+    |> Seq.collect (Array.map Dest.doc)
+    |> Seq.filter (fun doc -> doc <> srcDoc)
+    |> Seq.map Doc.pathFromRoot
+    |> Set.ofSeq
+
+let private orphansOf (folder: Folder) (linked: seq<Set<string>>) : Set<string> =
+    let linked = Set.unionMany linked
+
+    Folder.docs folder
+    |> Seq.map Doc.pathFromRoot
+    |> Seq.filter (fun path -> not (linked.Contains(path)))
+    |> Set.ofSeq
+
+let findOrphans (folder: Folder) : Set<string> =
+    Folder.docs folder |> Seq.map (linkedFrom folder) |> orphansOf folder
+
+/// External URLs of inline links and link definitions known by `checker` to be dead. URLs that
+/// weren't checked recently are queued for a check and reported once it completes.
+let checkUrls (checker: UrlCheck.Checker) (ttl: System.TimeSpan) (doc: Doc) : seq<Entry> =
+    let index = Doc.index doc
+
+    let inlineUrls =
+        Index.mdLinks index
+        |> Seq.choose (fun link ->
+            match link.data with
+            | MdLink.IL (_, Some url, _) -> (Url.ofUrlNode url).url
+            | _ -> None)
+
+    let defUrls =
+        Index.linkDefs index
+        |> Seq.filter (fun { data = ld } -> not (MdLinkDef.isFootnote ld))
+        |> Seq.map (fun { data = ld } -> MdLinkDef.url ld)
+
+    Seq.append inlineUrls defUrls
+    |> Seq.filter (fun url -> UrlCheck.isExternal url.text)
+    |> Seq.choose (fun url ->
+        match checker.TryStatus(ttl, url.text) with
+        | Some (UrlCheck.Dead reason) -> Some(DeadUrl(url, reason))
+        | Some UrlCheck.Alive
+        | None -> None)
+
+// ISO dates, optionally followed by a time, e.g. `2020-05-01` or `2020-05-01T10:00:00`
+let private isDate (text: string) =
+    let isDateOnly (date: string) =
+        System.DateTime.TryParseExact(
+            date,
+            "yyyy-MM-dd",
+            CultureInfo.InvariantCulture,
+            DateTimeStyles.None
+        )
+        |> fst
+
+    text.Length >= 10
+    && (text.Length = 10 || text[10] = 'T' || text[10] = ' ')
+    && isDateOnly (text.Substring(0, 10))
+
+// Quoted numbers and booleans are strings in YAML; quoted dates are common enough to accept them
+let private scalarConforms (fieldType: FieldType) (scalar: Frontmatter.Scalar) =
+    match fieldType with
+    | FieldType.String -> true
+    | FieldType.Number ->
+        let isNumber, _ =
+            System.Double.TryParse(scalar.text, NumberStyles.Float, CultureInfo.InvariantCulture)
+
+        not scalar.quoted && isNumber
+    | FieldType.Bool ->
+        not scalar.quoted
+        && (scalar.text.ToLower() = "true" || scalar.text.ToLower() = "false")
+    | FieldType.Date -> isDate scalar.text
+    | FieldType.List _ -> false
+
+/// Frontmatter fields that are required by the schema configured for the doc but missing, or
+/// whose values don't have the type the schema expects. Fields without a value count as missing.
+let checkFrontmatter (config: Config) (doc: Doc) : seq<Entry> =
+    let schema = config.DiagFrontmatterSchema(Doc.pathFromRoot doc)
+    let yml = (Doc.index doc).yamlFrontMatter
+    let fields = yml |> Option.map Frontmatter.fields |> Option.defaultValue [||]
+
+    // Missing fields are reported on the opening fence of the frontmatter or on the title
+    let missingRange =
+        match yml with
+        | Some yml -> Range.Mk(yml.range.Start.Line, 0, yml.range.Start.Line, 3)
+        | None ->
+            Doc.title doc
+            |> Option.map (fun title -> title.range)
+            |> Option.defaultValue (Range.Mk(0, 0, 0, 0))
+
+    let missing =
+        schema.required
+        |> Option.defaultValue [||]
+        |> Seq.filter (fun key ->
+            match Frontmatter.tryFind key fields |> Option.map (fun field -> field.value) with
+            | None
+            | Some Frontmatter.Null -> true
+            | Some _ -> false)
+        |> Seq.map (fun key -> FrontmatterViolation(missingRange, MissingField key))
+
+    let checkField (field: Frontmatter.Field) =
+        match Map.tryFind field.key schema.types, field.value with
+        | None, _
+        | _, Frontmatter.Null -> []
+        | Some (FieldType.List elType), Frontmatter.Items items ->
+            items
+            |> List.filter (scalarConforms elType >> not)
+            |> List.map (fun item ->
+                FrontmatterViolation(item.range, WrongItemType(field.key, elType)))
+        | Some fieldType, Frontmatter.Scalar scalar when scalarConforms fieldType scalar -> []
+        | Some fieldType, _ ->
+            [ FrontmatterViolation(field.range, WrongType(field.key, fieldType)) ]
+
+    Seq.append missing (Seq.collect checkField fields)
+
+/// Entries of the docs of a folder, kept to update them incrementally as the folder changes.
+type FolderCheck =
+    private
+        { folder: Folder
+          docEntries: Facts.Memo<list<Entry>>
+          linked: option<Facts.Memo<Set<string>>>
+          embedCycles: Map<PathUri, list<Node<WikiLink> * list<Doc>>> }
+
+// Entries that depend only on the doc itself and on how its links resolve
+let private checkDoc (folder: Folder) (config: Config) (doc: Doc) : list<Entry> =
     seq {
-        for doc in Folder.docs folder do
-            let docDiag =
-                seq {
-                    yield! checkLinks folder doc
-                    yield! checkNonBreakingWhitespace doc
-                }
-                |> List.ofSeq
-
-            Doc.path doc, docDiag
+        yield! checkLinks folder doc
+        yield! checkNonBreakingWhitespace doc
+        yield! checkHeadings doc
+        yield! checkHeadingStyle config doc
+        yield! checkTags config doc
+        yield! checkLinkSyntax doc
+        yield! checkFrontmatter config doc
+        yield! checkLinkDefs doc
     }
+    |> List.ofSeq
+
+// Links of the doc to other docs as typed, which is what embed cycles depend on
+let private outgoingLinks (exts: array<string>) (doc: Doc) =
+    Index.links (Doc.index doc)
+    |> Seq.choose (Uref.ofElement exts)
+    |> Seq.choose (function
+        | Uref.Doc name -> Some name.text
+        | Uref.Heading (Some name, heading) -> Some $"{name.text}#{heading.text}"
+        | Uref.Heading (None, _)
+        | Uref.LinkDef _ -> None)
+    |> Set.ofSeq
+
+// Related information of ambiguous links points into other docs, which can move without changing
+// how links resolve
+let private isReusable (entries: list<Entry>) =
+    entries
+    |> List.forall (function
+        | AmbiguousLink _ -> false
+        | _ -> true)
+
+/// Entries of the docs of `folder`. Entries of the docs that are the same as in `prev` are reused,
+/// unless a doc they link to changed what links resolve against, see `Facts.invalidated`. Edits of
+/// a doc that leave its name, headings and block anchors as they were re-check only that doc. The
+/// check stops with `OperationCanceledException` between docs once `cancel` is requested. URLs are
+/// checked only with a `urlChecker`.
+let checkFolderIncremental
+    (urlChecker: option<UrlCheck.Checker>)
+    (cancel: CancellationToken)
+    (prev: option<FolderCheck>)
+    (folder: Folder)
+    : FolderCheck * seq<PathUri * list<Entry>> =
+    // Without a bibliography there is nothing to check citations against
+    let bibKeys =
+        if Bibliography.isConfigured folder then
+            Bibliography.entries folder
+            |> Seq.map (fun entry -> entry.key)
+            |> Set.ofSeq
+            |> Some
+        else
+            None
+
+    let exts = (Folder.configOrDefault folder).CoreMarkdownFileExtensions()
+
+    // Settings of nested directories make the docs in them differ in what's checked
+    let docConfigs =
+        Folder.docs folder
+        |> Seq.map (fun doc -> Doc.path doc, Folder.configFor doc folder)
+        |> Map.ofSeq
+
+    // Orphans are opt-in: finding them resolves every link of the folder. A lone note is always
+    // an orphan, so they aren't reported in single-file mode
+    let orphanEnabledFor (doc: Doc) =
+        let config = docConfigs[Doc.path doc]
+        Rule.isOptedIn config Rule.orphan (config.DiagOrphanEnable())
+
+    let orphansEnabled =
+        not (Folder.isSingleFile folder) && Folder.docs folder |> Seq.exists orphanEnabledFor
+
+    let urlCheckTtl (config: Config) =
+        match urlChecker with
+        | Some checker when config.DiagUrlCheckEnable() ->
+            Some(checker, System.TimeSpan.FromMinutes(float (config.DiagUrlCheckTtlMinutes())))
+        | _ -> None
+
+    // Nothing is reused when the config changes
+    let prev =
+        prev
+        |> Option.filter (fun prev ->
+            Folder.config prev.folder = Folder.config folder
+            && Folder.dirConfigs prev.folder = Folder.dirConfigs folder
+            && Folder.isSingleFile prev.folder = Folder.isSingleFile folder)
+
+    let changes =
+        prev |> Option.map (fun prev -> Facts.changes prev.folder folder) |> Option.defaultValue []
+
+    let linksChanged =
+        changes
+        |> List.exists (function
+            | Some prevDoc, Some doc ->
+                Facts.linkTargets prevDoc <> Facts.linkTargets doc
+                || outgoingLinks exts prevDoc <> outgoingLinks exts doc
+            | _ -> true)
+
+    let docEntries =
+        Facts.Memo.update
+            isReusable
+            (fun doc ->
+                cancel.ThrowIfCancellationRequested()
+                checkDoc folder docConfigs[Doc.path doc] doc)
+            (prev |> Option.map (fun prev -> prev.docEntries))
+            folder
+
+    let linked =
+        if orphansEnabled then
+            Facts.Memo.update
+                (fun _ -> true)
+                (fun doc ->
+                    cancel.ThrowIfCancellationRequested()
+                    linkedFrom folder doc)
+                (prev |> Option.bind (fun prev -> prev.linked))
+                folder
+            |> Some
+        else
+            None
+
+    let orphans =
+        linked
+        |> Option.map (Facts.Memo.facts >> orphansOf folder)
+        |> Option.defaultValue Set.empty
+
+    // Moving an embed in or out of a section changes the cycles without changing any link
+    let hasEmbeds (doc: Doc) =
+        Index.wikiLinks (Doc.index doc) |> Array.exists (fun wl -> wl.data.embed)
+
+    let embedsChanged =
+        linksChanged
+        || changes
+           |> List.exists (fun (prevDoc, doc) ->
+               Option.exists hasEmbeds prevDoc || Option.exists hasEmbeds doc)
+
+    let embedCycles =
+        match prev with
+        | Some prev when not embedsChanged -> prev.embedCycles
+        | _ ->
+            cancel.ThrowIfCancellationRequested()
+            findEmbedCycles folder
+
+    let duplicateTitles = findDuplicateTitles folder
+
+    // Titles, images, citations and URLs are cheap to check, and depend on things that change
+    // without the doc changing, e.g. the bibliography files
+    let entries =
+        seq {
+            for doc in docs do
+                let config = docConfigs[Doc.path doc]
+
+                let docDiag =
+                    seq {
+                        yield! Facts.Memo.find doc docEntries
+
+                        match Map.tryFind (Doc.path doc) duplicateTitles with
+                        | Some others -> yield DuplicateTitle(doc, others)
+                        | None -> ()
+
+                        if orphans.Contains(Doc.pathFromRoot doc) && orphanEnabledFor doc then
+                            yield OrphanNote doc
+
+                        match Map.tryFind (Doc.path doc) embedCycles with
+                        | Some cycles ->
+                            for embed, cycle in cycles do
+                                yield CircularEmbed(embed, cycle)
+                        | None -> ()
+
+                        yield! checkImages folder doc
+
+                        match bibKeys with
+                        | Some bibKeys -> yield! checkCitations bibKeys doc
+                        | None -> ()
+
+                        match urlCheckTtl config with
+                        | Some (checker, ttl) -> yield! checkUrls checker ttl doc
+                        | None -> ()
+                    }
+                    |> Seq.filter (code >> Rule.isOff config >> not)
+                    |> List.ofSeq
+
+                Doc.path doc, docDiag
+        }
+        |> Array.ofSeq
+
+    let check =
+        { folder = folder
+          docEntries = docEntries
+          linked = linked
+          embedCycles = embedCycles }
+
+    check, Seq.ofArray entries
+
+let checkFolder (folder: Folder) : seq<PathUri * list<Entry>> =
+    checkFolderIncremental None CancellationToken.None None folder |> snd
 
 let refToHuman (ref: Dest) : string =
     match ref with
     | Dest.Doc { dest = doc } -> $"document {Doc.name doc}"
     | Dest.Heading (docLink, { data = heading }) ->
         $"heading {Heading.name heading} in the document {Doc.name (DocLink.doc docLink)}"
+    | Dest.LinkDef (_, { data = ld }) when MdLinkDef.isFootnote ld -> $"footnote {MdLinkDef.name ld}"
     | Dest.LinkDef (_, { data = ld }) -> $"link definition {MdLinkDef.name ld}"
+    | Dest.Block (docLink, { data = block }) ->
+        $"block ^{BlockAnchor.id block} in the document {Doc.name (DocLink.doc docLink)}"
 
 let docRefToHuman (InternName name) : string = $"document '{name}'"
 
 let urefToHuman (uref: Uref) : string =
     match uref with
     | Uref.Doc { data = name } -> docRefToHuman name
+    | Uref.Heading (docLink, heading) when heading.text.StartsWith('^') ->
+        match docLink with
+        | None -> $"block '{Node.text heading}'"
+        | Some { data = name } -> $"block '{Node.text heading}' in {docRefToHuman name}"
     | Uref.Heading (docLink, heading) ->
         match docLink with
         | None -> $"heading '{Node.text heading}'"
         | Some { data = name } -> $"heading '{Node.text heading}' in {docRefToHuman name}"
+    | Uref.LinkDef ld when (Node.text ld).StartsWith('^') -> $"footnote '{Node.text ld}'"
     | Uref.LinkDef ld -> $"link definition with the label '{Node.text ld}'"
 
+let private brokenLinkSeverity (el: Element) : Lsp.DiagnosticSeverity =
+    match el with
+    | WL _ -> Lsp.DiagnosticSeverity.Error
+    | ML _ -> Lsp.DiagnosticSeverity.Warning
+    | H _
+    | MLD _
+    | T _
+    | BA _
+    | C _
+    | TK _
+    | TB _
+    | CT _
+    | CO _
+    | YML _ -> Lsp.DiagnosticSeverity.Information
+
 let diagToLsp (diag: Entry) : Lsp.Diagnostic =
     match diag with
     | AmbiguousLink (el, uref, refs) ->
+        // Ambiguous links still resolve, so they never are errors
         let severity =
             match el with
-            | WL _ -> Lsp.DiagnosticSeverity.Error
+            | WL _
             | ML _ -> Lsp.DiagnosticSeverity.Warning
             | H _
             | MLD _
             | T _
+            | BA _
+            | C _
+            | TK _
+            | TB _
+            | CT _
+            | CO _
             | YML _ -> Lsp.DiagnosticSeverity.Information
 
         let mkRelated ref : DiagnosticRelatedInformation =
@@ -136,7 +937,10 @@ let diagToLsp (diag: Entry) : Lsp.Diagnostic =
             let msg = $"Duplicate definition of {refToHuman ref}"
             { Location = loc; Message = msg }
 
-        let related = refs |> Array.map mkRelated
+        let related =
+            refs
+            |> Array.sortBy (fun ref -> Dest.uri ref, (Dest.range ref).Start.Line)
+            |> Array.map mkRelated
 
         { Range = Element.range el
           Severity = Some severity
@@ -148,19 +952,39 @@ let diagToLsp (diag: Entry) : Lsp.Diagnostic =
           Tags = None
           Data = None }
     | BrokenLink (el, uref) ->
-        let severity =
-            match el with
-            | WL _ -> Lsp.DiagnosticSeverity.Error
-            | ML _ -> Lsp.DiagnosticSeverity.Warning
-            | H _
-            | MLD _
-            | T _
-            | YML _ -> Lsp.DiagnosticSeverity.Information
-
         let msg = $"Link to non-existent {urefToHuman uref}"
 
         { Range = Element.range el
-          Severity = Some severity
+          Severity = Some(brokenLinkSeverity el)
+          Code = Some(code diag)
+          CodeDescription = None
+          Source = Some "Marksman"
+          Message = msg
+          RelatedInformation = None
+          Tags = None
+          Data = None }
+    | BrokenAnchor (el, uref, anchor) ->
+        // Only the anchor is highlighted, the doc part of the link is fine
+        { Range = anchor.range
+          Severity = Some(brokenLinkSeverity el)
+          Code = Some(code diag)
+          CodeDescription = None
+          Source = Some "Marksman"
+          Message = $"Link to non-existent {urefToHuman uref}"
+          RelatedInformation = None
+          Tags = None
+          Data = None }
+    | NonPortableLink (_, name, portable, issue) ->
+        let msg =
+            match issue with
+            | CaseMismatch ->
+                $"Link to '{name.text}' matches '{portable}' only when ignoring case, "
+                + "which breaks on case-sensitive filesystems"
+            | BackslashSeparator ->
+                $"Link to '{name.text}' uses '\\' as a path separator, which works only on Windows"
+
+        { Range = name.range
+          Severity = Some Lsp.DiagnosticSeverity.Warning
           Code = Some(code diag)
           CodeDescription = None
           Source = Some "Marksman"
@@ -180,18 +1004,246 @@ let diagToLsp (diag: Entry) : Lsp.Diagnostic =
           RelatedInformation = None
           Tags = None
           Data = None }
+    | UnusedFootnote fn ->
+        { Range = fn.range
+          Severity = Some Lsp.DiagnosticSeverity.Hint
+          Code = Some(code diag)
+          CodeDescription = None
+          Source = Some "Marksman"
+          Message = $"Footnote '{MdLinkDef.name fn.data}' is never referenced"
+          RelatedInformation = None
+          Tags = Some [| DiagnosticTag.Unnecessary |]
+          Data = None }
+    | UnusedLinkDef ld ->
+        { Range = ld.range
+          Severity = Some Lsp.DiagnosticSeverity.Warning
+          Code = Some(code diag)
+          CodeDescription = None
+          Source = Some "Marksman"
+          Message = $"Link definition '{MdLinkDef.name ld.data}' is never referenced"
+          RelatedInformation = None
+          Tags = Some [| DiagnosticTag.Unnecessary |]
+          Data = None }
+    | MissingImage (link, path) ->
+        { Range = link.range
+          Severity = Some Lsp.DiagnosticSeverity.Warning
+          Code = Some(code diag)
+          CodeDescription = None
+          Source = Some "Marksman"
+          Message = $"Image file '{path}' doesn't exist"
+          RelatedInformation = None
+          Tags = None
+          Data = None }
+    | UnknownCitation citation ->
+        { Range = citation.range
+          Severity = Some Lsp.DiagnosticSeverity.Warning
+          Code = Some(code diag)
+          CodeDescription = None
+          Source = Some "Marksman"
+          Message = $"Citation key '{Citation.key citation.data}' is not in the bibliography"
+          RelatedInformation = None
+          Tags = None
+          Data = None }
+    | DeadUrl (url, reason) ->
+        { Range = url.range
+          Severity = Some Lsp.DiagnosticSeverity.Warning
+          Code = Some(code diag)
+          CodeDescription = None
+          Source = Some "Marksman"
+          Message = $"Link to '{url.text}' is dead: {reason}"
+          RelatedInformation = None
+          Tags = None
+          Data = None }
+    | MalformedLink (range, error) ->
+        let msg =
+            match error with
+            | UnclosedWikiLink -> "Wiki link is never closed with ']]'"
+            | EmptyTarget -> "Link has no target"
+            | EmptyAnchor -> "Nothing follows '#' in the link"
+            | EmptyCitationKey -> "Nothing follows '@' in the citation"
+
+        { Range = range
+          Severity = Some Lsp.DiagnosticSeverity.Warning
+          Code = Some(code diag)
+          CodeDescription = None
+          Source = Some "Marksman"
+          Message = msg
+          RelatedInformation = None
+          Tags = None
+          Data = None }
+    | FrontmatterViolation (range, error) ->
+        let msg =
+            match error with
+            | MissingField field -> $"Missing required frontmatter field '{field}'"
+            | WrongType (field, expected) ->
+                $"Frontmatter field '{field}' should be {FieldType.fmt expected}"
+            | WrongItemType (field, expected) ->
+                $"Items of frontmatter field '{field}' should be {FieldType.fmt expected}"
+
+        { Range = range
+          Severity = Some Lsp.DiagnosticSeverity.Warning
+          Code = Some(code diag)
+          CodeDescription = None
+          Source = Some "Marksman"
+          Message = msg
+          RelatedInformation = None
+          Tags = None
+          Data = None }
+    | OrphanNote doc ->
+        let range =
+            Doc.title doc
+            |> Option.map (fun title -> title.range)
+            |> Option.defaultValue (Range.Mk(0, 0, 0, 0))
+
+        { Range = range
+          Severity = Some Lsp.DiagnosticSeverity.Hint
+          Code = Some(code diag)
+          CodeDescription = None
+          Source = Some "Marksman"
+          Message = $"No other note links to {Doc.name doc}"
+          RelatedInformation = None
+          Tags = None
+          Data = None }
+    | DuplicateHeading (doc, heading, others) ->
+        let name = Heading.name heading.data
+
+        let mkRelated (other: Node<Heading>) : DiagnosticRelatedInformation =
+            { Location = { Uri = Doc.uri doc; Range = other.data.title.range }
+              Message = $"Another heading '{Heading.name other.data}'" }
+
+        { Range = heading.data.title.range
+          Severity = Some Lsp.DiagnosticSeverity.Warning
+          Code = Some(code diag)
+          CodeDescription = None
+          Source = Some "Marksman"
+          Message = $"Duplicate heading '{name}' makes links to it ambiguous"
+          RelatedInformation = Some(Array.map mkRelated others)
+          Tags = None
+          Data = None }
+    | CircularEmbed (embed, cycle) ->
+        let cycle = cycle |> List.map Doc.name |> String.concat " → "
+
+        { Range = embed.range
+          Severity = Some Lsp.DiagnosticSeverity.Error
+          Code = Some(code diag)
+          CodeDescription = None
+          Source = Some "Marksman"
+          Message = $"Circular embed: {cycle}"
+          RelatedInformation = None
+          Tags = None
+          Data = None }
+    | UnconventionalTag (tag, convention) ->
+        let name = tag.data.name.text
+        let expected = TagName.fmtConvention convention
+
+        let msg =
+            match convention with
+            | TagConvention.Pattern _ -> $"Tag '#{name}' doesn't match the pattern {expected}"
+            | _ -> $"Tag '#{name}' isn't {expected}"
+
+        { Range = tag.range
+          Severity = Some Lsp.DiagnosticSeverity.Warning
+          Code = Some(code diag)
+          CodeDescription = None
+          Source = Some "Marksman"
+          Message = msg
+          RelatedInformation = None
+          Tags = None
+          Data = None }
+    | MissingTitle doc ->
+        { Range = Range.Mk(0, 0, 0, 0)
+          Severity = Some Lsp.DiagnosticSeverity.Information
+          Code = Some(code diag)
+          CodeDescription = None
+          Source = Some "Marksman"
+          Message = $"Note has no title, it's known by its file name '{Doc.name doc}'"
+          RelatedInformation = None
+          Tags = None
+          Data = None }
+    | HeadingLevelJump (heading, prevLevel) ->
+        { Range = heading.range
+          Severity = Some Lsp.DiagnosticSeverity.Information
+          Code = Some(code diag)
+          CodeDescription = None
+          Source = Some "Marksman"
+          Message = $"Heading level jumps from H{prevLevel} to H{heading.data.level}"
+          RelatedInformation = None
+          Tags = None
+          Data = None }
+    | DuplicateTitle (doc, others) ->
+        let titleRange doc =
+            Doc.title doc
+            |> Option.map (fun title -> title.data.title.range)
+            |> Option.defaultValue (Range.Mk(0, 0, 0, 0))
+
+        let mkRelated (other: Doc) : DiagnosticRelatedInformation =
+            { Location = { Uri = Doc.uri other; Range = titleRange other }
+              Message = $"Another note '{Doc.pathFromRoot other}'" }
+
+        let related =
+            others
+            |> Array.sortBy Doc.pathFromRoot
+            |> Array.map mkRelated
+
+        { Range = titleRange doc
+          Severity = Some Lsp.DiagnosticSeverity.Warning
+          Code = Some(code diag)
+          CodeDescription = None
+          Source = Some "Marksman"
+          Message = $"Duplicate note name '{Doc.name doc}' makes links to it ambiguous"
+          RelatedInformation = Some related
+          Tags = None
+          Data = None }
+
+let private severityToLsp (severity: DiagSeverity) : option<Lsp.DiagnosticSeverity> =
+    match severity with
+    | DiagSeverity.Error -> Some Lsp.DiagnosticSeverity.Error
+    | DiagSeverity.Warning -> Some Lsp.DiagnosticSeverity.Warning
+    | DiagSeverity.Information -> Some Lsp.DiagnosticSeverity.Information
+    | DiagSeverity.Hint -> Some Lsp.DiagnosticSeverity.Hint
+    | DiagSeverity.Off -> None
+
+/// LSP diagnostic with the severity configured for the rule of the entry, if there is one.
+let configuredDiagToLsp (config: Config) (diag: Entry) : Lsp.Diagnostic =
+    let lsp = diagToLsp diag
+
+    match config.DiagRuleSeverity(code diag) |> Option.bind severityToLsp with
+    | Some severity -> { lsp with Severity = Some severity }
+    | None -> lsp
 
 type FolderDiag = array<PathUri * array<Lsp.Diagnostic>>
 
 module FolderDiag =
-    let mk (folder: Folder) : FolderDiag =
-        checkFolder folder
+    let private ofEntries (folder: Folder) (entries: seq<PathUri * list<Entry>>) : FolderDiag =
+        entries
         |> Seq.map (fun (uri, diags) ->
-            let lspDiags = List.map diagToLsp diags |> Array.ofList
+            let config =
+                match Folder.tryFindDocByPath uri folder with
+                | Some doc -> Folder.configFor doc folder
+                | None -> Folder.configOrDefault folder
+
+            let lspDiags = List.map (configuredDiagToLsp config) diags |> Array.ofList
 
             uri, lspDiags)
         |> Array.ofSeq
 
+    let private folderPath (folder: Folder) = (RootPath.path (Folder.rootPath folder)).LocalPath
+
+    let mk (folder: Folder) : FolderDiag =
+        Trace.spanOf "diag" "checkFolder" (folderPath folder) (fun () ->
+            checkFolder folder |> ofEntries folder)
+
+    /// Diagnostics of the folder, reusing what's still valid of `prev`.
+    let update
+        (urlChecker: option<UrlCheck.Checker>)
+        (cancel: CancellationToken)
+        (prev: option<FolderCheck>)
+        (folder: Folder)
+        : FolderCheck * FolderDiag =
+        Trace.spanOf "diag" "checkFolder" (folderPath folder) (fun () ->
+            let check, entries = checkFolderIncremental urlChecker cancel prev folder
+            check, ofEntries folder entries)
+
 type WorkspaceDiag = Map<FolderId, FolderDiag>
 
 module WorkspaceDiag =
@@ -201,3 +1253,64 @@ module WorkspaceDiag =
         |> Map.ofSeq
 
     let empty = Map.empty
+
+/// Entries of the folders of a workspace, to update their diagnostics incrementally.
+type WorkspaceCheck = Map<FolderId, FolderCheck>
+
+module WorkspaceCheck =
+    let empty: WorkspaceCheck = Map.empty
+
+    /// Diagnostics of the workspace, re-checking only the docs of `prev` that need it.
+    let update
+        (urlChecker: option<UrlCheck.Checker>)
+        (cancel: CancellationToken)
+        (prev: WorkspaceCheck)
+        (ws: Workspace)
+        : WorkspaceCheck * WorkspaceDiag =
+        let updated =
+            Workspace.folders ws
+            |> Seq.map (fun folder ->
+                let id = Folder.id folder
+                id, FolderDiag.update urlChecker cancel (Map.tryFind id prev) folder)
+            |> Array.ofSeq
+
+        let check = updated |> Array.map (fun (id, (check, _)) -> id, check) |> Map.ofArray
+        let diag = updated |> Array.map (fun (id, (_, diag)) -> id, diag) |> Map.ofArray
+        check, diag
+
+/// Name of the command that checks the whole workspace and replies with a `Report`.
+let checkWorkspaceCommand = "marksman.checkWorkspace"
+
+type DocReport = { uri: string; diagnostics: array<Lsp.Diagnostic> }
+
+/// Diagnostics of all the notes of a workspace, whether open or not, with totals by severity.
+type Report =
+    { documents: array<DocReport>
+      errors: int
+      warnings: int
+      information: int
+      hints: int }
+
+module Report =
+    let ofWorkspaceDiag (diag: WorkspaceDiag) : Report =
+        let documents =
+            diag
+            |> Map.values
+            |> Seq.concat
+            |> Seq.filter (fun (_, diags) -> not (Array.isEmpty diags))
+            |> Seq.map (fun (uri, diags) -> { uri = uri.DocumentUri; diagnostics = diags })
+            |> Seq.sortBy (fun doc -> doc.uri)
+            |> Array.ofSeq
+
+        let count severity =
+            documents
+            |> Array.sumBy (fun doc ->
+                doc.diagnostics
+                |> Array.filter (fun diag -> diag.Severity = Some severity)
+                |> Array.length)
+
+        { documents = documents
+          errors = count Lsp.DiagnosticSeverity.Error
+          warnings = count Lsp.DiagnosticSeverity.Warning
+          information = count Lsp.DiagnosticSeverity.Information
+          hints = count Lsp.DiagnosticSeverity.Hint }
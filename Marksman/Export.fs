@@ -0,0 +1,272 @@
+/// Static HTML of the notes of a folder, e.g. `marksman export --format html site/`. Links are
+/// resolved the same way as in the editor, and every page lists the notes linking to it.
+module Marksman.Export
+
+open System
+open System.IO
+open System.Net
+open Markdig
+open Markdig.Renderers
+open Markdig.Renderers.Html
+open Markdig.Syntax
+
+open Marksman.Cst
+open Marksman.Index
+open Marksman.Misc
+open Marksman.Refs
+open Marksman.Workspace
+
+[<RequireQualifiedAccess>]
+type Format = | Html
+
+module Format =
+    let ofString (input: string) : option<Format> =
+        match input.ToLowerInvariant() with
+        | "html" -> Some Format.Html
+        | _ -> None
+
+/// A file of the export. Paths are relative to the output directory and use '/'.
+type Page = { path: string; html: string }
+
+/// The non-markdown files of the folder, e.g. images and attachments, as paths relative to the
+/// root. They're copied to the same paths under the output directory, so that relative links to
+/// them keep working.
+let assets (folder: Folder) : array<string> = Folder.assets folder |> Array.ofSeq
+
+/// The page listing all the notes. A note at the root of the folder named `index` takes its place
+/// as the start page, in which case the listing goes to `allNotesPath`.
+let indexPath = "index.html"
+
+let allNotesPath = "all-notes.html"
+
+let private pagePath (doc: Doc) =
+    let path = (Doc.pathFromRoot doc).Replace('\\', '/').TrimStart('/')
+    Path.ChangeExtension(path, ".html")
+
+/// Link from the page at `fromPath` to the one at `toPath`.
+let relativeHref (fromPath: string) (toPath: string) : string =
+    let fromParts = fromPath.Split('/')
+    let toParts = toPath.Split('/')
+    let fromDirs = fromParts[.. fromParts.Length - 2]
+    let toDirs = toParts[.. toParts.Length - 2]
+
+    let shared =
+        Seq.zip fromDirs toDirs
+        |> Seq.takeWhile (fun (a, b) -> a = b)
+        |> Seq.length
+
+    Array.append
+        (Array.create (fromDirs.Length - shared) "..")
+        (toParts[shared..] |> Array.map Uri.EscapeDataString)
+    |> String.concat "/"
+
+let private title (doc: Doc) =
+    Doc.title doc
+    |> Option.map (fun { data = title } -> Heading.name title)
+    |> Option.defaultValue (Doc.name doc)
+
+/// Links of the doc with the destination they're exported with, picked by
+/// `core.link_resolution` when there are several.
+let private resolve (folder: Folder) (doc: Doc) : list<Element * option<Dest>> =
+    let strategy = (Folder.configOrDefault folder).CoreLinkResolution()
+
+    Dest.resolveLinks folder doc
+    |> Map.toList
+    |> List.map (fun (link, dests) -> link, Dest.pick strategy doc dests |> Array.tryHead)
+
+// Blocks get the id of their anchor, marker included, so that it doesn't clash with the slugs of
+// the headings
+let private blockId (anchor: BlockAnchor) = $"^{BlockAnchor.id anchor}"
+
+let private href (srcDoc: Doc) (dest: Dest) : option<string> =
+    let page = relativeHref (pagePath srcDoc) (pagePath (Dest.doc dest))
+
+    match dest with
+    | Dest.Heading (_, { data = heading }) when not (Heading.isTitle heading) ->
+        Some $"{page}#{Heading.slug heading |> Slug.toString}"
+    | Dest.Block (_, { data = anchor }) -> Some $"{page}#{blockId anchor}"
+    | Dest.Doc _
+    | Dest.Heading _ -> Some page
+    // References resolve to definitions in the same note, which are exported with them
+    | Dest.LinkDef _ -> None
+
+/// The text of the doc with its links pointing at the exported pages. Wiki links become inline
+/// links, or plain text when they don't resolve; other links only get their URL replaced. Block
+/// anchors are dropped, their blocks get an id instead.
+let private rewriteLinks (doc: Doc) (links: list<Element * option<Dest>>) : string =
+    let wikiLabel (wl: Node<WikiLink>) =
+        match wl.data.alias with
+        | Some alias -> alias.text
+        | None -> wl.text.TrimStart('!').TrimStart('[').TrimEnd(']')
+
+    let edits =
+        links
+        |> List.choose (fun (link, dest) ->
+            let target = dest |> Option.bind (href doc)
+
+            match link, target with
+            | WL wl, Some target -> Some(wl.range, $"[{wikiLabel wl}]({target})")
+            | WL wl, None -> Some(wl.range, wikiLabel wl)
+            | ML { data = MdLink.IL (_, Some url, _) }, Some target -> Some(url.range, target)
+            | MLD { data = linkDef }, Some target -> Some((MdLinkDef.url linkDef).range, target)
+            | _ -> None)
+
+    let anchors =
+        Index.blockAnchors (Doc.index doc) |> Array.map (fun anchor -> anchor.range, "")
+
+    Refactor.applyEdits (Doc.text doc) (Seq.append edits anchors)
+
+let private pipeline =
+    MarkdownPipelineBuilder()
+        .UseYamlFrontMatter()
+        .UsePipeTables()
+        .UseMathematics()
+        .UseTaskLists()
+        .UseFootnotes()
+        .UseAutoLinks()
+        .Build()
+
+// Rewriting links keeps the lines where they are, so headings and anchored blocks are found by
+// their line and get the ids that links to them use
+let private renderBody (doc: Doc) (markdown: string) : string =
+    let slugs =
+        (Doc.index doc).headings
+        |> Array.map (fun { data = heading } ->
+            (Heading.range heading).Start.Line, Heading.slug heading |> Slug.toString)
+        |> Map.ofArray
+
+    let blockIds =
+        Index.blockAnchors (Doc.index doc)
+        |> Array.map (fun { data = anchor } -> anchor.scope.Start.Line, blockId anchor)
+        |> Map.ofArray
+
+    let document = Markdown.Parse(markdown, pipeline)
+
+    for heading in document.Descendants<HeadingBlock>() do
+        Map.tryFind heading.Line slugs
+        |> Option.iter (fun slug -> heading.GetAttributes().Id <- slug)
+
+    for block in document.Descendants<LeafBlock>() do
+        match Map.tryFind block.Line blockIds with
+        | Some id when isNull (block.GetAttributes().Id) -> block.GetAttributes().Id <- id
+        | _ -> ()
+
+    use writer = new StringWriter()
+    let renderer = HtmlRenderer(writer)
+    pipeline.Setup(renderer)
+    renderer.Render(document) |> ignore
+    writer.Flush()
+    writer.ToString()
+
+let private encode (text: string) = WebUtility.HtmlEncode(text)
+
+let private listOf (fromPath: string) (docs: seq<Doc>) =
+    let items =
+        docs
+        |> Seq.map (fun doc ->
+            let href = relativeHref fromPath (pagePath doc)
+            $"<li><a href=\"{encode href}\">{encode (title doc)}</a></li>")
+
+    String.concat "\n" (Seq.concat [ [ "<ul>" ]; items; [ "</ul>" ] ])
+
+let private html (pageTitle: string) (body: string) =
+    [ "<!DOCTYPE html>"
+      "<html>"
+      "<head>"
+      "<meta charset=\"utf-8\">"
+      $"<title>{encode pageTitle}</title>"
+      "</head>"
+      "<body>"
+      body
+      "</body>"
+      "</html>"
+      "" ]
+    |> String.concat "\n"
+
+/// A page for every note of the folder, followed by the listing of all notes.
+let pages (folder: Folder) : array<Page> =
+    let docs = Folder.docs folder |> Seq.sortBy pagePath |> Array.ofSeq
+    let resolved = docs |> Array.map (fun doc -> doc, resolve folder doc)
+
+    let backlinks =
+        resolved
+        |> Seq.collect (fun (srcDoc, links) ->
+            links
+            |> Seq.choose (fun (_, dest) ->
+                dest
+                |> Option.map Dest.doc
+                |> Option.filter (fun destDoc -> Doc.path destDoc <> Doc.path srcDoc)
+                |> Option.map (fun destDoc -> Doc.path destDoc, srcDoc)))
+        |> Seq.distinctBy (fun (destPath, srcDoc) -> destPath, Doc.path srcDoc)
+        |> Seq.groupBy fst
+        |> Seq.map (fun (destPath, pairs) -> destPath, Seq.map snd pairs |> Array.ofSeq)
+        |> Map.ofSeq
+
+    let notePages =
+        resolved
+        |> Array.map (fun (doc, links) ->
+            let path = pagePath doc
+            let body = renderBody doc (rewriteLinks doc links)
+
+            let backlinksSection =
+                match Map.tryFind (Doc.path doc) backlinks with
+                | Some linking ->
+                    [ "<section class=\"backlinks\">"
+                      "<h2>Backlinks</h2>"
+                      listOf path linking
+                      "</section>" ]
+                    |> String.concat "\n"
+                | None -> ""
+
+            { path = path; html = html (title doc) (body + backlinksSection) })
+
+    let listingPath =
+        if notePages |> Array.exists (fun page -> page.path = indexPath) then
+            allNotesPath
+        else
+            indexPath
+
+    let listing =
+        { path = listingPath
+          html = html "Notes" ("<h1>Notes</h1>\n" + listOf listingPath docs) }
+
+    Array.append notePages [| listing |]
+
+/// Exports the folder at `dir` to `outDir`, along with its assets. The exit code is non-zero when
+/// the folder can't be read or the files can't be written.
+let run (dir: string) (format: Format) (outDir: string) : int =
+    match format, Check.tryLoadFolder dir with
+    | _, None ->
+        eprintfn $"Can't read the folder: {dir}"
+        2
+    | Format.Html, Some folder ->
+        try
+            let pages = pages folder
+            let root = (RootPath.path (Folder.rootPath folder)).LocalPath
+            let outRoot = Path.TrimEndingDirectorySeparator(Path.GetFullPath(outDir))
+
+            // A previous export to a directory of the folder isn't exported again
+            let assets =
+                assets folder
+                |> Array.filter (fun asset ->
+                    let path = Path.GetFullPath(Path.Join(root, asset))
+                    not (path.StartsWith(outRoot + string Path.DirectorySeparatorChar)))
+
+            let outPath (path: string) =
+                let path = Path.Join(outDir, path)
+                Directory.CreateDirectory(Path.GetDirectoryName(path)) |> ignore
+                path
+
+            for page in pages do
+                File.WriteAllText(outPath page.path, page.html)
+
+            for asset in assets do
+                File.Copy(Path.Join(root, asset), outPath asset, true)
+
+            printfn $"Wrote {pages.Length} pages and {assets.Length} assets to {outDir}"
+            0
+        with
+        | :? IOException
+        | :? UnauthorizedAccessException as exn ->
+            eprintfn $"Can't write the export: {exn.Message}"
+            2
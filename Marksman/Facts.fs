@@ -0,0 +1,112 @@
+/// Facts derived from the docs of a folder, memoized from one version of the folder to the next.
+/// A fact of a doc depends on the doc itself and on the docs its links name, so it's derived again
+/// only when one of them changes. Edits that leave what links resolve against as it was, e.g. of
+/// the text under a heading, don't spread to the docs linking to the doc.
+module Marksman.Facts
+
+open Marksman.Cst
+open Marksman.Index
+open Marksman.Misc
+open Marksman.Workspace
+
+/// What links of other docs resolve against: the name and the path of the doc, its headings by
+/// slug, explicit id and GitHub-style anchor, and its block anchors.
+let linkTargets (doc: Doc) =
+    let index = Doc.index doc
+    let headings = Index.headings index |> Array.map (fun { data = heading } -> heading)
+
+    Doc.slug doc,
+    Doc.pathFromRoot doc,
+    headings |> Array.map (fun heading -> Heading.slug heading, Heading.idSlug heading),
+    headings |> Array.map Heading.name |> GitHubSlug.ofStrings,
+    Index.blockAnchors index |> Array.map (fun { data = anchor } -> BlockAnchor.id anchor)
+
+/// Pairs of the previous and the current version of the docs that differ between the folders.
+let changes (prev: Folder) (folder: Folder) : list<option<Doc> * option<Doc>> =
+    let prevDocs = Folder.docs prev |> Seq.map (fun doc -> Doc.path doc, doc) |> Map.ofSeq
+    let docs = Folder.docs folder |> Array.ofSeq
+    let paths = docs |> Array.map Doc.path |> Set.ofArray
+
+    [ for doc in docs do
+          match Map.tryFind (Doc.path doc) prevDocs with
+          | Some prevDoc when LanguagePrimitives.PhysicalEquality prevDoc doc -> ()
+          | prevDoc -> yield prevDoc, Some doc
+      for KeyValue (path, prevDoc) in prevDocs do
+          if not (paths.Contains(path)) then
+              yield Some prevDoc, None ]
+
+/// Docs of `folder` whose facts derived in `prev` may no longer hold: the docs that changed, and
+/// the docs linking by name to a doc that changed what they resolve against, before or after the
+/// change. `None` when no fact holds anymore, e.g. when the config changed.
+let invalidated (prev: Folder) (folder: Folder) : option<Set<PathUri>> =
+    let sameConfig =
+        Folder.config prev = Folder.config folder
+        && Folder.dirConfigs prev = Folder.dirConfigs folder
+        && Folder.isSingleFile prev = Folder.isSingleFile folder
+
+    if not sameConfig then
+        None
+    else
+        let changes = changes prev folder
+
+        let linkingTo (folder: Folder) (doc: option<Doc>) =
+            doc
+            |> Option.map (fun doc -> Folder.docsLinkingTo doc folder)
+            |> Option.defaultValue Seq.empty
+
+        let linking =
+            changes
+            |> Seq.collect (function
+                | Some prevDoc, Some doc when linkTargets prevDoc = linkTargets doc -> Seq.empty
+                | prevDoc, doc -> Seq.append (linkingTo prev prevDoc) (linkingTo folder doc))
+            |> Seq.map Doc.path
+
+        changes
+        |> Seq.choose (fun (prevDoc, doc) -> Option.orElse prevDoc doc |> Option.map Doc.path)
+        |> Seq.append linking
+        |> Set.ofSeq
+        |> Some
+
+/// A fact of every doc of a folder.
+type Memo<'T> = private { folder: Folder; facts: Map<PathUri, 'T> }
+
+module Memo =
+    /// Facts of the docs of `folder`, reusing the ones in `prev` that still hold and that are
+    /// `reusable`. The others are derived again by `derive`.
+    let update
+        (reusable: 'T -> bool)
+        (derive: Doc -> 'T)
+        (prev: option<Memo<'T>>)
+        (folder: Folder)
+        : Memo<'T> =
+        let reused =
+            match prev with
+            | None -> Map.empty
+            | Some prev ->
+                match invalidated prev.folder folder with
+                | None -> Map.empty
+                | Some invalidated ->
+                    prev.facts
+                    |> Map.filter (fun path fact ->
+                        not (invalidated.Contains(path)) && reusable fact)
+
+        let facts =
+            Folder.docs folder
+            |> Seq.map (fun doc ->
+                let path = Doc.path doc
+
+                match Map.tryFind path reused with
+                | Some fact -> path, fact
+                | None -> path, derive doc)
+            |> Map.ofSeq
+
+        { folder = folder; facts = facts }
+
+    let ofFolder (derive: Doc -> 'T) (folder: Folder) : Memo<'T> =
+        update (fun _ -> true) derive None folder
+
+    let tryFind (doc: Doc) (memo: Memo<'T>) : option<'T> = Map.tryFind (Doc.path doc) memo.facts
+
+    let find (doc: Doc) (memo: Memo<'T>) : 'T = memo.facts[Doc.path doc]
+
+    let facts (memo: Memo<'T>) : seq<'T> = Map.values memo.facts
@@ -0,0 +1,19 @@
+module Marksman.Folding
+
+open Ionide.LanguageServerProtocol.Types
+
+open Marksman.Cst
+open Marksman.Index
+open Marksman.Workspace
+
+/// Folding ranges of multi-line callouts. The marker line stays visible when folded.
+let docFoldingRanges (doc: Doc) : array<FoldingRange> =
+    Doc.index doc
+    |> Index.callouts
+    |> Array.filter (fun { data = callout } -> callout.scope.End.Line > callout.scope.Start.Line)
+    |> Array.map (fun { data = callout } ->
+        { StartLine = callout.scope.Start.Line
+          StartCharacter = None
+          EndLine = callout.scope.End.Line
+          EndCharacter = None
+          Kind = Some FoldingRangeKind.Region })
@@ -0,0 +1,114 @@
+/// Top-level fields of the YAML frontmatter of a note. Only the part of YAML that notes use in
+/// practice is understood: `key: value` pairs whose values are scalars, nested mappings, or lists
+/// written either as `[a, b]` or as `- a` items on the lines that follow.
+module Marksman.Frontmatter
+
+open Ionide.LanguageServerProtocol.Types
+
+open Marksman.Cst
+open Marksman.Misc
+
+type Scalar = { text: string; quoted: bool; range: Range }
+
+type Value =
+    | Null
+    | Scalar of Scalar
+    | Items of list<Scalar>
+    | Mapping
+
+type Field = { key: string; value: Value; range: Range }
+
+let private isFence (line: string) =
+    let line = line.TrimEnd()
+    line = "---" || line = "..."
+
+let private parseScalar (range: Range) (input: string) : Scalar =
+    let input = input.Trim()
+
+    if input.Length > 0 && (input[0] = '"' || input[0] = '\'') then
+        let closing = input.IndexOf(input[0], 1)
+        let text = if closing < 0 then input.Substring(1) else input.Substring(1, closing - 1)
+        { text = text; quoted = true; range = range }
+    else
+        // Outside of quotes a comment starts with '#' preceded by whitespace
+        let text =
+            match input.IndexOf(" #") with
+            | -1 -> input
+            | idx -> input.Substring(0, idx).TrimEnd()
+
+        { text = text; quoted = false; range = range }
+
+let private parseValue (range: Range) (input: string) : Value =
+    let input = input.Trim()
+
+    if input.StartsWith('[') && input.EndsWith(']') then
+        input.Substring(1, input.Length - 2).Split(',')
+        |> Seq.filter (fun item -> not (item.IsWhitespace()))
+        |> Seq.map (parseScalar range)
+        |> List.ofSeq
+        |> Items
+    elif input.StartsWith('{') then
+        Mapping
+    elif input.StartsWith('|') || input.StartsWith('>') then
+        // Block scalars, e.g. `summary: |`, are strings spanning the indented lines that follow
+        Scalar { text = ""; quoted = true; range = range }
+    else
+        let scalar = parseScalar range input
+        if scalar.text = "" then Null else Scalar scalar
+
+/// Fields of the frontmatter in the order they are defined.
+let fields (yml: TextNode) : array<Field> =
+    let lines = yml.text.Split('\n') |> Array.map (fun line -> line.TrimEnd('\r'))
+    let startLine = yml.range.Start.Line
+    let fields = ResizeArray<Field>()
+
+    let lineRange (idx: int) (start: int) =
+        Range.Mk(startLine + idx, start, startLine + idx, lines[idx].TrimEnd().Length)
+
+    // A field without a value on its own line gets one from the indented lines that follow
+    let mutable pending: option<Field> = None
+
+    let flush () =
+        pending |> Option.iter fields.Add
+        pending <- None
+
+    let mutable idx = 1
+
+    while idx < lines.Length && not (isFence lines[idx]) do
+        let line = lines[idx]
+        let trimmed = line.Trim()
+
+        if trimmed = "" || trimmed.StartsWith('#') then
+            ()
+        elif System.Char.IsWhiteSpace(line[0]) || trimmed.StartsWith('-') then
+            match pending with
+            | Some field when trimmed = "-" || trimmed.StartsWith("- ") ->
+                let items =
+                    match field.value with
+                    | Items items -> items
+                    | _ -> []
+
+                let item = parseScalar (lineRange idx (line.IndexOf('-'))) (trimmed.Substring(1))
+                pending <- Some { field with value = Items(items @ [ item ]) }
+            | Some ({ value = Null } as field) -> pending <- Some { field with value = Mapping }
+            | _ -> ()
+        else
+            flush ()
+
+            match line.IndexOf(':') with
+            | -1 -> ()
+            | colon ->
+                let key = line.Substring(0, colon).Trim().Trim('"', '\'')
+                let range = lineRange idx 0
+
+                match parseValue range (line.Substring(colon + 1)) with
+                | Null -> pending <- Some { key = key; value = Null; range = range }
+                | value -> fields.Add({ key = key; value = value; range = range })
+
+        idx <- idx + 1
+
+    flush ()
+    fields.ToArray()
+
+let tryFind (key: string) (fields: array<Field>) : option<Field> =
+    fields |> Array.tryFind (fun field -> field.key = key)
@@ -0,0 +1,222 @@
+/// Last-commit info from git for hovers over links and code lenses over headings, when
+/// `git.enable` is on. Git runs in the directory of the note, so the repository is the one the
+/// workspace is in. Notes outside a repository, or without git installed, get no info.
+module Marksman.Git
+
+open System
+open System.Collections.Concurrent
+open System.Collections.Generic
+open System.ComponentModel
+open System.Diagnostics
+open System.Globalization
+open System.IO
+open Ionide.LanguageServerProtocol.Logging
+open Ionide.LanguageServerProtocol.Types
+
+open Marksman.Cst
+open Marksman.Index
+open Marksman.Workspace
+
+type Commit =
+    { hash: string
+      author: string
+      time: DateTimeOffset
+      summary: string }
+
+module Commit =
+    // Lines changed since the last commit are blamed on this one
+    let private uncommittedHash = String('0', 40)
+
+    let isUncommitted (commit: Commit) = commit.hash = uncommittedHash
+
+    let fmt (commit: Commit) : string =
+        if isUncommitted commit then
+            "Not committed yet"
+        else
+            let date =
+                commit.time.ToLocalTime().ToString("yyyy-MM-dd", CultureInfo.InvariantCulture)
+
+            $"{commit.author}, {date}: {commit.summary}"
+
+let private logger = LogProvider.getLoggerByName "Git"
+
+// Git is run for requests, which shouldn't hang on a repository that is slow to read
+let private timeout = TimeSpan.FromSeconds(5)
+
+let private tryRun (dir: string) (args: list<string>) : option<string> =
+    try
+        let info =
+            ProcessStartInfo(
+                "git",
+                WorkingDirectory = dir,
+                UseShellExecute = false,
+                RedirectStandardOutput = true,
+                RedirectStandardError = true
+            )
+
+        for arg in args do
+            info.ArgumentList.Add(arg)
+
+        use proc = Process.Start(info)
+        // Both pipes are drained while git runs, or it blocks once one of them is full
+        let output = proc.StandardOutput.ReadToEndAsync()
+        proc.StandardError.ReadToEndAsync() |> ignore
+
+        if proc.WaitForExit(int timeout.TotalMilliseconds) then
+            if proc.ExitCode = 0 then Some output.Result else None
+        else
+            proc.Kill()
+            None
+    with
+    | :? Win32Exception
+    | :? InvalidOperationException
+    | :? IOException as exn ->
+        logger.trace (Log.setMessage "Failed to run git" >> Log.addException exn)
+        None
+
+let private isHash (text: string) = text.Length = 40 && Seq.forall Uri.IsHexDigit text
+
+/// The commit of every line, by 0-based line, from the output of `git blame --porcelain`. Fields
+/// of a commit only come with its first line.
+let parseBlame (output: string) : array<Commit> =
+    let commits = Dictionary<string, Commit>()
+    let lines = ResizeArray<Commit>()
+    let mutable current = ""
+
+    let update (field: string) (value: string) =
+        let commit = commits[current]
+
+        commits[current] <-
+            match field with
+            | "author" -> { commit with author = value }
+            | "author-time" ->
+                match Int64.TryParse(value) with
+                | true, seconds ->
+                    { commit with time = DateTimeOffset.FromUnixTimeSeconds(seconds) }
+                | _ -> commit
+            | "summary" -> { commit with summary = value }
+            | _ -> commit
+
+    for line in output.Split('\n') do
+        if line.StartsWith('\t') then
+            lines.Add(commits[current])
+        else
+            match line.Split([| ' ' |], 2) with
+            | [| hash; _ |] when isHash hash ->
+                current <- hash
+
+                if not (commits.ContainsKey(hash)) then
+                    commits[hash] <-
+                        { hash = hash
+                          author = ""
+                          time = DateTimeOffset.MinValue
+                          summary = "" }
+            | [| field; value |] when current <> "" -> update field value
+            | _ -> ()
+
+    lines.ToArray()
+
+// Blames and last commits are computed again when the file changes, and after a while in case
+// it got committed
+let private blameTtl = TimeSpan.FromMinutes(1)
+
+let private blames = ConcurrentDictionary<string, DateTime * DateTime * array<Commit>>()
+
+/// The commit of every line of the file at `path` as saved; unsaved edits aren't known to git.
+let blame (path: string) : option<array<Commit>> =
+    let modified = File.GetLastWriteTimeUtc(path)
+    let now = DateTime.UtcNow
+
+    let isFresh (blamedModified, blamedAt, _) =
+        blamedModified = modified && now - blamedAt < blameTtl
+
+    match blames.TryGetValue(path) with
+    | true, (_, _, commits as blamed) when isFresh blamed -> Some commits
+    | _ ->
+        let args = [ "blame"; "--porcelain"; "--"; Path.GetFileName(path) ]
+
+        tryRun (Path.GetDirectoryName(path)) args
+        |> Option.map (fun output ->
+            let commits = parseBlame output
+
+            if Caches.enabled () then
+                blames[path] <- (modified, now, commits)
+
+            commits)
+
+let private lastCommits = ConcurrentDictionary<string, DateTime * DateTime * option<Commit>>()
+
+let private parseLastCommit (output: string) : option<Commit> =
+    match output.TrimEnd('\n').Split('\000') with
+    | [| hash; author; time; summary |] ->
+        match Int64.TryParse(time) with
+        | true, seconds ->
+            Some
+                { hash = hash
+                  author = author
+                  time = DateTimeOffset.FromUnixTimeSeconds(seconds)
+                  summary = summary }
+        | _ -> None
+    | _ -> None
+
+/// The last commit changing the file at `path`, computed again like `blame`.
+let lastCommit (path: string) : option<Commit> =
+    let modified = File.GetLastWriteTimeUtc(path)
+    let now = DateTime.UtcNow
+
+    let isFresh (loggedModified, loggedAt, _) =
+        loggedModified = modified && now - loggedAt < blameTtl
+
+    match lastCommits.TryGetValue(path) with
+    | true, (_, _, commit as logged) when isFresh logged -> commit
+    | _ ->
+        let args = [ "log"; "-1"; "--format=%H%x00%an%x00%at%x00%s"; "--"; Path.GetFileName(path) ]
+
+        tryRun (Path.GetDirectoryName(path)) args
+        |> Option.bind (fun output ->
+            let commit = parseLastCommit output
+
+            if Caches.enabled () then
+                lastCommits[path] <- (modified, now, commit)
+
+            commit)
+
+/// The latest commit of the lines `first` to `last`, both included.
+let latestIn (commits: array<Commit>) (first: int) (last: int) : option<Commit> =
+    let last = min last (commits.Length - 1)
+
+    if first > last then
+        None
+    else
+        commits[first..last] |> Array.maxBy (fun commit -> commit.time) |> Some
+
+/// Whether the doc is as saved, so that the lines git knows are the lines of the doc.
+let isSaved (doc: Doc) : bool =
+    match Doc.version doc with
+    | None -> true
+    | Some _ ->
+        try
+            File.ReadAllText((Doc.path doc).LocalPath) = (Doc.text doc).content
+        with
+        | :? IOException
+        | :? UnauthorizedAccessException -> false
+
+/// A lens over every heading with the latest commit of its section, subsections included. Docs
+/// with unsaved edits get none, since their lines may no longer be the ones blamed.
+let headingLenses (doc: Doc) : array<CodeLens> =
+    let commits = if isSaved doc then blame (Doc.path doc).LocalPath else None
+
+    match commits with
+    | None -> [||]
+    | Some commits ->
+        Index.headings (Doc.index doc)
+        |> Array.choose (fun { data = heading; range = range } ->
+            let scope = Heading.scope heading
+            // The scope ends at the start of the next section's line
+            let last = if scope.End.Character = 0 then scope.End.Line - 1 else scope.End.Line
+
+            latestIn commits scope.Start.Line last
+            |> Option.map (fun commit ->
+                { Range = range
+                  Command = Some { Title = Commit.fmt commit; Command = ""; Arguments = None }
+                  Data = None }))
@@ -14,6 +14,12 @@ type Index =
       mdLinks: array<Node<MdLink>>
       linkDefs: array<Node<MdLinkDef>>
       tags: array<Node<Tag>>
+      blockAnchors: array<Node<BlockAnchor>>
+      code: array<Node<Code>>
+      tasks: array<Node<Task>>
+      tables: array<Node<Table>>
+      citations: array<Node<Citation>>
+      callouts: array<Node<Callout>>
       yamlFrontMatter: option<TextNode> }
 
 module Index =
@@ -25,17 +31,30 @@ module Index =
         let mdLinks = ResizeArray()
         let linkDefs = ResizeArray()
         let tags = ResizeArray()
+        let blockAnchors = ResizeArray()
+        let code = ResizeArray()
+        let tasks = ResizeArray()
+        let tables = ResizeArray()
+        let citations = ResizeArray()
+        let callouts = ResizeArray()
         let mutable yaml = None
 
         for el in Cst.elementsAll cst do
             match el with
             | H hn ->
-                let slug = Heading.slug hn.data
+                let addBySlug slug =
+                    if not (headingsBySlug.ContainsKey(slug)) then
+                        headingsBySlug[slug] <- ResizeArray()
+
+                    headingsBySlug[ slug ].Add(hn)
 
-                if not (headingsBySlug.ContainsKey(slug)) then
-                    headingsBySlug[slug] <- ResizeArray()
+                let slug = Heading.slug hn.data
+                addBySlug slug
 
-                headingsBySlug[ slug ].Add(hn)
+                // Headings with an explicit id can be referenced both by title and by id
+                match Heading.idSlug hn.data with
+                | Some idSlug when idSlug <> slug -> addBySlug idSlug
+                | _ -> ()
 
                 if Heading.isTitle hn.data then
                     titles.Add(hn)
@@ -45,6 +64,12 @@ module Index =
             | ML ml -> mdLinks.Add(ml)
             | MLD linkDef -> linkDefs.Add(linkDef)
             | T t -> tags.Add(t)
+            | BA b -> blockAnchors.Add(b)
+            | C c -> code.Add(c)
+            | TK t -> tasks.Add(t)
+            | TB t -> tables.Add(t)
+            | CT c -> citations.Add(c)
+            | CO c -> callouts.Add(c)
             | YML yml -> yaml <- Some yml
 
         let headingsBySlug =
@@ -60,6 +85,12 @@ module Index =
         let linkDefs = linkDefs.ToArray()
         let headings = headings.ToArray()
         let tags = tags.ToArray()
+        let blockAnchors = blockAnchors.ToArray()
+        let code = code.ToArray()
+        let tasks = tasks.ToArray()
+        let tables = tables.ToArray()
+        let citations = citations.ToArray()
+        let callouts = callouts.ToArray()
 
         { titles = titles
           headings = headings
@@ -68,6 +99,12 @@ module Index =
           mdLinks = mdLinks
           linkDefs = linkDefs
           tags = tags
+          blockAnchors = blockAnchors
+          code = code
+          tasks = tasks
+          tables = tables
+          citations = citations
+          callouts = callouts
           yamlFrontMatter = yaml }
 
     let titles index = index.titles
@@ -85,6 +122,12 @@ module Index =
 
     let tags index = index.tags
 
+    let blockAnchors index = index.blockAnchors
+
+    let filterBlockAnchorsById (id: string) index =
+        index.blockAnchors
+        |> Array.filter (fun { data = b } -> BlockAnchor.id b = id)
+
     let tryFindLinkDef (label: LinkLabel) index =
         index.linkDefs
         |> Array.tryFind (fun { data = ld } -> (MdLinkDef.normalizedLabel ld) = label)
@@ -93,6 +136,25 @@ module Index =
         index.linkDefs
         |> Seq.filter (fun { data = ld } -> pred (MdLinkDef.normalizedLabel ld))
 
+    let code index = index.code
+
+    let tasks index = index.tasks
+
+    let tables index = index.tables
+
+    let citations index = index.citations
+
+    let callouts index = index.callouts
+
+    let citationAtPos (pos: Position) index =
+        index.citations
+        |> Array.tryFind (fun { range = range } -> range.ContainsInclusive(pos))
+
+    /// Checks whether the position is inside code, math or raw HTML.
+    let isInCode (pos: Position) index =
+        index.code
+        |> Array.exists (fun { range = range } -> range.Start <= pos && pos < range.End)
+
     let headingsBySlug index = index.headingsBySlug
 
     let headings index = index.headings
@@ -100,6 +162,18 @@ module Index =
     let filterHeadingBySlug slug index =
         index.headingsBySlug |> Map.tryFind slug |> Option.defaultValue []
 
+    /// Headings whose GitHub-style anchor is exactly `anchor`. Unlike `filterHeadingBySlug` this
+    /// distinguishes duplicate headings by the '-1', '-2', ... suffixes GitHub gives them.
+    let filterHeadingByGitHubSlug (anchor: string) index =
+        let anchors =
+            index.headings
+            |> Array.map (fun { data = h } -> Heading.name h)
+            |> GitHubSlug.ofStrings
+
+        Array.zip anchors index.headings
+        |> Array.filter (fun (a, _) -> a = anchor)
+        |> Array.map snd
+
     let linkAtPos (pos: Position) index =
         let matching el =
             let range = Node.range el
@@ -0,0 +1,140 @@
+/// Setup of the logs. What gets logged is decided by a filter, e.g. `debug,Server=trace`: the
+/// default level followed by overrides for the loggers whose names start with the given prefix.
+/// The filter comes from `--verbose` or the `MARKSMAN_LOG` variable, and can be replaced at
+/// runtime via the `marksman/setLogFilter` notification.
+module Marksman.Logging
+
+open System
+open Serilog
+open Serilog.Core
+open Serilog.Events
+
+type Filter =
+    { level: LogEventLevel
+      overrides: list<string * LogEventLevel> }
+
+module Filter =
+    let private levelOfVerbosity (verbosity: int) =
+        match verbosity with
+        | v when v <= 0 -> LogEventLevel.Error
+        | 1 -> LogEventLevel.Warning
+        | 2 -> LogEventLevel.Information
+        | 3 -> LogEventLevel.Debug
+        | _ -> LogEventLevel.Verbose
+
+    let ofVerbosity (verbosity: int) : Filter =
+        { level = levelOfVerbosity verbosity; overrides = [] }
+
+    let private tryParseLevel (input: string) : option<LogEventLevel> =
+        match input.Trim().ToLowerInvariant() with
+        | "error" -> Some LogEventLevel.Error
+        | "warn"
+        | "warning" -> Some LogEventLevel.Warning
+        | "info" -> Some LogEventLevel.Information
+        | "debug" -> Some LogEventLevel.Debug
+        | "trace" -> Some LogEventLevel.Verbose
+        | _ -> None
+
+    /// Parses `level,Prefix=level,...`. The default level can be omitted, in which case
+    /// `defaultLevel` is used.
+    let tryParse (defaultLevel: LogEventLevel) (input: string) : option<Filter> =
+        let parts =
+            input.Split(',', StringSplitOptions.RemoveEmptyEntries)
+            |> Array.map (fun part -> part.Trim())
+            |> List.ofArray
+
+        let rec go (filter: Filter) parts =
+            match parts with
+            | [] -> Some { filter with overrides = List.rev filter.overrides }
+            | (part: string) :: rest ->
+                match part.Split('=') with
+                | [| level |] ->
+                    tryParseLevel level
+                    |> Option.bind (fun level -> go { filter with level = level } rest)
+                | [| prefix; level |] when prefix.Trim() <> "" ->
+                    tryParseLevel level
+                    |> Option.bind (fun level ->
+                        let overrides = (prefix.Trim(), level) :: filter.overrides
+                        go { filter with overrides = overrides } rest)
+                | _ -> None
+
+        go { level = defaultLevel; overrides = [] } parts
+
+    /// Level for the logger `name`: the longest matching override wins.
+    let levelFor (name: string) (filter: Filter) : LogEventLevel =
+        filter.overrides
+        |> List.filter (fun (prefix, _) -> name.StartsWith(prefix, StringComparison.Ordinal))
+        |> List.sortByDescending (fun (prefix, _) -> prefix.Length)
+        |> List.tryHead
+        |> Option.map snd
+        |> Option.defaultValue filter.level
+
+    let minimumLevel (filter: Filter) : LogEventLevel =
+        filter.overrides |> List.map snd |> List.fold min filter.level
+
+let envVariable = "MARKSMAN_LOG"
+
+// Keeps loggers from even building the events that no filter can let through
+let private levelSwitch = LoggingLevelSwitch(LogEventLevel.Information)
+
+let mutable private current = Filter.ofVerbosity 2
+
+let private isIncluded (event: LogEvent) =
+    let name =
+        match event.Properties.TryGetValue(Constants.SourceContextPropertyName) with
+        | true, (:? ScalarValue as value) -> string value.Value
+        | _ -> ""
+
+    event.Level >= Filter.levelFor name current
+
+let setFilter (filter: Filter) : unit =
+    current <- filter
+    levelSwitch.MinimumLevel <- Filter.minimumLevel filter
+
+/// Logs go to stderr, since stdout is for the protocol, and also to `logFile` when it's given.
+let configure (verbosity: int) (logFile: option<string>) : unit =
+    let fromVerbosity = Filter.ofVerbosity verbosity
+
+    let filter =
+        match Environment.GetEnvironmentVariable(envVariable) with
+        | null
+        | "" -> fromVerbosity
+        | input ->
+            match Filter.tryParse fromVerbosity.level input with
+            | Some filter -> filter
+            | None ->
+                eprintfn $"Ignoring malformed {envVariable}: {input}"
+                fromVerbosity
+
+    setFilter filter
+
+    let outputTemplate =
+        "[{Timestamp:HH:mm:ss} {Level:u3}] <{SourceContext}> "
+        + "{Message:lj}: {Properties:lj}{NewLine}{Exception}"
+
+    let loggerConfig =
+        LoggerConfiguration()
+            .MinimumLevel.ControlledBy(levelSwitch)
+            .Filter.ByIncludingOnly(isIncluded)
+            .WriteTo.Console(
+                outputTemplate = outputTemplate,
+                standardErrorFromLevel = LogEventLevel.Verbose
+            )
+            .Enrich.FromLogContext()
+
+    let loggerConfig =
+        match logFile with
+        | Some path -> loggerConfig.WriteTo.File(path, outputTemplate = outputTemplate)
+        | None -> loggerConfig
+
+    Log.Logger <- loggerConfig.CreateLogger()
+
+type SetLogFilterParams = { filter: string }
+
+/// Handles `marksman/setLogFilter`; a malformed filter leaves the current one in place.
+let setFilterFromClient (par: SetLogFilterParams) : unit =
+    match Filter.tryParse current.level par.filter with
+    | Some filter ->
+        setFilter filter
+        Log.Information("Changed the log filter to {filter}", par.filter)
+    | None -> Log.Warning("Ignoring malformed log filter {filter}", par.filter)
@@ -14,8 +14,17 @@ let lineEndings = [| "\r\n"; "\n" |]
 
 let concatLines (lines: array<string>) : string = String.concat Environment.NewLine lines
 
+/// Configured extensions may be written with a leading '.' and in any case, e.g. ".Rmd".
+let normalizeExt (ext: string) : string = ext.TrimStart('.').ToLowerInvariant()
+
 let mkWatchGlob (configuredExts: seq<string>) : string =
-    let ext_pattern = "{" + (String.concat "," (configuredExts)) + "}"
+    // Globs are case-sensitive for some clients, hence both the configured and the normalized form
+    let exts =
+        configuredExts
+        |> Seq.collect (fun ext -> [ ext.TrimStart('.'); normalizeExt ext ])
+        |> Seq.distinct
+
+    let ext_pattern = "{" + (String.concat "," exts) + "}"
     $"**/*.{ext_pattern}"
 
 let isMarkdownFile (configuredExts: seq<string>) (path: string) : bool =
@@ -34,8 +43,8 @@ let isMarkdownFile (configuredExts: seq<string>) (path: string) : bool =
         match ext with
         | null -> false
         | ext ->
-            let ext = ext.TrimStart('.').ToLowerInvariant()
-            Seq.contains ext configuredExts
+            let ext = normalizeExt ext
+            configuredExts |> Seq.exists (fun configured -> normalizeExt configured = ext)
 
 let isPotentiallyMarkdownFile (configuredExts: seq<string>) (path: string) : bool =
     let ext = Path.GetExtension path
@@ -50,23 +59,32 @@ let fmtOption fmt value =
     | Some value -> $"{fmt value}"
     | None -> "∅"
 
+/// Simple case folding: going through upper case maps e.g. 'ſ' and 'S' to the same 's'.
+let foldCase (char: char) : char = Char.ToLowerInvariant(Char.ToUpperInvariant(char))
+
 type String with
 
     member this.Lines() : array<string> = this.Split(lineEndings, StringSplitOptions.None)
 
     member this.EndsWithNewline() : bool = Array.exists<string> this.EndsWith lineEndings
 
+    /// Case-insensitive subsequence check of a query, which callers NFC-normalize once as it
+    /// arrives. `other` is normalized too, so that e.g. a precomposed 'é' matches 'e' followed by a
+    /// combining acute accent; that takes no copy when it's in NFC already, as most text is.
     member this.IsSubSequenceOf(other: string) : bool =
-        let rec isMatching thisIdx otherIdx =
-            if thisIdx >= this.Length then
+        let sub = this
+        let other = other.Normalize()
+
+        let rec isMatching subIdx otherIdx =
+            if subIdx >= sub.Length then
                 true
             else if otherIdx >= other.Length then
                 false
             else
-                match this[thisIdx], other[otherIdx] with
-                | thisChar, otherChar when Char.ToLower(thisChar) = Char.ToLower(otherChar) ->
-                    isMatching (thisIdx + 1) (otherIdx + 1)
-                | _ -> isMatching thisIdx (otherIdx + 1)
+                match sub[subIdx], other[otherIdx] with
+                | subChar, otherChar when foldCase subChar = foldCase otherChar ->
+                    isMatching (subIdx + 1) (otherIdx + 1)
+                | _ -> isMatching subIdx (otherIdx + 1)
 
         if this.IsEmpty() then true else isMatching 0 0
 
@@ -81,7 +99,7 @@ type String with
         let mutable sepSeen = false
         let mutable chunkState = 0 // 0 no text chunk, 1 chunk in progress, 2 finished
 
-        for char in this.ToCharArray() do
+        for char in this.Normalize().ToCharArray() do
             let isPunct = Char.IsPunctuation(char) || Char.IsSymbol(char)
 
             let isSep = Char.IsWhiteSpace(char) || char = '-'
@@ -98,7 +116,7 @@ type String with
 
                 chunkState <- 1
 
-                sb <- sb.Append(Char.ToLower(char))
+                sb <- sb.Append(foldCase char)
             else if chunkState = 1 then
                 chunkState <- 2
 
@@ -153,6 +171,148 @@ type String with
     member this.TrimBoth(prefix: string, suffix: string) : string =
         this.TrimPrefix(prefix).TrimSuffix(suffix)
 
+/// fzf-style fuzzy matching: the query has to be a case-insensitive subsequence of the candidate,
+/// the score tells how good the match looks.
+module Fuzzy =
+    [<Literal>]
+    let private MatchScore = 16
+
+    [<Literal>]
+    let private ConsecutiveBonus = 8
+
+    [<Literal>]
+    let private MaxGapPenalty = 15
+
+    // Skipping to the next word is cheap so that initialisms, e.g. "pkm" for "Personal Knowledge
+    // Management", score like consecutive matches rather than scattered ones
+    [<Literal>]
+    let private WordJumpPenalty = 1
+
+    let private boundaryBonus (candidate: string) (idx: int) =
+        if idx = 0 then
+            8
+        else
+            let prev, cur = candidate[idx - 1], candidate[idx]
+
+            if not (Char.IsLetterOrDigit(prev)) && Char.IsLetterOrDigit(cur) then 6
+            elif Char.IsLower(prev) && Char.IsUpper(cur) then 4
+            else 0
+
+    /// Score of the best alignment of `query` within `candidate`, or None when the query isn't a
+    /// subsequence. Matches at the start, at word boundaries and in runs score higher; gaps
+    /// between matched characters cost up to `MaxGapPenalty` each. Like for `IsSubSequenceOf`,
+    /// the query is expected NFC-normalized.
+    let score (query: string) (candidate: string) : option<int> =
+        let candidate = candidate.Normalize()
+
+        if query.Length = 0 then
+            Some 0
+        elif query.Length > candidate.Length then
+            None
+        else
+            // best[j]: the best score of the query prefix matched so far, ending at candidate[j]
+            let mutable best: array<option<int>> = Array.create candidate.Length None
+
+            for i in 0 .. query.Length - 1 do
+                let next = Array.create candidate.Length None
+
+                for j in i .. candidate.Length - 1 do
+                    if foldCase query[i] = foldCase candidate[j] then
+                        let charScore = MatchScore + boundaryBonus candidate j
+
+                        if i = 0 then
+                            next[j] <- Some(charScore - min j 10)
+                        else
+                            let mutable acc = None
+
+                            for k in i - 1 .. j - 1 do
+                                match best[k] with
+                                | Some prevScore ->
+                                    let transition =
+                                        if k = j - 1 then
+                                            ConsecutiveBonus
+                                        elif boundaryBonus candidate j > 0 then
+                                            -WordJumpPenalty
+                                        else
+                                            -(min (j - k - 1) MaxGapPenalty)
+
+                                    let total = prevScore + charScore + transition
+
+                                    if acc |> Option.forall (fun acc -> total > acc) then
+                                        acc <- Some total
+                                | None -> ()
+
+                            next[j] <- acc
+
+                best <- next
+
+            match Array.choose id best with
+            | [||] -> None
+            | scores -> Some(Array.max scores)
+
+    let isMatch (query: string) (candidate: string) : bool = (score query candidate).IsSome
+
+    /// Whether `query` abbreviates `candidate` word by word: each character of the query matches
+    /// the start of a word or a later character of the word matched last, e.g. "pkm" abbreviates
+    /// "Personal Knowledge Management" and "NoteIdx" abbreviates "Note Index". Words are split at
+    /// punctuation, whitespace and camel humps; a hump in the query has to start a word.
+    let isAbbreviation (query: string) : string -> bool =
+        let query = query.Normalize() |> String.filter Char.IsLetterOrDigit
+
+        let startsHump i =
+            i > 0 && Char.IsUpper(query[i]) && Char.IsLower(query[i - 1])
+
+        fun candidate ->
+            let candidate = candidate.Normalize()
+
+            // matched[p]: the first p characters of the query are matched before the current
+            // character of the candidate; inWord[p]: so they are within the word it is part of
+            let matched = Array.create (query.Length + 1) false
+            let inWord = Array.create (query.Length + 1) false
+            matched[0] <- true
+
+            for j in 0 .. candidate.Length - 1 do
+                if Char.IsLetterOrDigit(candidate[j]) then
+                    let isWordStart = boundaryBonus candidate j > 0
+
+                    // Backwards, so that a character of the candidate matches one of the query
+                    for p in query.Length - 1 .. -1 .. 0 do
+                        if foldCase query[p] = foldCase candidate[j] then
+                            let reached =
+                                if isWordStart then
+                                    matched[p]
+                                else
+                                    inWord[p] && not (startsHump p)
+
+                            if reached then
+                                matched[p + 1] <- true
+                                inWord[p + 1] <- true
+                else
+                    Array.fill inWord 0 inWord.Length false
+
+            query.Length > 0 && matched[query.Length]
+
+    /// Number of single character insertions, deletions and substitutions turning `a` into `b`,
+    /// ignoring case.
+    let editDistance (a: string) (b: string) : int =
+        let mutable prev = Array.init (b.Length + 1) id
+
+        for i in 1 .. a.Length do
+            let cur = Array.zeroCreate (b.Length + 1)
+            cur[0] <- i
+
+            for j in 1 .. b.Length do
+                let cost = if foldCase a[i - 1] = foldCase b[j - 1] then 0 else 1
+                cur[j] <- min (min (prev[j] + 1) (cur[j - 1] + 1)) (prev[j - 1] + cost)
+
+            prev <- cur
+
+        prev[b.Length]
+
+    /// A sort key that puts better matches first and breaks ties by `label`.
+    let sortText (score: int) (label: string) : string =
+        $"{(Int32.MaxValue - max 0 score):D10}{label}"
+
 type Slug = Slug of string
 
 module Slug =
@@ -172,6 +332,39 @@ module Slug =
         let (Slug sup) = sup
         sub.IsSubStringOf(sup)
 
+/// Heading anchors the way GitHub renders them (the github-slugger algorithm). Unlike `Slug`
+/// this keeps every space (as '-'), '-' and '_', so 'A - B' becomes 'a---b'.
+module GitHubSlug =
+    let private isKept (char: char) =
+        match Char.GetUnicodeCategory(char) with
+        | Globalization.UnicodeCategory.NonSpacingMark
+        | Globalization.UnicodeCategory.SpacingCombiningMark -> true
+        | _ -> Char.IsLetterOrDigit(char) || char = '-' || char = '_'
+
+    let ofString (s: string) : string =
+        let sb = StringBuilder()
+
+        for char in s.Normalize().ToLowerInvariant() do
+            if char = ' ' then sb.Append('-') |> ignore
+            elif isKept char then sb.Append(char) |> ignore
+
+        sb.ToString()
+
+    /// Anchors of a document's headings in order; repeated anchors get '-1', '-2', ... suffixes.
+    let ofStrings (titles: seq<string>) : array<string> =
+        let occurrences = Collections.Generic.Dictionary<string, int>()
+
+        [| for title in titles do
+               let original = ofString title
+               let mutable result = original
+
+               while occurrences.ContainsKey(result) do
+                   occurrences[original] <- occurrences[original] + 1
+                   result <- $"{original}-{occurrences[original]}"
+
+               occurrences[result] <- 0
+               yield result |]
+
 let indentFmt (fmtA: 'A -> string) (a: 'A) =
     let reprA = fmtA a
 
@@ -189,18 +382,24 @@ type PathUri =
 
     member this.LocalPath: string = this.localPath
 
+    /// Unsaved buffers of the client, e.g. `untitled:Untitled-1`. They don't exist on disk and
+    /// are told apart by their URI rather than by their path.
+    member this.IsUntitled: bool = this.uri.StartsWith("untitled:")
+
+    member private this.Key: string = if this.IsUntitled then this.uri else this.localPath
+
     override this.Equals(obj) =
         match obj with
-        | :? PathUri as other -> this.LocalPath.Equals(other.LocalPath)
+        | :? PathUri as other -> this.Key.Equals(other.Key)
         | _ -> false
 
 
-    override this.GetHashCode() = this.LocalPath.GetHashCode()
+    override this.GetHashCode() = this.Key.GetHashCode()
 
     interface IComparable with
         member this.CompareTo(obj) =
             match obj with
-            | :? PathUri as other -> this.LocalPath.CompareTo(other.LocalPath)
+            | :? PathUri as other -> this.Key.CompareTo(other.Key)
             | _ -> failwith "Incompatible Type"
 
 
@@ -237,7 +436,16 @@ let localPathToUriString (filePath: string) : string =
         "file:///" + uri.ToString().TrimStart('/')
 
 module PathUri =
-    let ofString (str: string) : PathUri =
+    let private untitledScheme = "untitled:"
+
+    let private untitledName (uri: string) =
+        Uri.UnescapeDataString(uri.Substring(untitledScheme.Length))
+
+    /// The unsaved buffer `uri` placed in `dir`, so that its relative links resolve from there.
+    let untitledIn (dir: string) (uri: string) : PathUri =
+        { uri = uri; localPath = Path.Join(dir, Path.GetFileName(untitledName uri)) }
+
+    let private ofFileString (str: string) : PathUri =
         let unescaped = Uri.UnescapeDataString(str)
         let uri = Uri(unescaped)
         let localPath = uri.LocalPath
@@ -261,6 +469,12 @@ module PathUri =
 
         { uri = escapedUri; localPath = localPath }
 
+    let ofString (str: string) : PathUri =
+        if str.StartsWith(untitledScheme) then
+            { uri = str; localPath = untitledName str }
+        else
+            ofFileString str
+
 type Position with
 
     static member Mk(line: int, char: int) : Position = { Line = line; Character = char }
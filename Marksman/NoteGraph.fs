@@ -0,0 +1,225 @@
+/// The notes of a folder and the links between them, in a shape that external tools (site
+/// generators, graph visualizers) can consume, e.g. via `marksman index --output json`, or as
+/// Graphviz or GraphML via `marksman graph` and the `marksman/graph` request.
+module Marksman.NoteGraph
+
+open System
+open System.Xml.Linq
+open Newtonsoft.Json
+
+open Marksman.Cst
+open Marksman.Index
+open Marksman.Misc
+open Marksman.Refs
+open Marksman.Workspace
+
+type NoteHeading = { level: int; title: string; slug: string; line: int }
+
+type Note =
+    { path: string
+      title: option<string>
+      headings: array<NoteHeading>
+      tags: array<string>
+      /// Number of other notes linking to this one
+      backlinks: int
+      /// Only set by `withStats`, e.g. for `marksman index --stats`
+      stats: option<Stats.NoteStats> }
+
+/// A resolved link from one note to another one (or to itself). `heading` is set when the link
+/// targets a heading rather than the note as a whole. Lines are 0-based, as in LSP.
+type Edge =
+    { source: string
+      target: string
+      heading: option<string>
+      line: int }
+
+type Graph = { notes: array<Note>; links: array<Edge> }
+
+// Paths are relative to the folder root and use '/' on every platform
+let private notePath (doc: Doc) = (Doc.pathFromRoot doc).Replace('\\', '/')
+
+let private noteOfDoc (doc: Doc) : Note =
+    let index = Doc.index doc
+
+    let headings =
+        index.headings
+        |> Array.map (fun { data = heading } ->
+            { level = heading.level
+              title = Heading.name heading
+              slug = Heading.slug heading |> Slug.toString
+              line = (Heading.range heading).Start.Line })
+
+    let tags =
+        Index.tags index
+        |> Array.map (fun { data = tag } -> tag.name.text)
+        |> Array.distinct
+
+    { path = notePath doc
+      title = Doc.title doc |> Option.map (fun { data = title } -> Heading.name title)
+      headings = headings
+      tags = tags
+      backlinks = 0
+      stats = None }
+
+let private linksOfDoc (folder: Folder) (doc: Doc) : seq<Edge> =
+    Dest.resolveLinks folder doc
+    |> Map.toSeq
+    |> Seq.collect (fun (link, dests) ->
+        dests
+        |> Seq.choose (fun dest ->
+            let targetHeading =
+                match dest with
+                | Dest.Heading (_, { data = heading }) when not (Heading.isTitle heading) ->
+                    Some(Some(Heading.name heading))
+                | Dest.Doc _
+                | Dest.Heading _
+                | Dest.Block _ -> Some None
+                // Reference definitions live in the same note and aren't edges of the graph
+                | Dest.LinkDef _ -> None
+
+            targetHeading
+            |> Option.map (fun heading ->
+                { source = notePath doc
+                  target = notePath (Dest.doc dest)
+                  heading = heading
+                  line = (Element.range link).Start.Line })))
+
+let ofFolder (folder: Folder) : Graph =
+    let docs = Folder.docs folder |> Seq.sortBy notePath |> Array.ofSeq
+
+    let links =
+        docs
+        |> Seq.collect (linksOfDoc folder)
+        |> Seq.sortBy (fun link -> link.source, link.line, link.target)
+        |> Array.ofSeq
+
+    let backlinks =
+        links
+        |> Seq.filter (fun link -> link.source <> link.target)
+        |> Seq.distinctBy (fun link -> link.source, link.target)
+        |> Seq.countBy (fun link -> link.target)
+        |> Map.ofSeq
+
+    let noteOf doc =
+        let note = noteOfDoc doc
+        { note with backlinks = Map.tryFind note.path backlinks |> Option.defaultValue 0 }
+
+    { notes = docs |> Array.map noteOf; links = links }
+
+/// The graph with the statistics of every note.
+let withStats (folder: Folder) (graph: Graph) : Graph =
+    let docs = Folder.docs folder |> Seq.map (fun doc -> notePath doc, doc) |> Map.ofSeq
+
+    let withStatsOf (note: Note) =
+        { note with stats = Map.tryFind note.path docs |> Option.map (Stats.ofDoc folder) }
+
+    { graph with notes = Array.map withStatsOf graph.notes }
+
+let toJson (graph: Graph) : string =
+    (Ionide.LanguageServerProtocol.Server.serialize graph).ToString(Formatting.Indented)
+
+let private dotString (s: string) =
+    "\"" + s.Replace("\\", "\\\\").Replace("\"", "\\\"") + "\""
+
+/// The graph in the DOT language of Graphviz. Nodes are labelled with the titles of the notes, and
+/// links to headings with the heading.
+let toDot (graph: Graph) : string =
+    let nodes =
+        graph.notes
+        |> Array.map (fun note ->
+            let label = note.title |> Option.defaultValue note.path
+            let tags = String.Join(" ", note.tags)
+
+            $"  {dotString note.path} [label={dotString label}, tags={dotString tags}, "
+            + $"backlinks={note.backlinks}];")
+
+    let edges =
+        graph.links
+        |> Array.map (fun link ->
+            let label =
+                link.heading
+                |> Option.map (fun heading -> $" [label={dotString heading}]")
+                |> Option.defaultValue ""
+
+            $"  {dotString link.source} -> {dotString link.target}{label};")
+
+    [ [| "digraph notes {" |]; nodes; edges; [| "}"; "" |] ]
+    |> Array.concat
+    |> String.concat "\n"
+
+let private graphml = XNamespace.Get("http://graphml.graphdrawing.org/xmlns")
+
+/// The graph as GraphML, which graph editors like Gephi, yEd and Cytoscape open.
+let toGraphML (graph: Graph) : string =
+    let key (id: string) (target: string) (attrType: string) =
+        XElement(
+            graphml + "key",
+            XAttribute("id", id),
+            XAttribute("for", target),
+            XAttribute("attr.name", id),
+            XAttribute("attr.type", attrType)
+        )
+
+    let data (key: string) (value: obj) =
+        XElement(graphml + "data", XAttribute("key", key), value)
+
+    let node (note: Note) =
+        XElement(
+            graphml + "node",
+            XAttribute("id", note.path),
+            data "title" (note.title |> Option.defaultValue note.path |> box),
+            data "tags" (box (String.Join(" ", note.tags))),
+            data "backlinks" (box note.backlinks)
+        )
+
+    let edge (link: Edge) =
+        XElement(
+            graphml + "edge",
+            XAttribute("source", link.source),
+            XAttribute("target", link.target),
+            link.heading
+            |> Option.map (fun heading -> data "heading" (box heading))
+            |> Option.toArray,
+            data "line" (box link.line)
+        )
+
+    let root =
+        XElement(
+            graphml + "graphml",
+            key "title" "node" "string",
+            key "tags" "node" "string",
+            key "backlinks" "node" "int",
+            key "heading" "edge" "string",
+            key "line" "edge" "int",
+            XElement(
+                graphml + "graph",
+                XAttribute("id", "notes"),
+                XAttribute("edgedefault", "directed"),
+                Array.map node graph.notes,
+                Array.map edge graph.links
+            )
+        )
+
+    // The declaration is left out by `ToString` of the document
+    let document = XDocument(XDeclaration("1.0", "utf-8", null), root)
+    string document.Declaration + "\n" + string document
+
+[<RequireQualifiedAccess>]
+type Format =
+    | Json
+    | Dot
+    | GraphML
+
+module Format =
+    let ofString (input: string) : option<Format> =
+        match input.ToLowerInvariant() with
+        | "json" -> Some Format.Json
+        | "dot" -> Some Format.Dot
+        | "graphml" -> Some Format.GraphML
+        | _ -> None
+
+let format (format: Format) (graph: Graph) : string =
+    match format with
+    | Format.Json -> toJson graph
+    | Format.Dot -> toDot graph
+    | Format.GraphML -> toGraphML graph
@@ -0,0 +1,113 @@
+/// Numbers of the items of ordered lists and, optionally, of headings, e.g. `## 2.3.1 Scope` in
+/// specification-style notes. `marksman.renumber` updates them in place after items or sections
+/// were added, removed or moved.
+module Marksman.Numbering
+
+open System
+open System.Text.RegularExpressions
+open Ionide.LanguageServerProtocol.Types
+open Markdig
+open Markdig.Syntax
+
+open Marksman.Cst
+open Marksman.Workspace
+
+let private pipeline = MarkdownPipelineBuilder().UseYamlFrontMatter().Build()
+
+let private itemNumber = Regex(@"\G[ \t]*(\d{1,9})", RegexOptions.Compiled)
+
+/// Edits numbering the items of every ordered list one after the other, from the number of its
+/// first item. Items numbered right already are left alone.
+let listEdits (doc: Doc) : array<TextEdit> =
+    let text = Doc.text doc
+    let document = Markdown.Parse(text.content, pipeline)
+
+    let edit (m: Match) (number: string) =
+        let group = m.Groups[1]
+        let start = text.lineMap.FindPosition(group.Index)
+        let end_ = text.lineMap.FindPosition(group.Index + group.Length)
+
+        if group.Value = number then
+            None
+        else
+            Some { Range = Range.Mk(start, end_); NewText = number }
+
+    document.Descendants<ListBlock>()
+    |> Seq.filter (fun listBlock -> listBlock.IsOrdered)
+    |> Seq.collect (fun listBlock ->
+        let first =
+            match Int32.TryParse(listBlock.OrderedStart) with
+            | true, first -> first
+            | _ -> 1
+
+        listBlock
+        |> Seq.indexed
+        |> Seq.choose (fun (index, item) ->
+            let m = itemNumber.Match(text.content, item.Span.Start)
+            if m.Success then edit m (string (first + index)) else None))
+    |> Array.ofSeq
+
+// Numbers as written by `headingEdits`, e.g. `3.` or `2.3.1`. A plain `3` could be part of the
+// title, like in `3 ways to go`
+let private headingNumber = Regex(@"^(\d+(\.\d+)+\.?|\d+\.)([ \t]+|$)", RegexOptions.Compiled)
+
+let private plainNumber = Regex(@"^\d+([ \t]+|$)", RegexOptions.Compiled)
+
+/// The number of every heading by its place among the sections of its parent, e.g. `2.3.1` for
+/// the first subsection of the third subsection of the second section. Titles aren't numbered,
+/// so the sections under a title are numbered from 1.
+let headingNumbers (doc: Doc) : array<Node<Heading> * string> =
+    let rec number (prefix: string) (els: array<Element>) =
+        let headings =
+            els
+            |> Array.choose (function
+                | H heading -> Some heading
+                | _ -> None)
+
+        let sections =
+            headings |> Array.filter (fun { data = heading } -> not (Heading.isTitle heading))
+
+        seq {
+            for { data = heading } in headings do
+                if Heading.isTitle heading then
+                    yield! number "" heading.children
+
+            for index, section in Array.indexed sections do
+                let sectionNumber = $"{prefix}{index + 1}"
+                yield section, sectionNumber
+                yield! number (sectionNumber + ".") section.data.children
+        }
+
+    number "" (Doc.cst doc) |> Array.ofSeq
+
+/// Edits putting the number of every heading in front of its title, in place of the number it
+/// has already: one in the form written here, with a dot after a single component like `2.`, or
+/// the very number of the heading.
+let headingEdits (doc: Doc) : array<TextEdit> =
+    headingNumbers doc
+    |> Array.choose (fun ({ data = heading }, number) ->
+        let title = heading.title
+        let written = if number.Contains('.') then number else number + "."
+
+        let existing =
+            match headingNumber.Match(title.text), plainNumber.Match(title.text) with
+            | existing, _ when existing.Success -> existing
+            | _, plain when plain.Success && plain.Value.TrimEnd() = number -> plain
+            | existing, _ -> existing
+
+        let rest = title.text.Substring(existing.Length)
+        let newText = if rest = "" then written else written + " "
+
+        if existing.Value = newText then
+            None
+        else
+            let start = title.range.Start
+            let end_ = Position.Mk(start.Line, start.Character + existing.Length)
+            Some { Range = Range.Mk(start, end_); NewText = newText })
+
+/// Edits renumbering the ordered lists of the doc, and its headings when `headings` is set.
+let edits (headings: bool) (doc: Doc) : array<TextEdit> =
+    if headings then
+        Array.append (listEdits doc) (headingEdits doc)
+    else
+        listEdits doc
@@ -19,10 +19,12 @@ module Markdown =
         (
             text: string,
             doc: Option<string * SourceSpan>,
-            heading: option<string * SourceSpan>
+            heading: option<string * SourceSpan>,
+            embed: bool
         ) =
         inherit LeafInline()
         member val Text = text
+        member val Embed = embed
 
         member val Doc = Option.map fst doc
         member val DocSpan = Option.map snd doc
@@ -63,17 +65,117 @@ module Markdown =
                 false
 
 
+    /// Footnote definition, e.g. `[^1]: Some text`. Lines indented under it, blank lines between
+    /// them included, and lines right after a line of its content that don't start another block
+    /// are part of its content.
+    type FootnoteDefBlock(parser: BlockParser) =
+        inherit LeafBlock(parser)
+
+        member val Label = String.Empty with get, set
+        member val LabelSpan = SourceSpan.Empty with get, set
+        member val Content = String.Empty with get, set
+        member val ContentSpan = SourceSpan.Empty with get, set
+        member val AfterBlankLine = false with get, set
+
+    type FootnoteDefParser() as this =
+        inherit BlockParser()
+
+        static let footnoteDefPat =
+            System.Text.RegularExpressions.Regex(@"^\[(\^[^\]\s]+)\]:[ \t]*(.*?)[ \t\r]*$")
+
+        // Lines that may start another block, e.g. a heading, a list or the next footnote or link
+        // definition, and so don't continue the footnote unless they are indented
+        static let blockStartPat =
+            System.Text.RegularExpressions.Regex(
+                @"^ {0,3}([#>*+_=<|~`-]|\d{1,9}[.)]([ \t]|$)|\[[^\]]+\]:)"
+            )
+
+        do this.OpeningCharacters <- [| '[' |]
+
+        override this.TryOpen(processor) =
+            if processor.IsCodeIndent then
+                BlockState.None
+            else
+                let line = processor.Line
+                let lineStart = line.Start
+                let m = footnoteDefPat.Match(line.ToString())
+
+                if m.Success then
+                    let label = m.Groups[1]
+                    let content = m.Groups[2]
+
+                    let block = FootnoteDefBlock(this)
+                    block.Span <- SourceSpan(lineStart, lineStart + m.Length - 1)
+                    block.Line <- processor.LineIndex
+                    block.Column <- processor.Column
+                    block.Label <- label.Value
+
+                    block.LabelSpan <-
+                        SourceSpan(lineStart + label.Index, lineStart + label.Index + label.Length - 1)
+
+                    block.Content <- content.Value
+
+                    if content.Length > 0 then
+                        block.ContentSpan <-
+                            SourceSpan(
+                                lineStart + content.Index,
+                                lineStart + content.Index + content.Length - 1
+                            )
+
+                    processor.NewBlocks.Push(block)
+                    BlockState.ContinueDiscard
+                else
+                    BlockState.None
+
+        override this.TryContinue(processor, block) =
+            let fn = block :?> FootnoteDefBlock
+
+            if processor.IsBlankLine then
+                fn.AfterBlankLine <- true
+                BlockState.ContinueDiscard
+            else
+                let line = processor.Line
+                let content = line.ToString()
+
+                let continues =
+                    processor.IsCodeIndent
+                    || not fn.AfterBlankLine && not (blockStartPat.IsMatch(content))
+
+                if continues then
+                    let start = line.Start + content.Length - content.TrimStart().Length
+                    let end_ = line.Start + content.TrimEnd().Length - 1
+
+                    fn.ContentSpan <-
+                        if fn.ContentSpan.IsEmpty then
+                            SourceSpan(start, end_)
+                        else
+                            SourceSpan(fn.ContentSpan.Start, end_)
+
+                    fn.Content <- line.Text.Substring(fn.ContentSpan.Start, fn.ContentSpan.Length)
+                    fn.Span <- SourceSpan(fn.Span.Start, end_)
+                    fn.AfterBlankLine <- false
+                    BlockState.ContinueDiscard
+                else
+                    BlockState.None
+
     type WikiLinkParser() as this =
         inherit InlineParser()
 
-        do this.OpeningCharacters <- [| '[' |]
+        do this.OpeningCharacters <- [| '['; '!' |]
 
         override this.Match(processor, slice) =
-            let nextChar = slice.PeekCharExtra(1)
+            let embedStart = slice.Start
+            let isEmbed = slice.CurrentChar = '!'
 
-            let isRef = nextChar = '['
+            let isRef =
+                if isEmbed then
+                    slice.PeekCharExtra(1) = '[' && slice.PeekCharExtra(2) = '['
+                else
+                    slice.PeekCharExtra(1) = '['
 
             if isRef then
+                if isEmbed then slice.NextChar() |> ignore
+
                 let start = slice.Start
                 let offsetStart = processor.GetSourcePosition(start)
                 let offsetInnerStart = offsetStart + 2
@@ -104,7 +206,11 @@ module Markdown =
                     let offsetEnd = offsetStart + (end_ - start)
                     let offsetInnerEnd = offsetEnd - 2
 
-                    let text = slice.Text.Substring(start, end_ - start + 1)
+                    let text =
+                        if isEmbed then
+                            slice.Text.Substring(embedStart, end_ - embedStart + 1)
+                        else
+                            slice.Text.Substring(start, end_ - start + 1)
 
                     let doc, heading =
                         match offsetHashDelim with
@@ -154,8 +260,12 @@ module Markdown =
                             Some(docText, SourceSpan(offsetDocStart, offsetDocEnd)), None
 
 
-                    let link = WikiLinkInline(text, doc, heading)
-                    link.Span <- SourceSpan(offsetStart, offsetEnd)
+                    let link = WikiLinkInline(text, doc, heading, isEmbed)
+
+                    let offsetLinkStart =
+                        if isEmbed then offsetStart - 1 else offsetStart
+
+                    link.Span <- SourceSpan(offsetLinkStart, offsetEnd)
                     processor.Inline <- link
 
                 found
@@ -167,10 +277,13 @@ module Markdown =
             MarkdownPipelineBuilder()
                 .UsePreciseSourceLocation()
                 .UseYamlFrontMatter()
+                .UsePipeTables()
+                .UseMathematics()
 
         pipelineBuilder.InlineParsers.Insert(0, MarkdigPatches.PatchedLinkInlineParser())
         pipelineBuilder.InlineParsers.Insert(0, WikiLinkParser())
         pipelineBuilder.InlineParsers.Add(TagsParser())
+        pipelineBuilder.BlockParsers.Add(FootnoteDefParser())
         pipelineBuilder.Build()
 
     let sourceSpanToRange (text: Text) (span: SourceSpan) : Range =
@@ -180,16 +293,308 @@ module Markdown =
             { Start = start; End = start }
         else
             let endInclusive = text.lineMap.FindPosition(span.End)
-            let endOffset = if Char.IsSurrogate(text.content, span.End) then 2 else 1
+            let charLength = if Char.IsSurrogate(text.content, span.End) then 2 else 1
+            let charEnd = min (span.End + charLength) text.content.Length
+
+            let endOffset =
+                PositionEncoding.units
+                    (PositionEncoding.current ())
+                    (fun i -> text.content[i])
+                    span.End
+                    charEnd
 
             { Start = start
               End = { endInclusive with Character = endInclusive.Character + endOffset } }
 
 
+    let private blockAnchorPat =
+        System.Text.RegularExpressions.Regex(@"(?:^|\s)\^([A-Za-z0-9-]+)[ \t\r]*$")
+
+    let private tryParseBlockAnchor (text: Text) (para: ParagraphBlock) : option<Node<BlockAnchor>> =
+        if para.Span.IsEmpty then
+            None
+        else
+            let paraText = text.content.Substring(para.Span.Start, para.Span.Length)
+            let m = blockAnchorPat.Match(paraText)
+
+            let id = m.Groups[1]
+            // Include the leading ^ into the anchor's range
+            let anchorStart = para.Span.Start + id.Index - 1
+            let anchorEnd = para.Span.Start + id.Index + id.Length - 1
+
+            let isInCode () =
+                para.Descendants<CodeInline>()
+                |> Seq.exists (fun code ->
+                    code.Span.Start <= anchorStart && anchorStart <= code.Span.End)
+
+            if m.Success && not (isInCode ()) then
+                let anchorRange = sourceSpanToRange text (SourceSpan(anchorStart, anchorEnd))
+                let idRange = { anchorRange with Start = anchorRange.Start.NextChar(1) }
+
+                let anchor =
+                    { id = Node.mkText id.Value idRange
+                      scope = sourceSpanToRange text para.Span }
+
+                Some(Node.mk $"^{id.Value}" anchorRange anchor)
+            else
+                None
+
+    let private taskPat =
+        System.Text.RegularExpressions.Regex(@"^\[([ xX])\](?:[ \t]+|$)([^\r\n]*)")
+
+    /// Parses the `[ ]`/`[x]` marker at the start of the first paragraph of a list item.
+    let private tryParseTask (text: Text) (para: ParagraphBlock) : option<Node<Task>> =
+        let isFirstInListItem =
+            match para.Parent with
+            | :? ListItemBlock as item -> item.Count > 0 && obj.ReferenceEquals(item[0], para)
+            | _ -> false
+
+        if not isFirstInListItem || para.Span.IsEmpty then
+            None
+        else
+            let paraText = text.content.Substring(para.Span.Start, para.Span.Length)
+            let m = taskPat.Match(paraText)
+
+            if m.Success then
+                let markerRange =
+                    sourceSpanToRange text (SourceSpan(para.Span.Start, para.Span.Start + 2))
+
+                let content = m.Groups[2]
+                let contentText = content.Value.TrimEnd()
+                let contentStart = para.Span.Start + content.Index
+
+                let contentRange =
+                    if contentText.Length = 0 then
+                        Range.Mk(markerRange.End, markerRange.End)
+                    else
+                        sourceSpanToRange
+                            text
+                            (SourceSpan(contentStart, contentStart + contentText.Length - 1))
+
+                let task =
+                    { completed = m.Groups[1].Value <> " "
+                      text = Node.mkText contentText contentRange }
+
+                Some(Node.mk (paraText.Substring(0, 3)) markerRange task)
+            else
+                None
+
+    // `-`/`+` after the marker make the callout foldable, e.g. `> [!TIP]- Collapsed by default`
+    let private calloutPat =
+        System.Text.RegularExpressions.Regex(@"^\[!([A-Za-z][\w-]*)\][+-]?[ \t]*([^\r\n]*)")
+
+    /// Parses the `[!KIND]` marker at the start of the first paragraph of a block quote.
+    let private tryParseCallout (text: Text) (quote: QuoteBlock) : option<Node<Callout>> =
+        match Seq.tryHead quote with
+        | Some (:? ParagraphBlock as para) when not para.Span.IsEmpty ->
+            let paraText = text.content.Substring(para.Span.Start, para.Span.Length)
+            let m = calloutPat.Match(paraText)
+
+            if m.Success then
+                let kind = m.Groups[1]
+                let kindStart = para.Span.Start + kind.Index
+                let kindSpan = SourceSpan(kindStart, kindStart + kind.Length - 1)
+                let markerSpan = SourceSpan(para.Span.Start, kindSpan.End + 1)
+
+                let title =
+                    let title = m.Groups[2]
+                    let titleText = title.Value.TrimEnd()
+                    let titleStart = para.Span.Start + title.Index
+
+                    if titleText.Length = 0 then
+                        None
+                    else
+                        let titleSpan = SourceSpan(titleStart, titleStart + titleText.Length - 1)
+                        Some(Node.mkText titleText (sourceSpanToRange text titleSpan))
+
+                let callout =
+                    { kind = Node.mkText kind.Value (sourceSpanToRange text kindSpan)
+                      title = title
+                      scope = sourceSpanToRange text quote.Span }
+
+                let markerText = text.content.Substring(markerSpan.Start, markerSpan.Length)
+                Some(Node.mk markerText (sourceSpanToRange text markerSpan) callout)
+            else
+                None
+        | _ -> None
+
+    /// Splits a table row into trimmed cells. A `|` inside a cell has to be escaped as `\|`.
+    let private tableRowCells (text: Text) (lineStart: int) (line: string) : array<TextNode> =
+        let pipes = ResizeArray()
+        let mutable i = 0
+
+        while i < line.Length do
+            match line[i] with
+            | '\\' -> i <- i + 2
+            | '|' ->
+                pipes.Add(i)
+                i <- i + 1
+            | _ -> i <- i + 1
+
+        let bounds = Seq.concat [ [ -1 ]; List.ofSeq pipes; [ line.Length ] ]
+
+        let segments =
+            bounds
+            |> Seq.pairwise
+            |> Seq.map (fun (pipe, nextPipe) -> pipe + 1, nextPipe)
+            |> Array.ofSeq
+
+        let isBlank (start, end_) = line.Substring(start, end_ - start).IsWhitespace()
+
+        // Leading and trailing pipes are optional and don't delimit cells
+        let segments =
+            if segments.Length > 1 && line.TrimStart().StartsWith('|') && isBlank segments[0] then
+                segments[1..]
+            else
+                segments
+
+        let segments =
+            if segments.Length > 1
+               && line.TrimEnd().EndsWith('|')
+               && isBlank segments[segments.Length - 1] then
+                segments[.. segments.Length - 2]
+            else
+                segments
+
+        segments
+        |> Array.map (fun (start, end_) ->
+            let content = line.Substring(start, end_ - start)
+            let trimmed = content.Trim()
+            let cellStart = lineStart + start + (content.Length - content.TrimStart().Length)
+
+            let range =
+                if trimmed.Length = 0 then
+                    let pos = text.lineMap.FindPosition(lineStart + start)
+                    Range.Mk(pos, pos)
+                else
+                    sourceSpanToRange text (SourceSpan(cellStart, cellStart + trimmed.Length - 1))
+
+            Node.mkText trimmed range)
+
+    let private columnAlign (delimiter: string) =
+        match delimiter.StartsWith(':'), delimiter.Length > 1 && delimiter.EndsWith(':') with
+        | true, true -> ColumnAlign.Center
+        | true, false -> ColumnAlign.Left
+        | false, true -> ColumnAlign.Right
+        | false, false -> ColumnAlign.Default
+
+    let private parseTable
+        (text: Text)
+        (table: Markdig.Extensions.Tables.Table)
+        : option<Node<Table>> =
+        let tableText = text.content.Substring(table.Span.Start, table.Span.Length)
+
+        // A table in a block quote or a list item starts after the container's marker, e.g.
+        // `> `, and the lines after the first one start with it or with the indent of the item
+        let firstLine = text.lineMap.FindPosition(table.Span.Start).Line
+        let prefixWidth = table.Span.Start - fst text.lineMap.Map[firstLine]
+
+        let isPrefixChar (char: char) = char = '>' || Char.IsWhiteSpace(char)
+
+        let prefixOf (line: string) =
+            let mutable i = 0
+
+            while i < min prefixWidth line.Length && isPrefixChar (line[i]) do
+                i <- i + 1
+
+            line.Substring(0, i)
+
+        let lines =
+            seq {
+                let mutable lineStart = table.Span.Start
+
+                for index, rawLine in Array.indexed (tableText.Split('\n')) do
+                    let line = rawLine.TrimEnd('\r')
+                    let prefix = if index = 0 then "" else prefixOf line
+                    yield lineStart + prefix.Length, prefix, line.Substring(prefix.Length)
+                    lineStart <- lineStart + rawLine.Length + 1
+            }
+            |> Seq.filter (fun (_, _, line) -> not (line.IsWhitespace()))
+            |> Array.ofSeq
+
+        if lines.Length < 2 then
+            None
+        else
+            let cells (lineStart, _, line) = tableRowCells text lineStart line
+            let _, linePrefix, _ = lines[1]
+
+            let parsed =
+                { header = cells lines[0]
+                  alignments = cells lines[1] |> Array.map (Node.text >> columnAlign)
+                  rows = lines[2..] |> Array.map cells
+                  linePrefix = linePrefix }
+
+            Some(Node.mk tableText (sourceSpanToRange text table.Span) parsed)
+
+    let private headingIdPat =
+        System.Text.RegularExpressions.Regex(@"[ \t]*\{#([^\s{}]+)\}$")
+
+    // Keys start with a letter, digit or '_' and may contain internal punctuation, e.g. `@doe:99`
+    let private citationPat =
+        System.Text.RegularExpressions.Regex(
+            @"(?<=^|[\s;\[-])@([\p{L}\p{N}_](?:[\p{L}\p{N}_:.#$%&+?<>~/-]*[\p{L}\p{N}_])?)"
+        )
+
+    /// Citations inside the label of a bracketed shortcut link, e.g. `[see @doe99; @roe20]`.
+    let private parseCitations (text: Text) (labelSpan: SourceSpan) : array<Node<Citation>> =
+        let label = text.content.Substring(labelSpan.Start, labelSpan.Length)
+
+        [| for m in citationPat.Matches(label) do
+               let key = m.Groups[1]
+               let start = labelSpan.Start + m.Index
+               let keySpan = SourceSpan(labelSpan.Start + key.Index, start + m.Length - 1)
+               let keyNode = Node.mkText key.Value (sourceSpanToRange text keySpan)
+               let range = sourceSpanToRange text (SourceSpan(start, keySpan.End))
+               yield Node.mk m.Value range { key = keyNode } |]
+
+    let private regionTogglePat =
+        System.Text.RegularExpressions.Regex(@"^<!--[ \t]*marksman:(off|on)[ \t]*-->\s*$")
+
+    /// Source spans between `<!-- marksman:off -->` and the following `<!-- marksman:on -->`
+    /// (or the end of the document). `toggles` are comment offsets with their text.
+    let private disabledRegions (text: Text) (toggles: seq<int * string>) : list<int * int> =
+        let folder (offStart, regions) (offset, comment) =
+            let m = regionTogglePat.Match(comment)
+
+            match offStart, m.Success && m.Groups[1].Value = "off" with
+            | None, true -> Some offset, regions
+            | Some start, false when m.Success -> None, (start, offset) :: regions
+            | _ -> offStart, regions
+
+        match toggles |> Seq.sortBy fst |> Seq.fold folder (None, []) with
+        | Some start, regions -> (start, text.content.Length) :: regions
+        | None, regions -> regions
+
     let scrapeText (text: Text) : array<Element> =
         let parsed: MarkdownObject = Markdown.Parse(text.content, markdigPipeline)
 
         let elements = ResizeArray()
+        let toggles = ResizeArray()
+
+        let tasks =
+            parsed.Descendants<ParagraphBlock>()
+            |> Seq.choose (fun para ->
+                tryParseTask text para |> Option.map (fun task -> para.Span.Start, task))
+            |> Map.ofSeq
+
+        let callouts =
+            parsed.Descendants<QuoteBlock>()
+            |> Seq.choose (fun quote ->
+                tryParseCallout text quote |> Option.map (fun callout -> quote.Span.Start, callout))
+            |> Map.ofSeq
+
+        let calloutMarkers =
+            callouts
+            |> Map.values
+            |> Seq.map (fun callout -> text.lineMap.FindOffset(callout.range.Start))
+            |> Set.ofSeq
+
+        // `[x]` of a task and `[!NOTE]` of a callout are valid shortcut reference links for
+        // Markdig; they aren't links though
+        let isMarker (link: LinkInline) =
+            link.IsShortcut
+            && (link.Span.Length = 3 && tasks.ContainsKey(link.Span.Start)
+                || calloutMarkers.Contains(link.Span.Start))
 
         for b in parsed.Descendants() do
             match b with
@@ -207,18 +612,47 @@ module Markdown =
                 // TODO: remove after https://github.com/xoofx/markdig/pull/696 is released
                 if h.Span.End < text.content.Length then
                     let fullText = text.content.Substring(h.Span.Start, h.Span.Length)
-                    let title0 = fullText.TrimStart(' ', '#')
-                    let headingPrefixLen = fullText.Length - title0.Length
+
+                    // Setext headings have the title on the line(s) above the === or --- underline
+                    let content, title0 =
+                        if h.IsSetext then
+                            let underlineStart = fullText.LastIndexOf('\n')
+
+                            let content =
+                                if underlineStart < 0 then
+                                    fullText
+                                else
+                                    fullText.Substring(0, underlineStart).TrimEnd('\r')
+
+                            content, content.TrimStart(' ')
+                        else
+                            fullText, fullText.TrimStart(' ', '#')
+
+                    let headingPrefixLen = content.Length - title0.Length
                     let title = title0.TrimEnd(' ')
-                    let headingSuffixLen = title0.Length - title.Length
+                    let titleStart = h.Span.Start + headingPrefixLen
+
+                    // Pandoc/kramdown-style explicit id, e.g. `# Heading {#custom-id}`
+                    let title, id =
+                        let m = headingIdPat.Match(title)
+
+                        if m.Success && m.Index > 0 then
+                            let idGroup = m.Groups[1]
+                            let idStart = titleStart + idGroup.Index
+
+                            let idRange =
+                                sourceSpanToRange
+                                    text
+                                    (SourceSpan(idStart, idStart + idGroup.Length - 1))
+
+                            title.Substring(0, m.Index), Some(Node.mkText idGroup.Value idRange)
+                        else
+                            title, None
 
                     let titleRange =
                         sourceSpanToRange
                             text
-                            (SourceSpan(
-                                h.Span.Start + headingPrefixLen,
-                                h.Span.End - headingSuffixLen
-                            ))
+                            (SourceSpan(titleStart, titleStart + title.Length - 1))
 
                     let range = sourceSpanToRange text h.Span
 
@@ -228,10 +662,52 @@ module Markdown =
                             range
                             { level = level
                               title = Node.mkText title titleRange
+                              id = id
                               scope = range
                               children = [||] }
 
                     elements.Add(H heading)
+            // NOTE: MathBlock is a CodeBlock too, hence it has to be matched first
+            | :? Markdig.Extensions.Mathematics.MathBlock as math when
+                math.Span.End < text.content.Length
+                ->
+                let mathText = text.content.Substring(math.Span.Start, math.Span.Length)
+                let range = sourceSpanToRange text math.Span
+                elements.Add(C(Node.mk mathText range (Code.Math true)))
+            | :? Markdig.Extensions.Mathematics.MathInline as math ->
+                let mathText = text.content.Substring(math.Span.Start, math.Span.Length)
+                let range = sourceSpanToRange text math.Span
+                // `$$...$$` within a paragraph is display math too
+                elements.Add(C(Node.mk mathText range (Code.Math(math.DelimiterCount > 1))))
+            // NOTE: YamlFrontMatterBlock is a CodeBlock too, hence it has to be matched first
+            | :? CodeBlock as code when code.Span.End < text.content.Length ->
+                let codeText = text.content.Substring(code.Span.Start, code.Span.Length)
+                let range = sourceSpanToRange text code.Span
+
+                let info =
+                    match code with
+                    | :? FencedCodeBlock as fenced when not (String.IsNullOrEmpty fenced.Info) ->
+                        Some fenced.Info
+                    | _ -> None
+
+                elements.Add(C(Node.mk codeText range (Code.Block info)))
+            | :? Markdig.Extensions.Tables.Table as table when
+                not table.Span.IsEmpty && table.Span.End < text.content.Length
+                ->
+                parseTable text table |> Option.iter (TB >> elements.Add)
+            | :? CodeInline as code ->
+                let codeText = text.content.Substring(code.Span.Start, code.Span.Length)
+                let range = sourceSpanToRange text code.Span
+                elements.Add(C(Node.mk codeText range Code.Inline))
+            | :? HtmlBlock as html when html.Span.End < text.content.Length ->
+                let htmlText = text.content.Substring(html.Span.Start, html.Span.Length)
+                let range = sourceSpanToRange text html.Span
+                elements.Add(C(Node.mk htmlText range Code.Html))
+                toggles.Add((html.Span.Start, htmlText))
+            | :? HtmlInline as html when html.Tag.StartsWith("<!--") ->
+                let range = sourceSpanToRange text html.Span
+                elements.Add(C(Node.mk html.Tag range Code.Html))
+                toggles.Add((html.Span.Start, html.Tag))
             | :? WikiLinkInline as link ->
                 let doc =
                     match link.Doc, link.DocSpan with
@@ -245,10 +721,46 @@ module Markdown =
                         Node.mkText heading (sourceSpanToRange text headingSpan) |> Some
                     | _ -> None
 
-                let wikiLink: WikiLink = { doc = doc; heading = heading }
+                // Everything after the first '|' is the alias, e.g. [[doc#heading|alias]]. The
+                // parts are found by their offsets into the text, which the line map turns into
+                // positions in the position encoding.
+                let splitAlias (node: TextNode) (aliasEnd: Position) =
+                    let bar = node.text.IndexOf('|')
+                    let start = text.lineMap.FindOffset(node.range.Start)
+                    let at offset = text.lineMap.FindPosition(start + offset)
+
+                    let target =
+                        let range = Range.Mk(node.range.Start, at bar)
+                        Some(Node.mkText (node.text.Substring(0, bar)) range)
+                        |> Option.filter (fun target -> target.text <> "")
+
+                    let aliasRange = Range.Mk(at (bar + 1), aliasEnd)
+                    target, Some(Node.mkText (text.Substring(aliasRange)) aliasRange)
+
+                let doc, heading, alias =
+                    match doc, heading with
+                    // A '#' after the '|' is a part of the alias
+                    | Some docNode, Some headingNode when docNode.text.Contains('|') ->
+                        let doc, alias = splitAlias docNode headingNode.range.End
+                        doc, None, alias
+                    | Some docNode, None when docNode.text.Contains('|') ->
+                        let doc, alias = splitAlias docNode docNode.range.End
+                        doc, None, alias
+                    | _, Some headingNode when headingNode.text.Contains('|') ->
+                        let heading, alias = splitAlias headingNode headingNode.range.End
+                        doc, heading, alias
+                    | _ -> doc, heading, None
+
+                let wikiLink: WikiLink =
+                    { doc = doc
+                      heading = heading
+                      alias = alias
+                      embed = link.Embed }
+
                 let range = sourceSpanToRange text link.Span
                 let xref = Node.mk link.Text range wikiLink
                 elements.Add(WL xref)
+            | :? LinkInline as l when isMarker l -> ()
             | :? LinkInline as l ->
                 let linkRange = sourceSpanToRange text l.Span
 
@@ -270,6 +782,16 @@ module Markdown =
                 let urlSpan = l.UrlSpan
                 let url = l.Url
 
+                // Destinations in angle brackets, e.g. `(<my note.md>)`, may contain spaces;
+                // the brackets themselves aren't part of the url
+                let urlSpan =
+                    if urlSpan.Length >= 2
+                       && text.content[urlSpan.Start] = '<'
+                       && text.content[urlSpan.End] = '>' then
+                        SourceSpan(urlSpan.Start + 1, urlSpan.End - 1)
+                    else
+                        urlSpan
+
                 if not l.IsShortcut then
                     if isRegularLink then
                         let label = Node.mkText label (sourceSpanToRange text labelSpan)
@@ -303,9 +825,12 @@ module Markdown =
                         let link = MdLink.RF(text_, label) |> Node.mk linkText linkRange
                         elements.Add(ML link)
                 else
-                    let label = Node.mkText label (sourceSpanToRange text labelSpan)
-                    let link = MdLink.RS(label) |> Node.mk linkText linkRange
-                    elements.Add(ML link)
+                    match parseCitations text labelSpan with
+                    | [||] ->
+                        let label = Node.mkText label (sourceSpanToRange text labelSpan)
+                        let link = MdLink.RS(label) |> Node.mk linkText linkRange
+                        elements.Add(ML link)
+                    | citations -> citations |> Array.iter (CT >> elements.Add)
             | :? LinkReferenceDefinition as linkDef ->
                 let defRange = sourceSpanToRange text linkDef.Span
 
@@ -333,6 +858,25 @@ module Markdown =
                 elements.Add(MLD def)
 
                 ()
+            | :? FootnoteDefBlock as fn ->
+                let defRange = sourceSpanToRange text fn.Span
+                let defText = text.content.Substring(fn.Span.Start, fn.Span.Length)
+
+                let label = Node.mkText fn.Label (sourceSpanToRange text fn.LabelSpan)
+
+                // Footnotes are represented as link definitions with the footnote's content in place
+                // of the URL. This way `[^1]` resolves to its footnote like any other reference link.
+                let contentRange =
+                    if fn.ContentSpan.IsEmpty then
+                        { Start = label.range.End.NextChar(2); End = defRange.End }
+                    else
+                        sourceSpanToRange text fn.ContentSpan
+
+                let content = Node.mkText fn.Content contentRange
+
+                let def = MdLinkDef.mk label content None |> Node.mk defText defRange
+
+                elements.Add(MLD def)
             | :? TagInline as tag ->
                 let tagText = tag.Text
                 let tagRange = sourceSpanToRange text tag.Span
@@ -349,8 +893,27 @@ module Markdown =
                 elements.Add(T tag)
 
                 ()
+            | :? QuoteBlock as quote ->
+                callouts.TryFind(quote.Span.Start) |> Option.iter (CO >> elements.Add)
+            | :? ParagraphBlock as para ->
+                tasks.TryFind(para.Span.Start) |> Option.iter (TK >> elements.Add)
+                tryParseBlockAnchor text para |> Option.iter (BA >> elements.Add)
             | _ -> ()
 
+        // Code and HTML elements are kept in disabled regions: they don't reference anything
+        let disabled = disabledRegions text toggles
+
+        let isDisabled el =
+            match el with
+            | C _ -> false
+            | _ ->
+                let start = text.lineMap.FindOffset((Element.range el).Start)
+
+                disabled
+                |> List.exists (fun (regionStart, regionEnd) ->
+                    regionStart <= start && start < regionEnd)
+
+        elements.RemoveAll(fun el -> isDisabled el) |> ignore
         elements.ToArray()
 
 let rec private reconstructHierarchy (text: Text) (flat: seq<Element>) : seq<Element> =
@@ -401,6 +964,12 @@ let rec private reconstructHierarchy (text: Text) (flat: seq<Element>) : seq<Ele
             match el with
             | YML _
             | T _
+            | BA _
+            | C _
+            | TK _
+            | TB _
+            | CT _
+            | CO _
             | WL _
             | ML _
             | MLD _ ->
@@ -412,6 +981,7 @@ let rec private reconstructHierarchy (text: Text) (flat: seq<Element>) : seq<Ele
         let guardHead =
             { level = -1
               title = Node.mkText "" (text.EndRange())
+              id = None
               scope = text.EndRange()
               children = [||] }
             |> Node.mk "" (text.EndRange())
@@ -454,3 +1024,128 @@ let rec parseText (text: Text) : Cst =
 
         sortElements text elements
         elements
+
+// Headings starting at the beginning of a line end whatever block comes before them, so the text
+// between two of them parses the same on its own as within the note. Returns their lines
+let private sectionStarts (flat: seq<Element>) : array<int> =
+    flat
+    |> Seq.choose (function
+        | H h when h.range.Start.Character = 0 && h.text.StartsWith('#') -> Some h.range.Start.Line
+        | _ -> None)
+    |> Array.ofSeq
+
+let private isLinkDef =
+    function
+    | MLD _ -> true
+    | _ -> false
+
+/// Parse of `newText` that reuses `prevCst`, the parse of `prevText`, for the sections that the
+/// change didn't touch: only the text between the headings around the changed lines is parsed
+/// again. Changes that can affect how the rest of the note parses, e.g. an opened code fence or a
+/// link definition that other sections may refer to, lead to parsing the whole text.
+let reparseText (prevText: Text) (prevCst: Cst) (newText: Text) : Cst =
+    let prevLines = prevText.lineMap.NumLines
+    let newLines = newText.lineMap.NumLines
+
+    let sameLine (prevLine: int) (newLine: int) =
+        let prevStart, prevEnd = prevText.lineMap.Map[prevLine]
+        let newStart, newEnd = newText.lineMap.Map[newLine]
+        let length = prevEnd - prevStart
+
+        length = newEnd - newStart
+        && String.CompareOrdinal(prevText.content, prevStart, newText.content, newStart, length) = 0
+
+    let common = min prevLines newLines
+
+    let mutable prefix = 0
+
+    while prefix < common && sameLine prefix prefix do
+        prefix <- prefix + 1
+
+    let suffixMatches count =
+        count < common - prefix && sameLine (prevLines - 1 - count) (newLines - 1 - count)
+
+    let mutable suffix = 0
+
+    while suffixMatches suffix do
+        suffix <- suffix + 1
+
+    let flat =
+        Cst.elementsAll prevCst
+        |> Seq.map (function
+            | H h -> H { h with data = { h.data with children = [||] } }
+            | el -> el)
+        |> Array.ofSeq
+
+    // Regions disabled with `<!-- marksman:off -->` can span any number of sections
+    let hasToggles =
+        prevText.content.Contains("marksman:") || newText.content.Contains("marksman:")
+
+    if prefix = prevLines && prefix = newLines then
+        prevCst
+    elif String.IsNullOrEmpty prevText.content
+         || String.IsNullOrEmpty newText.content
+         || hasToggles
+         || Array.exists isLinkDef flat then
+        parseText newText
+    else
+        let starts = sectionStarts flat
+        let startLine el = (Element.range el).Start.Line
+        let lineShift = newLines - prevLines
+
+        let regionStart =
+            starts |> Array.filter (fun line -> line < prefix) |> Array.fold max 0
+
+        let prevRegionEnd = starts |> Array.tryFind (fun line -> line >= prevLines - suffix)
+        let regionEnd = prevRegionEnd |> Option.map (fun line -> line + lineShift)
+
+        // The heading after the region is parsed too, to check that it's still a heading
+        let regionText =
+            let startOffset = fst newText.lineMap.Map[regionStart]
+
+            let endOffset =
+                match regionEnd with
+                | Some line -> snd newText.lineMap.Map[line]
+                | None -> newText.content.Length
+
+            mkText (newText.content.Substring(startOffset, endOffset - startOffset))
+
+        let regionElements =
+            Markdown.scrapeText regionText |> Array.map (Element.shiftLines regionStart)
+
+        let isRegionEnd el =
+            match el, regionEnd with
+            | H h, Some line -> h.range.Start = Position.Mk(line, 0)
+            | _ -> false
+
+        let before = flat |> Array.filter (fun el -> startLine el < regionStart)
+
+        let endsBeforeRegion el =
+            let end_ = (Element.range el).End
+            end_.Line < regionStart || end_ = Position.Mk(regionStart, 0)
+
+        let isSafe =
+            (Option.isNone regionEnd || Array.exists isRegionEnd regionElements)
+            && Array.forall endsBeforeRegion before
+            && not (Array.exists isLinkDef regionElements)
+
+        if not isSafe then
+            parseText newText
+        else
+            let changed = regionElements |> Array.filter (isRegionEnd >> not)
+
+            let after =
+                match prevRegionEnd with
+                | Some line ->
+                    flat
+                    |> Array.filter (fun el -> startLine el >= line)
+                    |> Array.map (Element.shiftLines lineShift)
+                | None -> [||]
+
+            let elements =
+                Array.concat [ before; changed; after ]
+                |> reconstructHierarchy newText
+                |> Array.ofSeq
+
+            sortElements newText elements
+            elements
@@ -5,43 +5,18 @@ open System.Diagnostics
 open System.Threading
 open Ionide.LanguageServerProtocol
 open Ionide.LanguageServerProtocol.Logging
-open Serilog
 
 module MS = Marksman.Server
 
 open FSharp.SystemCommandLine
 
-let configureLogging (verbosity: int) : unit =
-    let loggerConfig = LoggerConfiguration()
-
-    let loggerConfig =
-        match verbosity with
-        | 0 -> loggerConfig.MinimumLevel.Error()
-        | 1 -> loggerConfig.MinimumLevel.Warning()
-        | 2 -> loggerConfig.MinimumLevel.Information()
-        | 3 -> loggerConfig.MinimumLevel.Debug()
-        | _ -> loggerConfig.MinimumLevel.Verbose()
-
-    Log.Logger <-
-        loggerConfig
-            .WriteTo
-            .Console(
-                outputTemplate =
-                    "[{Timestamp:HH:mm:ss} {Level:u3}] <{SourceContext}> {Message:lj}: {Properties:lj}{NewLine}{Exception}",
-                standardErrorFromLevel = Events.LogEventLevel.Verbose
-            )
-            .Enrich.FromLogContext()
-            .CreateLogger()
-
-    ()
-
-let startLSP (args: int * bool) : int =
-    let verbosity, waitForDebugger = args
+let startLSP (args: int * bool * string) : int =
+    let verbosity, waitForDebugger, logFile = args
 
     use input = Console.OpenStandardInput()
     use output = Console.OpenStandardOutput()
 
-    configureLogging verbosity
+    Logging.configure verbosity (if String.IsNullOrEmpty(logFile) then None else Some logFile)
     let logger = LogProvider.getLoggerByName "LSP Entry"
 
     if waitForDebugger && not Debugger.IsAttached then
@@ -53,25 +28,184 @@ let startLSP (args: int * bool) : int =
 
     logger.info (Log.setMessage "Starting Marksman LSP server")
 
-    let requestHandlings = Server.defaultRequestHandlings ()
+    let requestHandlings =
+        Server.defaultRequestHandlings ()
+        |> Map.add
+            "marksman/tasks"
+            (Server.serverRequestHandling (fun (s: MS.MarksmanServer) p -> s.MarksmanTasks(p)))
+        |> Map.add
+            "marksman/stats"
+            (Server.serverRequestHandling (fun (s: MS.MarksmanServer) p -> s.MarksmanStats(p)))
+        |> Map.add
+            "marksman/graph"
+            (Server.serverRequestHandling (fun (s: MS.MarksmanServer) p -> s.MarksmanGraph(p)))
+        |> Map.add
+            "marksman/setLogFilter"
+            (Server.serverRequestHandling (fun (_: MS.MarksmanServer) p ->
+                async {
+                    Logging.setFilterFromClient p
+                    return LspResult.success ()
+                }))
+
+    // Kept to stop the server here as well: clients don't always send `shutdown` and `exit`
+    let server: ref<option<MS.MarksmanServer>> = ref None
+
+    let createServer client =
+        let created = new MS.MarksmanServer(client)
+        server.Value <- Some created
+        created
+
+    // Every request and notification is a span of the profile written by `marksman.dumpProfile`
+    let tracer =
+        { new Server.IRequestTracer with
+            member _.Trace methodName handling =
+                Marksman.Trace.spanAsync "request" methodName handling }
 
     let result =
         Server.start
+            tracer
             requestHandlings
             input
             output
             MS.MarksmanClient
-            (fun client -> new MS.MarksmanServer(client))
+            createServer
             Server.defaultRpc
 
+    server.Value |> Option.iter (fun server -> server.Dispose())
     logger.trace (Log.setMessage "Stopped Marksman LSP server")
 
     int result
 
+let runCheck (args: int * string * string) : int =
+    let verbosity, dir, format = args
+
+    // Logs go to stderr and would drown the report at the default verbosity of the server
+    Logging.configure (verbosity - 2) None
+
+    match Check.Format.ofString format with
+    | Some format -> Check.run dir format
+    | None ->
+        eprintfn $"Unknown format: {format}"
+        2
+
+let runIndex (args: int * string * string * bool) : int =
+    let verbosity, dir, output, stats = args
+    Logging.configure (verbosity - 2) None
+
+    match output.ToLowerInvariant(), Check.tryLoadFolder dir with
+    | "json", Some folder ->
+        let graph = NoteGraph.ofFolder folder
+        let graph = if stats then NoteGraph.withStats folder graph else graph
+        printfn "%s" (NoteGraph.toJson graph)
+        0
+    | "json", None ->
+        eprintfn $"Can't read the folder: {dir}"
+        2
+    | _ ->
+        eprintfn $"Unknown output: {output}"
+        2
+
+let runGraph (args: int * string * string) : int =
+    let verbosity, dir, format = args
+    Logging.configure (verbosity - 2) None
+
+    match NoteGraph.Format.ofString format, Check.tryLoadFolder dir with
+    | Some format, Some folder ->
+        printfn "%s" (NoteGraph.ofFolder folder |> NoteGraph.format format)
+        0
+    | Some _, None ->
+        eprintfn $"Can't read the folder: {dir}"
+        2
+    | None, _ ->
+        eprintfn $"Unknown format: {format}"
+        2
+
+let runExport (args: int * string * string * string) : int =
+    let verbosity, outDir, format, dir = args
+    Logging.configure (verbosity - 2) None
+
+    match Export.Format.ofString format with
+    | Some format -> Export.run dir format outDir
+    | None ->
+        eprintfn $"Unknown format: {format}"
+        2
+
+let runConvertLinks (args: int * string * string) : int =
+    let verbosity, dir, style = args
+    Logging.configure (verbosity - 2) None
+
+    match Refactor.LinkStyle.ofString style, Check.tryLoadFolder dir with
+    | Some style, Some folder ->
+        try
+            let converted =
+                Workspace.Folder.docs folder
+                |> Seq.choose (fun doc ->
+                    Refactor.convertedText folder doc style |> Option.map (fun text -> doc, text))
+                |> Array.ofSeq
+
+            for doc, text in converted do
+                IO.File.WriteAllText((Workspace.Doc.path doc).LocalPath, text)
+
+            printfn $"Converted the links of {converted.Length} notes"
+            0
+        with
+        | :? IO.IOException
+        | :? UnauthorizedAccessException as exn ->
+            eprintfn $"Can't write the notes: {exn.Message}"
+            2
+    | Some _, None ->
+        eprintfn $"Can't read the folder: {dir}"
+        2
+    | None, _ ->
+        eprintfn $"Unknown style: {style}"
+        2
+
+let runUnusedAttachments (args: int * string * bool) : int =
+    let verbosity, dir, delete = args
+    Logging.configure (verbosity - 2) None
+
+    match Check.tryLoadFolder dir with
+    | None ->
+        eprintfn $"Can't read the folder: {dir}"
+        2
+    // Without asset directories, unused files are guessed by their extension, which is fine for
+    // listing them but not for deleting them
+    | Some folder when
+        delete
+        && Array.isEmpty ((Workspace.Folder.configOrDefault folder).ComplAssetDirs())
+        ->
+        eprintfn "Deleting unused attachments requires completion.asset_dirs to be configured"
+        2
+    | Some folder ->
+        let root = (Workspace.RootPath.path (Workspace.Folder.rootPath folder)).LocalPath
+        let unused = Assets.unused folder
+
+        for path in unused do
+            printfn "%s" path
+
+        if not delete then
+            // Like check, so that scripts can tell that there's something to clean up
+            if Array.isEmpty unused then 0 else 1
+        else
+            try
+                for path in unused do
+                    IO.File.Delete(IO.Path.Join(root, path))
+
+                eprintfn $"Deleted {unused.Length} files"
+                0
+            with
+            | :? IO.IOException
+            | :? UnauthorizedAccessException as exn ->
+                eprintfn $"Can't delete the files: {exn.Message}"
+                2
+
 [<EntryPoint>]
 let main args =
     let verbosity =
-        Input.Option([ "--verbose"; "-v" ], 2, "Set logging verbosity level")
+        Input.Option([ "--verbose"; "--verbosity"; "-v" ], 2, "Set logging verbosity level")
+
+    let logFile =
+        Input.Option([ "--log-file" ], "", "Also write the logs to the file")
 
     let waitForDebugger =
         Input.Option(
@@ -83,12 +217,110 @@ let main args =
     let lspCommand =
         command "server" {
             description "Start LSP server on stdin/stdout"
-            inputs (verbosity, waitForDebugger)
+            inputs (verbosity, waitForDebugger, logFile)
             setHandler startLSP
         }
 
+    let checkDir =
+        Input.Argument("dir", ".", "Directory of the notes to check")
+
+    let checkFormat =
+        Input.Option([ "--format"; "-f" ], "human", "Output format: human, json or sarif")
+
+    let checkCommand =
+        command "check" {
+            description "Check the notes of a directory and exit with a non-zero code on errors"
+            inputs (verbosity, checkDir, checkFormat)
+            setHandler runCheck
+        }
+
+    let indexDir =
+        Input.Argument("dir", ".", "Directory of the notes to index")
+
+    let indexOutput =
+        Input.Option([ "--output"; "-o" ], "json", "Output format: json")
+
+    let indexStats =
+        Input.Option(
+            [ "--stats" ],
+            false,
+            "Include the word count, reading time and link counts of every note"
+        )
+
+    let indexCommand =
+        command "index" {
+            description "Print the notes of a directory with their headings, tags and links"
+            inputs (verbosity, indexDir, indexOutput, indexStats)
+            setHandler runIndex
+        }
+
+    let graphDir =
+        Input.Argument("dir", ".", "Directory of the notes to graph")
+
+    let graphFormat =
+        Input.Option([ "--format"; "-f" ], "dot", "Output format: dot, json or graphml")
+
+    let graphCommand =
+        command "graph" {
+            description "Print the graph of the links between the notes of a directory"
+            inputs (verbosity, graphDir, graphFormat)
+            setHandler runGraph
+        }
+
+    let exportOutDir =
+        Input.Argument("out-dir", "site", "Directory to write the pages to")
+
+    let exportFormat =
+        Input.Option([ "--format"; "-f" ], "html", "Output format: html")
+
+    let exportDir =
+        Input.Option([ "--dir"; "-d" ], ".", "Directory of the notes to export")
+
+    let exportCommand =
+        command "export" {
+            description "Render the notes of a directory to linked pages, with an index page"
+            inputs (verbosity, exportOutDir, exportFormat, exportDir)
+            setHandler runExport
+        }
+
+    let convertDir =
+        Input.Argument("dir", ".", "Directory of the notes to convert")
+
+    let convertStyle =
+        Input.Option([ "--to"; "-t" ], "markdown", "Style of the links: wiki or markdown")
+
+    let convertCommand =
+        command "convert-links" {
+            description "Rewrite the links of the notes of a directory in the given style"
+            inputs (verbosity, convertDir, convertStyle)
+            setHandler runConvertLinks
+        }
+
+    let unusedDir =
+        Input.Argument("dir", ".", "Directory of the notes and attachments")
+
+    let unusedDelete =
+        Input.Option(
+            [ "--delete" ],
+            false,
+            "Delete the unused attachments, when asset directories are configured"
+        )
+
+    let unusedCommand =
+        command "unused-attachments" {
+            description "Print the files of the asset directories that no note links to"
+            inputs (verbosity, unusedDir, unusedDelete)
+            setHandler runUnusedAttachments
+        }
+
     rootCommand args {
         description "Marksman is a language server for Markdown"
-        setHandler (fun () -> startLSP (2, false))
+        setHandler (fun () -> startLSP (2, false, ""))
         addCommand lspCommand
+        addCommand checkCommand
+        addCommand indexCommand
+        addCommand graphCommand
+        addCommand exportCommand
+        addCommand convertCommand
+        addCommand unusedCommand
     }
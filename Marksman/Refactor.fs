@@ -1,5 +1,7 @@
 module Marksman.Refactor
 
+open System.IO
+open System.Text.RegularExpressions
 open Ionide.LanguageServerProtocol.Types
 
 open Marksman.Workspace
@@ -48,10 +50,43 @@ let mkWorkspaceEdit
         docEdit.Edits |> Array.sortInPlaceWith (fun x y -> -(compare x y))
 
     if supportsDocumentEdit then
-        { Changes = None; DocumentChanges = Some docEdits }
+        { Changes = None
+          DocumentChanges = Some(Array.map DocumentChange.Edit docEdits)
+          ChangeAnnotations = None }
     else
         { Changes = Some(WorkspaceEdit.DocumentChangesToChanges docEdits)
-          DocumentChanges = None }
+          DocumentChanges = None
+          ChangeAnnotations = None }
+
+/// Like `mkWorkspaceEdit`, with every edit labelled by `annotation` for clients that support it,
+/// e.g. to preview the edits and ask for confirmation before applying them.
+let mkAnnotatedWorkspaceEdit
+    (client: State.ClientDescription)
+    (annotation: ChangeAnnotation)
+    (docEdits: array<TextDocumentEdit>)
+    : WorkspaceEdit =
+    let edit = mkWorkspaceEdit client.SupportsDocumentEdit docEdits
+
+    if client.SupportsChangeAnnotations then
+        let id = "marksman"
+
+        let annotate (docEdit: TextDocumentEdit) =
+            let withId (textEdit: TextEdit) : AnnotatedTextEdit =
+                { Range = textEdit.Range
+                  NewText = textEdit.NewText
+                  AnnotationId = id }
+
+            let annotated: AnnotatedTextDocumentEdit =
+                { TextDocument = docEdit.TextDocument
+                  Edits = Array.map withId docEdit.Edits }
+
+            DocumentChange.AnnotatedEdit annotated
+
+        { edit with
+            DocumentChanges = Some(Array.map annotate docEdits)
+            ChangeAnnotations = Some(Map.ofList [ id, annotation ]) }
+    else
+        edit
 
 let renameMarkdownLabel (newLabel: string) (element: Element) : option<TextEdit> =
     match element with
@@ -92,6 +127,10 @@ let renameHeadingLink
         match el with
         | WL { data = wl } ->
             let toEdit = if Heading.isTitle heading then wl.doc else wl.heading
+            // Links to the explicit id stay valid when the title changes
+            let toEdit =
+                toEdit
+                |> Option.filter (fun node -> not (Heading.isTargetedById node.text heading))
 
             toEdit
             |> Option.map (fun node -> { Range = node.range; NewText = Slug.toString newSlug })
@@ -100,12 +139,15 @@ let renameHeadingLink
 
             let toEdit =
                 if not (Heading.isTitle heading) then
-                    docUrl |> Option.bind Url.anchor
+                    docUrl
+                    |> Option.bind Url.anchor
+                    |> Option.filter (fun node -> not (Heading.isTargetedById node.text heading))
                 else
                     None
 
             toEdit
-            |> Option.map (fun node -> { Range = node.range; NewText = Slug.toString newSlug })
+            |> Option.map (fun node ->
+                { Range = node.range; NewText = Url.encodeAnchor (Slug.toString newSlug) })
         | _ -> None
     else
         None
@@ -229,3 +271,505 @@ let renameRange (srcDoc: Doc) (pos: Position) : option<Range> =
         else
             None
     | _ -> None
+
+/// Applies edits that don't overlap to the text, from the end so that the offsets of the edits
+/// still to apply don't move.
+let applyEdits (text: Text.Text) (edits: seq<Range * string>) : string =
+    let content = System.Text.StringBuilder(text.content)
+
+    for range, newText in Seq.sortByDescending (fun (range: Range, _) -> range.Start) edits do
+        let start, end_ = text.lineMap.FindRange(range)
+        content.Remove(start, end_ - start).Insert(start, newText: string) |> ignore
+
+    content.ToString()
+
+/// The URL of `toDoc` in a link from `fromDoc`, root-relative when `original` is
+let docUrl (fromDoc: Doc) (toDoc: Doc) (original: string) : string =
+    if original.StartsWith('/') then
+        (Doc.pathFromRoot toDoc).AbsPathUrlEncode()
+    else
+        let fromDir = Path.GetDirectoryName((Doc.path fromDoc).LocalPath)
+
+        Path.GetRelativePath(fromDir, (Doc.path toDoc).LocalPath).Split([| '/'; '\\' |])
+        |> Array.map (fun part -> part.UrlEncode())
+        |> String.concat "/"
+
+/// The edit making the link `el` of `doc` point at `dest` instead of the note it points at now.
+/// The heading or block anchor the link points to is kept; links within a note have nothing to
+/// edit.
+let retargetLink
+    (style: Config.ComplWikiStyle)
+    (doc: Doc)
+    (dest: Doc)
+    (el: Element)
+    : option<TextEdit> =
+    let urlEdit (angled: bool) (url: TextNode) =
+        (Url.ofUrlNode url).url
+        |> Option.map (fun path ->
+            let newText = Url.toDestination angled (docUrl doc dest path.text)
+            { Range = path.range; NewText = newText })
+
+    match el with
+    | WL { data = { doc = Some target } } ->
+        let newText = Compl.CompletionHelpers.wikiTargetLink style dest
+        Some { Range = target.range; NewText = newText }
+    | ML ({ data = MdLink.IL (_, Some url, _) } as link) -> urlEdit (MdLink.isAngled link) url
+    | MLD { data = def } -> urlEdit false (MdLinkDef.url def)
+    | _ -> None
+
+/// Edits of the links of `doc` that point at `src`, to make them point at `dest`.
+let private retargetLinksIn (folder: Folder) (src: Doc) (dest: Doc) (doc: Doc) : array<TextEdit> =
+    let config = Folder.configOrDefault folder
+    let strategy = config.CoreLinkResolution()
+
+    Dest.resolveLinks folder doc
+    |> Map.toSeq
+    |> Seq.choose (fun (link, dests) ->
+        match Dest.pick strategy doc dests |> Array.tryHead with
+        // References resolve to the definitions next to them, which are edited themselves
+        | Some (Dest.LinkDef _) -> None
+        | Some target when Doc.path (Dest.doc target) = Doc.path src ->
+            retargetLink (config.ComplWikiStyle()) doc dest link
+        | _ -> None)
+    |> Array.ofSeq
+
+/// Edits of the relative URLs of `src`, e.g. of images and of links to other notes, keeping them
+/// pointing at the same files from the directory of `dest`.
+let private rebaseLinksIn (src: Doc) (dest: Doc) : array<TextEdit> =
+    let srcDir = Path.GetDirectoryName((Doc.path src).LocalPath)
+    let destDir = Path.GetDirectoryName((Doc.path dest).LocalPath)
+
+    let isRelative (url: string) =
+        url <> ""
+        && not (url.StartsWith('/'))
+        && not (System.Uri.IsWellFormedUriString(url, System.UriKind.Absolute))
+
+    let urlEdit (angled: bool) (url: TextNode) =
+        (Url.ofUrlNode url).url
+        |> Option.filter (fun path -> isRelative path.text)
+        |> Option.map (fun path ->
+            let target = Path.GetFullPath(Path.Join(srcDir, path.text.UrlDecode()))
+
+            let rebased =
+                Path.GetRelativePath(destDir, target).Split([| '/'; '\\' |])
+                |> Array.map (fun part -> part.UrlEncode())
+                |> String.concat "/"
+
+            { Range = path.range; NewText = Url.toDestination angled rebased })
+
+    let index = Doc.index src
+
+    let linkEdits =
+        Index.mdLinks index
+        |> Seq.choose (fun link ->
+            match link.data with
+            | MdLink.IL (_, Some url, _) -> urlEdit (MdLink.isAngled link) url
+            | _ -> None)
+
+    let defEdits =
+        Index.linkDefs index |> Seq.choose (fun { data = def } -> urlEdit false (MdLinkDef.url def))
+
+    if srcDir = destDir then
+        [||]
+    else
+        Seq.append linkEdits defEdits |> Array.ofSeq
+
+// Edits shifting the headings by `by` levels, staying within levels 1 to 6. Setext headings
+// become ATX ones, since they have no levels past 2.
+let private shiftHeadings (text: Text.Text) (headings: seq<Node<Heading>>) (by: int) =
+    headings
+    |> Seq.collect (fun { data = heading; range = range } ->
+        let level = heading.level + by |> max 1 |> min 6
+        let line = text.LineContent(range.Start.Line)
+        let hashes = line.IndexOf('#')
+
+        if level = heading.level then
+            []
+        elif hashes >= 0 && line[.. hashes - 1].IsWhitespace() then
+            let pos = Position.Mk(range.Start.Line, hashes)
+
+            if level > heading.level then
+                [ Range.Mk(pos, pos), System.String('#', level - heading.level) ]
+            else
+                let end_ = Position.Mk(range.Start.Line, hashes + heading.level - level)
+                [ Range.Mk(pos, end_), "" ]
+        else
+            let titleStart = heading.title.range.Start
+            let titleLineEnd = (text.LineContentRange(titleStart.Line)).End
+
+            [ Range.Mk(titleStart, titleStart), System.String('#', level) + " "
+              Range.Mk(titleLineEnd, range.End), "" ])
+
+/// The text of `src` as a part of `dest`: without its front matter, with its headings under the
+/// title of `dest`, with its links to itself pointing at `dest`, and with its relative links
+/// pointing at the same files from the directory of `dest`.
+let mergedContent (folder: Folder) (src: Doc) (dest: Doc) : string =
+    let text = Doc.text src
+    let index = Doc.index src
+
+    let frontMatter =
+        index.yamlFrontMatter
+        |> Option.map (fun yml ->
+            let last = text.EndRange().End
+            let next = Position.Mk(yml.range.End.Line + 1, 0)
+            let end_ = if yml.range.End.Character = 0 || next > last then yml.range.End else next
+            Range.Mk(Position.Mk(0, 0), end_), "")
+        |> Option.toList
+
+    // The outermost headings become sections of `dest`
+    let shift =
+        match index.headings |> Array.map (fun { data = heading } -> heading.level) with
+        | [||] -> 0
+        | levels -> max 0 (2 - Array.min levels)
+
+    let headings = shiftHeadings text index.headings shift |> List.ofSeq
+
+    // Links to `src` itself point at `dest`, other relative links are rebased onto its directory
+    let retargeted = retargetLinksIn folder src dest src
+
+    let rebased =
+        rebaseLinksIn src dest
+        |> Array.filter (fun edit ->
+            not (retargeted |> Array.exists (fun retarget -> retarget.Range = edit.Range)))
+
+    let links =
+        Array.append retargeted rebased |> Seq.map (fun edit -> edit.Range, edit.NewText)
+
+    let merged = applyEdits text (Seq.concat [ frontMatter; headings; List.ofSeq links ])
+    let merged = merged.TrimStart('\r', '\n')
+
+    if merged.EndsWith('\n') then merged else merged + "\n"
+
+/// Merges the note `src` into `dest`: its content is appended to `dest`, links to it anywhere
+/// in the folder point at `dest` instead, and the note itself is deleted.
+let mergeNotes (folder: Folder) (src: Doc) (dest: Doc) : Result<WorkspaceEdit, string> =
+    if Doc.path src = Doc.path dest then
+        Result.Error "Can't merge a note into itself"
+    else
+        let destText = Doc.text dest
+        let destEnd = destText.lineMap.FindPosition(destText.content.Length)
+
+        let separator =
+            match destText.content with
+            | "" -> ""
+            | content when content.EndsWith('\n') -> "\n"
+            | _ -> "\n\n"
+
+        let append =
+            { Range = Range.Mk(destEnd, destEnd)
+              NewText = separator + mergedContent folder src dest }
+
+        let docEdits =
+            Folder.docs folder
+            |> Seq.filter (fun doc -> Doc.path doc <> Doc.path src)
+            |> Seq.choose (fun doc ->
+                let edits = retargetLinksIn folder src dest doc
+
+                let edits =
+                    if Doc.path doc = Doc.path dest then Array.append edits [| append |] else edits
+
+                if Array.isEmpty edits then
+                    None
+                else
+                    let lspDoc = { Uri = Doc.uri doc; Version = Doc.version doc }
+                    Some { TextDocument = lspDoc; Edits = edits })
+            |> Array.ofSeq
+
+        for docEdit in docEdits do
+            docEdit.Edits |> Array.sortInPlaceWith (fun x y -> -(compare x y))
+
+        let changes =
+            Array.append
+                (Array.map DocumentChange.Edit docEdits)
+                [| DocumentChange.Delete(DeleteFile.Create(Doc.uri src)) |]
+
+        Ok
+            { Changes = None
+              DocumentChanges = Some changes
+              ChangeAnnotations = None }
+
+/// The heading on the line of `pos` with the sub-headings in its scope, the heading first.
+let private sectionAt (doc: Doc) (pos: Position) : option<array<Node<Heading>>> =
+    let headings = (Doc.index doc).headings
+
+    headings
+    |> Array.tryFind (fun { range = range } -> range.Start.Line = pos.Line)
+    |> Option.map (fun { data = heading } ->
+        let scope = Heading.scope heading
+
+        headings
+        |> Array.filter (fun { range = range } ->
+            range.Start >= scope.Start && range.Start < scope.End))
+
+/// Actions on a heading that promote or demote it together with its sub-headings, so that the
+/// hierarchy of the section stays the same. There's no action that would move a heading past
+/// level 1 or 6.
+let sectionLevelActions
+    (supportsDocumentEdit: bool)
+    (doc: Doc)
+    (range: Range)
+    : array<string * WorkspaceEdit> =
+    match sectionAt doc range.Start with
+    | None -> [||]
+    | Some section ->
+        let levels = section |> Array.map (fun { data = heading } -> heading.level)
+
+        let action name by =
+            let edits =
+                shiftHeadings (Doc.text doc) section by
+                |> Seq.map (fun (range, newText) -> { Range = range; NewText = newText })
+                |> Array.ofSeq
+
+            let lspDoc = { Uri = Doc.uri doc; Version = Doc.version doc }
+            let docEdit = { TextDocument = lspDoc; Edits = edits }
+            name, mkWorkspaceEdit supportsDocumentEdit [| docEdit |]
+
+        [| if Array.min levels > 1 then action "Promote the section" (-1)
+           if Array.max levels < 6 then action "Demote the section" 1 |]
+
+let private subheadings (els: array<Element>) : array<Node<Heading>> =
+    els
+    |> Array.choose (function
+        | H heading -> Some heading
+        | _ -> None)
+
+// The heading on the line with its siblings, which are the sections of its parent or the
+// outermost sections of the note, and its index among them
+let rec private siblingsAt
+    (line: int)
+    (siblings: array<Node<Heading>>)
+    : option<array<Node<Heading>> * int> =
+    match siblings |> Array.tryFindIndex (fun { range = range } -> range.Start.Line = line) with
+    | Some index -> Some(siblings, index)
+    | None ->
+        siblings
+        |> Array.tryFind (fun { data = heading } ->
+            let scope = Heading.scope heading
+            scope.Start.Line <= line && line < scope.End.Line)
+        |> Option.bind (fun { data = heading } -> siblingsAt line (subheadings heading.children))
+
+// The edit putting consecutive sections in a new order, where `order` has the index of the
+// section going to each place. Sections span whole lines, and blank lines at their end stay in
+// place, so that the spacing between sections stays the same.
+let private reorderEdit
+    (text: Text.Text)
+    (sections: array<Node<Heading>>)
+    (order: array<int>)
+    : TextEdit =
+    let lines =
+        sections
+        |> Array.map (fun { data = heading } ->
+            let scope = Heading.scope heading
+            Range.Mk(scope.Start.Line, 0, scope.End.Line, 0))
+
+    let chunks = lines |> Array.map text.Substring
+    let body (chunk: string) = chunk.TrimEnd()
+    let trailer (chunk: string) = chunk.Substring((body chunk).Length)
+
+    let newText =
+        order
+        |> Array.mapi (fun place index -> body chunks[index] + trailer chunks[place])
+        |> String.concat ""
+
+    { Range = Range.Mk(lines[0].Start, lines[lines.Length - 1].End)
+      NewText = newText }
+
+/// Actions on a heading that move its section above or below the next section of the same level,
+/// and that sort its subsections by title. Sections of different levels are left in their order,
+/// since moving one past the other would change which one is the parent.
+let sectionOrderActions
+    (supportsDocumentEdit: bool)
+    (doc: Doc)
+    (range: Range)
+    : array<string * WorkspaceEdit> =
+    let text = Doc.text doc
+
+    let action name (sections: array<Node<Heading>>) order =
+        let lspDoc = { Uri = Doc.uri doc; Version = Doc.version doc }
+        let docEdit = { TextDocument = lspDoc; Edits = [| reorderEdit text sections order |] }
+        name, mkWorkspaceEdit supportsDocumentEdit [| docEdit |]
+
+    match siblingsAt range.Start.Line (subheadings (Doc.cst doc)) with
+    | None -> [||]
+    | Some (siblings, index) ->
+        let level (heading: Node<Heading>) = heading.data.level
+        let current = siblings[index]
+        let sameLevel other = level siblings[other] = level current
+
+        let children = subheadings current.data.children
+
+        let sorted =
+            children
+            |> Array.indexed
+            |> Array.sortBy (fun (index, { data = heading }) ->
+                (Heading.name heading).ToLowerInvariant(), index)
+            |> Array.map fst
+
+        let canSort =
+            children.Length > 1
+            && children |> Array.forall (fun child -> level child = level children[0])
+            && sorted <> Array.init children.Length id
+
+        [| if index > 0 && sameLevel (index - 1) then
+               action "Move the section up" siblings[index - 1 .. index] [| 1; 0 |]
+           if index < siblings.Length - 1 && sameLevel (index + 1) then
+               action "Move the section down" siblings[index .. index + 1] [| 1; 0 |]
+           if canSort then
+               action "Sort the subsections" children sorted |]
+
+[<RequireQualifiedAccess>]
+type LinkStyle =
+    | Wiki
+    | Markdown
+
+module LinkStyle =
+    let ofString (input: string) : option<LinkStyle> =
+        match input.ToLowerInvariant() with
+        | "wiki" -> Some LinkStyle.Wiki
+        | "markdown" -> Some LinkStyle.Markdown
+        | _ -> None
+
+// Where the converted link goes: the note, unless it's the linking one, and the anchor
+type private LinkTarget =
+    { doc: option<Doc>
+      anchor: option<string>
+      heading: option<string> }
+
+let private linkTarget (dest: Dest) : option<LinkTarget> =
+    let docOf =
+        function
+        | Explicit { dest = doc } -> Some doc
+        | Implicit _ -> None
+
+    match dest with
+    | Dest.Doc { dest = doc } -> Some { doc = Some doc; anchor = None; heading = None }
+    | Dest.Heading (docLink, { data = heading }) ->
+        let anchor =
+            heading.id
+            |> Option.map Node.text
+            |> Option.defaultWith (fun () -> GitHubSlug.ofString (Heading.name heading))
+
+        Some
+            { doc = docOf docLink
+              anchor = Some anchor
+              heading = Some(Heading.name heading) }
+    | Dest.Block (docLink, { data = block }) ->
+        let id = $"^{BlockAnchor.id block}"
+        Some { doc = docOf docLink; anchor = Some id; heading = Some id }
+    | Dest.LinkDef _ -> None
+
+/// The edit rewriting the link `el` of `doc` in `style`. Links that have the style already, or
+/// don't point to a note, are left as they are. Aliases of wiki links become the text of
+/// markdown links and the other way around.
+let convertLink (folder: Folder) (doc: Doc) (style: LinkStyle) (el: Element) : option<TextEdit> =
+    let config = Folder.configOrDefault folder
+
+    let target =
+        Dest.tryResolveElement folder doc el
+        |> Dest.pick (config.CoreLinkResolution()) doc
+        |> Array.tryHead
+        |> Option.bind linkTarget
+
+    let embed (text: string) = if text.StartsWith('!') then "!" else ""
+
+    match style, el, target with
+    | LinkStyle.Markdown, WL wl, Some target ->
+        let label =
+            wl.data.alias
+            |> Option.map Node.text
+            |> Option.orElse target.heading
+            |> Option.orElse (target.doc |> Option.map Doc.name)
+            |> Option.defaultValue ""
+
+        let path =
+            target.doc
+            |> Option.map (fun targetDoc -> docUrl doc targetDoc "")
+            |> Option.defaultValue ""
+
+        let anchor =
+            target.anchor
+            |> Option.map (fun anchor -> "#" + Url.encodeAnchor anchor)
+            |> Option.defaultValue ""
+
+        Some
+            { Range = wl.range
+              NewText = $"{embed wl.text}[{label}]({path}{anchor})" }
+    | LinkStyle.Wiki, ML ({ data = MdLink.IL (label, Some _, _) } as ml), Some target ->
+        let targetLink =
+            target.doc
+            |> Option.map (Compl.CompletionHelpers.wikiTargetLink (config.ComplWikiStyle()))
+
+        let heading = target.heading |> Option.map (fun heading -> heading.EncodeForWiki())
+
+        // The text stays as the alias, unless it's what the link shows anyway
+        let shown =
+            [ target.heading; target.doc |> Option.map Doc.name; targetLink ]
+            |> List.choose id
+
+        let alias =
+            if label.text.IsWhitespace() || List.contains label.text shown then
+                ""
+            else
+                $"|{label.text}"
+
+        let link = WikiLink.render targetLink heading false
+
+        Some
+            { Range = ml.range
+              NewText = $"{embed ml.text}[[{link}{alias}]]" }
+    | _ -> None
+
+/// Edits rewriting every link of `doc` in `style`.
+let convertLinks (folder: Folder) (doc: Doc) (style: LinkStyle) : array<TextEdit> =
+    Index.links (Doc.index doc)
+    |> Seq.choose (convertLink folder doc style)
+    |> Array.ofSeq
+
+/// The text of `doc` with its links in `style`, unless there's no link to rewrite.
+let convertedText (folder: Folder) (doc: Doc) (style: LinkStyle) : option<string> =
+    match convertLinks folder doc style with
+    | [||] -> None
+    | edits ->
+        edits
+        |> Seq.map (fun edit -> edit.Range, edit.NewText)
+        |> applyEdits (Doc.text doc)
+        |> Some
+
+/// What `marksman.findAndReplace` looks for in the text of the notes.
+[<RequireQualifiedAccess>]
+type Pattern =
+    | Literal of string
+    | Regex of Regex
+
+/// Edits replacing the matches of `pattern` in `doc` with `replacement`, in which `$1`, `${name}`
+/// and the like refer to the groups of a regex. Empty matches are skipped.
+let replaceEdits (pattern: Pattern) (replacement: string) (doc: Doc) : array<TextEdit> =
+    let text = Doc.text doc
+    let content = text.content
+
+    let matches =
+        match pattern with
+        | Pattern.Literal "" -> [||]
+        | Pattern.Literal literal ->
+            let rec from (start: int) =
+                seq {
+                    match content.IndexOf(literal, start, System.StringComparison.Ordinal) with
+                    | -1 -> ()
+                    | index ->
+                        yield index, literal.Length, replacement
+                        yield! from (index + literal.Length)
+                }
+
+            from 0 |> Array.ofSeq
+        | Pattern.Regex regex ->
+            regex.Matches(content)
+            |> Seq.filter (fun m -> m.Length > 0)
+            |> Seq.map (fun m -> m.Index, m.Length, m.Result(replacement))
+            |> Array.ofSeq
+
+    matches
+    |> Array.map (fun (start, length, newText) ->
+        let range =
+            Range.Mk(text.lineMap.FindPosition(start), text.lineMap.FindPosition(start + length))
+
+        { Range = range; NewText = newText })
@@ -8,6 +8,7 @@ open FSharpPlus.Operators
 open FSharpPlus.GenericBuilders
 open Ionide.LanguageServerProtocol.Types
 
+open Marksman.Config
 open Marksman.Cst
 open Marksman.Index
 open Marksman.Misc
@@ -105,6 +106,9 @@ module Uref =
             match ml.data with
             | MdLink.IL (_, Some url, _) ->
                 let docUrl = Url.ofUrlNode url
+                // Anchors of inline links can be percent-encoded, e.g. #my%20heading
+                let decode (anchor: TextNode) = { anchor with text = anchor.text.UrlDecode() }
+                let docUrl = { docUrl with anchor = Option.map decode docUrl.anchor }
 
                 match docUrl.url, docUrl.anchor with
                 | Some url, Some anchor ->
@@ -121,6 +125,12 @@ module Uref =
         | H _
         | YML _
         | T _
+        | BA _
+        | C _
+        | TK _
+        | TB _
+        | CT _
+        | CO _
         | MLD _ -> None
 
     let hasExplicitDoc =
@@ -136,6 +146,7 @@ type FileLinkKind =
     | FileName
     | FileStem
     | Title
+    | ZettelId
 
 type FileLink = { link: string; kind: FileLinkKind; dest: Doc }
 
@@ -190,12 +201,25 @@ module FileLink =
                 fileStemLink, fileNameLink, filePathLink
             | None -> None, None, None
 
-        let completionStyle = (Folder.configOrDefault folder).ComplWikiStyle()
+        let config = Folder.configOrDefault folder
+        let completionStyle = config.ComplWikiStyle()
+
+        let idLink () =
+            let docFileStem = Path.GetFileNameWithoutExtension(Doc.pathFromRoot doc)
+
+            if
+                config.CoreZettelIdEnable()
+                && Zettel.tryId (config.CoreZettelIdFormat()) docFileStem = Some name
+            then
+                Some { link = name; kind = FileLinkKind.ZettelId; dest = doc }
+            else
+                None
 
         let fileLink =
             fileStemLink
             |> Option.orElse fileNameLink
             |> Option.orElse filePathLink
+            |> Option.orElseWith idLink
 
         match titleLink, fileLink with
         | Some _, None -> titleLink
@@ -204,20 +228,26 @@ module FileLink =
         | Some _, Some _ when completionStyle = Config.TitleSlug -> titleLink
         | Some _, Some _ -> fileLink
 
-    let isFuzzyMatchDoc (folder: Folder) (srcDoc: Doc) (InternName name) (doc: Doc) : bool =
-        let byTitle = Slug.isSubString (Slug.ofString name) (Doc.slug doc)
+    // Partially applied to the name, the name is prepared once for all the docs
+    let isFuzzyMatchDoc (folder: Folder) (srcDoc: Doc) (InternName name) : Doc -> bool =
+        let slug = Slug.ofString name
+        let abbreviates = Fuzzy.isAbbreviation name
 
-        let byPath () =
-            match
-                InternName.tryResolveToRootPath (Folder.rootPath folder) (Doc.path srcDoc) name
-            with
-            | Some linkRootPath ->
-                linkRootPath
-                    .AbsPathUrlEncode()
-                    .IsSubStringOf((Doc.pathFromRoot doc).AbsPathUrlEncode())
-            | None -> false
+        let linkRootPath =
+            lazy (InternName.tryResolveToRootPath (Folder.rootPath folder) (Doc.path srcDoc) name)
+
+        fun doc ->
+            let byTitle = Slug.isSubString slug (Doc.slug doc) || abbreviates (Doc.name doc)
+
+            let byPath () =
+                match linkRootPath.Value with
+                | Some linkRootPath ->
+                    linkRootPath
+                        .AbsPathUrlEncode()
+                        .IsSubStringOf((Doc.pathFromRoot doc).AbsPathUrlEncode())
+                | None -> false
 
-        byTitle || byPath ()
+            byTitle || byPath ()
 
     let filterMatchingDocs (folder: Folder) (srcDoc: Doc) (name: InternName) : seq<FileLink> =
         Folder.docs folder |> Seq.choose (tryMatchDoc folder srcDoc name)
@@ -243,13 +273,15 @@ type Dest =
     | Doc of FileLink
     | Heading of DocLink * Node<Heading>
     | LinkDef of Doc * Node<MdLinkDef>
+    | Block of DocLink * Node<BlockAnchor>
 
 module Dest =
     let doc: Dest -> Doc =
         function
         | Dest.Doc { dest = doc }
         | Dest.LinkDef (doc, _) -> doc
-        | Dest.Heading (docLink, _) -> DocLink.doc docLink
+        | Dest.Heading (docLink, _)
+        | Dest.Block (docLink, _) -> DocLink.doc docLink
 
     let element: Dest -> Element option =
         function
@@ -257,6 +289,7 @@ module Dest =
         | Dest.Doc _ -> None
         | Dest.Heading (_, h) -> Some(H h)
         | Dest.LinkDef (_, ld) -> Some(MLD ld)
+        | Dest.Block (_, b) -> Some(BA b)
 
     let range: Dest -> Range =
         function
@@ -266,12 +299,14 @@ module Dest =
             |> Option.defaultWith (Doc.text doc).FullRange
         | Dest.Heading (_, heading) -> heading.range
         | Dest.LinkDef (_, linkDef) -> linkDef.range
+        | Dest.Block (_, block) -> block.data.scope
 
     let scope: Dest -> Range =
         function
         | Dest.Doc { dest = doc } -> (Doc.text doc).FullRange()
         | Dest.Heading (_, heading) -> heading.data.scope
         | Dest.LinkDef (_, linkDef) -> linkDef.range
+        | Dest.Block (_, block) -> block.data.scope
 
     let uri (ref: Dest) : DocumentUri = doc ref |> Doc.uri
 
@@ -280,13 +315,42 @@ module Dest =
     let overlapsWith this other =
         match this, other with
         | Dest.Doc { dest = thisDoc }, Dest.Doc { dest = otherDoc }
-        | Dest.Doc { dest = thisDoc }, Dest.Heading (Explicit { dest = otherDoc }, _) ->
+        | Dest.Doc { dest = thisDoc }, Dest.Heading (Explicit { dest = otherDoc }, _)
+        | Dest.Doc { dest = thisDoc }, Dest.Block (Explicit { dest = otherDoc }, _) ->
             thisDoc = otherDoc
         | Dest.Doc _, _ -> false
         | Dest.Heading (thisDocLink, thisHeading), Dest.Heading (otherDocLink, otherHeading) ->
             DocLink.isSame thisDocLink otherDocLink && thisHeading = otherHeading
+        | Dest.Block (thisDocLink, thisBlock), Dest.Block (otherDocLink, otherBlock) ->
+            DocLink.isSame thisDocLink otherDocLink && thisBlock = otherBlock
         | _, _ -> this = other
 
+    let private dirsFromRoot (doc: Doc) =
+        let parts = (Doc.pathFromRoot doc).Replace('\\', '/').TrimStart('/').Split('/')
+        parts[.. parts.Length - 2]
+
+    let private sharedDirs (srcDoc: Doc) (dest: Dest) =
+        Seq.zip (dirsFromRoot srcDoc) (dirsFromRoot (doc dest))
+        |> Seq.takeWhile (fun (a, b) -> a = b)
+        |> Seq.length
+
+    /// Destinations chosen by the `strategy` among all the ones a link resolves to. `All` keeps
+    /// every destination ordered by path and position, the others pick at most one.
+    let pick (strategy: CoreLinkResolution) (srcDoc: Doc) (dests: seq<Dest>) : array<Dest> =
+        let byPath (dest: Dest) =
+            let start = (range dest).Start
+            Doc.pathFromRoot (doc dest), start.Line, start.Character
+
+        let dests = dests |> Seq.sortBy byPath |> Array.ofSeq
+        let depth (dest: Dest) = (dirsFromRoot (doc dest)).Length
+
+        // Sorting is stable, so ties are broken by path
+        let closest (key: Dest -> int * int) = dests |> Array.sortBy key |> Array.truncate 1
+
+        match strategy with
+        | CoreLinkResolution.All -> dests
+        | CoreLinkResolution.Nearest -> closest (fun dest -> -(sharedDirs srcDoc dest), depth dest)
+        | CoreLinkResolution.ShortestPath -> closest (fun dest -> depth dest, 0)
 
     let tryResolveUref (uref: Uref) (srcDoc: Doc) (folder: Folder) : seq<Dest> =
         match uref with
@@ -310,17 +374,37 @@ module Dest =
                     |> Seq.map Explicit)
                 |> Option.defaultValue [ Implicit srcDoc ]
 
-            seq {
-                for doc in matchingDocs do
-                    let headings =
-                        doc
-                        |> DocLink.doc
-                        |> Doc.index
-                        |> Index.filterHeadingBySlug (Slug.ofString heading.text)
+            // Block references look like headings starting with ^, e.g. [[doc#^block-id]]
+            if heading.text.StartsWith('^') then
+                let blockId = heading.text.Substring(1)
 
-                    for h in headings do
-                        yield Dest.Heading(doc, h)
-            }
+                seq {
+                    for doc in matchingDocs do
+                        let blocks =
+                            doc
+                            |> DocLink.doc
+                            |> Doc.index
+                            |> Index.filterBlockAnchorsById blockId
+
+                        for b in blocks do
+                            yield Dest.Block(doc, b)
+                }
+            else
+                seq {
+                    for doc in matchingDocs do
+                        let index = doc |> DocLink.doc |> Doc.index
+
+                        let headings =
+                            match Index.filterHeadingBySlug (Slug.ofString heading.text) index with
+                            // GitHub disambiguates duplicate headings with numeric suffixes,
+                            // e.g. #setup-1 points to the second 'Setup' heading
+                            | [] ->
+                                Index.filterHeadingByGitHubSlug heading.text index |> List.ofArray
+                            | headings -> headings
+
+                        for h in headings do
+                            yield Dest.Heading(doc, h)
+                }
 
     let tryResolveElement (folder: Folder) (doc: Doc) (element: Element) : seq<Dest> =
         let configuredExts =
@@ -330,6 +414,51 @@ module Dest =
         | Some uref -> tryResolveUref uref doc folder
         | None -> Seq.empty
 
+    /// The text an embed of `dest` shows in place, e.g. the section of the heading for
+    /// `![[doc#heading]]`, or a whole doc without its frontmatter. The embeds within it are shown
+    /// in turn, following the first of their destinations, except the ones showing a part that
+    /// is being shown already, which are left as they are.
+    let embeddedText (folder: Folder) (dest: Dest) : string =
+        let embeddedScope (dest: Dest) =
+            match dest, (Doc.index (doc dest)).yamlFrontMatter with
+            | Dest.Doc _, Some yml -> Range.Mk(yml.range.End, (scope dest).End)
+            | _ -> scope dest
+
+        let rec render (shown: Set<string * Range>) (dest: Dest) =
+            let destDoc = doc dest
+            let text = Doc.text destDoc
+            let destScope = embeddedScope dest
+            let shown = Set.add (Doc.pathFromRoot destDoc, destScope) shown
+
+            let embeds =
+                Index.wikiLinks (Doc.index destDoc)
+                |> Array.filter (fun wl ->
+                    wl.data.embed
+                    && destScope.ContainsInclusive(wl.range.Start)
+                    && destScope.ContainsInclusive(wl.range.End))
+
+            let rendered = System.Text.StringBuilder()
+            let mutable cursor = destScope.Start
+
+            for wl in embeds do
+                rendered.Append(text.Substring(Range.Mk(cursor, wl.range.Start))) |> ignore
+
+                let next =
+                    tryResolveElement folder destDoc (WL wl)
+                    |> Seq.tryHead
+                    |> Option.filter (fun next ->
+                        not (Set.contains (Doc.pathFromRoot (doc next), embeddedScope next) shown))
+
+                match next with
+                | Some next -> rendered.Append(render shown next) |> ignore
+                | None -> rendered.Append(wl.text) |> ignore
+
+                cursor <- wl.range.End
+
+            rendered.Append(text.Substring(Range.Mk(cursor, destScope.End))).ToString()
+
+        render Set.empty dest
+
     let resolveLinks (folder: Folder) (doc: Doc) : Map<Element, array<Dest>> =
         let links = Index.links (Doc.index doc)
 
@@ -367,6 +496,7 @@ module Dest =
             | H h when Heading.isTitle h.data ->
                 [| Dest.Doc { link = h.text; kind = FileLinkKind.Title; dest = srcDoc } |]
             | H h -> [| Dest.Heading(Implicit srcDoc, h) |]
+            | BA b -> [| Dest.Block(Implicit srcDoc, b) |]
             | link when Element.isLink link ->
                 let linkToDecl = resolveLinks folder srcDoc
                 Map.tryFind link linkToDecl |> Option.defaultValue [||]
@@ -377,7 +507,8 @@ module Dest =
                 match declToFind with
                 | Dest.LinkDef _ -> [ srcDoc ]
                 | Dest.Heading _
-                | Dest.Doc _ -> Folder.docs folder |> List.ofSeq
+                | Dest.Block _
+                | Dest.Doc _ -> Folder.docsLinkingTo (doc declToFind) folder |> List.ofSeq
 
             let referencingEls =
                 seq {
@@ -408,3 +539,15 @@ module Dest =
 
 
         declsToFind |> Seq.collect (resolveDecl includeDecl)
+
+    /// Finds all occurrences of a tag in the folder.
+    /// Tags don't have a declaration, so every occurrence (including `tag` itself) is returned.
+    let findTagRefs (folder: Folder) (tag: Node<Tag>) : seq<Doc * Element> =
+        let tagName = tag.data.name.text
+
+        seq {
+            for doc in Folder.docsWithTag tagName folder do
+                for docTag in Index.tags (Doc.index doc) do
+                    if docTag.data.name.text = tagName then
+                        yield doc, T docTag
+        }
@@ -11,6 +11,8 @@ type TokenType =
     | WikiLink
     | RefLink
     | Tag
+    | Math
+    | Callout
 
 module TokenType =
     let toLspName =
@@ -18,14 +20,18 @@ module TokenType =
         | WikiLink -> "class"
         | RefLink -> "class"
         | Tag -> "enumMember"
+        | Math -> "macro"
+        | Callout -> "keyword"
 
     let toNum =
         function
         | WikiLink -> 0u
         | RefLink -> 1u
         | Tag -> 2u
+        | Math -> 3u
+        | Callout -> 4u
 
-    let mapping = [| WikiLink; RefLink; Tag |] |> Array.map toLspName
+    let mapping = [| WikiLink; RefLink; Tag; Math; Callout |] |> Array.map toLspName
 
 type Token =
     { range: Range
@@ -97,6 +103,15 @@ module Token =
                 | None -> ()
 
             for tag in Index.tags index -> { range = tag.range; typ = Tag }
+
+            for code in Index.code index do
+                match code.data with
+                | Code.Math _ -> yield { range = code.range; typ = Math }
+                | Code.Block _
+                | Code.Inline
+                | Code.Html -> ()
+
+            for callout in Index.callouts index -> { range = callout.range; typ = Callout }
         }
 
     let isInRange (range: Range) token =
@@ -2,6 +2,7 @@ module Marksman.Server
 
 open System
 open System.IO
+open System.Threading
 
 open Microsoft.FSharp.Control
 
@@ -10,6 +11,7 @@ open Ionide.LanguageServerProtocol.Types
 open Ionide.LanguageServerProtocol.Server
 open Ionide.LanguageServerProtocol.Logging
 open FSharpPlus.GenericBuilders
+open Newtonsoft.Json.Linq
 
 open Marksman.Config
 open Marksman.Cst
@@ -75,16 +77,12 @@ module ServerUtil =
                 else
                     Map.empty
 
-    let readWorkspace (userConfig: option<Config>) (roots: Map<string, RootPath>) : list<Folder> =
-        seq {
-            for KeyValue (name, root) in roots do
-                match Folder.tryLoad userConfig name root with
-                | Some folder -> yield folder
-                | _ -> ()
-        }
-        |> List.ofSeq
-
-    let mkServerCaps (markdownExts: array<string>) (par: InitializeParams) : ServerCapabilities =
+    let mkServerCaps
+        (markdownExts: array<string>)
+        (backlinksSection: bool)
+        (positionEncoding: Text.PositionEncoding)
+        (par: InitializeParams)
+        : ServerCapabilities =
         let workspaceFoldersCaps =
             { Supported = Some true; ChangeNotifications = Some true }
 
@@ -117,7 +115,10 @@ module ServerUtil =
         let textSyncCaps =
             { TextDocumentSyncOptions.Default with
                 OpenClose = Some true
-                Change = Some TextDocumentSyncKind.Incremental }
+                Change = Some TextDocumentSyncKind.Incremental
+                // Only to refresh the backlinks section; otherwise saves would wait on the server
+                // for nothing
+                WillSaveWaitUntil = Some backlinksSection }
 
 
         let clientDesc = ClientDescription.ofParams par
@@ -132,19 +133,28 @@ module ServerUtil =
                 Some(U2.First true)
 
         { ServerCapabilities.Default with
+            PositionEncoding = Some(Text.PositionEncoding.toString positionEncoding)
             Workspace = Some workspaceCaps
             WorkspaceSymbolProvider = Some(not clientDesc.IsVSCode)
             TextDocumentSync = Some textSyncCaps
             DocumentSymbolProvider = Some(not clientDesc.IsVSCode)
+            // Registered in `initialized` when the client allows for it
             CompletionProvider =
-                Some
-                    { TriggerCharacters = Some [| '['; '#'; '(' |]
-                      ResolveProvider = None
-                      AllCommitCharacters = None }
+                if clientDesc.SupportsDynamicCompletion then
+                    None
+                else
+                    Some
+                        { TriggerCharacters = Some Compl.Prompt.triggerCharacters
+                          ResolveProvider = Some true
+                          AllCommitCharacters = None }
             DefinitionProvider = Some true
             HoverProvider = Some true
+            FoldingRangeProvider = Some true
             ReferencesProvider = Some true
             CodeActionProvider = Some codeActionOptions
+            // Lenses are only computed with `git.enable`, which may differ between folders
+            CodeLensProvider = Some { ResolveProvider = Some false }
+            ExecuteCommandProvider = Some { commands = Some Commands.names }
             SemanticTokensProvider =
                 Some
                     { Legend = { TokenTypes = Semato.TokenType.mapping; TokenModifiers = [||] }
@@ -152,27 +162,113 @@ module ServerUtil =
                       Full = { Delta = Some false } |> U2.Second |> Some }
             RenameProvider = renameOptions }
 
-type MarksmanStatusParams = { state: string; docCount: int }
+    let completionRegistration: Registration =
+        let triggerCharacters = Compl.Prompt.triggerCharacters |> Array.map string
+
+        let options =
+            JObject(
+                // null stands for the document selector the client has itself
+                JProperty("documentSelector", JValue.CreateNull()),
+                JProperty("triggerCharacters", JArray(triggerCharacters)),
+                JProperty("resolveProvider", true)
+            )
+
+        { Id = "marksman-completion"
+          Method = "textDocument/completion"
+          RegisterOptions = Some options }
+
+    // Folder configs are re-read when they change
+    let configWatcherRegistration: Registration =
+        let watcher = JObject(JProperty("globPattern", "**/.marksman.toml"))
+
+        { Id = "marksman-config-watcher"
+          Method = "workspace/didChangeWatchedFiles"
+          RegisterOptions = Some(JObject(JProperty("watchers", JArray(watcher)))) }
 
-type MarksmanClient(notiSender: ClientNotificationSender, _reqSender: ClientRequestSender) =
+    // Assets are listed when a folder is loaded; files created or deleted later are told about.
+    // Changes of their content don't matter
+    let assetWatcherRegistration: Registration =
+        let kind = int WatchKind.Create ||| int WatchKind.Delete
+
+        let watcher =
+            JObject(JProperty("globPattern", "**/*"), JProperty("kind", kind))
+
+        { Id = "marksman-asset-watcher"
+          Method = "workspace/didChangeWatchedFiles"
+          RegisterOptions = Some(JObject(JProperty("watchers", JArray(watcher)))) }
+
+    let hoverContent (client: ClientDescription) (text: string) : HoverContent =
+        if client.SupportsMarkdownHover then
+            MarkupContent(markdown text)
+        else
+            MarkupContent(plaintext text)
+
+    let adaptDiagnostics (client: ClientDescription) (diags: array<Diagnostic>) =
+        if client.SupportsRelatedInformation then
+            diags
+        else
+            diags |> Array.map (fun diag -> { diag with RelatedInformation = None })
+
+/// Params of `marksman/graph`. The graph is of the folder containing `uri`, which can be left
+/// out when the workspace has a single folder. `format` is "json" (the default), "dot" or
+/// "graphml"; graphs in the latter two come as a string.
+type GraphParams =
+    { uri: option<string>
+      format: option<string> }
+
+/// Params of `marksman/status`.
+type MarksmanStatusParams =
+    { state: string
+      docCount: int
+      /// "indexing" while folders of the workspace are read, "ready" otherwise
+      phase: string
+      /// How long reading the folders took the last time
+      indexingMs: int64
+      /// Notes skipped by the ignore globs
+      ignoredCount: int
+      /// Notes skipped for being too large or binary
+      skippedCount: int
+      /// Rough size of the managed heap
+      memoryBytes: int64
+      /// Rough size of the texts of the notes; the rest of the heap is mostly what's derived from
+      /// them, e.g. parses and indexes
+      textBytes: int64
+      /// False while the heap is over `core.memory_limit_mb`, when derived data isn't cached
+      cachesEnabled: bool }
+
+type MarksmanClient(notiSender: ClientNotificationSender, reqSender: ClientRequestSender) =
     inherit LspClient()
 
+    override this.ClientRegisterCapability(par: RegistrationParams) =
+        reqSender.Send "client/registerCapability" (box par)
+
+    override this.WorkspaceConfiguration(par: ConfigurationParams) =
+        reqSender.Send "workspace/configuration" (box par)
+
+    override this.WorkspaceApplyEdit(par: ApplyWorkspaceEditParams) =
+        reqSender.Send "workspace/applyEdit" (box par)
+
     override this.TextDocumentPublishDiagnostics(par: PublishDiagnosticsParams) =
         notiSender "textDocument/publishDiagnostics" (box par) |> Async.Ignore
 
+    member this.WorkDoneProgressCreate(par: WorkDoneProgressCreateParams) : AsyncLspResult<unit> =
+        reqSender.Send "window/workDoneProgress/create" (box par)
+
+    member this.Progress(par: ProgressParams<'T>) =
+        notiSender "$/progress" (box par) |> Async.Ignore
+
     member this.MarksmanUpdateStatus(par: MarksmanStatusParams) =
         notiSender "marksman/status" (box par) |> Async.Ignore
 
+/// Diagnostics to publish after moving from `prevState`, for which `existingDiag` was published, to
+/// `newState` with `newDiag`. Diagnostics are passed in rather than recalculated, since they don't
+/// only depend on the state, e.g. URL checks complete in the background.
 let calcDiagnosticsUpdate
     (prevState: Option<State>)
+    (existingDiag: WorkspaceDiag)
     (newState: State)
+    (newDiag: WorkspaceDiag)
     : seq<PublishDiagnosticsParams> =
-    let existingDiag =
-        prevState
-        |> Option.map State.diag
-        |> Option.defaultValue WorkspaceDiag.empty
-
-    let newDiag = State.diag newState
 
     let allFolders =
         Set.union (Map.keys existingDiag |> Set.ofSeq) (Map.keys newDiag |> Set.ofSeq)
@@ -228,49 +324,138 @@ let calcDiagnosticsUpdate
                         >> Log.addContext "doc" docUri
                     )
 
-                    let publishParams = { Uri = docUri.DocumentUri; Diagnostics = newDocDiag }
+                    let diags = ServerUtil.adaptDiagnostics (State.client newState) newDocDiag
+                    let publishParams = { Uri = docUri.DocumentUri; Diagnostics = diags }
 
                     yield publishParams
     }
 
-type DiagnosticsManager(client: MarksmanClient) =
+type private DiagMessage =
+    | NewState of State
+    // The diagnostics of the state, checked off the agent. `None` when the check was cancelled
+    | Checked of option<WorkspaceCheck * WorkspaceDiag>
+    | StopDiag
+
+type DiagnosticsManager(client: MarksmanClient, urlChecker: UrlCheck.Checker) =
     let logger = LogProvider.getLoggerByName "BackgroundAgent"
 
-    let agent: MailboxProcessor<State> =
+    // Set on shutdown, after which the client no longer expects diagnostics
+    let mutable stopped = false
+
+    let agent: MailboxProcessor<DiagMessage> =
         MailboxProcessor.Start(fun inbox ->
-            let rec accumulate lastProcessedState mostRecentState =
+            let rec accumulate lastProcessed mostRecentState =
                 async {
-                    // 200ms grace period to avoid recalculating diagnostics during active editing
-                    // The diagnostics update still feels pretty much instant, but doing it this
-                    // way is much more efficient
-                    let! newState = inbox.TryReceive(timeout = 200)
-
-                    match newState with
-                    | None -> return! publishOn lastProcessedState mostRecentState
-                    | Some newState -> return! accumulate lastProcessedState newState
+                    // Grace period, 200ms by default, to avoid recalculating diagnostics during
+                    // active editing; every new state restarts it. The diagnostics update still
+                    // feels pretty much instant, but doing it this way is much more efficient
+                    let debounce = (State.userConfigOrDefault mostRecentState).DiagDebounceMs()
+
+                    match! inbox.TryReceive(timeout = debounce) with
+                    | None -> return! check lastProcessed mostRecentState
+                    | Some (NewState newState) -> return! accumulate lastProcessed newState
+                    | Some (Checked _) -> return! accumulate lastProcessed mostRecentState
+                    | Some StopDiag -> return ()
                 }
 
-            and publishOn lastProcessedState mostRecentState =
+            // The check runs off the agent, so that new states can cancel it. Only the notes that
+            // changed, or whose links may resolve differently, are checked again
+            and check lastProcessed mostRecentState =
                 async {
-                    let diagnostics = calcDiagnosticsUpdate lastProcessedState mostRecentState
+                    let _, _, lastCheck = lastProcessed
+                    let cancel = new CancellationTokenSource()
+
+                    async {
+                        let result =
+                            try
+                                WorkspaceCheck.update
+                                    (Some urlChecker)
+                                    cancel.Token
+                                    lastCheck
+                                    (State.workspace mostRecentState)
+                                |> Some
+                            with
+                            | :? OperationCanceledException -> None
+                            | exn ->
+                                logger.error (
+                                    Log.setMessage "Failed to check the diagnostics"
+                                    >> Log.addException exn
+                                )
+
+                                None
+
+                        inbox.Post(Checked result)
+                    }
+                    |> Async.Start
+
+                    return! checking lastProcessed mostRecentState cancel
+                }
 
-                    for update in diagnostics do
-                        do! client.TextDocumentPublishDiagnostics(update)
+            and checking lastProcessed mostRecentState (cancel: CancellationTokenSource) =
+                async {
+                    match! inbox.Receive() with
+                    | NewState newState ->
+                        logger.trace (Log.setMessage "Diagnostics are outdated by a new state")
+                        cancel.Cancel()
+                        return! cancelling lastProcessed (Some newState) cancel
+                    | StopDiag ->
+                        cancel.Cancel()
+                        return! cancelling lastProcessed None cancel
+                    | Checked _ when stopped ->
+                        cancel.Dispose()
+                        return ()
+                    | Checked None ->
+                        cancel.Dispose()
+                        return! waitStateUpdate lastProcessed
+                    | Checked (Some (newCheck, newDiag)) ->
+                        cancel.Dispose()
+                        let lastState, lastDiag, _ = lastProcessed
+
+                        let diagnostics =
+                            calcDiagnosticsUpdate lastState lastDiag mostRecentState newDiag
+
+                        for update in diagnostics do
+                            do! client.TextDocumentPublishDiagnostics(update)
+
+                        return! waitStateUpdate (Some mostRecentState, newDiag, newCheck)
+                }
 
-                    return! waitStateUpdate (Some mostRecentState)
+            // Waits for the cancelled check to let go of its token. `None` for the state once the
+            // agent is stopped
+            and cancelling lastProcessed mostRecentState (cancel: CancellationTokenSource) =
+                async {
+                    match! inbox.Receive() with
+                    | Checked _ ->
+                        cancel.Dispose()
+
+                        match mostRecentState with
+                        | Some state -> return! accumulate lastProcessed state
+                        | None -> return ()
+                    | NewState newState when Option.isSome mostRecentState ->
+                        return! cancelling lastProcessed (Some newState) cancel
+                    | NewState _ -> return! cancelling lastProcessed None cancel
+                    | StopDiag -> return! cancelling lastProcessed None cancel
                 }
 
-            and waitStateUpdate lastProcessedState =
+            and waitStateUpdate lastProcessed =
                 async {
-                    let! newState = inbox.Receive()
-                    return! accumulate lastProcessedState newState
+                    match! inbox.Receive() with
+                    | NewState newState -> return! accumulate lastProcessed newState
+                    | Checked _ -> return! waitStateUpdate lastProcessed
+                    | StopDiag -> return ()
                 }
 
             logger.trace (Log.setMessage "Preparing to start background agent")
 
-            waitStateUpdate None)
+            waitStateUpdate (None, WorkspaceDiag.empty, WorkspaceCheck.empty))
 
-    member this.UpdateDiagnostics(state: State) : unit = agent.Post(state)
+    member this.UpdateDiagnostics(state: State) : unit =
+        if not stopped then agent.Post(NewState state)
+
+    /// Abandons the diagnostics being calculated and ignores the states that come afterwards.
+    member this.Stop() : unit =
+        stopped <- true
+        agent.Post(StopDiag)
 
     interface IDisposable with
         member _.Dispose() = (agent :> IDisposable).Dispose()
@@ -282,40 +467,139 @@ let queueDiagnosticsUpdate
     =
     manager.UpdateDiagnostics(newState)
 
-type StatusMessage = DocCount of int
+type StatusMessage =
+    /// Sent once the client is initialized, since status notifications can't come before that
+    | Enable
+    | DocCount of docs: int * ignored: int * skipped: int
+    | Indexing
+    | Indexed of elapsed: TimeSpan
+    | CachesEnabled of bool
+    /// Sent on shutdown, after which the client no longer expects notifications
+    | Disable
+
+type private Status =
+    { enabled: bool
+      docCount: int
+      ignoredCount: int
+      skippedCount: int
+      indexing: bool
+      indexingMs: int64
+      cachesEnabled: bool }
 
 type StatusManager(client: MarksmanClient) =
     let logger = LogProvider.getLoggerByName "StatusAgent"
 
+    // Changes with every edit, so like the memory it doesn't warrant an update on its own
+    let mutable textBytes = 0L
+
+    let toParams (status: Status) : MarksmanStatusParams =
+        { state = "ok"
+          docCount = status.docCount
+          phase = if status.indexing then "indexing" else "ready"
+          indexingMs = status.indexingMs
+          ignoredCount = status.ignoredCount
+          skippedCount = status.skippedCount
+          memoryBytes = Caches.heapBytes ()
+          textBytes = textBytes
+          cachesEnabled = status.cachesEnabled }
+
     let agent =
         MailboxProcessor.Start(fun inbox ->
-            let rec loop cnt =
+            let rec loop (status: Status) (sent: option<Status>) =
                 async {
                     let! msg = inbox.Receive()
 
-                    match msg with
-                    | DocCount newCnt when cnt <> newCnt ->
+                    let newStatus =
+                        match msg with
+                        | Enable -> { status with enabled = true }
+                        | Disable -> { status with enabled = false }
+                        | DocCount (docs, ignored, skipped) ->
+                            { status with
+                                docCount = docs
+                                ignoredCount = ignored
+                                skippedCount = skipped }
+                        | Indexing -> { status with indexing = true }
+                        | Indexed elapsed ->
+                            { status with
+                                indexing = false
+                                indexingMs = int64 elapsed.TotalMilliseconds }
+                        | CachesEnabled enabled -> { status with cachesEnabled = enabled }
+
+                    // Memory changes all the time, so on its own it doesn't warrant an update
+                    if newStatus.enabled && Some newStatus <> sent then
                         logger.trace (
                             Log.setMessage "StatusAgent sending update"
-                            >> Log.addContext "docCount" (cnt, newCnt)
+                            >> Log.addContext "docCount" newStatus.docCount
+                            >> Log.addContext "indexing" newStatus.indexing
                         )
 
-                        do! client.MarksmanUpdateStatus({ state = "ok"; docCount = newCnt })
-                        return! loop newCnt
-                    | _ -> return! loop cnt
+                        do! client.MarksmanUpdateStatus(toParams newStatus)
+                        return! loop newStatus (Some newStatus)
+                    else
+                        return! loop newStatus sent
                 }
 
             logger.trace (Log.setMessage "StatusAgent starting")
-            loop 0)
 
-    member this.UpdateDocCount(cnt: int) : unit = agent.Post(DocCount cnt)
+            let initial: Status =
+                { enabled = false
+                  docCount = 0
+                  ignoredCount = 0
+                  skippedCount = 0
+                  indexing = false
+                  indexingMs = 0L
+                  cachesEnabled = Caches.enabled () }
+
+            loop initial None)
+
+    member this.Enable() : unit = agent.Post(Enable)
+
+    member this.UpdateDocCount(ws: Workspace) : unit =
+        let counts = Workspace.docCount ws, Workspace.ignoredCount ws, Workspace.skippedCount ws
+        textBytes <- Workspace.textBytes ws
+        agent.Post(DocCount counts)
+
+    member this.Indexing() : unit = agent.Post(Indexing)
+
+    member this.Indexed(elapsed: TimeSpan) : unit = agent.Post(Indexed elapsed)
+
+    member this.UpdateCachesEnabled(enabled: bool) : unit = agent.Post(CachesEnabled enabled)
+
+    member this.Disable() : unit = agent.Post(Disable)
 
     interface IDisposable with
         member _.Dispose() = (agent :> IDisposable).Dispose()
 
 let queueStatusUpdate (manager: StatusManager) (_: Option<State>) (newState: State) : unit =
-    let docCount = State.workspace newState |> Workspace.docCount
-    manager.UpdateDocCount docCount
+    manager.UpdateDocCount(State.workspace newState)
+
+// The lowest limit of the folders' configs, which are merged over the user config
+let private memoryLimitMb (ws: Workspace) : int =
+    let configs =
+        match List.ofSeq (Workspace.folders ws) with
+        | [] -> Workspace.userConfig ws |> Option.toList
+        | folders -> folders |> List.map Folder.configOrDefault
+
+    configs
+    |> List.map (fun config -> config.CoreMemoryLimitMb())
+    |> List.filter (fun limit -> limit > 0)
+    |> List.fold (fun lowest limit -> if lowest = 0 then limit else min lowest limit) 0
+
+let applyMemoryLimit (manager: StatusManager) (_: Option<State>) (newState: State) : unit =
+    let limitMb = memoryLimitMb (State.workspace newState)
+    let heap = Caches.heapBytes ()
+
+    if Caches.applyLimit limitMb heap then
+        let logger = LogProvider.getLoggerByName "Caches"
+
+        logger.info (
+            Log.setMessage "Memory limit crossed, toggled caches"
+            >> Log.addContext "heapBytes" heap
+            >> Log.addContext "limitMb" limitMb
+            >> Log.addContext "enabled" (Caches.enabled ())
+        )
+
+        manager.UpdateCachesEnabled(Caches.enabled ())
 
 type Hook = { name: string; fn: Option<State> -> State -> unit }
 
@@ -354,7 +638,7 @@ type StateManager(initState: State) =
             >> Log.addContext "curRev" (State.revision state)
         )
 
-        hook.fn prevState state
+        Trace.span "hook" hook.name (fun () -> hook.fn prevState state)
 
     let agent: MailboxProcessor<StateMessage> =
         MailboxProcessor.Start
@@ -372,7 +656,9 @@ type StateManager(initState: State) =
                         chan.Reply state
                         return! go prevState state hooks
                     | MutateState mutator ->
-                        let newState, addedHooks = mutator state
+                        // Other requests wait for the state while this runs
+                        let newState, addedHooks =
+                            Trace.span "state" "mutate" (fun () -> mutator state)
 
                         // Step 1: run _added_ hooks on the existing state
 
@@ -412,6 +698,15 @@ type StateManager(initState: State) =
             return f state
         }
 
+    /// Like `AccessExclusively`, without waiting for the mutation. Messages are processed in
+    /// order, so accesses that come after see the mutated state.
+    member this.Mutate(f: State -> Mutation<unit>) : unit =
+        let mutator state =
+            let mutation = f state
+            mutation.state, mutation.hooks
+
+        agent.Post(MutateState mutator)
+
     member this.AccessExclusively<'R>(f: State -> Mutation<'R>) : Async<'R> =
         let mkMsg (chan: AsyncReplyChannel<'R>) =
             let mutator state =
@@ -426,28 +721,255 @@ type StateManager(initState: State) =
     interface IDisposable with
         member _.Dispose() = (agent :> IDisposable).Dispose()
 
+/// Changes of open docs that came in quick succession, e.g. while pasting or editing with several
+/// cursors, gathered so that they're applied together. `flush` gets the changes pending,
+/// `window` milliseconds after the first change of a burst or when flushed explicitly.
+type ChangeBuffer(window: int, flush: list<DidChangeTextDocumentParams> -> unit) =
+    let pending = Collections.Generic.Dictionary<PathUri, DidChangeTextDocumentParams>()
+
+    // Under the lock, so that whoever finds no changes pending afterwards comes after `flush`
+    let flushPending () =
+        lock pending (fun () ->
+            if pending.Count > 0 then
+                let changes = List.ofSeq pending.Values
+                pending.Clear()
+                flush changes)
+
+    let timer =
+        new Timer(TimerCallback(fun _ -> flushPending ()), null, Timeout.Infinite, Timeout.Infinite)
+
+    member this.Add(change: DidChangeTextDocumentParams) : unit =
+        let docUri = change.TextDocument.Uri |> PathUri.ofString
+
+        lock pending (fun () ->
+            if pending.Count = 0 then
+                timer.Change(window, Timeout.Infinite) |> ignore
+
+            // Changes of a doc apply one after another, so a burst is a single change of them all
+            match pending.TryGetValue(docUri) with
+            | true, prev ->
+                pending[docUri] <-
+                    { change with
+                        ContentChanges = Array.append prev.ContentChanges change.ContentChanges }
+            | _ -> pending[docUri] <- change)
+
+    member this.Flush() : unit = flushPending ()
+
+    interface IDisposable with
+        member _.Dispose() = timer.Dispose()
+
 type MarksmanServer(client: MarksmanClient) =
     inherit LspServer()
 
-    let diagnosticsManager = new DiagnosticsManager(client)
+    let urlChecker = new UrlCheck.Checker()
+
+    let diagnosticsManager = new DiagnosticsManager(client, urlChecker)
+
+    // Diagnostics are updated whenever URL checks complete, until the server is disposed
+    let mutable urlCheckUpdates: option<IDisposable> = None
 
     let statusManager = new StatusManager(client)
 
+    let complSession = Compl.Session.Store()
+
     let mutable stateManager: option<StateManager> = None
 
     let requireStateManager () =
         stateManager
         |> Option.defaultWith (fun () -> failwith "State is not initialized")
 
+    let logger = LogProvider.getLoggerByName "MarksmanServer"
+
+    // Milliseconds from the first change of a burst until it's applied
+    let changeBurstWindow = 50
+
+    let applyChanges (changes: list<DidChangeTextDocumentParams>) (state: State) =
+        let apply (state: State) (change: DidChangeTextDocumentParams) =
+            let docUri = change.TextDocument.Uri |> PathUri.ofString
+
+            match State.tryFindFolderAndDoc docUri state with
+            | Some (folder, doc) ->
+                let newDoc = Doc.applyLspChange change doc
+                let newFolder = Folder.withDoc newDoc folder
+                State.updateFolder newFolder state
+            | _ ->
+                logger.warn (
+                    Log.setMessage "Document not found"
+                    >> Log.addContext "method" "textDocumentDidChange"
+                    >> Log.addContext "uri" docUri
+                )
+
+                state
+
+        List.fold apply state changes
+
+    // A doc is re-parsed and its folder re-indexed once per burst of changes rather than once per
+    // change. Each access to the state applies the pending changes first, so none of them is ever
+    // missing from what requests see. Changes come only after the state is initialized
+    let changeBuffer =
+        new ChangeBuffer(
+            changeBurstWindow,
+            fun changes ->
+                stateManager
+                |> Option.iter (fun sm ->
+                    sm.Mutate(fun state -> Mutation.state (applyChanges changes state)))
+        )
+
     let withState (f: State -> 'R) =
         let sm = requireStateManager ()
-        sm.AccessToRead f
+
+        async {
+            changeBuffer.Flush()
+            return! sm.AccessToRead f
+        }
 
     let withStateExclusive (f: State -> Mutation<'R>) =
         let sm = requireStateManager ()
-        sm.AccessExclusively f
 
-    let logger = LogProvider.getLoggerByName "MarksmanServer"
+        async {
+            changeBuffer.Flush()
+            return! sm.AccessExclusively f
+        }
+
+    let mutable stopped = false
+
+    // Clients may not send `exit`, or may close the connection right after `shutdown`, so this
+    // runs on whichever comes first. Nothing is persisted between sessions, so there is nothing
+    // to flush yet; an on-disk index would be written here
+    let stopBackgroundWork () =
+        if not stopped then
+            stopped <- true
+            diagnosticsManager.Stop()
+            statusManager.Disable()
+            urlChecker.Stop()
+
+    // Number of loaded notes between indexing progress reports
+    let progressReportEvery = 100
+
+    let reportProgress (token: option<ProgressToken>) (value: 'T) : Async<unit> =
+        match token with
+        | Some token -> client.Progress({ Token = token; Value = value })
+        | None -> async.Return()
+
+    // Items per `$/progress` notification when the client asked for partial results
+    let partialResultChunk = 256
+
+    // Sends the results as they're produced, so that clients can start showing them on large
+    // folders. The response itself must then be empty
+    let sendPartialResults (token: ProgressToken) (chunks: seq<'T>) : Async<unit> =
+        async {
+            for chunk in chunks do
+                if not stopped then
+                    do! client.Progress({ Token = token; Value = chunk })
+        }
+
+    // Progress is shown by the clients that let the server create a token for it
+    let tryCreateProgressToken (clientDesc: ClientDescription) : Async<option<ProgressToken>> =
+        async {
+            if clientDesc.SupportsWorkDoneProgress then
+                let token: ProgressToken = U2.Second "marksman/indexing"
+
+                match! client.WorkDoneProgressCreate({ Token = token }) with
+                | Ok () -> return Some token
+                | Error err ->
+                    logger.warn (
+                        Log.setMessage "Client refused to create a progress token"
+                        >> Log.addContext "error" err.Message
+                    )
+
+                    return None
+            else
+                return None
+        }
+
+    /// Reads the folders of the workspace one by one, adding each to the state once it's read,
+    /// so that requests are served from the start. Folders with notes opened in the meantime go
+    /// first; until their folder is read, open notes are served as single-file folders.
+    let indexWorkspace () : Async<unit> =
+        async {
+            let! clientDesc, openPaths, userConfig, roots =
+                withState (fun state ->
+                    let openPaths =
+                        Workspace.folders (State.workspace state)
+                        |> Seq.collect Folder.docs
+                        |> Seq.map Doc.path
+                        |> List.ofSeq
+
+                    State.client state,
+                    openPaths,
+                    Workspace.userConfig (State.workspace state),
+                    State.foldersToIndex state)
+
+            let hasOpenDocs (root: RootPath) =
+                openPaths |> List.exists (fun path -> RootPath.contains path root)
+
+            let roots =
+                Map.toList roots |> List.sortBy (fun (_, root) -> not (hasOpenDocs root))
+
+            let! token = tryCreateProgressToken clientDesc
+            do! reportProgress token (WorkDoneProgressBegin.Create("Indexing notes"))
+            statusManager.Indexing()
+            let stopwatch = Diagnostics.Stopwatch.StartNew()
+            let numNotes = ref 0
+
+            for name, root in roots do
+                // The client may have removed the folder in the meantime
+                let! stillWanted = withState (State.isToIndex root)
+
+                if not stopped && stillWanted then
+                    let onDocLoaded (loaded: int) =
+                        if loaded % progressReportEvery = 0 then
+                            WorkDoneProgressReport.Create($"{name}: {loaded} notes")
+                            |> reportProgress token
+                            |> Async.RunSynchronously
+
+                    match Folder.tryLoadWithProgress onDocLoaded userConfig name root with
+                    | Some folder ->
+                        numNotes.Value <- numNotes.Value + Folder.docCount folder
+
+                        // Or while the folder was read
+                        do!
+                            withStateExclusive (
+                                State.tryWithIndexedFolder folder >> Mutation.stateOpt
+                            )
+                    | None -> ()
+
+            statusManager.Indexed(stopwatch.Elapsed)
+
+            logger.debug (
+                Log.setMessage "Completed reading workspace folders"
+                >> Log.addContext "numFolders" roots.Length
+                >> Log.addContext "numNotes" numNotes.Value
+            )
+
+            do! reportProgress token (WorkDoneProgressEnd.Create($"Indexed {numNotes.Value} notes"))
+        }
+
+    let runCommandEffect (effect: Commands.Effect) : Async<unit> =
+        async {
+            match effect with
+            | Commands.PublishDiag diag ->
+                let! clientDesc = withState State.client
+
+                for folderDiag in Map.values diag do
+                    for docUri, diags in folderDiag do
+                        let diags = ServerUtil.adaptDiagnostics clientDesc diags
+
+                        do!
+                            client.TextDocumentPublishDiagnostics(
+                                { Uri = docUri.DocumentUri; Diagnostics = diags }
+                            )
+            | Commands.ApplyEdit (label, edit) ->
+                match! client.WorkspaceApplyEdit({ Label = Some label; Edit = edit }) with
+                | Ok { Applied = true } -> ()
+                | Ok _ ->
+                    logger.warn (Log.setMessage "Client didn't apply the edit of a command")
+                | Error err ->
+                    logger.warn (
+                        Log.setMessage "Failed to apply the edit of a command"
+                        >> Log.addContext "error" err.Message
+                    )
+        }
 
     let tryLoadUserConfig () : option<Config> =
         if File.Exists(Config.userConfigFile) then
@@ -473,6 +995,46 @@ type MarksmanServer(client: MarksmanClient) =
 
             None
 
+    // Settings of the client are under this section, laid out like the config file
+    let clientConfigSection = "marksman"
+
+    let fetchClientConfig () : Async<option<Config>> =
+        async {
+            let par = { items = [| { ScopeUri = None; Section = Some clientConfigSection } |] }
+            let! settings = client.WorkspaceConfiguration(par)
+
+            match settings with
+            | Ok [| settings |] when settings.Type = JTokenType.Object ->
+                return Config.ofJson settings
+            | Ok _ -> return None
+            | Error err ->
+                logger.warn (
+                    Log.setMessage "Failed to fetch client settings"
+                    >> Log.addContext "error" err.Message
+                )
+
+                return None
+        }
+
+    /// Applies client settings on top of the user config file; folder configs still win.
+    let applyClientConfig (clientConfig: option<Config>) : Async<unit> =
+        async {
+            logger.debug (
+                Log.setMessage "Applying client settings"
+                >> Log.addContext "settings" clientConfig
+            )
+
+            let userConfig = Config.mergeOpt clientConfig (tryLoadUserConfig ())
+
+            // Folders are read again off a snapshot, so that other requests don't wait for it
+            let! folders = withState (State.workspace >> Workspace.folders >> List.ofSeq)
+            let reloaded = folders |> List.choose (Folder.tryReload userConfig)
+
+            do!
+                withStateExclusive
+                <| fun state -> Mutation.state (State.withUserConfig userConfig reloaded state)
+        }
+
     override this.Initialize(par: InitializeParams) : AsyncLspResult<InitializeResult> =
         let workspaceFolders = ServerUtil.extractWorkspaceFolders par
         let clientDesc = ClientDescription.ofParams par
@@ -482,18 +1044,22 @@ type MarksmanServer(client: MarksmanClient) =
             >> Log.addContext "workspace" workspaceFolders
         )
 
-        let userConfig = tryLoadUserConfig ()
-        let folders = ServerUtil.readWorkspace userConfig workspaceFolders
-        let numNotes = folders |> List.sumBy Folder.docCount
+        // Positions are counted in the encoding the client prefers from here on, before any doc
+        // is read
+        let positionEncoding = Text.PositionEncoding.negotiate clientDesc.PositionEncodings
+        Text.PositionEncoding.set positionEncoding
 
         logger.debug (
-            Log.setMessage "Completed reading workspace folders"
-            >> Log.addContext "numFolders" folders.Length
-            >> Log.addContext "numNotes" numNotes
+            Log.setMessage "Negotiated the position encoding"
+            >> Log.addContext "encoding" (Text.PositionEncoding.toString positionEncoding)
         )
 
-        let workspace = Workspace.ofFolders userConfig folders
-        let initState = State.mk clientDesc workspace
+        // Folders are read after the initialization, so that the client doesn't wait for them
+        let userConfig = tryLoadUserConfig ()
+        let workspace = Workspace.ofFolders userConfig []
+
+        let initState =
+            State.mk clientDesc workspace |> State.withFoldersToIndex workspaceFolders
         stateManager <- Some(new StateManager(initState))
 
         // Workspace may contain several folders with their own configuration. However, server
@@ -503,14 +1069,24 @@ type MarksmanServer(client: MarksmanClient) =
         //
         // NOTE: this doesn't address the case when a folder is added to the workspace later on.
         // We'd need to add dynamic registration of capabilities on the server side.
+        let configs =
+            Map.values workspaceFolders
+            |> Seq.map (fun root ->
+                Folder.tryLoadConfig userConfig root |> Option.defaultValue Config.Default)
+            |> Array.ofSeq
+
         let configuredExts =
-            Workspace.folders workspace
-            |> Seq.map Folder.configOrDefault
+            configs
             |> Seq.collect (fun c -> c.CoreMarkdownFileExtensions())
             |> Seq.distinct
             |> Array.ofSeq
 
-        let serverCaps = ServerUtil.mkServerCaps configuredExts par
+        // The same goes for the backlinks section, which is refreshed on save only in the folders
+        // where it's on
+        let backlinksSection = configs |> Array.exists (fun c -> c.CoreBacklinksSection())
+
+        let serverCaps =
+            ServerUtil.mkServerCaps configuredExts backlinksSection positionEncoding par
 
         let initResult =
             { InitializeResult.Default with Capabilities = serverCaps }
@@ -532,13 +1108,29 @@ type MarksmanServer(client: MarksmanClient) =
             )
 
             let diagHook = queueDiagnosticsUpdate diagnosticsManager
-            let mutable newHooks = [ { name = "diag"; fn = diagHook } ]
+            let memoryHook = applyMemoryLimit statusManager
+
+            let mutable newHooks =
+                [ { name = "diag"; fn = diagHook }; { name = "memory"; fn = memoryHook } ]
+
+            // URL checks complete in the background, without a state change to trigger the diag
+            let onUrlsChecked () =
+                async {
+                    let! state = (requireStateManager ()).AccessToRead id
+                    diagnosticsManager.UpdateDiagnostics(state)
+                }
+                |> Async.Start
+
+            urlCheckUpdates <- Some(Observable.subscribe onUrlsChecked urlChecker.Updated)
 
             if (State.client state).SupportsStatus then
                 logger.debug (
                     Log.setMessage "Client supports status notifications. Initializing agent."
                 )
 
+                statusManager.UpdateDocCount(State.workspace state)
+                statusManager.Enable()
+
                 let statusHook = queueStatusUpdate statusManager
                 newHooks <- { name = "status"; fn = statusHook } :: newHooks
             else
@@ -547,40 +1139,57 @@ type MarksmanServer(client: MarksmanClient) =
                         "Client doesn't support status notifications. Agent won't be initialized."
                 )
 
+            if (State.client state).SupportsDynamicCompletion then
+                logger.debug (Log.setMessage "Registering completion dynamically")
+
+                client.ClientRegisterCapability(
+                    { Registrations = [| ServerUtil.completionRegistration |] }
+                )
+                |> Async.Ignore
+                |> Async.Start
+
+            if (State.client state).SupportsConfiguration then
+                logger.debug (Log.setMessage "Fetching client settings")
+
+                async {
+                    match! fetchClientConfig () with
+                    | Some clientConfig -> do! applyClientConfig (Some clientConfig)
+                    | None -> ()
+                }
+                |> Async.Start
+
+            if (State.client state).SupportsDynamicWatchedFiles then
+                logger.debug (Log.setMessage "Registering config and asset file watchers")
+
+                client.ClientRegisterCapability(
+                    { Registrations =
+                        [| ServerUtil.configWatcherRegistration
+                           ServerUtil.assetWatcherRegistration |] }
+                )
+                |> Async.Ignore
+                |> Async.Start
+
+            indexWorkspace () |> Async.Start
+
             logger.debug (Log.setMessage "Initialization complete.")
 
             Mutation.hooks newHooks
 
     override this.Shutdown() =
         logger.trace (Log.setMessage "Preparing for shutdown")
+        stopBackgroundWork ()
+        logger.debug (Log.setMessage "Shutdown complete")
         async.Return()
 
     override this.Exit() =
         logger.trace (Log.setMessage "Exiting")
+        stopBackgroundWork ()
         async.Return()
 
     override this.TextDocumentDidChange(par: DidChangeTextDocumentParams) =
-        withStateExclusive
-        <| fun state ->
-            let docUri = par.TextDocument.Uri |> PathUri.ofString
-
-            let newState =
-                match State.tryFindFolderAndDoc docUri state with
-                | Some (folder, doc) ->
-                    let newDoc = Doc.applyLspChange par doc
-                    let newFolder = Folder.withDoc newDoc folder
-
-                    State.updateFolder newFolder state |> Some
-                | _ ->
-                    logger.warn (
-                        Log.setMessage "Document not found"
-                        >> Log.addContext "method" "textDocumentDidChange"
-                        >> Log.addContext "uri" docUri
-                    )
-
-                    None
-
-            Mutation.stateOpt newState
+        // Runs before the next message is handled, which keeps the changes in order
+        changeBuffer.Add(par)
+        async.Return()
 
     override this.TextDocumentDidClose(par: DidCloseTextDocumentParams) =
         withStateExclusive
@@ -598,6 +1207,19 @@ type MarksmanServer(client: MarksmanClient) =
 
                 Mutation.state newState
 
+    override this.TextDocumentWillSaveWaitUntil(par: WillSaveTextDocumentParams) =
+        withState
+        <| fun state ->
+            let edits =
+                State.tryFindFolderAndDoc (PathUri.ofString par.TextDocument.Uri) state
+                |> Option.filter (fun (folder, doc) ->
+                    (Folder.configFor doc folder).CoreBacklinksSection())
+                |> Option.bind (fun (folder, doc) -> Backlinks.update folder doc)
+                |> Option.map (fun action ->
+                    [| { Range = action.edit; NewText = action.newText } |])
+
+            LspResult.success edits
+
 
     override this.TextDocumentDidOpen(par: DidOpenTextDocumentParams) =
         withStateExclusive
@@ -606,11 +1228,20 @@ type MarksmanServer(client: MarksmanClient) =
 
             let newState =
                 match State.tryFindFolderEnclosing path state with
+                | None when path.IsUntitled ->
+                    if par.TextDocument.LanguageId = "markdown" then
+                        State.openUntitled par.TextDocument state
+                    else
+                        state
                 | None ->
                     let configuredExts =
                         (State.userConfigOrDefault state).CoreMarkdownFileExtensions()
 
-                    if isMarkdownFile configuredExts path.LocalPath then
+                    // A lone file is a note when the client says so, whatever its extension
+                    if
+                        isMarkdownFile configuredExts path.LocalPath
+                        || par.TextDocument.LanguageId = "markdown"
+                    then
                         let singletonRoot =
                             Path.GetDirectoryName path.LocalPath |> RootPath.ofString
 
@@ -634,13 +1265,134 @@ type MarksmanServer(client: MarksmanClient) =
             Mutation.state newState
 
     override this.WorkspaceDidChangeWorkspaceFolders(par: DidChangeWorkspaceFoldersParams) =
-        withStateExclusive
-        <| fun state ->
-            let newState =
-                State.updateFoldersFromLsp par.Event.Added par.Event.Removed state
+        async {
+            let removedRoots =
+                par.Event.Removed |> Array.map (fun f -> RootPath.ofString f.Uri)
 
-            Mutation.state newState
+            statusManager.Indexing()
+            let stopwatch = Diagnostics.Stopwatch.StartNew()
+
+            let! userConfig = withState (State.workspace >> Workspace.userConfig)
+
+            let added =
+                par.Event.Added
+                |> Array.choose (fun folder ->
+                    Folder.tryLoad userConfig folder.Name (RootPath.ofString folder.Uri))
+                |> List.ofArray
+
+            let removed =
+                removedRoots |> Array.map (RootPath.path >> FolderId.ofPath) |> List.ofArray
+
+            do!
+                withStateExclusive
+                <| fun state -> Mutation.state (State.updateFolders added removed state)
+
+            statusManager.Indexed(stopwatch.Elapsed)
+        }
+
+
+    override this.WorkspaceDidChangeConfiguration(par: DidChangeConfigurationParams) =
+        async {
+            // Clients either push the settings or only notify that they changed
+            let pushed =
+                match par.Settings with
+                | :? JObject as settings -> settings[clientConfigSection]
+                | _ -> null
+
+            let! supportsConfiguration =
+                withState (fun state -> (State.client state).SupportsConfiguration)
+
+            if not (isNull pushed) && pushed.Type = JTokenType.Object then
+                do! applyClientConfig (Config.ofJson pushed)
+            elif supportsConfiguration then
+                let! clientConfig = fetchClientConfig ()
+                do! applyClientConfig clientConfig
+        }
+
+    override this.WorkspaceDidChangeWatchedFiles(par: DidChangeWatchedFilesParams) =
+        async {
+            let! ws = withState State.workspace
+
+            let isConfig (change: FileEvent) =
+                Path.GetFileName((PathUri.ofString change.Uri).LocalPath) = ".marksman.toml"
+
+            let configChanges, assetChanges = par.Changes |> Array.partition isConfig
 
+            if not (Array.isEmpty assetChanges) then
+                do!
+                    withStateExclusive
+                    <| fun state ->
+                        let updateAsset state (change: FileEvent) =
+                            let path = PathUri.ofString change.Uri
+
+                            let update =
+                                match change.Type with
+                                | FileChangeType.Created -> Some(Folder.withAsset path)
+                                | FileChangeType.Deleted -> Some(Folder.withoutAsset path)
+                                | _ -> None
+
+                            match State.tryFindFolderEnclosing path state, update with
+                            | Some folder, Some update -> State.updateFolder (update folder) state
+                            | _ -> state
+
+                        Mutation.state (Array.fold updateAsset state assetChanges)
+
+            let changedConfigs =
+                configChanges
+                |> Array.map (fun change -> (PathUri.ofString change.Uri).LocalPath)
+
+            // Configs of subdirectories are part of the folder config too
+            let isChanged folder =
+                let root = (RootPath.path (Folder.rootPath folder)).LocalPath
+
+                let prefix =
+                    Path.TrimEndingDirectorySeparator(root) + string Path.DirectorySeparatorChar
+
+                changedConfigs |> Array.exists (fun path -> path.StartsWith(prefix))
+
+            let reloaded =
+                Workspace.folders ws
+                |> Seq.filter isChanged
+                |> Seq.choose (Folder.tryReload (Workspace.userConfig ws))
+                |> List.ofSeq
+
+            for folder in reloaded do
+                logger.debug (
+                    Log.setMessage "Reloaded folder after its config changed"
+                    >> Log.addContext "folder" (Folder.rootPath folder)
+                )
+
+            // Folders are read off a snapshot, so that other requests don't wait for it
+            if not (List.isEmpty reloaded) then
+                do!
+                    withStateExclusive
+                    <| fun state -> Mutation.state (State.withReloadedFolders reloaded state)
+        }
+
+    override this.WorkspaceExecuteCommand(par: ExecuteCommandParams) =
+        async {
+            match Commands.tryFind par.Command with
+            | None -> return LspResult.invalidParams $"Unknown command: {par.Command}"
+            | Some command ->
+                let args = defaultArg par.Arguments [||]
+                let! outcome = withState (fun state -> command.run state args)
+
+                match outcome with
+                | Error err -> return LspResult.invalidParams err
+                | Ok outcome ->
+                    match outcome.update with
+                    | Some update -> do! withStateExclusive (update >> Mutation.stateOpt)
+                    | None -> ()
+
+                    for effect in outcome.effects do
+                        do! runCommandEffect effect
+
+                    logger.debug (
+                        Log.setMessage "Executed a command" >> Log.addContext "command" command.name
+                    )
+
+                    return LspResult.success outcome.result
+        }
 
     override this.WorkspaceDidCreateFiles(par: CreateFilesParams) =
         withStateExclusive
@@ -661,8 +1413,11 @@ type MarksmanServer(client: MarksmanClient) =
                     let configuredExts =
                         (Folder.configOrDefault folder).CoreMarkdownFileExtensions()
 
-                    if isMarkdownFile configuredExts docUri.LocalPath then
-                        match Doc.tryLoad (Folder.rootPath folder) docUri with
+                    if
+                        isMarkdownFile configuredExts docUri.LocalPath
+                        && not (Folder.isIgnored docUri folder)
+                    then
+                        match Folder.tryLoadDoc docUri folder with
                         | Some doc ->
                             let newFolder = Folder.withDoc doc folder
                             newState <- State.updateFolder newFolder newState
@@ -700,11 +1455,16 @@ type MarksmanServer(client: MarksmanClient) =
 
 
     override this.WorkspaceSymbol(pars) =
-        withState
-        <| fun state ->
-            let ws = State.workspace state
-            let symbols = Symbols.workspaceSymbols pars.Query ws
-            LspResult.success (Some symbols)
+        async {
+            let! ws = withState State.workspace
+
+            match pars.PartialResultToken with
+            | Some token ->
+                let chunks = Symbols.workspaceSymbolChunks partialResultChunk pars.Query ws
+                do! sendPartialResults token chunks
+                return LspResult.success (Some [||])
+            | None -> return LspResult.success (Some(Symbols.workspaceSymbols pars.Query ws))
+        }
 
     override this.TextDocumentDocumentSymbol(par: DocumentSymbolParams) =
         withState
@@ -719,6 +1479,17 @@ type MarksmanServer(client: MarksmanClient) =
             let response = State.tryFindDoc docUri state |> Option.map getSymbols
             LspResult.success response
 
+    override this.TextDocumentFoldingRange(par: FoldingRangeParams) =
+        withState
+        <| fun state ->
+            let docUri = par.TextDocument.Uri |> PathUri.ofString
+
+            let response =
+                State.tryFindDoc docUri state
+                |> Option.map (Folding.docFoldingRanges >> List.ofArray)
+
+            LspResult.success response
+
     override this.TextDocumentCompletion(par: CompletionParams) =
         withState
         <| fun state ->
@@ -731,13 +1502,42 @@ type MarksmanServer(client: MarksmanClient) =
                 monad' {
                     let! folder = State.tryFindFolderEnclosing docUri state
 
-                    match Compl.findCandidates folder docUri pos with
+                    let toClientFormat =
+                        if (State.client state).SupportsSnippets then
+                            id
+                        else
+                            Compl.Snippet.toPlainItem
+
+                    let trigger =
+                        par.Context
+                        |> Option.filter (fun ctx ->
+                            ctx.triggerKind = CompletionTriggerKind.TriggerCharacter)
+                        |> Option.bind (fun ctx -> ctx.triggerCharacter)
+
+                    // Candidates are filtered by the input and capped, so the client has to ask
+                    // again as the input grows rather than filter the items itself
+                    match
+                        Compl.findTriggeredCandidates complSession folder docUri pos trigger
+                    with
                     | [||] -> return! None
-                    | candidates -> { IsIncomplete = true; Items = candidates }
+                    | candidates ->
+                        { IsIncomplete = true; Items = Array.map toClientFormat candidates }
                 }
 
             LspResult.success candidates
 
+    override this.CompletionItemResolve(item: CompletionItem) =
+        withState
+        <| fun state ->
+            let resolved =
+                monad' {
+                    let! docUri = Compl.ItemData.tryGet Compl.ItemData.docUriKey item
+                    let! folder = State.tryFindFolderEnclosing (PathUri.ofString docUri) state
+                    Compl.Preview.resolve folder item
+                }
+
+            LspResult.success (Option.defaultValue item resolved)
+
     override this.TextDocumentDefinition(par: TextDocumentPositionParams) =
         withState
         <| fun state ->
@@ -754,7 +1554,9 @@ type MarksmanServer(client: MarksmanClient) =
                     let! atPos = Doc.index srcDoc |> Index.linkAtPos par.Position
                     let! uref = Uref.ofElement configuredExts atPos
 
-                    let refs = Dest.tryResolveUref uref srcDoc folder
+                    let refs =
+                        Dest.tryResolveUref uref srcDoc folder
+                        |> Dest.pick ((Folder.configOrDefault folder).CoreLinkResolution()) srcDoc
 
                     let locs =
                         refs
@@ -770,78 +1572,167 @@ type MarksmanServer(client: MarksmanClient) =
             LspResult.success goto
 
     override this.TextDocumentHover(par: TextDocumentPositionParams) =
-        withState
-        <| fun state ->
-            let docUri = par.TextDocument.Uri |> PathUri.ofString
+        async {
+            // What to show is found in the state, and then the last commit of the linked doc
+            // is asked from git off the state agent, since git may take a while to answer
+            let! found =
+                withState
+                <| fun state ->
+                    let docUri = par.TextDocument.Uri |> PathUri.ofString
+
+                    monad {
+                        let! folder = State.tryFindFolderEnclosing docUri state
+
+                        let configuredExts =
+                            (Folder.configOrDefault folder).CoreMarkdownFileExtensions()
+
+                        let! srcDoc = Folder.tryFindDocByPath docUri folder
+
+                        let! atPos =
+                            Doc.index srcDoc
+                            |> Index.citationAtPos par.Position
+                            |> Option.map CT
+                            |> Option.orElseWith (fun () ->
+                                Doc.index srcDoc |> Index.linkAtPos par.Position)
+
+                        match atPos with
+                        | CT citation ->
+                            let! entry = Bibliography.tryFind folder (Citation.key citation.data)
+                            Bibliography.Entry.fmt entry, Some citation.range, None
+                        | ML link when MdLink.isImage link ->
+                            let! description = Assets.describeImageLink folder srcDoc link
+                            description, None, None
+                        | WL wl when
+                            wl.data.embed
+                            && Seq.isEmpty (Dest.tryResolveElement folder srcDoc atPos)
+                            ->
+                            let! description = Assets.describeEmbed folder wl
+                            description, None, None
+                        | _ ->
+                            let! uref = Uref.ofElement configuredExts atPos
+                            // NOTE: Due to ambiguity there may be several sources for hover.
+                            // Since hover request requires a single result we return the first
+                            // one picked by the configured resolution strategy, which makes it
+                            // deterministic.
+                            let strategy = (Folder.configOrDefault folder).CoreLinkResolution()
+
+                            let! ref =
+                                Dest.tryResolveUref uref srcDoc folder
+                                |> Dest.pick strategy srcDoc
+                                |> Array.tryHead
+
+                            let destScope = Dest.scope ref
+                            let destDoc = Dest.doc ref
+
+                            let gitPath =
+                                if (Folder.configOrDefault folder).GitEnable() then
+                                    Some (Doc.path destDoc).LocalPath
+                                else
+                                    None
+
+                            // Embeds show what they embed, with the embeds within it shown in
+                            // turn
+                            let destText =
+                                match atPos with
+                                | WL wl when wl.data.embed -> Dest.embeddedText folder ref
+                                | _ -> (Doc.text destDoc).Substring destScope
+
+                            destText, None, gitPath
+                    }
+                    |> Option.map (fun found -> State.client state, found)
 
             let hover =
-                monad {
-                    let! folder = State.tryFindFolderEnclosing docUri state
-
-                    let configuredExts =
-                        (Folder.configOrDefault folder).CoreMarkdownFileExtensions()
-
-                    let! srcDoc = Folder.tryFindDocByPath docUri folder
-                    let! atPos = Doc.index srcDoc |> Index.linkAtPos par.Position
-                    let! uref = Uref.ofElement configuredExts atPos
-                    // NOTE: Due to ambiguity there may be several sources for hover. Since hover
-                    // request requires a single result we return the first. When links are not
-                    // ambiguous this is OK, otherwise the author is to blame for ambiguity anyway.
-                    let! ref = Dest.tryResolveUref uref srcDoc folder |> Seq.tryHead
-
-                    let destScope = Dest.scope ref
+                found
+                |> Option.map (fun (client, (text, range, gitPath)) ->
+                    let lastCommit =
+                        gitPath
+                        |> Option.bind Git.lastCommit
+                        |> Option.map (fun commit ->
+                            $"\n\n---\n\nLast changed by {Git.Commit.fmt commit}")
 
                     let content =
-                        (Dest.doc >> Doc.text <| ref).Substring destScope
-                        |> markdown
-                        |> MarkupContent
+                        text + Option.defaultValue "" lastCommit |> ServerUtil.hoverContent client
 
-                    let hover = { Contents = content; Range = None }
+                    { Contents = content; Range = range })
 
-                    hover
-                }
+            return LspResult.success hover
+        }
 
-            LspResult.success hover
 
+    override this.TextDocumentCodeLens(par: CodeLensParams) =
+        async {
+            let! doc =
+                withState
+                <| fun state ->
+                    State.tryFindFolderAndDoc (PathUri.ofString par.TextDocument.Uri) state
+                    |> Option.filter (fun (folder, doc) ->
+                        (Folder.configFor doc folder).GitEnable())
+                    |> Option.map snd
 
+            // Git is run off the state agent, which would otherwise wait for it
+            return LspResult.success (doc |> Option.map Git.headingLenses)
+        }
 
     override this.TextDocumentReferences(par: ReferenceParams) =
-        withState
-        <| fun state ->
+        async {
             let docUri = par.TextDocument.Uri |> PathUri.ofString
 
-            let locs =
-                monad' {
-                    let! folder = State.tryFindFolderEnclosing docUri state
-                    let! curDoc = Folder.tryFindDocByPath docUri folder
-                    let! atPos = Cst.elementAtPos par.Position (Doc.cst curDoc)
-
-                    let referencingEls =
-                        Dest.findElementRefs par.Context.IncludeDeclaration folder curDoc atPos
-
-                    let toLoc (doc, el, _) = { Uri = Doc.uri doc; Range = Element.range el }
-
-                    referencingEls |> Seq.map toLoc |> Array.ofSeq
-                }
-
-            let locs = Option.map Array.ofSeq locs
-
-            LspResult.success locs
+            // Lazy, so that partial results go out while the references are still being found
+            let! locs =
+                withState
+                <| fun state ->
+                    monad' {
+                        let! folder = State.tryFindFolderEnclosing docUri state
+                        let! curDoc = Folder.tryFindDocByPath docUri folder
+                        let! atPos = Cst.elementAtPos par.Position (Doc.cst curDoc)
+
+                        let toLoc doc el = { Uri = Doc.uri doc; Range = Element.range el }
+
+                        match atPos with
+                        | T tag ->
+                            Dest.findTagRefs folder tag
+                            |> Seq.map (fun (doc, el) -> toLoc doc el)
+                        | _ ->
+                            Dest.findElementRefs par.Context.IncludeDeclaration folder curDoc atPos
+                            |> Seq.map (fun (doc, el, _) -> toLoc doc el)
+                    }
+
+            match locs, par.PartialResultToken with
+            | Some locs, Some token ->
+                do! sendPartialResults token (Seq.chunkBySize partialResultChunk locs)
+                return LspResult.success (Some [||])
+            | locs, _ -> return LspResult.success (Option.map Array.ofSeq locs)
+        }
 
     override this.TextDocumentSemanticTokensFull(par: SemanticTokensParams) =
-        withState
-        <| fun state ->
+        async {
             let docPath = par.TextDocument.Uri |> PathUri.ofString
 
-            let tokens =
-                monad' {
-                    let! folder = State.tryFindFolderEnclosing docPath state
-                    let! doc = Folder.tryFindDocByPath docPath folder
-                    let data = Semato.Token.ofIndexEncoded (Doc.index doc)
-                    { ResultId = None; Data = data }
-                }
-
-            LspResult.success tokens
+            let! data =
+                withState
+                <| fun state ->
+                    monad' {
+                        let! folder = State.tryFindFolderEnclosing docPath state
+                        let! doc = Folder.tryFindDocByPath docPath folder
+                        Semato.Token.ofIndexEncoded (Doc.index doc)
+                    }
+
+            match data, par.PartialResultToken with
+            | Some data, Some token ->
+                // Tokens are encoded relative to the previous one, so chunks are cut at token
+                // boundaries and appending them gives the full data back
+                let chunks =
+                    Array.chunkBySize (partialResultChunk * 5) data
+                    |> Seq.map (fun chunk -> ({ Data = chunk }: SemanticTokensPartialResult))
+
+                do! sendPartialResults token chunks
+                return LspResult.success (Some { ResultId = None; Data = [||] })
+            | data, _ ->
+                return
+                    data
+                    |> Option.map (fun data -> { ResultId = None; Data = data })
+                    |> LspResult.success
+        }
 
     override this.TextDocumentSemanticTokensRange(par: SemanticTokensRangeParams) =
         withState
@@ -860,13 +1751,13 @@ type MarksmanServer(client: MarksmanClient) =
             LspResult.success tokens
 
     override this.TextDocumentCodeAction(opts: CodeActionParams) =
-        withStateExclusive
+        withState
         <| fun state ->
             let docPath = opts.TextDocument.Uri |> PathUri.ofString
 
-            let codeAction title edit =
+            let codeAction kind title edit =
                 { Title = title
-                  Kind = Some CodeActionKind.Source
+                  Kind = Some kind
                   Diagnostics = None
                   Command = None
                   Data = None
@@ -875,30 +1766,92 @@ type MarksmanServer(client: MarksmanClient) =
                   Edit = Some edit }
 
             match State.tryFindFolderAndDoc docPath state with
-            | None -> Mutation.output (LspResult.success None)
+            | None -> LspResult.success None
             | Some (folder, doc) ->
-                let config = Folder.configOrDefault folder
+                let config = Folder.configFor doc folder
 
                 let tocAction =
                     if config.CaTocEnable() then
-                        CodeActions.tableOfContents opts.Range opts.Context doc
+                        CodeActions.tableOfContents
+                            (config.CaTocMaxLevel())
+                            opts.Range
+                            opts.Context
+                            doc
                         |> Option.toArray
                         |> Array.map (fun ca ->
                             let wsEdit =
                                 (CodeActions.documentEdit ca.edit ca.newText opts.TextDocument.Uri)
 
-                            codeAction ca.name wsEdit)
+                            codeAction CodeActionKind.Source ca.name wsEdit)
+                    else
+                        [||]
+
+                let tableActions =
+                    if config.CaTableEnable() then
+                        CodeActions.tableActions opts.Range opts.Context doc
+                        |> Array.map (fun ca ->
+                            let wsEdit =
+                                (CodeActions.documentEdit ca.edit ca.newText opts.TextDocument.Uri)
+
+                            codeAction CodeActionKind.RefactorRewrite ca.name wsEdit)
+                    else
+                        [||]
+
+                let sectionActions =
+                    if config.CaSectionEnable() then
+                        let supportsDocumentEdit = (State.client state).SupportsDocumentEdit
+
+                        Array.append
+                            (Refactor.sectionLevelActions supportsDocumentEdit doc opts.Range)
+                            (Refactor.sectionOrderActions supportsDocumentEdit doc opts.Range)
+                        |> Array.map (fun (name, wsEdit) ->
+                            codeAction CodeActionKind.RefactorRewrite name wsEdit)
                     else
                         [||]
 
+                let fixAction fix =
+                    match fix with
+                    | CodeActions.EditDoc ca ->
+                        let wsEdit =
+                            CodeActions.documentEdit ca.edit ca.newText opts.TextDocument.Uri
+
+                        codeAction CodeActionKind.QuickFix ca.name wsEdit
+                    | CodeActions.CreateNote (name, path, content) ->
+                        let command =
+                            { Title = name
+                              Command = CodeActions.createNoteCommand
+                              Arguments = Some [| JValue(path) :> JToken; JValue(content) |] }
+
+                        { Title = name
+                          Kind = Some CodeActionKind.QuickFix
+                          Diagnostics = None
+                          Command = Some command
+                          Data = None
+                          IsPreferred = None
+                          Disabled = None
+                          Edit = None }
+
+                let fixes = CodeActions.quickFixes folder opts.Context doc
+
+                let quickFixes =
+                    fixes
+                    |> Array.mapi (fun idx (fix, diag) ->
+                        // Fixes are listed nearest first for each diagnostic
+                        let isFirst = idx = 0 || snd fixes[idx - 1] <> diag
+
+                        { fixAction fix with
+                            Diagnostics = Some [| diag |]
+                            IsPreferred = Some isFirst })
+
                 let codeActions: TextDocumentCodeActionResult =
-                    tocAction |> Array.map U2.Second
+                    Array.concat [ quickFixes; tocAction; tableActions; sectionActions ]
+                    |> Array.map U2.Second
 
-                Mutation.output (LspResult.success (Some codeActions))
+                LspResult.success (Some codeActions)
 
 
     override this.TextDocumentRename(pars) =
-        withStateExclusive
+        withState
         <| fun state ->
             let docPath = pars.TextDocument.Uri |> PathUri.ofString
 
@@ -919,11 +1872,11 @@ type MarksmanServer(client: MarksmanClient) =
                 }
 
             match edit with
-            | None -> Mutation.output (Ok None)
-            | Some result -> Mutation.output result
+            | None -> Ok None
+            | Some result -> result
 
     override this.TextDocumentPrepareRename(pars) =
-        withStateExclusive
+        withState
         <| fun state ->
             let docPath = pars.TextDocument.Uri |> PathUri.ofString
 
@@ -934,11 +1887,60 @@ type MarksmanServer(client: MarksmanClient) =
                     return! Refactor.renameRange srcDoc pars.Position
                 }
 
-            Mutation.output (renameRange |> Option.map PrepareRenameResult.Range |> Ok)
+            renameRange |> Option.map PrepareRenameResult.Range |> Ok
+
+    member this.MarksmanGraph(par: GraphParams) : AsyncLspResult<JToken> =
+        withState
+        <| fun state ->
+            // Params are optional for this request and come as null when omitted
+            let uri, format =
+                if isNull (box par) then
+                    None, "json"
+                else
+                    par.uri, par.format |> Option.defaultValue "json"
+
+            let folder =
+                match uri, List.ofSeq (Workspace.folders (State.workspace state)) with
+                | Some uri, _ -> State.tryFindFolderEnclosing (PathUri.ofString uri) state
+                | None, [ folder ] -> Some folder
+                | None, _ -> None
+
+            match folder, NoteGraph.Format.ofString format with
+            | None, _ -> LspResult.invalidParams "Expected the URI of a folder of the workspace"
+            | _, None -> LspResult.invalidParams $"Unknown format: {format}"
+            | Some folder, Some NoteGraph.Format.Json ->
+                NoteGraph.ofFolder folder |> serialize |> LspResult.success
+            | Some folder, Some format ->
+                let text = NoteGraph.ofFolder folder |> NoteGraph.format format
+                LspResult.success (JValue(text) :> JToken)
+
+    member this.MarksmanTasks(par: Tasks.TasksParams) : AsyncLspResult<array<Tasks.TaskInfo>> =
+        withState
+        <| fun state ->
+            // Params are optional for this request and come as null when omitted
+            let includeCompleted =
+                if isNull (box par) then
+                    false
+                else
+                    par.includeCompleted |> Option.defaultValue false
+
+            let tasks = Tasks.workspaceTasks includeCompleted (State.workspace state)
+            LspResult.success tasks
+
+    member this.MarksmanStats(par: Stats.StatsParams) : AsyncLspResult<option<Stats.NoteStats>> =
+        withState
+        <| fun state ->
+            State.tryFindFolderAndDoc (PathUri.ofString par.uri) state
+            |> Option.map (fun (folder, doc) -> Stats.ofDoc folder doc)
+            |> LspResult.success
 
     override this.Dispose() =
+        stopBackgroundWork ()
+        (changeBuffer :> IDisposable).Dispose()
         (statusManager :> IDisposable).Dispose()
         (diagnosticsManager :> IDisposable).Dispose()
+        urlCheckUpdates |> Option.iter (fun updates -> updates.Dispose())
+        (urlChecker :> IDisposable).Dispose()
 
         match stateManager with
         | Some stateManager -> (stateManager :> IDisposable).Dispose()
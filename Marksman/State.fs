@@ -30,6 +30,30 @@ type ClientDescription =
 
         docChange = Some true
 
+    /// Whether workspace edits may have change annotations, which takes document changes as well.
+    member this.SupportsChangeAnnotations: bool =
+        let annotations =
+            monad' {
+                let! ws = this.caps.Workspace
+                let! edit = ws.WorkspaceEdit
+                return! edit.ChangeAnnotationSupport
+            }
+
+        this.SupportsDocumentEdit && Option.isSome annotations
+
+    /// Whether workspace edits may delete files, which takes document changes as well.
+    member this.SupportsDeleteFile: bool =
+        let resourceOperations =
+            monad' {
+                let! ws = this.caps.Workspace
+                let! edit = ws.WorkspaceEdit
+                return! edit.ResourceOperations
+            }
+
+        this.SupportsDocumentEdit
+        && resourceOperations
+           |> Option.exists (Array.contains ResourceOperationKind.Delete)
+
     member this.SupportsStatus: bool =
         match this.caps.Experimental with
         | None -> false
@@ -43,6 +67,77 @@ type ClientDescription =
         }
         |> Option.defaultValue false
 
+    member this.SupportsSnippets: bool =
+        monad' {
+            let! textDoc = this.caps.TextDocument
+            let! completion = textDoc.Completion
+            let! item = completion.CompletionItem
+            return! item.SnippetSupport
+        }
+        |> Option.defaultValue false
+
+    member this.SupportsDynamicCompletion: bool =
+        monad' {
+            let! textDoc = this.caps.TextDocument
+            let! completion = textDoc.Completion
+            return! completion.DynamicRegistration
+        }
+        |> Option.defaultValue false
+
+    member this.SupportsDynamicWatchedFiles: bool =
+        monad' {
+            let! ws = this.caps.Workspace
+            let! watchedFiles = ws.DidChangeWatchedFiles
+            return! watchedFiles.DynamicRegistration
+        }
+        |> Option.defaultValue false
+
+    member this.SupportsConfiguration: bool =
+        monad' {
+            let! ws = this.caps.Workspace
+            return! ws.Configuration
+        }
+        |> Option.defaultValue false
+
+    member this.SupportsWorkDoneProgress: bool =
+        monad' {
+            let! window = this.caps.Window
+            return! window.WorkDoneProgress
+        }
+        |> Option.defaultValue false
+
+    member this.PositionEncodings: array<string> =
+        monad' {
+            let! general = this.caps.General
+            return! general.PositionEncodings
+        }
+        |> Option.defaultValue [||]
+
+    member this.SupportsApplyEdit: bool =
+        monad' {
+            let! ws = this.caps.Workspace
+            return! ws.ApplyEdit
+        }
+        |> Option.defaultValue false
+
+    /// Clients that don't tell which hover formats they render are assumed to render markdown.
+    member this.SupportsMarkdownHover: bool =
+        monad' {
+            let! textDoc = this.caps.TextDocument
+            let! hover = textDoc.Hover
+            return! hover.ContentFormat
+        }
+        |> Option.map (Array.contains MarkupKind.Markdown)
+        |> Option.defaultValue true
+
+    member this.SupportsRelatedInformation: bool =
+        monad' {
+            let! textDoc = this.caps.TextDocument
+            let! diag = textDoc.PublishDiagnostics
+            return! diag.RelatedInformation
+        }
+        |> Option.defaultValue false
+
     member this.SupportsPrepareRename: bool =
         monad' {
             let! textDoc = this.caps.TextDocument
@@ -55,7 +150,12 @@ module ClientDescription =
     let ofParams (par: InitializeParams) : ClientDescription =
         let caps =
             par.Capabilities
-            |> Option.defaultValue { Workspace = None; TextDocument = None; Experimental = None }
+            |> Option.defaultValue
+                { Workspace = None
+                  TextDocument = None
+                  Window = None
+                  General = None
+                  Experimental = None }
 
         { info = par.ClientInfo; caps = caps }
 
@@ -64,6 +164,8 @@ type State =
     private
         { client: ClientDescription
           workspace: Workspace
+          /// Roots of the workspace folders still to be read in the background, by their names
+          toIndex: Map<string, RootPath>
           revision: int }
 
     member this.Diag() : WorkspaceDiag = WorkspaceDiag.mk this.workspace
@@ -72,7 +174,7 @@ module State =
     let private logger = LogProvider.getLoggerByName "State"
 
     let mk (client: ClientDescription) (ws: Workspace) =
-        { client = client; workspace = ws; revision = 0 }
+        { client = client; workspace = ws; toIndex = Map.empty; revision = 0 }
 
     let client s = s.client
 
@@ -85,6 +187,15 @@ module State =
 
     let revision s = s.revision
 
+    /// Roots of the workspace folders still to be read in the background, by their names.
+    let foldersToIndex (state: State) : Map<string, RootPath> = state.toIndex
+
+    let withFoldersToIndex (roots: Map<string, RootPath>) (state: State) : State =
+        { state with toIndex = roots }
+
+    let isToIndex (root: RootPath) (state: State) : bool =
+        state.toIndex |> Map.exists (fun _ wanted -> wanted = root)
+
     let diag (s: State) = s.Diag()
 
     let tryFindFolderEnclosing (uri: PathUri) (state: State) : option<Folder> =
@@ -105,46 +216,130 @@ module State =
         | None -> None
         | Some (_, doc) -> Some doc
 
-    let updateFoldersFromLsp
-        (added: WorkspaceFolder[])
-        (removed: WorkspaceFolder[])
-        (state: State)
-        : State =
+    let updateFolder (newFolder: Folder) (state: State) : State =
+        let newWs = Workspace.withFolder newFolder state.workspace
+        { state with workspace = newWs; revision = state.revision + 1 }
+
+    /// Adds a folder indexed in the background. Notes opened while it was indexed are single-file
+    /// folders by now; they move into the folder, keeping their unsaved changes.
+    let withIndexedFolder (folder: Folder) (state: State) : State =
+        let root = Folder.rootPath folder
+        let exts = (Folder.configOrDefault folder).CoreMarkdownFileExtensions()
+
+        let isOpenedInside (doc: Doc) =
+            let path = Doc.path doc
+
+            not path.IsUntitled
+            && RootPath.contains path root
+            && isMarkdownFile exts path.LocalPath
+
+        let opened =
+            Workspace.folders state.workspace
+            |> Seq.filter (fun other ->
+                Folder.isSingleFile other && Folder.docs other |> Seq.forall isOpenedInside)
+            |> List.ofSeq
+
+        let folder =
+            opened
+            |> Seq.collect Folder.docs
+            |> Seq.fold
+                (fun folder doc ->
+                    let doc = Doc.mk (Doc.path doc) root (Doc.version doc) (Doc.text doc)
+                    Folder.withDoc doc folder)
+                folder
+
+        let workspace =
+            Workspace.withoutFolders (opened |> List.map Folder.id) state.workspace
+            |> Workspace.withFolder folder
+
+        { state with
+            workspace = workspace
+            toIndex = state.toIndex |> Map.filter (fun _ wanted -> wanted <> root)
+            revision = state.revision + 1 }
+
+    /// Like `withIndexedFolder` for a folder of the workspace read in the background. `None` when
+    /// the folder was removed from the workspace while it was read.
+    let tryWithIndexedFolder (folder: Folder) (state: State) : option<State> =
+        if isToIndex (Folder.rootPath folder) state then
+            Some(withIndexedFolder folder state)
+        else
+            None
+
+    /// Adds and removes folders of the workspace. Added folders are read beforehand, so that
+    /// other requests don't wait for them.
+    let updateFolders (added: list<Folder>) (removed: list<FolderId>) (state: State) : State =
         logger.trace (
             Log.setMessage "Updating workspace folders"
             >> Log.addContext "numAdded" added.Length
             >> Log.addContext "numRemoved" removed.Length
         )
 
-        let removedUris =
-            removed
-            |> Array.map (fun f -> PathUri.ofString f.Uri |> FolderId.ofPath)
-
-        let userConfig = workspace state |> Workspace.userConfig
-
-        let addedFolders =
-            seq {
-                for f in added do
-                    let rootUri = RootPath.ofString f.Uri
-
-                    let folder = Folder.tryLoad userConfig f.Name rootUri
-
-                    match folder with
-                    | Some folder -> yield folder
-                    | _ -> ()
-            }
-
-        let newWorkspace =
-            Workspace.withoutFolders removedUris state.workspace
-            |> Workspace.withFolders addedFolders
-
-        { client = state.client
-          workspace = newWorkspace
-          revision = state.revision + 1 }
-
-    let updateFolder (newFolder: Folder) (state: State) : State =
-        let newWs = Workspace.withFolder newFolder state.workspace
-        { state with workspace = newWs; revision = state.revision + 1 }
+        // Removed folders that haven't been read yet aren't read anymore
+        let isRemoved (root: RootPath) =
+            List.contains (FolderId.ofPath (RootPath.path root)) removed
+
+        let state =
+            { state with
+                workspace = Workspace.withoutFolders removed state.workspace
+                toIndex = state.toIndex |> Map.filter (fun _ root -> not (isRemoved root))
+                revision = state.revision + 1 }
+
+        List.fold (fun state folder -> withIndexedFolder folder state) state added
+
+    /// Replaces folders by the ones read again from disk off an earlier snapshot of the state.
+    /// Open docs are taken from the current state since they may have changed after the
+    /// snapshot, and folders removed after it stay removed.
+    let withReloadedFolders (reloaded: seq<Folder>) (state: State) : State =
+        let current =
+            Workspace.folders state.workspace
+            |> Seq.map (fun folder -> Folder.id folder, folder)
+            |> Map.ofSeq
+
+        reloaded
+        |> Seq.fold
+            (fun state folder ->
+                match Map.tryFind (Folder.id folder) current with
+                | None -> state
+                | Some currentFolder ->
+                    let folder =
+                        Folder.docs currentFolder
+                        |> Seq.filter (fun doc -> Option.isSome (Doc.version doc))
+                        |> Seq.fold (fun folder doc -> Folder.withDoc doc folder) folder
+
+                    updateFolder folder state)
+            state
+
+    /// The state with a new user-level config. Folders are read again since the config decides
+    /// which of the files are notes; `reloaded` are the folders read with the new config.
+    let withUserConfig (userConfig: option<Config>) (reloaded: seq<Folder>) (state: State) : State =
+        let state = withReloadedFolders reloaded state
+
+        { state with
+            workspace = Workspace.ofFolders userConfig (Workspace.folders state.workspace)
+            revision = state.revision + 1 }
+
+    /// Adds the unsaved buffer at the root of the first workspace folder, so that its links
+    /// resolve from there, or in single-file mode when there are no folders. It gets an identity
+    /// on disk once the client saves it, which comes as closing it and opening the saved file.
+    let openUntitled (item: TextDocumentItem) (state: State) : State =
+        let text = Text.mkText item.Text
+
+        let multiFileFolder =
+            Workspace.folders state.workspace
+            |> Seq.tryFind (fun folder -> not (Folder.isSingleFile folder))
+
+        match multiFileFolder with
+        | Some folder ->
+            let root = Folder.rootPath folder
+            let path = PathUri.untitledIn (RootPath.path root).LocalPath item.Uri
+            let doc = Doc.mk path root (Some item.Version) text
+            updateFolder (Folder.withDoc doc folder) state
+        | None ->
+            let dir = System.Environment.CurrentDirectory
+            let path = PathUri.untitledIn dir item.Uri
+            let doc = Doc.mk path (RootPath.ofString dir) (Some item.Version) text
+            let userConfig = Workspace.userConfig state.workspace
+            updateFolder (Folder.singleFile doc userConfig) state
 
     let removeFolder (keyPath: FolderId) (state: State) : State =
         let newWs = Workspace.withoutFolder keyPath state.workspace
@@ -0,0 +1,43 @@
+/// Statistics of a note for editors to show, e.g. in the status bar, via the `marksman/stats`
+/// request, and for the notes of a folder via `marksman index --stats`.
+module Marksman.Stats
+
+open System
+
+open Marksman.Index
+open Marksman.Workspace
+
+type NoteStats =
+    { words: int
+      headings: int
+      /// Links in other notes pointing to this one or into it
+      inboundLinks: int
+      outboundLinks: int
+      readingMinutes: int }
+
+type StatsParams = { uri: string }
+
+let wordsPerMinute = 200
+
+/// Words of the note, without its front matter. Markup on its own, e.g. list bullets, isn't one.
+let wordCount (doc: Doc) : int =
+    let text = Doc.text doc
+
+    let content =
+        match (Doc.index doc).yamlFrontMatter with
+        | Some yaml -> snd (text.Cutout(yaml.range))
+        | None -> text.content
+
+    content.Split((null: array<char>), StringSplitOptions.RemoveEmptyEntries)
+    |> Seq.filter (Seq.exists Char.IsLetterOrDigit)
+    |> Seq.length
+
+let ofDoc (folder: Folder) (doc: Doc) : NoteStats =
+    let index = Doc.index doc
+    let words = wordCount doc
+
+    { words = words
+      headings = index.headings.Length
+      inboundLinks = Compl.Preview.backlinkCount folder doc
+      outboundLinks = Seq.length (Index.links index)
+      readingMinutes = (words + wordsPerMinute - 1) / wordsPerMinute }
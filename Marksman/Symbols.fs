@@ -22,16 +22,37 @@ let headingToSymbolInfo (docUri: PathUri) (h: Node<Heading>) : SymbolInformation
 
     sym
 
-let rec headingToDocumentSymbol (isEmacs: bool) (h: Node<Heading>) : DocumentSymbol =
+let calloutToSymbolInfo (docUri: PathUri) (c: Node<Callout>) : SymbolInformation =
+    let name = $"{Callout.kind c.data}: {Callout.name c.data}"
+    let location = { Uri = docUri.DocumentUri; Range = c.range }
+
+    { Name = name
+      Kind = SymbolKind.Event
+      Location = location
+      ContainerName = None }
+
+let calloutToDocumentSymbol (c: Node<Callout>) : DocumentSymbol =
+    { Name = Callout.name c.data
+      Detail = Some(Callout.kind c.data)
+      Kind = SymbolKind.Event
+      Range = c.data.scope
+      SelectionRange = c.range
+      Children = None }
+
+let rec private elementToDocumentSymbol (isEmacs: bool) (el: Element) : option<DocumentSymbol> =
+    match el with
+    | H h -> Some(headingToDocumentSymbol isEmacs h)
+    | CO c -> Some(calloutToDocumentSymbol c)
+    | _ -> None
+
+and headingToDocumentSymbol (isEmacs: bool) (h: Node<Heading>) : DocumentSymbol =
     let name = Heading.name h.data
     let kind = SymbolKind.String
     let range = h.data.scope
     let selectionRange = h.range
 
     let children =
-        h.data.children
-        |> Element.pickHeadings
-        |> Array.map (headingToDocumentSymbol isEmacs)
+        h.data.children |> Array.choose (elementToDocumentSymbol isEmacs)
 
     let children =
         if Array.isEmpty children then
@@ -64,34 +85,51 @@ let docSymbols
     (doc: Doc)
     : U2<array<SymbolInformation>, array<DocumentSymbol>> =
     if hierarchy then
-        let topLevelHeadings =
-            Doc.cst doc |> Seq.collect (Element.asHeading >> Option.toList)
-
-        topLevelHeadings
-        |> Seq.map (headingToDocumentSymbol isEmacs)
+        Doc.cst doc
+        |> Seq.choose (elementToDocumentSymbol isEmacs)
         |> Array.ofSeq
         |> Second
     else
-        let allHeadings = Doc.index >> Index.headings <| doc
-
-        allHeadings
-        |> Seq.map (headingToSymbolInfo (Doc.path doc))
+        let headings =
+            Doc.index doc
+            |> Index.headings
+            |> Seq.map (fun h -> h.range.Start, headingToSymbolInfo (Doc.path doc) h)
+
+        let callouts =
+            Doc.index doc
+            |> Index.callouts
+            |> Seq.map (fun c -> c.range.Start, calloutToSymbolInfo (Doc.path doc) c)
+
+        Seq.append headings callouts
+        |> Seq.sortBy fst
+        |> Seq.map snd
         |> Array.ofSeq
         |> First
 
-let workspaceSymbols (query: string) (ws: Workspace) : array<SymbolInformation> =
+let private scoredSymbols (query: string) (ws: Workspace) : seq<int * SymbolInformation> =
+    let query = query.Normalize()
+
     seq {
         for folder in Workspace.folders ws do
-            for doc in Folder.docs folder do
-                let headings = Doc.index doc |> Index.headings
-
-                let matchingHeadings =
-                    headings
-                    |> Seq.filter (fun { data = h } -> query.IsSubSequenceOf(Heading.name h))
+            for doc, ({ data = h } as heading) in Folder.headingsMatching query folder do
+                match Fuzzy.score query (Heading.name h) with
+                | Some score -> yield score, headingToSymbolInfo (Doc.path doc) heading
+                | None -> ()
+    }
 
-                let matchingSymbols =
-                    matchingHeadings |> Seq.map (headingToSymbolInfo (Doc.path doc))
+// Best matches first; the sort is stable so equal scores keep the document order
+let private bestFirst (symbols: seq<int * SymbolInformation>) : array<SymbolInformation> =
+    symbols |> Seq.sortByDescending fst |> Seq.map snd |> Array.ofSeq
 
-                yield! matchingSymbols
-    }
-    |> Array.ofSeq
+let workspaceSymbols (query: string) (ws: Workspace) : array<SymbolInformation> =
+    scoredSymbols query ws |> bestFirst
+
+/// Symbols matching `query` in chunks of at most `size`, for partial results. Each chunk is
+/// matched only when it's asked for, so the chunks follow the document order and only the symbols
+/// of a chunk are sorted best first.
+let workspaceSymbolChunks
+    (size: int)
+    (query: string)
+    (ws: Workspace)
+    : seq<array<SymbolInformation>> =
+    scoredSymbols query ws |> Seq.chunkBySize size |> Seq.map bestFirst
@@ -0,0 +1,85 @@
+module Marksman.Tables
+
+open System
+
+open Marksman.Cst
+
+/// Plain cell contents of a table, normalized so that every row has the same number of cells.
+type Grid =
+    { header: array<string>
+      alignments: array<ColumnAlign>
+      rows: array<array<string>> }
+
+module Grid =
+    let private padTo (count: int) (filler: 'a) (cells: array<'a>) =
+        if cells.Length >= count then
+            cells
+        else
+            Array.append cells (Array.create (count - cells.Length) filler)
+
+    let ofTable (table: Table) : Grid =
+        let columns = Table.columnCount table
+        let texts = Array.map Node.text >> padTo columns ""
+
+        { header = texts table.header
+          alignments = table.alignments |> padTo columns ColumnAlign.Default
+          rows = table.rows |> Array.map texts }
+
+    let columnCount (grid: Grid) = grid.header.Length
+
+    /// Inserts an empty row so that it becomes the `at`-th row of the table's body.
+    let insertRow (at: int) (grid: Grid) : Grid =
+        let at = Math.Clamp(at, 0, grid.rows.Length)
+        let empty = Array.create (columnCount grid) ""
+        { grid with rows = Array.insertAt at empty grid.rows }
+
+    /// Inserts an empty column so that it becomes the `at`-th column.
+    let insertColumn (at: int) (grid: Grid) : Grid =
+        let at = Math.Clamp(at, 0, columnCount grid)
+
+        { header = Array.insertAt at "" grid.header
+          alignments = Array.insertAt at ColumnAlign.Default grid.alignments
+          rows = grid.rows |> Array.map (Array.insertAt at "") }
+
+    let private renderLines (grid: Grid) : array<string> =
+        let widths =
+            Array.init (columnCount grid) (fun col ->
+                Seq.append [ grid.header ] grid.rows
+                |> Seq.map (fun row -> row[col].Length)
+                |> Seq.fold max 3)
+
+        let pad (align: ColumnAlign) (width: int) (cell: string) =
+            match align with
+            | ColumnAlign.Right -> cell.PadLeft(width)
+            | ColumnAlign.Center ->
+                let left = (width - cell.Length) / 2
+                cell.PadLeft(cell.Length + left).PadRight(width)
+            | ColumnAlign.Default
+            | ColumnAlign.Left -> cell.PadRight(width)
+
+        let delimiter (align: ColumnAlign) (width: int) =
+            match align with
+            | ColumnAlign.Default -> String('-', width)
+            | ColumnAlign.Left -> ":" + String('-', width - 1)
+            | ColumnAlign.Right -> String('-', width - 1) + ":"
+            | ColumnAlign.Center -> ":" + String('-', width - 2) + ":"
+
+        let renderRow (cells: array<string>) = "| " + String.Join(" | ", cells) + " |"
+
+        let renderCells (row: array<string>) =
+            row
+            |> Array.mapi (fun col cell -> pad grid.alignments[col] widths[col] cell)
+            |> renderRow
+
+        let delimiterRow = Array.map2 delimiter grid.alignments widths |> renderRow
+
+        Array.append [| renderCells grid.header; delimiterRow |] (Array.map renderCells grid.rows)
+
+    /// Renders the table with aligned pipes and cells padded according to column alignment.
+    let render (grid: Grid) : string =
+        String.Join(Environment.NewLine, renderLines grid)
+
+    /// Like `render`, with the lines after the first starting with `linePrefix`, e.g. `> ` for a
+    /// table in a block quote.
+    let renderNested (linePrefix: string) (grid: Grid) : string =
+        String.Join(Environment.NewLine + linePrefix, renderLines grid)
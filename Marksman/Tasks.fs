@@ -0,0 +1,30 @@
+module Marksman.Tasks
+
+open Ionide.LanguageServerProtocol.Types
+
+open Marksman.Cst
+open Marksman.Index
+open Marksman.Workspace
+
+/// A task list item as reported by the `marksman/tasks` request.
+type TaskInfo = { location: Location; text: string; completed: bool }
+
+type TasksParams = { includeCompleted: option<bool> }
+
+let docTasks (doc: Doc) : seq<TaskInfo> =
+    Doc.index doc
+    |> Index.tasks
+    |> Seq.map (fun { data = task; range = range } ->
+        { location = { Uri = Doc.uri doc; Range = range }
+          text = Node.text task.text
+          completed = task.completed })
+
+/// Tasks across all folders of the workspace; only open tasks unless `includeCompleted` is set.
+let workspaceTasks (includeCompleted: bool) (ws: Workspace) : array<TaskInfo> =
+    seq {
+        for folder in Workspace.folders ws do
+            for doc in Folder.docs folder do
+                yield! docTasks doc
+    }
+    |> Seq.filter (fun task -> includeCompleted || not task.completed)
+    |> Array.ofSeq
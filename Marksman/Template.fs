@@ -0,0 +1,17 @@
+/// Templates of new notes, e.g. `templates/default.md`: markdown with variables like `{{title}}`
+/// that are replaced when a note is created from it.
+module Marksman.Template
+
+open System.Text.RegularExpressions
+
+let private variable = Regex(@"\{\{\s*([A-Za-z_][\w-]*)\s*\}\}", RegexOptions.Compiled)
+
+/// Replaces the variables of `template` with their values in `vars`. Unknown variables are kept
+/// as they are, so that templates can contain `{{...}}` meant for something else.
+let render (vars: Map<string, string>) (template: string) : string =
+    let replace (m: Match) =
+        match Map.tryFind m.Groups[1].Value vars with
+        | Some value -> value
+        | None -> m.Value
+
+    variable.Replace(template, MatchEvaluator(replace))
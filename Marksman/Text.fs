@@ -10,34 +10,142 @@ open Misc
 type LineRange = int * int
 type CharRange = int * int
 
+/// How the `Character` of a position counts the chars of its line. The server counts them in the
+/// encoding it agreed on with the client, UTF-16 unless the client preferred another.
+type PositionEncoding =
+    | Utf8
+    | Utf16
+    | Utf32
+
+module PositionEncoding =
+    let ofString (name: string) : option<PositionEncoding> =
+        match name with
+        | "utf-8" -> Some Utf8
+        | "utf-16" -> Some Utf16
+        | "utf-32" -> Some Utf32
+        | _ -> None
+
+    let toString (encoding: PositionEncoding) : string =
+        match encoding with
+        | Utf8 -> "utf-8"
+        | Utf16 -> "utf-16"
+        | Utf32 -> "utf-32"
+
+    /// The first of the encodings offered by the client, in the order of its preference, that the
+    /// server knows. UTF-16 when there's none, as every client supports it.
+    let negotiate (offered: array<string>) : PositionEncoding =
+        offered |> Array.tryPick ofString |> Option.defaultValue Utf16
+
+    let mutable private inUse = Utf16
+
+    /// The encoding of the positions the server receives and sends.
+    let current () : PositionEncoding = inUse
+
+    let set (encoding: PositionEncoding) : unit = inUse <- encoding
+
+    // The chars and the code units taken by the char, or the surrogate pair, at `offset`
+    let private step encoding (charAt: int -> char) (offset: int) (end_: int) : int * int =
+        let c = charAt offset
+
+        if
+            Char.IsHighSurrogate(c)
+            && offset + 1 < end_
+            && Char.IsLowSurrogate(charAt (offset + 1))
+        then
+            match encoding with
+            | Utf8 -> 2, 4
+            | Utf16 -> 2, 2
+            | Utf32 -> 2, 1
+        else
+            match encoding with
+            | Utf8 when c < '\u0080' -> 1, 1
+            | Utf8 when c < '\u0800' -> 1, 2
+            | Utf8 -> 1, 3
+            | Utf16
+            | Utf32 -> 1, 1
+
+    /// The number of code units the chars `start..end_` take in `encoding`.
+    let units encoding (charAt: int -> char) (start: int) (end_: int) : int =
+        match encoding with
+        | Utf16 -> end_ - start
+        | _ ->
+            let mutable offset = start
+            let mutable units = 0
+
+            while offset < end_ do
+                let chars, charUnits = step encoding charAt offset end_
+                offset <- offset + chars
+                units <- units + charUnits
+
+            units
+
+    /// The offset of the char `units` code units in `encoding` after `start`, unless it's past
+    /// `end_`. Code units in the middle of a char are taken for the end of it.
+    let tryOffset encoding (charAt: int -> char) (start: int) (end_: int) (units: int) =
+        match encoding with
+        | Utf16 -> if start + units > end_ then None else Some(start + units)
+        | _ ->
+            let mutable offset = start
+            let mutable left = units
+
+            while left > 0 && offset < end_ do
+                let chars, charUnits = step encoding charAt offset end_
+                offset <- offset + chars
+                left <- left - charUnits
+
+            if left > 0 then None else Some offset
+
+    /// The number of code units `str` takes in the current encoding.
+    let length (str: string) : int = units inUse (fun i -> str[i]) 0 str.Length
+
+// The line of the char at `offset`. The empty line at the end holds the offset past the text
+let private tryFindLine (lines: array<LineRange>) (offset: int) : option<int> =
+    let rec go l h =
+        if l > h then
+            None
+        else
+            let m = l + (h - l) / 2
+            let start, end_ = lines[m]
+
+            if start > offset then go l (m - 1)
+            else if start <= offset && offset < end_ then Some m
+            else if start = end_ && start = offset then Some m
+            else if offset >= end_ then go (m + 1) h
+            else None
+
+    go 0 (lines.Length - 1)
+
+// The offset of `pos` given the lines of a text and its chars
+let private tryFindOffset (lines: array<LineRange>) (charAt: int -> char) (pos: Position) =
+    if pos.Line >= lines.Length then
+        None
+    else
+        let start, end_ = lines[pos.Line]
+        PositionEncoding.tryOffset (PositionEncoding.current ()) charAt start end_ pos.Character
+
+/// Lines of a text, by offsets into it. Positions are counted in `PositionEncoding.current ()`.
 type LineMap =
-    | LineMap of array<LineRange>
+    | LineMap of array<LineRange> * content: string
 
     member this.Map =
-        let (LineMap arr) = this
+        let (LineMap(arr, _)) = this
         arr
 
+    member private this.CharAt(offset: int) : char =
+        let (LineMap(_, content)) = this
+        content[offset]
+
     member this.NumLines = this.Map.Length - 1
 
     member this.TryFindPosition(offset: int) : Option<Position> =
-        let rec go l h =
-            if l > h then
-                None
-            else
-                let m = l + (h - l) / 2
-                let start, end_ = this.Map[m]
-
-                if start > offset then go l (m - 1)
-                else if start <= offset && offset < end_ then Some m
-                else if start = end_ && start = offset then Some m
-                else if offset >= end_ then go (m + 1) h
-                else None
-
-        match go 0 (this.Map.Length - 1) with
+        match tryFindLine this.Map offset with
         | Some lineIdx ->
             let start, _ = this.Map[lineIdx]
+            let encoding = PositionEncoding.current ()
 
-            Some { Line = lineIdx; Character = offset - start }
+            Some
+                { Line = lineIdx
+                  Character = PositionEncoding.units encoding this.CharAt start offset }
         | _ -> None
 
     member this.FindPosition(offset: int) : Position =
@@ -45,13 +153,7 @@ type LineMap =
         |> Option.defaultWith (fun _ -> failwith $"Couldn't find offset's position: {offset}")
 
     member this.TryFindOffset(pos: Position) : option<int> =
-        if pos.Line >= this.Map.Length then
-            None
-        else
-            let start, end_ = this.Map[pos.Line]
-            let offset = start + pos.Character
-
-            if offset > end_ then None else Some(offset)
+        tryFindOffset this.Map this.CharAt pos
 
     member this.FindOffset(pos: Position) : int =
         this.TryFindOffset(pos)
@@ -109,8 +211,10 @@ type Text =
 
     member this.LineContentRange(line: int) : Range =
         let start, end_ = this.LineContentOffsets(line)
+        let encoding = PositionEncoding.current ()
+        let length = PositionEncoding.units encoding (fun i -> this.content[i]) start end_
 
-        Range.Mk(line, 0, line, end_ - start)
+        Range.Mk(line, 0, line, length)
 
     member this.LineContent(line: int) : string = this.Substring(this.LineContentRange(line))
 
@@ -146,7 +250,7 @@ type internal TrackingTextReader(baseReader: TextReader) =
 
     override this.Peek() : int = baseReader.Peek()
 
-let mkLineMap (str: string) : LineMap =
+let private lineRanges (str: string) : array<LineRange> =
     use reader = new TrackingTextReader(new StringReader(str))
 
     let lineMap = ResizeArray<int * int>()
@@ -168,7 +272,9 @@ let mkLineMap (str: string) : LineMap =
     // Add an empty new line for text insertion at the end
     lineMap.Add(start, start)
 
-    LineMap(lineMap.ToArray())
+    lineMap.ToArray()
+
+let mkLineMap (str: string) : LineMap = LineMap(lineRanges str, str)
 
 let documentBeginning = Range.Mk(0, 0, 0, 0)
 
@@ -180,26 +286,71 @@ let mkPosition (line, char) = { Line = line; Character = char }
 
 let mkRange (start, end_) = { Start = mkPosition start; End = mkPosition end_ }
 
-let private applyChangeOne (text: Text) (change: TextDocumentContentChangeEvent) : Text =
-    match change.Range with
-    | Some range ->
-        let lineMap = text.lineMap
-        let start = range.Start |> lineMap.FindOffset
-        let end_ = range.End |> lineMap.FindOffset
-        // Since End points to the position AFTER the last char we don't need +1 here
-        let length = end_ - start
+/// Lines of `content` after the chars `start..end_` of the previous content were replaced by
+/// `insertedLength` chars. Only the lines touched by the edit are scanned again, the lines after
+/// them are shifted.
+let private spliceLines
+    (lines: array<LineRange>)
+    (start: int, end_: int)
+    (insertedLength: int)
+    (content: StringBuilder)
+    : array<LineRange> =
+    let findLine offset =
+        tryFindLine lines offset
+        |> Option.defaultWith (fun _ -> failwith $"Couldn't find offset's line: {offset}")
+
+    let delta = insertedLength - (end_ - start)
+    // The previous line is scanned too: its '\r' joins with an inserted '\n'
+    let firstLine = max 0 (findLine start - 1)
+    let endLine = findLine end_
+    let lastLine = lines.Length - 1
+
+    let scanStart = fst lines[firstLine]
+
+    let scanEnd =
+        if endLine = lastLine then content.Length else snd lines[endLine] + delta
+
+    let scanned =
+        lineRanges (content.ToString(scanStart, scanEnd - scanStart))
+        |> Array.map (fun (s, e) -> s + scanStart, e + scanStart)
+
+    // The empty line at the end of the scanned part is only the end of the whole text when
+    // there's nothing after it
+    let scanned =
+        if endLine = lastLine then scanned else Array.sub scanned 0 (scanned.Length - 1)
+
+    let shifted =
+        lines[endLine + 1 ..] |> Array.map (fun (s, e) -> s + delta, e + delta)
+
+    Array.concat [ lines[.. firstLine - 1]; scanned; shifted ]
+
+/// Applies the changes to a single buffer and keeps the line map up to date between them, so a
+/// batch of changes costs one copy of the content rather than one per change.
+let applyTextChange (changeEvents: array<TextDocumentContentChangeEvent>) (text: Text) : Text =
+    let content = StringBuilder(text.content)
 
-        let newContent =
-            StringBuilder(text.content)
-                .Remove(start, length)
-                .Insert(start, change.Text)
-                .ToString()
+    let findOffset lines pos =
+        tryFindOffset lines (fun i -> content[i]) pos
+        |> Option.defaultWith (fun _ -> failwith $"Position outside of line map: {pos}")
 
-        mkText newContent
-    | None -> mkText change.Text
+    let applyOne (lines: array<LineRange>) (change: TextDocumentContentChangeEvent) =
+        match change.Range with
+        | Some range ->
+            let start = findOffset lines range.Start
+            let end_ = findOffset lines range.End
+            // Since End points to the position AFTER the last char we don't need +1 here
+            let length = end_ - start
 
-let applyTextChange (changeEvents: array<TextDocumentContentChangeEvent>) (text: Text) : Text =
-    Array.fold applyChangeOne text changeEvents
+            content.Remove(start, length).Insert(start, change.Text) |> ignore
+            spliceLines lines (start, end_) change.Text.Length content
+        | None ->
+            content.Clear().Append(change.Text) |> ignore
+            lineRanges change.Text
+
+    let lines = Array.fold applyOne text.lineMap.Map changeEvents
+    let content = content.ToString()
+
+    { content = content; lineMap = LineMap(lines, content) }
 
 type Span =
     { text: Text
@@ -32,8 +32,9 @@ module Entry =
         let slug = entry.link |> Slug.toString
         $"{offset}- [{entry.title}](#{slug})"
 
+    // Headings with an explicit id are linked by it, which stays valid when the title changes
     let fromHeading (heading: Heading) : Entry =
-        let slug = Heading.slug heading
+        let slug = Heading.idSlug heading |> Option.defaultValue (Heading.slug heading)
         { level = heading.level; link = slug; title = heading.title.text }
 
 type InsertionPoint =
@@ -55,6 +56,12 @@ module TableOfContents =
         else
             Some { entries = Array.map Entry.fromHeading headings }
 
+    /// Leaves out the entries deeper than `maxLevel`; there's no table when none are left.
+    let upTo (maxLevel: int) (toc: TableOfContents) : TableOfContents option =
+        match toc.entries |> Array.filter (fun entry -> entry.level <= maxLevel) with
+        | [||] -> None
+        | entries -> Some { entries = entries }
+
     let insertionPoint (doc: Doc) : InsertionPoint =
         let index = Doc.index doc
 
@@ -0,0 +1,124 @@
+/// Spans of the work done by the server: requests, parsing, loading of folders, diagnostics. The
+/// recent ones are kept in memory and `marksman.dumpProfile` writes them as a Chrome trace, which
+/// `chrome://tracing`, Perfetto and speedscope can open, to attach to reports of slowness.
+module Marksman.Trace
+
+open System
+open System.Collections.Generic
+open System.Diagnostics
+open System.IO
+open Newtonsoft.Json
+open Newtonsoft.Json.Linq
+
+type Span =
+    { name: string
+      category: string
+      /// E.g. the path of the note that was parsed
+      detail: option<string>
+      /// Microseconds since the start of the server
+      start: int64
+      duration: int64
+      /// Spans of async work may move between threads and overlap on them, so they have none
+      thread: option<int> }
+
+// Only the most recent spans are kept, so that tracing can stay on all the time
+let capacity = 20000
+
+let private clock = Stopwatch.StartNew()
+
+let private spans = Queue<Span>(capacity)
+
+// Ticks of a TimeSpan are 100ns
+let private now () = clock.Elapsed.Ticks / 10L
+
+let private record category name detail (start: int64) (thread: option<int>) =
+    let span =
+        { name = name
+          category = category
+          detail = detail
+          start = start
+          duration = now () - start
+          thread = thread }
+
+    lock spans (fun () ->
+        if spans.Count >= capacity then
+            spans.Dequeue() |> ignore
+
+        spans.Enqueue(span))
+
+let spanOf (category: string) (name: string) (detail: string) (f: unit -> 'T) : 'T =
+    let start = now ()
+
+    try
+        f ()
+    finally
+        record category name (Some detail) start (Some Environment.CurrentManagedThreadId)
+
+let span (category: string) (name: string) (f: unit -> 'T) : 'T =
+    let start = now ()
+
+    try
+        f ()
+    finally
+        record category name None start (Some Environment.CurrentManagedThreadId)
+
+let spanAsync (category: string) (name: string) (work: Async<'T>) : Async<'T> =
+    async {
+        let start = now ()
+
+        try
+            return! work
+        finally
+            record category name None start None
+    }
+
+/// The recorded spans, oldest first.
+let recent () : array<Span> = lock spans (fun () -> spans.ToArray())
+
+let clear () : unit = lock spans (fun () -> spans.Clear())
+
+/// The spans in the Trace Event Format: complete events for the spans of a thread, and pairs of
+/// async events, each pair with its own id, for the others.
+let toChromeTrace (spans: array<Span>) : JObject =
+    let pid = Environment.ProcessId
+
+    let event (phase: string) (span: Span) (ts: int64) =
+        let event =
+            JObject(
+                JProperty("name", span.name),
+                JProperty("cat", span.category),
+                JProperty("ph", phase),
+                JProperty("ts", ts),
+                JProperty("pid", pid)
+            )
+
+        span.detail
+        |> Option.iter (fun detail ->
+            event.Add(JProperty("args", JObject(JProperty("detail", detail)))))
+
+        event
+
+    let events =
+        spans
+        |> Seq.indexed
+        |> Seq.collect (fun (id, span) ->
+            match span.thread with
+            | Some thread ->
+                let complete = event "X" span span.start
+                complete.Add(JProperty("dur", span.duration))
+                complete.Add(JProperty("tid", thread))
+                [ complete ]
+            | None ->
+                [ event "b" span span.start; event "e" span (span.start + span.duration) ]
+                |> List.map (fun half ->
+                    half.Add(JProperty("id", id))
+                    half.Add(JProperty("tid", 0))
+                    half))
+
+    JObject(JProperty("traceEvents", JArray(events)), JProperty("displayTimeUnit", "ms"))
+
+/// Writes the recent spans to `path` and returns the number of spans written.
+let dump (path: string) : int =
+    let spans = recent ()
+    File.WriteAllText(path, (toChromeTrace spans).ToString(Formatting.None))
+    spans.Length
@@ -0,0 +1,135 @@
+/// Opt-in checks of whether external http(s) link targets are alive. Checks run in the background
+/// one at a time, and their results are cached so that notes don't hit the network on every change.
+module Marksman.UrlCheck
+
+open System
+open System.Collections.Concurrent
+open System.Net
+open System.Net.Http
+open System.Threading.Tasks
+open Ionide.LanguageServerProtocol.Logging
+
+type Status =
+    | Alive
+    | Dead of reason: string
+
+let private logger = LogProvider.getLoggerByName "UrlCheck"
+
+let isExternal (url: string) =
+    url.StartsWith("http://", StringComparison.OrdinalIgnoreCase)
+    || url.StartsWith("https://", StringComparison.OrdinalIgnoreCase)
+
+// Keeps the checker from hammering servers when a workspace links to many URLs
+let private delayBetweenChecks = TimeSpan.FromMilliseconds(500)
+
+let private statusOfResponse (response: HttpResponseMessage) =
+    if response.IsSuccessStatusCode then
+        Alive
+    else
+        Dead $"HTTP {int response.StatusCode} {response.ReasonPhrase}"
+
+let private fetch (http: HttpClient) (url: string) : Async<Status> =
+    async {
+        try
+            use head = new HttpRequestMessage(HttpMethod.Head, url)
+            use! response = http.SendAsync(head) |> Async.AwaitTask
+
+            // Some servers don't implement HEAD
+            match response.StatusCode with
+            | HttpStatusCode.MethodNotAllowed
+            | HttpStatusCode.NotImplemented ->
+                use get = new HttpRequestMessage(HttpMethod.Get, url)
+
+                use! response =
+                    http.SendAsync(get, HttpCompletionOption.ResponseHeadersRead)
+                    |> Async.AwaitTask
+
+                return statusOfResponse response
+            | _ -> return statusOfResponse response
+        with
+        | :? TaskCanceledException -> return Dead "timed out"
+        | :? HttpRequestException as exn -> return Dead exn.Message
+        | :? AggregateException as exn -> return Dead(exn.GetBaseException().Message)
+        | :? UriFormatException
+        | :? InvalidOperationException -> return Dead "malformed URL"
+    }
+
+/// Checks URLs through `handler` one at a time, caching their status. Each server owns one, so
+/// that nothing is shared between instances.
+type Checker(handler: HttpMessageHandler) =
+    let http =
+        let client = new HttpClient(handler, Timeout = TimeSpan.FromSeconds(10))
+        client.DefaultRequestHeaders.UserAgent.ParseAdd("marksman")
+        client
+
+    let cache = ConcurrentDictionary<string, DateTime * Status>()
+
+    let queued = ConcurrentDictionary<string, unit>()
+
+    let updatedEvent = Event<unit>()
+
+    // Set on shutdown, after which no more URLs are checked
+    let mutable stopped = false
+
+    let worker =
+        MailboxProcessor<string>.Start (fun inbox ->
+            let rec loop () =
+                async {
+                    let! url = inbox.Receive()
+
+                    // URLs queued before the shutdown are dropped without a request
+                    if stopped then
+                        return! loop ()
+                    else
+                        let! status = fetch http url
+
+                        logger.trace (
+                            Log.setMessage "Checked URL"
+                            >> Log.addContext "url" url
+                            >> Log.addContext "status" status
+                        )
+
+                        cache[url] <- (DateTime.UtcNow, status)
+                        queued.TryRemove(url) |> ignore
+
+                        if inbox.CurrentQueueLength = 0 then
+                            updatedEvent.Trigger()
+
+                        do! Async.Sleep(delayBetweenChecks)
+                        return! loop ()
+                }
+
+            loop ())
+
+    new() = Checker(new HttpClientHandler())
+
+    /// Fires after a batch of queued URLs has been checked.
+    member _.Updated: IEvent<unit> = updatedEvent.Publish
+
+    /// Last known status of the URL. URLs that were never checked or whose status is older than
+    /// `ttl` are queued for a check; until it completes the stale status, if any, is returned.
+    member _.TryStatus(ttl: TimeSpan, url: string) : option<Status> =
+        let cached =
+            match cache.TryGetValue(url) with
+            | true, cached -> Some cached
+            | _ -> None
+
+        let isFresh =
+            cached
+            |> Option.exists (fun (checkedAt, _) -> DateTime.UtcNow - checkedAt < ttl)
+
+        if not stopped && not isFresh && queued.TryAdd(url, ()) then
+            worker.Post(url)
+
+        cached |> Option.map snd
+
+    /// Stops checking URLs, including the requests that are in flight. Used on shutdown.
+    member _.Stop() : unit =
+        stopped <- true
+        http.CancelPendingRequests()
+
+    interface IDisposable with
+        member this.Dispose() =
+            this.Stop()
+            (worker :> IDisposable).Dispose()
+            http.Dispose()
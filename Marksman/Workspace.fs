@@ -30,34 +30,173 @@ module RootPath =
     let ofString s = ofPath (PathUri.ofString s)
     let path (RootPath p) = p
 
+    let contains (inner: PathUri) (RootPath root) =
+        let prefix =
+            Path.TrimEndingDirectorySeparator(root.LocalPath) + string Path.DirectorySeparatorChar
+
+        inner.LocalPath.StartsWith(prefix)
+
+type Parsed = { cst: Cst; index: Index }
+
+/// Parse of a doc that can be dropped to save memory. Parses are held on to while the heap is
+/// under `core.memory_limit_mb`. Over it only the parses of the most recently used docs are held,
+/// and the others are parsed again from the text when needed.
+[<Sealed>]
+type LazyParse(text: Text, parsed: Parsed) =
+    member val internal Held: option<Parsed> = Some parsed with get, set
+
+    member val internal Node: Collections.Generic.LinkedListNode<WeakReference<LazyParse>> =
+        null with get, set
+
+    member this.Text = text
+
+module LazyParse =
+    // Number of parses held on to while the heap is over `core.memory_limit_mb`
+    [<Literal>]
+    let private HeldOverLimit = 256
+
+    // Parses that are held, the most recently used first. The list doesn't keep them alive, so
+    // that parses of docs that are gone, e.g. of their older versions, are collected with them.
+    let private recent = Collections.Generic.LinkedList<WeakReference<LazyParse>>()
+
+    // Length of the list when entries of collected parses were last removed from it
+    let mutable private sweptCount = 0
+
+    let private sweep () =
+        let mutable node = recent.First
+
+        while not (isNull node) do
+            let next = node.Next
+
+            match node.Value.TryGetTarget() with
+            | true, _ -> ()
+            | false, _ -> recent.Remove(node)
+
+            node <- next
+
+        sweptCount <- recent.Count
+
+    let private touch (lazyParse: LazyParse) =
+        if isNull lazyParse.Node then
+            lazyParse.Node <- recent.AddFirst(WeakReference<LazyParse>(lazyParse))
+        elif not (obj.ReferenceEquals(recent.First, lazyParse.Node)) then
+            recent.Remove(lazyParse.Node)
+            recent.AddFirst(lazyParse.Node)
+
+        if recent.Count > 2 * max sweptCount HeldOverLimit then
+            sweep ()
+
+        // Over the memory limit the parses used least recently are dropped
+        if not (Caches.enabled ()) then
+            while recent.Count > HeldOverLimit do
+                let evicted = recent.Last
+                recent.RemoveLast()
+
+                match evicted.Value.TryGetTarget() with
+                | true, lazyParse ->
+                    lazyParse.Node <- null
+                    lazyParse.Held <- None
+                | false, _ -> ()
+
+    let mk (text: Text) (parsed: Parsed) : LazyParse =
+        let lazyParse = LazyParse(text, parsed)
+        lock recent (fun () -> touch lazyParse)
+        lazyParse
+
+    let get (lazyParse: LazyParse) : Parsed =
+        // Parsing happens outside of the lock, so that it doesn't hold up other docs
+        let parsed =
+            match lock recent (fun () -> lazyParse.Held) with
+            | Some parsed -> parsed
+            | None ->
+                // The parse was dropped to save memory
+                let cst =
+                    Trace.span "parse" "parseAgain" (fun () -> parseText lazyParse.Text)
+
+                { cst = cst; index = Index.ofCst cst }
+
+        lock recent (fun () ->
+            lazyParse.Held <- Some parsed
+            touch lazyParse)
+
+        parsed
+
+    /// Stops holding on to the parse, e.g. of a version of a doc that was edited since.
+    let release (lazyParse: LazyParse) : unit =
+        lock recent (fun () ->
+            if not (isNull lazyParse.Node) then
+                recent.Remove(lazyParse.Node)
+                lazyParse.Node <- null
+
+            lazyParse.Held <- None)
+
+/// Only the name is kept apart from the parse, since it's looked up for every doc when links
+/// are resolved. Docs are equal when their paths, versions and texts are; the parse and the
+/// name are derived from those.
+[<CustomEquality; CustomComparison>]
 type Doc =
     { path: PathUri
       rootPath: RootPath
       version: option<int>
       text: Text
-      cst: Cst
-      index: Index }
+      name: string
+      parsed: LazyParse }
+
+    member private this.Key = this.path, this.rootPath, this.version, this.text
+
+    override this.Equals(other) =
+        match other with
+        | :? Doc as other -> this.Key = other.Key
+        | _ -> false
+
+    override this.GetHashCode() = hash this.Key
+
+    interface IComparable with
+        member this.CompareTo(other) =
+            match other with
+            | :? Doc as other -> compare this.Key other.Key
+            | _ -> invalidArg "other" "Can't compare a doc with something else"
 
 module Doc =
     let logger = LogProvider.getLoggerByName "Doc"
 
-    let mk path rootPath version text =
-        let cst = parseText text
+    let index (doc: Doc) : Index = (LazyParse.get doc.parsed).index
+
+    let cst (doc: Doc) : Cst = (LazyParse.get doc.parsed).cst
+
+    let private pathName (rootPath: RootPath) (path: PathUri) =
+        Path.GetRelativePath((RootPath.path rootPath).LocalPath, path.LocalPath)
+        |> Path.GetFileNameWithoutExtension
+
+    let private mkParsed path rootPath version text cst =
         let index = Index.ofCst cst
 
+        let name =
+            match Index.title index with
+            | Some { data = hd } -> Heading.name hd
+            | None -> pathName rootPath path
+
         { path = path
           rootPath = rootPath
           version = version
           text = text
-          cst = cst
-          index = index }
+          name = name
+          parsed = LazyParse.mk text { cst = cst; index = index } }
+
+    let mk (path: PathUri) rootPath version text =
+        let cst = Trace.spanOf "parse" "parse" path.LocalPath (fun () -> parseText text)
+        mkParsed path rootPath version text cst
 
     let text doc = doc.text
 
     let withText newText doc =
-        let newCst = parseText newText
-        let newIndex = Index.ofCst newCst
-        { doc with text = newText; cst = newCst; index = newIndex }
+        // Edits usually touch a single section of a note, so the rest of the parse is reused
+        let newCst =
+            Trace.spanOf "parse" "reparse" doc.path.LocalPath (fun () ->
+                reparseText doc.text (cst doc) newText)
+
+        LazyParse.release doc.parsed
+        mkParsed doc.path doc.rootPath doc.version newText newCst
 
 
     let applyLspChange (change: DidChangeTextDocumentParams) (doc: Doc) : Doc =
@@ -105,6 +244,48 @@ module Doc =
         with :? FileNotFoundException ->
             None
 
+    // Text files don't have NUL bytes, so one among the first few KB gives away a binary file.
+    // UTF-16 and UTF-32 text has them all over, but starts with a byte order mark
+    let private looksBinary (path: string) : bool =
+        let isBom (bytes: array<byte>) =
+            match bytes with
+            | [| 0xFFuy; 0xFEuy |]
+            | [| 0xFEuy; 0xFFuy |] -> true
+            | _ -> false
+
+        try
+            use stream = File.OpenRead(path)
+            let buffer = Array.zeroCreate<byte> 8192
+            let read = stream.Read(buffer, 0, buffer.Length)
+            let buffer = Array.truncate read buffer
+
+            not (isBom (Array.truncate 2 buffer)) && Array.contains 0uy buffer
+        with
+        | :? IOException
+        | :? UnauthorizedAccessException -> false
+
+    /// Why the file shouldn't be indexed even though it's a note by its extension and location.
+    let skipReason (maxFileSizeKb: int) (file: FileInfo) : option<string> =
+        if file.Length > int64 maxFileSizeKb * 1024L then Some "too large"
+        elif looksBinary file.FullName then Some "binary"
+        else None
+
+    /// Like `tryLoad`, unless the file is too large or binary, see `skipReason`.
+    let tryLoadNote (maxFileSizeKb: int) (root: RootPath) (path: PathUri) : option<Doc> =
+        let file = FileInfo(path.LocalPath)
+
+        match skipReason maxFileSizeKb file with
+        | _ when not file.Exists -> None
+        | None -> tryLoad root path
+        | Some reason ->
+            logger.warn (
+                Log.setMessage "Skipping a file that can't be a note"
+                >> Log.addContext "file" path.LocalPath
+                >> Log.addContext "reason" reason
+            )
+
+            None
+
     let uri (doc: Doc) : DocumentUri = doc.path.DocumentUri
 
     let rootPath (doc: Doc) : RootPath = doc.rootPath
@@ -115,35 +296,197 @@ module Doc =
         let folderPath = (RootPath.path doc.rootPath).LocalPath
         Path.GetRelativePath(folderPath, docPath)
 
-    let title (doc: Doc) : option<Node<Heading>> = Index.title doc.index
-
-    let index (doc: Doc) : Index = doc.index
+    let title (doc: Doc) : option<Node<Heading>> = Index.title (index doc)
 
-    let cst (doc: Doc) : Cst = doc.cst
-
-    let name (doc: Doc) : string =
-        match title doc with
-        | Some { data = hd } -> Heading.name hd
-        | None -> pathFromRoot doc |> Path.GetFileNameWithoutExtension
+    let name (doc: Doc) : string = doc.name
 
     let slug (doc: Doc) : Slug = name doc |> Slug.ofString
 
-    let headings (doc: Doc) : seq<Node<Heading>> = Index.headings doc.index
+    let headings (doc: Doc) : seq<Node<Heading>> = Index.headings (index doc)
 
-    let linkDefs (doc: Doc) : array<Node<MdLinkDef>> = Index.linkDefs doc.index
+    let linkDefs (doc: Doc) : array<Node<MdLinkDef>> = Index.linkDefs (index doc)
 
     let linkDefMatching (sub: LinkLabel) (doc: Doc) : seq<Node<MdLinkDef>> =
-        Index.filterLinkDefs (LinkLabel.isSubSequenceOf sub) doc.index
+        Index.filterLinkDefs (LinkLabel.isSubSequenceOf sub) (index doc)
 
-    let linkAtPos (pos: Position) (doc: Doc) : option<Element> = Index.linkAtPos pos doc.index
+    let linkAtPos (pos: Position) (doc: Doc) : option<Element> = Index.linkAtPos pos (index doc)
 
     let version (doc: Doc) : option<int> = doc.version
 
+/// Folder-level index of tags: maps a tag name to the documents that mention it.
+/// It's maintained incrementally as documents are added/removed so that tag queries don't need
+/// to scan every document in the folder.
+type TagIndex = Map<string, Set<PathUri>>
+
+module TagIndex =
+    let empty: TagIndex = Map.empty
+
+    let private docTags (doc: Doc) : Set<string> =
+        Index.tags (Doc.index doc)
+        |> Seq.map (fun { data = tag } -> tag.name.text)
+        |> Set.ofSeq
+
+    let withDoc (doc: Doc) (index: TagIndex) : TagIndex =
+        let addPath =
+            function
+            | None -> Some(Set.singleton doc.path)
+            | Some paths -> Some(Set.add doc.path paths)
+
+        docTags doc
+        |> Set.fold (fun index tag -> Map.change tag addPath index) index
+
+    let withoutDoc (doc: Doc) (index: TagIndex) : TagIndex =
+        let removePath =
+            function
+            | None -> None
+            | Some paths ->
+                let paths = Set.remove doc.path paths
+                if Set.isEmpty paths then None else Some paths
+
+        docTags doc
+        |> Set.fold (fun index tag -> Map.change tag removePath index) index
+
+    let ofDocs (docs: seq<Doc>) : TagIndex = Seq.fold (flip withDoc) empty docs
+
+    let docsWithTag (tag: string) (index: TagIndex) : Set<PathUri> =
+        Map.tryFind tag index |> Option.defaultValue Set.empty
+
+/// Folder-level index of the names links use for docs: maps a name to the documents with links
+/// using it. A link resolves only to the docs going by one of its names, so the names of a doc
+/// give the documents that may link to it without resolving the links of the whole folder.
+/// Names are either slugs, matching titles, or file stems.
+type LinkIndex = Map<string, Set<PathUri>>
+
+module LinkIndex =
+    let empty: LinkIndex = Map.empty
+
+    let private slugName (name: string) = "slug:" + Slug.toString (Slug.ofString name)
+
+    let private stemName (name: string) =
+        "stem:" + Path.GetFileNameWithoutExtension(name).AbsPathUrlEncode()
+
+    // Whether IDs resolve depends on the config, so every name that may be one is indexed
+    let private idName (name: string) = "id:" + name
+
+    /// Names that the doc goes by in links.
+    let docNames (doc: Doc) : list<string> =
+        let stem = Path.GetFileNameWithoutExtension(Doc.pathFromRoot doc)
+
+        [ slugName (Doc.name doc); stemName (Doc.pathFromRoot doc) ]
+        @ (Zettel.idPart stem |> Option.map idName |> Option.toList)
+
+    let private linkNames (doc: Doc) : Set<string> =
+        Index.links (Doc.index doc)
+        |> Seq.choose (function
+            | WL wl -> wl.data.doc |> Option.map Node.text
+            | ML { data = MdLink.IL (_, Some url, _) } ->
+                (Url.ofUrlNode url).url |> Option.map Node.text
+            | _ -> None)
+        |> Seq.collect (fun name ->
+            let id = Zettel.idPart name |> Option.filter ((=) name) |> Option.map idName
+            [ slugName name; stemName name ] @ Option.toList id)
+        |> Set.ofSeq
+
+    let withDoc (doc: Doc) (index: LinkIndex) : LinkIndex =
+        let addPath =
+            function
+            | None -> Some(Set.singleton doc.path)
+            | Some paths -> Some(Set.add doc.path paths)
+
+        linkNames doc
+        |> Set.fold (fun index name -> Map.change name addPath index) index
+
+    let withoutDoc (doc: Doc) (index: LinkIndex) : LinkIndex =
+        let removePath =
+            function
+            | None -> None
+            | Some paths ->
+                let paths = Set.remove doc.path paths
+                if Set.isEmpty paths then None else Some paths
+
+        linkNames doc
+        |> Set.fold (fun index name -> Map.change name removePath index) index
+
+    let ofDocs (docs: seq<Doc>) : LinkIndex = Seq.fold (flip withDoc) empty docs
+
+    /// Documents with links using one of the names of `doc`.
+    let docsNaming (doc: Doc) (index: LinkIndex) : Set<PathUri> =
+        docNames doc
+        |> List.choose (fun name -> Map.tryFind name index)
+        |> Set.unionMany
+
+/// Folder-level index of the headings for workspace symbols: maps a case-folded char to the
+/// headings having it in their name, identified by the doc and their position in its index. A
+/// fuzzy query only matches names having all of its chars, so the index narrows down the
+/// headings to score.
+type SymbolIndex = Map<char, Set<PathUri * int>>
+
+module SymbolIndex =
+    let empty: SymbolIndex = Map.empty
+
+    let private charsOf (name: string) : Set<char> =
+        name.Normalize() |> Seq.map foldCase |> Set.ofSeq
+
+    let private docEntries (doc: Doc) : seq<char * (PathUri * int)> =
+        Index.headings (Doc.index doc)
+        |> Seq.indexed
+        |> Seq.collect (fun (i, { data = heading }) ->
+            charsOf (Heading.name heading) |> Seq.map (fun char -> char, (doc.path, i)))
+
+    let withDoc (doc: Doc) (index: SymbolIndex) : SymbolIndex =
+        let addKey key =
+            function
+            | None -> Some(Set.singleton key)
+            | Some keys -> Some(Set.add key keys)
+
+        docEntries doc
+        |> Seq.fold (fun index (char, key) -> Map.change char (addKey key) index) index
+
+    let withoutDoc (doc: Doc) (index: SymbolIndex) : SymbolIndex =
+        let removeKey key =
+            function
+            | None -> None
+            | Some keys ->
+                let keys = Set.remove key keys
+                if Set.isEmpty keys then None else Some keys
+
+        docEntries doc
+        |> Seq.fold (fun index (char, key) -> Map.change char (removeKey key) index) index
+
+    let ofDocs (docs: seq<Doc>) : SymbolIndex = Seq.fold (flip withDoc) empty docs
+
+    /// Headings with all the chars of `query`, or None for an empty query, which matches all.
+    let candidates (query: string) (index: SymbolIndex) : option<Set<PathUri * int>> =
+        let chars = charsOf query
+
+        if Set.isEmpty chars then
+            None
+        else
+            chars
+            |> Seq.map (fun char -> Map.tryFind char index |> Option.defaultValue Set.empty)
+            |> Set.intersectMany
+            |> Some
+
 type MultiFile =
     { name: string
       root: RootPath
       docs: Map<PathUri, Doc>
-      config: option<Config> }
+      /// Docs in `completion.create_note.template_dir`, which aren't notes of the folder
+      templates: Map<PathUri, Doc>
+      tags: TagIndex
+      links: LinkIndex
+      symbols: SymbolIndex
+      config: option<Config>
+      /// Configs of the subdirectories that have a `.marksman.toml`, by their path from the root
+      dirConfigs: Map<string, Config>
+      /// Non-markdown files that aren't hidden or ignored, by their path from the root
+      assets: Set<string>
+      /// Notes skipped by the ignore globs when the folder was loaded
+      ignored: int
+      /// Notes skipped for being too large or binary when the folder was loaded
+      skipped: int
+      /// Changes when docs are added or removed, or the config changes; edits of docs keep it
+      docSet: int64 }
 
 type SingleFile = { doc: Doc; config: option<Config> }
 
@@ -163,8 +506,43 @@ module Folder =
         | SingleFile _ -> true
         | MultiFile _ -> false
 
+    let mutable private lastDocSet = 0L
+
+    let private nextDocSet () =
+        System.Threading.Interlocked.Increment(&lastDocSet)
+
+    let private templateDirOf (config: option<Config>) : string =
+        let config = Option.defaultValue Config.Default config
+        config.ComplCreateNoteTemplateDir().Replace('\\', '/').Trim('/')
+
+    // Templates are kept apart from the notes: their placeholders, e.g. `# {{title}}`, would
+    // otherwise be taken for headings and links of the folder
+    let private isTemplateIn (templateDir: string) (doc: Doc) : bool =
+        templateDir <> ""
+        && (Doc.pathFromRoot doc).Replace('\\', '/').TrimStart('/').StartsWith(templateDir + "/")
+
+    let private mkMultiFile name root docs config dirConfigs assets ignored skipped =
+        let templates, docs =
+            Map.partition (fun _ doc -> isTemplateIn (templateDirOf config) doc) docs
+
+        MultiFile(
+            { name = name
+              root = root
+              docs = docs
+              templates = templates
+              tags = TagIndex.ofDocs (Map.values docs)
+              links = LinkIndex.ofDocs (Map.values docs)
+              symbols = SymbolIndex.ofDocs (Map.values docs)
+              config = config
+              dirConfigs = dirConfigs
+              assets = assets
+              ignored = ignored
+              skipped = skipped
+              docSet = nextDocSet () }
+        )
+
     let multiFile name root docs config =
-        MultiFile({ name = name; root = root; docs = docs; config = config })
+        mkMultiFile name root docs config Map.empty Set.empty 0 0
 
 
     let config =
@@ -177,7 +555,42 @@ module Folder =
     let withConfig config =
         function
         | SingleFile folder -> SingleFile { folder with config = config }
-        | MultiFile folder -> MultiFile { folder with config = config }
+        | MultiFile folder when templateDirOf config <> templateDirOf folder.config ->
+            let docs = Seq.append folder.docs folder.templates |> Seq.map (|KeyValue|) |> Map.ofSeq
+
+            mkMultiFile
+                folder.name
+                folder.root
+                docs
+                config
+                folder.dirConfigs
+                folder.assets
+                folder.ignored
+                folder.skipped
+        | MultiFile folder -> MultiFile { folder with config = config; docSet = nextDocSet () }
+
+    let dirConfigs: Folder -> Map<string, Config> =
+        function
+        | SingleFile _ -> Map.empty
+        | MultiFile { dirConfigs = dirConfigs } -> dirConfigs
+
+    // Directories containing the path from the root, the outermost first, e.g. `a`, `a/b` for
+    // `a/b/note.md`
+    let private dirsOf (pathFromRoot: string) : list<string> =
+        let segments = pathFromRoot.Replace('\\', '/').Split('/')
+
+        [ for count in 1 .. segments.Length - 1 ->
+              String.Join('/', Array.take count segments) ]
+
+    let configFor (doc: Doc) (folder: Folder) : Config =
+        let dirConfigs = dirConfigs folder
+
+        let mergeOver merged dirConfig = Config.mergeOpt (Some dirConfig) merged
+
+        dirsOf (Doc.pathFromRoot doc)
+        |> List.choose (fun dir -> Map.tryFind dir dirConfigs)
+        |> List.fold mergeOver (config folder)
+        |> Option.defaultValue Config.Default
 
     let docs: Folder -> seq<Doc> =
         function
@@ -197,7 +610,8 @@ module Folder =
     let tryFindDocByPath (uri: PathUri) : Folder -> option<Doc> =
         function
         | SingleFile { doc = doc } -> Some doc |> Option.filter (fun x -> x.path = uri)
-        | MultiFile { docs = docs } -> Map.tryFind uri docs
+        | MultiFile { docs = docs; templates = templates } ->
+            Map.tryFind uri docs |> Option.orElse (Map.tryFind uri templates)
 
     let private readIgnoreFiles (root: PathUri) : array<string> =
         let lines = ResizeArray()
@@ -221,14 +635,48 @@ module Folder =
 
         lines.ToArray()
 
-    let private loadDocs (configuredExts: array<string>) (root: RootPath) : seq<Doc> =
-        let rec collect (cur: PathUri) (ignoreMatchers: list<GlobMatcher>) =
+    let private rootIgnoreMatchers (configuredIgnores: array<string>) (root: RootPath) =
+        let rootPath = (RootPath.path root).LocalPath
+        [ GlobMatcher.mk rootPath configuredIgnores; GlobMatcher.mkDefault rootPath ]
+
+    // Where the directory really is: (the final target of) a symlink resolves to another place.
+    // `path` is where the directory is without resolving the directory itself. None for links
+    // that can't be resolved, e.g. the ones pointing to themselves
+    let private realDir (path: string) (dir: DirectoryInfo) : option<string> =
+        if isNull dir.LinkTarget then
+            Some path
+        else
+            try
+                match dir.ResolveLinkTarget(true) with
+                | null -> None
+                | target -> Some(Path.GetFullPath(target.FullName))
+            with :? IOException ->
+                None
+
+    let private loadDocs
+        (onIgnored: unit -> unit)
+        (onSkipped: unit -> unit)
+        (onAsset: string -> unit)
+        (onDir: string -> unit)
+        (config: Config)
+        (root: RootPath)
+        : seq<Doc> =
+        let configuredExts = config.CoreMarkdownFileExtensions()
+        let maxFileSizeKb = config.CoreMaxFileSizeKb()
+        let followSymlinks = config.CoreFollowSymlinks()
+
+        // Real paths of the directories scanned so far. With symlinks a directory can be reached
+        // by several paths, or from inside itself
+        let scanned = Collections.Generic.HashSet<string>()
+
+        let rec collect (cur: PathUri) (real: string) (ignoreMatchers: list<GlobMatcher>) =
             let ignoreMatchers =
                 match readIgnoreFiles cur with
                 | [||] -> ignoreMatchers
                 | pats -> GlobMatcher.mk cur.LocalPath pats :: ignoreMatchers
 
             let di = DirectoryInfo(cur.LocalPath)
+            onDir cur.LocalPath
 
             try
                 let files = di.GetFiles()
@@ -236,31 +684,58 @@ module Folder =
 
                 seq {
                     for file in files do
-                        if
-                            (isMarkdownFile configuredExts file.FullName)
-                            && not (GlobMatcher.ignoresAny ignoreMatchers file.FullName)
-                        then
-                            let pathUri = PathUri.ofString file.FullName
-
-                            let document = Doc.tryLoad root pathUri
-
-                            match document with
-                            | Some document -> yield document
-                            | _ -> ()
-                        else
-                            logger.trace (
-                                Log.setMessage "Skipping ignored file"
-                                >> Log.addContext "file" file.FullName
-                            )
+                        if isMarkdownFile configuredExts file.FullName then
+                            if not (GlobMatcher.ignoresAny ignoreMatchers file.FullName) then
+                                match Doc.skipReason maxFileSizeKb file with
+                                | None ->
+                                    let pathUri = PathUri.ofString file.FullName
+
+                                    let document = Doc.tryLoad root pathUri
+
+                                    match document with
+                                    | Some document -> yield document
+                                    | _ -> ()
+                                | Some reason ->
+                                    logger.warn (
+                                        Log.setMessage "Skipping a file that can't be a note"
+                                        >> Log.addContext "file" file.FullName
+                                        >> Log.addContext "reason" reason
+                                        >> Log.addContext "size" file.Length
+                                    )
+
+                                    onSkipped ()
+                            else
+                                logger.trace (
+                                    Log.setMessage "Skipping ignored file"
+                                    >> Log.addContext "file" file.FullName
+                                )
+
+                                onIgnored ()
+                        elif not (GlobMatcher.ignoresAny ignoreMatchers file.FullName) then
+                            onAsset file.FullName
 
                     for dir in dirs do
-                        if not (GlobMatcher.ignoresAny ignoreMatchers dir.FullName) then
-                            yield! collect (PathUri.ofString dir.FullName) ignoreMatchers
-                        else
+                        if GlobMatcher.ignoresAny ignoreMatchers dir.FullName then
                             logger.trace (
                                 Log.setMessage "Skipping ignored directory"
                                 >> Log.addContext "file" dir.FullName
                             )
+                        elif not (isNull dir.LinkTarget) && not followSymlinks then
+                            logger.trace (
+                                Log.setMessage "Skipping symlinked directory"
+                                >> Log.addContext "file" dir.FullName
+                            )
+                        else
+                            match realDir (Path.Join(real, dir.Name)) dir with
+                            | Some realSubdir when scanned.Add(realSubdir) ->
+                                let subdir = PathUri.ofString dir.FullName
+                                yield! collect subdir realSubdir ignoreMatchers
+                            | _ ->
+                                logger.debug (
+                                    Log.setMessage "Skipping directory that is already scanned"
+                                    >> Log.addContext "file" dir.FullName
+                                    >> Log.addContext "target" dir.LinkTarget
+                                )
                 }
             with
             | :? UnauthorizedAccessException as exn ->
@@ -280,11 +755,21 @@ module Folder =
 
                 Seq.empty
 
-        collect (RootPath.path root) [ GlobMatcher.mkDefault (RootPath.path root).LocalPath ]
+        let rootPath = (RootPath.path root).LocalPath
+        let realRoot = realDir rootPath (DirectoryInfo(rootPath)) |> Option.defaultValue rootPath
+        scanned.Add(realRoot) |> ignore
 
-    let private tryLoadFolderConfig (root: RootPath) : option<Config> =
-        let folderConfigPath =
-            Path.Join((RootPath.path root).LocalPath, ".marksman.toml")
+        collect (RootPath.path root) realRoot (rootIgnoreMatchers (config.CoreIgnore()) root)
+
+    let private assetPathFromRoot (root: RootPath) (path: string) : string =
+        Path.GetRelativePath((RootPath.path root).LocalPath, path).Replace('\\', '/')
+
+    // Files in hidden directories, e.g. `.obsidian`, are settings of tools rather than assets
+    let private isHiddenPath (pathFromRoot: string) : bool =
+        pathFromRoot.Split('/') |> Array.exists (fun part -> part.StartsWith('.'))
+
+    let private tryLoadConfigIn (dir: string) : option<Config> =
+        let folderConfigPath = Path.Join(dir, ".marksman.toml")
 
         if File.Exists(folderConfigPath) then
             logger.trace (
@@ -309,25 +794,69 @@ module Folder =
 
             None
 
-    let tryLoad (userConfig: option<Config>) (name: string) (root: RootPath) : option<Folder> =
+    let private tryLoadFolderConfig (root: RootPath) : option<Config> =
+        tryLoadConfigIn (RootPath.path root).LocalPath
+
+    let tryLoadConfig (userConfig: option<Config>) (root: RootPath) : option<Config> =
+        Config.mergeOpt (tryLoadFolderConfig root) userConfig
+
+    // Configs of the directories, by their path from the root. The config of the root itself is
+    // the folder config
+    let private loadDirConfigs (root: RootPath) (dirs: seq<string>) : Map<string, Config> =
+        let rootPath = (RootPath.path root).LocalPath
+
+        dirs
+        |> Seq.map (fun dir -> Path.GetRelativePath(rootPath, dir).Replace('\\', '/'))
+        |> Seq.filter (fun dir -> dir <> ".")
+        |> Seq.choose (fun dir ->
+            tryLoadConfigIn (Path.Join(rootPath, dir))
+            |> Option.map (fun config -> dir, config))
+        |> Map.ofSeq
+
+    let tryLoadWithProgress
+        (onDocLoaded: int -> unit)
+        (userConfig: option<Config>)
+        (name: string)
+        (root: RootPath)
+        : option<Folder> =
         logger.trace (Log.setMessage "Loading folder documents" >> Log.addContext "uri" root)
 
         if Directory.Exists((RootPath.path root).LocalPath) then
 
-            let folderConfig = tryLoadFolderConfig root
-            let folderConfig = Config.mergeOpt folderConfig userConfig
-
-            let configuredExts =
-                (Option.defaultValue Config.Default folderConfig)
-                    .CoreMarkdownFileExtensions()
+            let folderConfig = tryLoadConfig userConfig root
+            let config = Option.defaultValue Config.Default folderConfig
 
-            let documents =
-                loadDocs configuredExts root
-                |> Seq.map (fun doc -> doc.path, doc)
-                |> Map.ofSeq
+            let ignored = ref 0
+            let onIgnored () = ignored.Value <- ignored.Value + 1
+            let skipped = ref 0
+            let onSkipped () = skipped.Value <- skipped.Value + 1
+            let assets = ResizeArray()
+            let dirs = ResizeArray()
 
+            let onAsset (path: string) =
+                let pathFromRoot = assetPathFromRoot root path
+                if not (isHiddenPath pathFromRoot) then assets.Add(pathFromRoot)
 
-            MultiFile { name = name; root = root; docs = documents; config = folderConfig }
+            let documents =
+                Trace.spanOf "workspace" "loadFolder" (RootPath.path root).LocalPath (fun () ->
+                    loadDocs onIgnored onSkipped onAsset dirs.Add config root
+                    |> Seq.mapi (fun idx doc ->
+                        onDocLoaded (idx + 1)
+                        doc.path, doc)
+                    |> Map.ofSeq)
+
+
+            let dirConfigs = loadDirConfigs root dirs
+
+            mkMultiFile
+                name
+                root
+                documents
+                folderConfig
+                dirConfigs
+                (Set.ofSeq assets)
+                ignored.Value
+                skipped.Value
             |> Some
         else
             logger.warn (
@@ -337,6 +866,63 @@ module Folder =
 
             None
 
+    let tryLoad (userConfig: option<Config>) (name: string) (root: RootPath) : option<Folder> =
+        tryLoadWithProgress ignore userConfig name root
+
+    let isIgnored (path: PathUri) (folder: Folder) : bool =
+        match folder with
+        | SingleFile _ -> false
+        | MultiFile { root = root; config = config } ->
+            let config = Option.defaultValue Config.Default config
+            let rootPath = (RootPath.path root).LocalPath
+            let relDir = Path.GetRelativePath(rootPath, Path.GetDirectoryName(path.LocalPath))
+
+            // Walks down from the root like the scan does: a directory's ignore files apply to
+            // what's inside it, and nothing inside an ignored directory is looked at
+            let rec go (dir: string) (segments: list<string>) (matchers: list<GlobMatcher>) =
+                let matchers =
+                    match readIgnoreFiles (PathUri.ofString dir) with
+                    | [||] -> matchers
+                    | pats -> GlobMatcher.mk dir pats :: matchers
+
+                match segments with
+                | [] -> GlobMatcher.ignoresAny matchers path.LocalPath
+                | segment :: rest ->
+                    let subdir = Path.Join(dir, segment)
+                    GlobMatcher.ignoresAny matchers subdir || go subdir rest matchers
+
+            let segments =
+                if relDir = "." || relDir.StartsWith("..") then
+                    []
+                else
+                    relDir.Split(Path.DirectorySeparatorChar) |> List.ofArray
+
+            go rootPath segments (rootIgnoreMatchers (config.CoreIgnore()) root)
+
+    let private withoutDocIndexes (oldDoc: Doc) (folder: MultiFile) : MultiFile =
+        { folder with
+            tags = TagIndex.withoutDoc oldDoc folder.tags
+            links = LinkIndex.withoutDoc oldDoc folder.links
+            symbols = SymbolIndex.withoutDoc oldDoc folder.symbols }
+
+    // The folder with the configs of the directories containing a doc new to it that weren't
+    // there when the folder was loaded, e.g. of a directory moved into the folder
+    let private withDirConfigsOf (newDoc: Doc) (folder: MultiFile) : MultiFile =
+        let rootPath = (RootPath.path folder.root).LocalPath
+
+        let found =
+            dirsOf (Doc.pathFromRoot newDoc)
+            |> List.filter (fun dir -> not (Map.containsKey dir folder.dirConfigs))
+            |> List.choose (fun dir ->
+                tryLoadConfigIn (Path.Join(rootPath, dir))
+                |> Option.map (fun config -> dir, config))
+
+        if List.isEmpty found then
+            folder
+        else
+            let addConfig dirConfigs (dir, config) = Map.add dir config dirConfigs
+            { folder with dirConfigs = List.fold addConfig folder.dirConfigs found }
+
     let withDoc (newDoc: Doc) : Folder -> Folder =
         function
         | MultiFile folder ->
@@ -344,7 +930,21 @@ module Folder =
                 failwith
                     $"Updating a folder with an unrelated doc: folder={folder.root}; doc={newDoc.rootPath}"
 
-            MultiFile { folder with docs = Map.add newDoc.path newDoc folder.docs }
+            if isTemplateIn (templateDirOf folder.config) newDoc then
+                MultiFile { folder with templates = Map.add newDoc.path newDoc folder.templates }
+            else
+                let folder =
+                    match Map.tryFind newDoc.path folder.docs with
+                    | Some oldDoc -> withoutDocIndexes oldDoc folder
+                    | None when newDoc.path.IsUntitled -> { folder with docSet = nextDocSet () }
+                    | None -> { withDirConfigsOf newDoc folder with docSet = nextDocSet () }
+
+                MultiFile
+                    { folder with
+                        docs = Map.add newDoc.path newDoc folder.docs
+                        tags = TagIndex.withDoc newDoc folder.tags
+                        links = LinkIndex.withDoc newDoc folder.links
+                        symbols = SymbolIndex.withDoc newDoc folder.symbols }
         | SingleFile ({ doc = existingDoc } as folder) ->
             if newDoc.path <> existingDoc.path then
                 failwith
@@ -352,10 +952,31 @@ module Folder =
 
             SingleFile { folder with doc = newDoc }
 
+    let tryReload (userConfig: option<Config>) (folder: Folder) : option<Folder> =
+        match folder with
+        | SingleFile _ -> Some(withConfig userConfig folder)
+        | MultiFile { name = name; root = root; docs = docs; templates = templates } ->
+            // Open docs may have unsaved changes, so they are kept rather than re-read
+            let openDocs =
+                Seq.append docs.Values templates.Values
+                |> Seq.filter (fun doc -> Option.isSome doc.version)
+
+            tryLoad userConfig name root
+            |> Option.map (fun reloaded -> Seq.fold (flip withDoc) reloaded openDocs)
+
     let withoutDoc (docPath: PathUri) : Folder -> option<Folder> =
         function
         | MultiFile folder ->
-            MultiFile { folder with docs = Map.remove docPath folder.docs }
+            let folder =
+                match Map.tryFind docPath folder.docs with
+                | Some oldDoc -> withoutDocIndexes oldDoc folder
+                | None -> folder
+
+            MultiFile
+                { folder with
+                    docs = Map.remove docPath folder.docs
+                    templates = Map.remove docPath folder.templates
+                    docSet = nextDocSet () }
             |> Some
         | SingleFile { doc = doc } ->
             if doc.path <> docPath then
@@ -364,12 +985,52 @@ module Folder =
             else
                 None
 
+    let assets: Folder -> Set<string> =
+        function
+        | SingleFile _ -> Set.empty
+        | MultiFile { assets = assets } -> assets
+
+    let withAsset (path: PathUri) (folder: Folder) : Folder =
+        match folder with
+        | MultiFile multi when RootPath.contains path multi.root ->
+            let exts = (configOrDefault folder).CoreMarkdownFileExtensions()
+            let pathFromRoot = assetPathFromRoot multi.root path.LocalPath
+
+            if
+                isMarkdownFile exts path.LocalPath
+                || isHiddenPath pathFromRoot
+                || isIgnored path folder
+            then
+                folder
+            else
+                MultiFile { multi with assets = Set.add pathFromRoot multi.assets }
+        | _ -> folder
+
+    let withoutAsset (path: PathUri) (folder: Folder) : Folder =
+        match folder with
+        | MultiFile multi when RootPath.contains path multi.root ->
+            let pathFromRoot = assetPathFromRoot multi.root path.LocalPath
+
+            // A deleted directory takes the files in it along
+            let isGone (asset: string) =
+                asset = pathFromRoot || asset.StartsWith(pathFromRoot + "/")
+
+            MultiFile { multi with assets = Set.filter (isGone >> not) multi.assets }
+        | _ -> folder
+
+    let tryLoadDoc (path: PathUri) (folder: Folder) : option<Doc> =
+        Doc.tryLoadNote ((configOrDefault folder).CoreMaxFileSizeKb()) (rootPath folder) path
+
     let closeDoc (docPath: PathUri) (folder: Folder) : option<Folder> =
         match folder with
-        | MultiFile { root = root } ->
-            match Doc.tryLoad root docPath with
-            | Some doc -> withDoc doc folder |> Some
-            | _ -> withoutDoc docPath folder
+        | MultiFile _ ->
+            // Ignored and unsaved docs are only known while they are open
+            if docPath.IsUntitled then
+                withoutDoc docPath folder
+            else
+                match tryLoadDoc docPath folder with
+                | Some doc when not (isIgnored docPath folder) -> withDoc doc folder |> Some
+                | _ -> withoutDoc docPath folder
         | SingleFile { doc = doc } ->
             if doc.path <> docPath then
                 failwith
@@ -402,11 +1063,66 @@ module Folder =
 
         docs folder |> Seq.tryFind isMatchingDoc
 
+    let templates: Folder -> seq<Doc> =
+        function
+        | SingleFile _ -> Seq.empty
+        | MultiFile { templates = templates } -> Map.values templates
+
     let docCount: Folder -> int =
         function
         | SingleFile _ -> 1
         | MultiFile { docs = docs } -> docs.Values.Count
 
+    let docSetVersion: Folder -> int64 =
+        function
+        | SingleFile _ -> 0L
+        | MultiFile { docSet = docSet } -> docSet
+
+    let ignoredCount: Folder -> int =
+        function
+        | SingleFile _ -> 0
+        | MultiFile { ignored = ignored } -> ignored
+
+    let skippedCount: Folder -> int =
+        function
+        | SingleFile _ -> 0
+        | MultiFile { skipped = skipped } -> skipped
+
+    /// Find all documents that mention a given tag.
+    let docsWithTag (tag: string) : Folder -> seq<Doc> =
+        function
+        | SingleFile { doc = doc } ->
+            let hasTag =
+                Index.tags (Doc.index doc) |> Array.exists (fun t -> t.data.name.text = tag)
+
+            if hasTag then Seq.singleton doc else Seq.empty
+        | MultiFile { docs = docs; tags = tags } ->
+            TagIndex.docsWithTag tag tags |> Seq.choose (fun path -> Map.tryFind path docs)
+
+    let headingsMatching (query: string) : Folder -> seq<Doc * Node<Heading>> =
+        let allHeadings (doc: Doc) =
+            Index.headings (Doc.index doc) |> Seq.map (fun heading -> doc, heading)
+
+        function
+        | SingleFile { doc = doc } -> allHeadings doc
+        | MultiFile { docs = docs; symbols = symbols } ->
+            match SymbolIndex.candidates query symbols with
+            | None -> Map.values docs |> Seq.collect allHeadings
+            | Some keys ->
+                keys
+                |> Seq.choose (fun (path, i) ->
+                    Map.tryFind path docs
+                    |> Option.map (fun doc -> doc, (Index.headings (Doc.index doc))[i]))
+
+    let docsLinkingTo (doc: Doc) : Folder -> seq<Doc> =
+        function
+        | SingleFile { doc = single } -> Seq.singleton single
+        | MultiFile { docs = docs; links = links } ->
+            // Links to headings of the same doc don't name it, e.g. [[#heading]]
+            LinkIndex.docsNaming doc links
+            |> Set.add doc.path
+            |> Seq.choose (fun path -> Map.tryFind path docs)
+
 type Workspace = { config: option<Config>; folders: Map<FolderId, Folder> }
 
 module Workspace =
@@ -432,7 +1148,10 @@ module Workspace =
     let tryFindFolderEnclosing (innerPath: PathUri) (workspace: Workspace) : option<Folder> =
         workspace.folders
         |> Map.tryPick (fun keyPath folder ->
-            if innerPath.LocalPath.StartsWith((FolderId.path keyPath).LocalPath) then
+            if innerPath.IsUntitled then
+                // Unsaved buffers are wherever they were put when opened
+                Folder.tryFindDocByPath innerPath folder |> Option.map (fun _ -> folder)
+            elif innerPath.LocalPath.StartsWith((FolderId.path keyPath).LocalPath) then
                 Some folder
             else
                 None)
@@ -476,3 +1195,14 @@ module Workspace =
 
     let docCount (workspace: Workspace) : int =
         workspace.folders.Values |> Seq.sumBy Folder.docCount
+
+    let ignoredCount (workspace: Workspace) : int =
+        workspace.folders.Values |> Seq.sumBy Folder.ignoredCount
+
+    let skippedCount (workspace: Workspace) : int =
+        workspace.folders.Values |> Seq.sumBy Folder.skippedCount
+
+    let textBytes (workspace: Workspace) : int64 =
+        workspace.folders.Values
+        |> Seq.collect Folder.docs
+        |> Seq.sumBy (fun doc -> 2L * int64 doc.text.content.Length)
@@ -0,0 +1,42 @@
+/// Zettelkasten IDs: timestamps that the names of notes start with, e.g. `202401121230 Idea.md`.
+/// With `core.zettel_id.enable`, links can name a note by its ID alone, e.g. `[[202401121230]]`.
+module Marksman.Zettel
+
+open System
+open System.Globalization
+
+open Marksman.Misc
+
+let private separators = [| '-'; '_'; '.' |]
+
+/// The part of a file stem that may be an ID in some format: its leading digits with the
+/// separators between them, e.g. `20240112-1230` of `20240112-1230 Idea`.
+let idPart (stem: string) : option<string> =
+    let length =
+        stem
+        |> Seq.takeWhile (fun c -> Char.IsDigit(c) || Array.contains c separators)
+        |> Seq.length
+
+    let part = stem.Substring(0, length).TrimEnd(separators)
+
+    if part = "" || (part.Length < stem.Length && Char.IsLetter(stem[part.Length])) then
+        None
+    else
+        Some part
+
+/// The ID of a note with the file stem, when its ID part is a timestamp in `format`.
+let tryId (format: string) (stem: string) : option<string> =
+    let isTimestamp part =
+        let parsed, _ =
+            DateTime.TryParseExact(part, format, CultureInfo.InvariantCulture, DateTimeStyles.None)
+
+        parsed
+
+    idPart stem |> Option.filter isTimestamp
+
+let newId (format: string) (now: DateTime) : string =
+    now.ToString(format, CultureInfo.InvariantCulture)
+
+/// The name of a new note with the ID, followed by the title when there's one.
+let noteName (id: string) (title: string) : string =
+    if title.IsWhitespace() then id else $"{id} {title.Trim()}"
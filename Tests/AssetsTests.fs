@@ -0,0 +1,167 @@
+module Marksman.AssetsTests
+
+open System.IO
+open Xunit
+
+open Marksman.Assets
+open Marksman.Helpers
+
+let withTempFile (name: string) (content: array<byte>) (f: string -> unit) =
+    let dir = Path.Join(Path.GetTempPath(), Path.GetRandomFileName())
+    Directory.CreateDirectory(dir) |> ignore
+    let path = Path.Join(dir, name)
+
+    try
+        File.WriteAllBytes(path, content)
+        f path
+    finally
+        Directory.Delete(dir, true)
+
+let pngHeader =
+    Array.concat [ [| 0x89uy; 0x50uy; 0x4Euy; 0x47uy; 0x0Duy; 0x0Auy; 0x1Auy; 0x0Auy |]
+                   [| 0uy; 0uy; 0uy; 13uy |]
+                   "IHDR"B
+                   [| 0uy; 0uy; 2uy; 128uy |] // width = 640
+                   [| 0uy; 0uy; 1uy; 224uy |] // height = 480
+                   [| 8uy; 6uy |] ]
+
+[<Fact>]
+let dimensions_png () =
+    withTempFile "a.png" pngHeader (fun path ->
+        Assert.Equal(Some(640, 480), tryReadDimensions path))
+
+[<Fact>]
+let dimensions_gif () =
+    let gif = Array.append "GIF89a"B [| 16uy; 0uy; 8uy; 0uy; 0uy; 0uy |]
+
+    withTempFile "a.gif" gif (fun path -> Assert.Equal(Some(16, 8), tryReadDimensions path))
+
+[<Fact>]
+let dimensions_unknownFormat () =
+    withTempFile "a.png" "not an image at all"B (fun path ->
+        Assert.Equal(None, tryReadDimensions path))
+
+[<Fact>]
+let describe_png () =
+    withTempFile "a.png" pngHeader (fun path ->
+        Assert.Equal(Some "`a.png`\n\nSize: 26 B\n\nDimensions: 640×480", describe path))
+
+[<Fact>]
+let relativeLink_fromSubdirectory () =
+    let doc = FakeDoc.Mk(path = "notes/sub/doc.md", contentLines = [||])
+    Assert.Equal("../../img/a%20b.png", relativeLink doc "img/a b.png")
+    Assert.Equal("pic.png", relativeLink doc "notes/sub/pic.png")
+    Assert.Equal("../img.png", relativeLink doc "notes/img.png")
+
+[<Fact>]
+let relativeLink_fromRoot () =
+    let doc = FakeDoc.Mk(path = "doc.md", contentLines = [||])
+    Assert.Equal("img/a.png", relativeLink doc "/img/a.png")
+
+[<Fact>]
+let unused_notLinked () =
+    let dir = Path.Join(Path.GetTempPath(), Path.GetRandomFileName())
+    Directory.CreateDirectory(Path.Join(dir, "img")) |> ignore
+
+    try
+        File.WriteAllText(Path.Join(dir, "a.md"), "# A\n![](img/used.png)\n![[embedded.png]]\n")
+
+        for name in [ "used.png"; "embedded.png"; "unused.png" ] do
+            File.WriteAllText(Path.Join(dir, "img", name), "")
+
+        let root = Workspace.RootPath.ofPath (Misc.PathUri.ofString dir)
+
+        match Workspace.Folder.tryLoad None "test" root with
+        | Some folder -> Assert.Equal<string>([| "img/unused.png" |], unused folder)
+        | None -> failwith "Can't load the folder"
+    finally
+        Directory.Delete(dir, true)
+
+let private unusedIn (files: list<string * string>) : array<string> =
+    let dir = Path.Join(Path.GetTempPath(), Path.GetRandomFileName())
+
+    try
+        for file, content in files do
+            let path = Path.Join(dir, file)
+            Directory.CreateDirectory(Path.GetDirectoryName(path)) |> ignore
+            File.WriteAllText(path, content)
+
+        let root = Workspace.RootPath.ofPath (Misc.PathUri.ofString dir)
+
+        match Workspace.Folder.tryLoad None "test" root with
+        | Some folder -> unused folder
+        | None -> failwith "Can't load the folder"
+    finally
+        Directory.Delete(dir, true)
+
+[<Fact>]
+let unused_onlyAttachmentsWithoutAssetDirs () =
+    let note = "---\ncover: img/cover.png\n---\n# A\n<img src=\"img/html.png\">\n"
+
+    let files =
+        [ "a.md", note
+          "img/cover.png", ""
+          "img/html.png", ""
+          "img/unused.png", ""
+          "refs.bib", ""
+          "Makefile", ""
+          "LICENSE", ""
+          "build.sh", "" ]
+
+    Assert.Equal<string>([| "img/unused.png" |], unusedIn files)
+
+[<Fact>]
+let unused_neverBibliographiesOrTemplates () =
+    let config =
+        "[core]\nbibliography = [\"refs.bib\"]\n[completion]\nasset_dirs = [\".\"]\n"
+
+    let files =
+        [ ".marksman.toml", config
+          "a.md", "# A\n"
+          "refs.bib", ""
+          "templates/daily.txt", ""
+          "build.sh", "" ]
+
+    Assert.Equal<string>([| "build.sh" |], unusedIn files)
+
+let private withFolder (files: list<string>) (f: string -> Workspace.Folder -> unit) =
+    let dir = Path.Join(Path.GetTempPath(), Path.GetRandomFileName())
+
+    try
+        for file in files do
+            let path = Path.Join(dir, file)
+            Directory.CreateDirectory(Path.GetDirectoryName(path)) |> ignore
+            File.WriteAllText(path, if file = ".gitignore" then "build/\n" else "")
+
+        let root = Workspace.RootPath.ofPath (Misc.PathUri.ofString dir)
+
+        match Workspace.Folder.tryLoad None "test" root with
+        | Some folder -> f dir folder
+        | None -> failwith "Can't load the folder"
+    finally
+        Directory.Delete(dir, true)
+
+[<Fact>]
+let files_skipIgnoredAndHidden () =
+    let files =
+        [ ".gitignore"; "a.md"; "img/a.png"; "build/b.png"; ".obsidian/c.png"; "doc.pdf" ]
+
+    withFolder files (fun _ folder ->
+        Assert.Equal<string>([| "doc.pdf"; "img/a.png" |], Assets.files folder)
+        Assert.Equal<string>([| "img/a.png" |], imageFiles folder))
+
+[<Fact>]
+let files_followCreatedAndDeleted () =
+    withFolder [ ".gitignore"; "a.md"; "img/a.png" ] (fun dir folder ->
+        let pathOf (file: string) = Misc.PathUri.ofString (Path.Join(dir, file))
+
+        let folder =
+            folder
+            |> Workspace.Folder.withAsset (pathOf "img/b.png")
+            |> Workspace.Folder.withAsset (pathOf "build/c.png")
+            |> Workspace.Folder.withAsset (pathOf "b.md")
+
+        Assert.Equal<string>([| "img/a.png"; "img/b.png" |], Assets.files folder)
+
+        let folder = Workspace.Folder.withoutAsset (pathOf "img") folder
+        Assert.Empty(Assets.files folder))
@@ -0,0 +1,59 @@
+module Marksman.BacklinksTests
+
+open Xunit
+
+open Marksman.Helpers
+open Marksman.Misc
+open Marksman.Workspace
+
+let private docA = FakeDoc.Mk(path = "a.md", contentLines = [| "# A"; "[[b]]" |])
+let private docC = FakeDoc.Mk(path = "c.md", contentLines = [| "# C"; "[B](b.md)" |])
+
+let private withB (lines: array<string>) =
+    let docB = FakeDoc.Mk(path = "b.md", contentLines = lines)
+    docB, FakeFolder.Mk([ docA; docB; docC ])
+
+[<Fact>]
+let update_create () =
+    let docB, folder = withB [| "# B"; "text" |]
+
+    match Backlinks.update folder docB with
+    | Some action ->
+        Assert.Equal("Create the backlinks", action.name)
+        Assert.Equal("\n\n## Backlinks\n\n- [A](a.md)\n- [C](c.md)\n", action.newText)
+        Assert.Equal(Range.Mk(2, 0, 2, 0), action.edit)
+    | None -> failwith "Expected the backlinks section"
+
+[<Fact>]
+let update_upToDate () =
+    let docB, folder =
+        withB [| "# B"; "text"; ""; "## Backlinks"; ""; "- [A](a.md)"; "- [C](c.md)"; "" |]
+
+    Assert.Equal(None, Backlinks.update folder docB)
+
+[<Fact>]
+let update_stale () =
+    let docB, folder = withB [| "# B"; "text"; ""; "## Backlinks"; ""; "- [Z](z.md)"; "" |]
+
+    match Backlinks.update folder docB with
+    | Some action ->
+        Assert.Equal("Update the backlinks", action.name)
+        Assert.Equal("## Backlinks\n\n- [A](a.md)\n- [C](c.md)\n", action.newText)
+        Assert.Equal(Range.Mk(3, 0, 6, 0), action.edit)
+    | None -> failwith "Expected the backlinks section"
+
+[<Fact>]
+let update_removeWithSeparator () =
+    let docB = FakeDoc.Mk(path = "b.md", contentLines = [| "# B"; "text"; ""; "## Backlinks"; "" |])
+
+    match Backlinks.update (FakeFolder.Mk([ docB ])) docB with
+    | Some action ->
+        Assert.Equal("Remove the backlinks", action.name)
+        Assert.Equal("", action.newText)
+        Assert.Equal(Range.Mk(2, 0, 4, 0), action.edit)
+    | None -> failwith "Expected the backlinks section to be removed"
+
+[<Fact>]
+let linkingDocs_skipsSections () =
+    let _, folder = withB [| "# B"; ""; "## Backlinks"; ""; "- [A](a.md)"; "" |]
+    Assert.Empty(Backlinks.linkingDocs folder docA)
@@ -0,0 +1,107 @@
+module Marksman.BibliographyTests
+
+open Xunit
+
+open Marksman.Bibliography
+
+module BibTeXTests =
+    [<Fact>]
+    let parse_entries () =
+        let content =
+            """
+@string{acm = "ACM"}
+
+@article{doe99,
+  author = {Doe, John and Roe, Jane},
+  title = {The {Markdown} Handbook},
+  journal = "Journal of " # acm,
+  year = 1999
+}
+
+@book(smith:2020, title = "Notes", date = {2020-05-01})
+"""
+
+        let entries = BibTeX.parse content
+
+        Assert.Equal<string>([| "doe99"; "smith:2020" |], entries |> Array.map (fun e -> e.key))
+
+        let doe = entries[0]
+        Assert.Equal("article", doe.kind)
+        Assert.Equal(Some "The Markdown Handbook", doe.title)
+        Assert.Equal<string>([ "Doe, John"; "Roe, Jane" ], doe.authors)
+        Assert.Equal(Some "1999", doe.year)
+        Assert.Equal(Some "Journal of ACM", doe.container)
+
+        Assert.Equal(Some "2020", entries[1].year)
+
+    [<Fact>]
+    let parse_skipsMalformed () =
+        let entries = BibTeX.parse "@misc{, title = {No key}}\n@misc{ok}"
+        Assert.Equal<string>([| "ok" |], entries |> Array.map (fun e -> e.key))
+
+module CslJsonTests =
+    [<Fact>]
+    let parse_entries () =
+        let content =
+            """[
+  { "id": "doe99",
+    "type": "article-journal",
+    "title": "The Markdown Handbook",
+    "author": [ { "family": "Doe", "given": "John" }, { "literal": "ACM" } ],
+    "issued": { "date-parts": [ [ 1999, 4 ] ] },
+    "container-title": "Journal" },
+  { "title": "No id" }
+]"""
+
+        let entries = CslJson.parse content
+
+        Assert.Equal(1, entries.Length)
+
+        let doe = entries[0]
+        Assert.Equal("doe99", doe.key)
+        Assert.Equal("article-journal", doe.kind)
+        Assert.Equal<string>([ "Doe, John"; "ACM" ], doe.authors)
+        Assert.Equal(Some "1999", doe.year)
+        Assert.Equal(Some "Journal", doe.container)
+
+module EntryTests =
+    [<Fact>]
+    let fmt_full () =
+        let entry =
+            { key = "doe99"
+              kind = "article"
+              title = Some "Title"
+              authors = [ "Doe, John" ]
+              year = Some "1999"
+              container = Some "Journal" }
+
+        Assert.Equal("`@doe99` (article)\n\nDoe, John (1999). *Title*. Journal.", Entry.fmt entry)
+
+    [<Fact>]
+    let fmt_keyOnly () =
+        let entry =
+            { key = "doe99"
+              kind = "misc"
+              title = None
+              authors = []
+              year = None
+              container = None }
+
+        Assert.Equal("`@doe99` (misc)", Entry.fmt entry)
+
+    [<Fact>]
+    let summary_authorsYearTitle () =
+        let entry =
+            { key = "doe99"
+              kind = "article"
+              title = Some "Title"
+              authors = [ "Doe, John"; "Jane Roe"; "Poe, Edgar" ]
+              year = Some "1999"
+              container = None }
+
+        Assert.Equal("Doe et al. (1999) Title", Entry.summary entry)
+        let twoAuthors = { entry with authors = entry.authors[..1] }
+        Assert.Equal("Doe and Roe (1999) Title", Entry.summary twoAuthors)
+
+        let keyOnly = { entry with authors = []; year = None; title = None }
+        Assert.Equal("article", Entry.summary keyOnly)
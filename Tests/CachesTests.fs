@@ -0,0 +1,30 @@
+// Caches are switched on and off for the whole process
+[<Xunit.Collection(Marksman.Helpers.GlobalState)>]
+module Marksman.CachesTests
+
+open Xunit
+
+[<Fact>]
+let applyLimit_turnsCachesOffAndBackOn () =
+    let mb = 1024L * 1024L
+
+    try
+        Assert.False(Caches.applyLimit 100 (50L * mb))
+        Assert.True(Caches.enabled ())
+
+        Assert.True(Caches.applyLimit 100 (150L * mb))
+        Assert.False(Caches.enabled ())
+
+        // Caches stay off until the heap is well under the limit
+        Assert.False(Caches.applyLimit 100 (90L * mb))
+        Assert.False(Caches.enabled ())
+
+        Assert.True(Caches.applyLimit 100 (70L * mb))
+        Assert.True(Caches.enabled ())
+    finally
+        Caches.applyLimit 0 0L |> ignore
+
+[<Fact>]
+let applyLimit_noLimit () =
+    Assert.False(Caches.applyLimit 0 System.Int64.MaxValue)
+    Assert.True(Caches.enabled ())
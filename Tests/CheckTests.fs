@@ -0,0 +1,42 @@
+module Marksman.CheckTests
+
+open Newtonsoft.Json.Linq
+open Xunit
+
+open Marksman.Diag
+open Marksman.Helpers
+open Marksman.Misc
+open Marksman.Workspace
+
+let private report () =
+    let doc1 = FakeDoc.Mk(path = "notes/a.md", contentLines = [| "# A"; "[[missing]]" |])
+    let doc2 = FakeDoc.Mk(path = "b.md", contentLines = [| "# B"; "[gone](gone.md)" |])
+    let folder = FakeFolder.Mk([ doc1; doc2 ])
+
+    Map.ofList [ Folder.id folder, FolderDiag.mk folder ] |> Report.ofWorkspaceDiag
+
+[<Fact>]
+let fmtHuman_relativePaths () =
+    Assert.Equal(
+        concatLines
+            [| "b.md:2:1: warning: Link to non-existent document 'gone.md' [broken-link]"
+               "notes/a.md:2:1: error: Link to non-existent document 'missing' [broken-link]"
+               "1 errors, 1 warnings, 0 notes" |],
+        Check.fmtHuman dummyRoot (report ())
+    )
+
+[<Fact>]
+let fmtSarif_results () =
+    let log = JObject.Parse(Check.fmtSarif dummyRoot (report ()))
+    let results = log.SelectTokens("$.runs[0].results[*]") |> Array.ofSeq
+
+    Assert.Equal<string * string * string>(
+        [| "broken-link", "warning", "b.md"; "broken-link", "error", "notes/a.md" |],
+        results
+        |> Array.map (fun result ->
+            result.Value<string>("ruleId"),
+            result.Value<string>("level"),
+            result
+                .SelectToken("locations[0].physicalLocation.artifactLocation.uri")
+                .Value<string>())
+    )
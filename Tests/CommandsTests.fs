@@ -0,0 +1,110 @@
+module Marksman.CommandsTests
+
+open System.IO
+
+open Ionide.LanguageServerProtocol.Types
+open Newtonsoft.Json.Linq
+open Xunit
+
+open Marksman.Commands
+open Marksman.Helpers
+open Marksman.State
+open Marksman.Workspace
+
+let private stateOf (applyEdit: bool) (docs: seq<Doc>) =
+    let workspaceCaps =
+        { ApplyEdit = Some applyEdit
+          WorkspaceEdit = None
+          DidChangeConfiguration = None
+          DidChangeWatchedFiles = None
+          Configuration = None
+          Symbol = None
+          SemanticTokens = None
+          InlayHint = None }
+
+    let client =
+        { info = None
+          caps =
+            { Workspace = Some workspaceCaps
+              TextDocument = None
+              Window = None
+              General = None
+              Experimental = None } }
+
+    State.mk client (Workspace.ofFolders None [ FakeFolder.Mk(docs) ])
+
+[<Fact>]
+let names_unique () =
+    Assert.Equal(names.Length, Array.distinct names |> Array.length)
+    Assert.Contains(CodeActions.createNoteCommand, names)
+
+[<Fact>]
+let createNote_outsideWorkspace () =
+    let dir = Path.Join(Path.GetTempPath(), Path.GetRandomFileName())
+    let path = Path.Join(dir, "note.md")
+    let args = [| JValue(path) :> JToken; JValue("# Note") |]
+
+    match createNote.run (stateOf true []) args with
+    | Error _ -> Assert.False(Directory.Exists(dir))
+    | other -> failwith $"Unexpected outcome: {other}"
+
+[<Fact>]
+let updateToc_appliesEdit () =
+    let doc = FakeDoc.Mk(path = "a.md", contentLines = [| "# A"; "## B"; "## C" |])
+    let args = [| JValue(Doc.uri doc) :> JToken |]
+
+    match updateToc.run (stateOf true [ doc ]) args with
+    | Ok { effects = [ ApplyEdit (_, edit) ] } ->
+        Assert.Equal<DocumentUri>([ Doc.uri doc ], edit.Changes.Value |> Map.keys |> List.ofSeq)
+    | other -> failwith $"Unexpected outcome: {other}"
+
+    // Without workspace/applyEdit the client gets the edit in the result
+    match updateToc.run (stateOf false [ doc ]) args with
+    | Ok { effects = []; result = result } -> Assert.NotNull(result.SelectToken("changes"))
+    | other -> failwith $"Unexpected outcome: {other}"
+
+[<Fact>]
+let updateToc_wrongArguments () =
+    let doc = FakeDoc.Mk(path = "a.md", contentLines = [| "# A" |])
+
+    match updateToc.run (stateOf true [ doc ]) [||] with
+    | Error _ -> ()
+    | other -> failwith $"Unexpected outcome: {other}"
+
+[<Fact>]
+let openDailyNote_existing () =
+    let name = System.DateTime.Today.AddDays(-1.0).ToString("yyyy-MM-dd")
+    let doc = FakeDoc.Mk(path = $"{name}.md", contentLines = [| $"# {name}" |])
+    let args = [| JValue(-1) :> JToken |]
+
+    match openDailyNote.run (stateOf true [ doc ]) args with
+    | Ok { update = None; result = result } ->
+        Assert.Equal(Doc.uri doc, result.SelectToken("uri").Value<string>())
+    | other -> failwith $"Unexpected outcome: {other}"
+
+[<Fact>]
+let openDailyNote_wrongArguments () =
+    let args = [| JValue(true) :> JToken |]
+
+    match openDailyNote.run (stateOf true []) args with
+    | Error _ -> ()
+    | other -> failwith $"Unexpected outcome: {other}"
+
+[<Fact>]
+let findAndReplace_acrossNotes () =
+    let a = FakeDoc.Mk(path = "a.md", contentLines = [| "# A"; "#draft" |])
+    let b = FakeDoc.Mk(path = "b.md", contentLines = [| "# B" |])
+    let args = [| JValue("#draft") :> JToken; JValue("#final") |]
+
+    match findAndReplace.run (stateOf true [ a; b ]) args with
+    | Ok { effects = [ ApplyEdit (_, edit) ] } ->
+        Assert.Equal<DocumentUri>([ Doc.uri a ], edit.Changes.Value |> Map.keys |> List.ofSeq)
+    | other -> failwith $"Unexpected outcome: {other}"
+
+[<Fact>]
+let findAndReplace_invalidRegex () =
+    let args = [| JValue("(") :> JToken; JValue(""); JValue(true) |]
+
+    match findAndReplace.run (stateOf true []) args with
+    | Error _ -> ()
+    | other -> failwith $"Unexpected outcome: {other}"
@@ -1,6 +1,7 @@
 module Marksman.ComplTests
 
 open Ionide.LanguageServerProtocol.Types
+open Newtonsoft.Json.Linq
 open Snapper.Attributes
 open Xunit
 open Snapper
@@ -367,6 +368,22 @@ module Candidates =
 
         checkSnapshot (findCandidates folder (Doc.path doc1) (Position.Mk(1, 3)))
 
+    [<Fact>]
+    let inline_encodedPath () =
+        let doc1 =
+            FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[](<my>)"; "[](my)" |])
+
+        let doc2 = FakeDoc.Mk(path = "my note.md", contentLines = [| "# My note" |])
+        let folder = FakeFolder.Mk([ doc1; doc2 ])
+
+        let newTexts line col =
+            findCandidates folder (Doc.path doc1) (Position.Mk(line, col))
+            |> Array.choose (fun item -> item.TextEdit |> Option.map (fun edit -> edit.NewText))
+
+        // Spaces can be in destinations in angle brackets, elsewhere they are encoded
+        Assert.Contains("/my note.md", newTexts 1 5)
+        Assert.Contains("/my%20note.md", newTexts 2 4)
+
     [<Fact>]
     let partialWikiDoc () =
         let doc1 =
@@ -390,6 +407,18 @@ module Candidates =
 
         checkSnapshot (findCandidates folder (Doc.path doc1) (Position.Mk(1, 3)))
 
+    [<Fact>]
+    let partialWikiHeading_AllDocs () =
+        let doc1 =
+            FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[[#h2"; "## H2 here" |])
+
+        let doc2 =
+            FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2"; "## H2.1"; "## Other" |])
+
+        let folder = FakeFolder.Mk([ doc1; doc2 ])
+
+        checkSnapshot (findCandidates folder (Doc.path doc1) (Position.Mk(1, 5)))
+
     [<Fact>]
     let partialWikiDocHeading () =
         let doc1 =
@@ -589,3 +618,532 @@ module Candidates =
         [<Fact>]
         let tagWithName () =
             checkSnapshot (findCandidates folder (Doc.path doc1) (Position.Mk(2, 15)))
+
+        [<Fact>]
+        let tagUsagesInDetailAndSortText () =
+            let doc3 =
+                FakeDoc.Mk(path = "doc3.md", contentLines = [| "#anotherTag #anotherTag #"; "#tag" |])
+
+            let folder = FakeFolder.Mk([ doc1; doc2; doc3 ])
+
+            let byLabel =
+                findCandidates folder (Doc.path doc3) (Position.Mk(0, 25))
+                |> Array.map (fun ci -> ci.Label, ci)
+                |> Map.ofArray
+
+            Assert.Equal(Some "3 usages", byLabel["anotherTag"].Detail)
+            Assert.Equal(Some "1 usage", byLabel["otherDocTag"].Detail)
+
+            let sorted =
+                byLabel.Values
+                |> Seq.sortBy (fun ci -> ci.SortText)
+                |> Seq.map (fun ci -> ci.Label)
+                |> Seq.take 2
+                |> Array.ofSeq
+
+            Assert.Equal<string>([| "anotherTag"; "tag" |], sorted)
+
+module CodeCompletion =
+    [<Fact>]
+    let noCompletionInsideCode () =
+        let doc1 =
+            FakeDoc.Mk(
+                path = "doc1.md",
+                contentLines = [| "# Doc 1"; "```"; "[[d"; "```"; "`[[d`" |]
+            )
+
+        let doc2 = FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2" |])
+        let folder = FakeFolder.Mk([ doc1; doc2 ])
+
+        Assert.Empty(findCandidates folder (Doc.path doc1) (Position.Mk(2, 3)))
+        Assert.Empty(findCandidates folder (Doc.path doc1) (Position.Mk(4, 3)))
+
+module SnippetTests =
+    [<Fact>]
+    let placeholder_escaped () =
+        Assert.Equal("${1:Cost \\$5 \\}}", Snippet.placeholder 1 "Cost $5 }")
+
+    [<Fact>]
+    let toPlainText_placeholdersAndTabStops () =
+        Assert.Equal("[Doc 2](/doc2.md)", Snippet.toPlainText "[${1:Doc 2}](/doc2.md)$0")
+
+    [<Fact>]
+    let toPlainText_roundTrip () =
+        let text = "[a $b} \\c](/x.md)"
+        Assert.Equal(text, Snippet.toPlainText (Snippet.escape text))
+
+    [<Fact>]
+    let partialWiki_isSnippet () =
+        let doc1 = FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[[" |])
+        let doc2 = FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2" |])
+        let folder = FakeFolder.Mk([ doc1; doc2 ])
+
+        let item = findCandidates folder (Doc.path doc1) (Position.Mk(1, 2)) |> Array.exactlyOne
+
+        Assert.Equal(Some InsertTextFormat.Snippet, item.InsertTextFormat)
+        Assert.Equal(Some "[[doc-2]]", item.TextEdit |> Option.map (fun edit -> edit.NewText))
+
+module FolderTests =
+    let private mkFolder () =
+        let docs =
+            [ "index.md"
+              "projects/alpha/a.md"
+              "projects/alpha/b.md"
+              "projects/beta.md"
+              "old/x.md" ]
+            |> List.map (fun path -> FakeDoc.Mk(path = path, contentLines = [| "# Doc" |]))
+
+        FakeFolder.Mk(docs)
+
+    [<Fact>]
+    let findFolderCandidates_topLevel () =
+        Assert.Equal<string * int>(
+            [| "old/", 1; "projects/", 3 |],
+            Candidates.findFolderCandidates (mkFolder ()) ""
+        )
+
+    [<Fact>]
+    let findFolderCandidates_nested () =
+        let folder = mkFolder ()
+        let expected = [| "projects/alpha/", 2 |]
+
+        Assert.Equal<string * int>(expected, Candidates.findFolderCandidates folder "/projects/")
+        Assert.Equal<string * int>(expected, Candidates.findFolderCandidates folder "projects/al")
+        Assert.Empty(Candidates.findFolderCandidates folder "projects/x")
+
+    [<Fact>]
+    let partialInline_leavesLinkOpen () =
+        let doc1 = FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[x](/pro" |])
+        let doc2 = FakeDoc.Mk(path = "projects/a.md", contentLines = [| "# A" |])
+        let folder = FakeFolder.Mk([ doc1; doc2 ])
+
+        let item =
+            findCandidates folder (Doc.path doc1) (Position.Mk(1, 8))
+            |> Array.filter (fun item -> item.Kind = Some CompletionItemKind.Folder)
+            |> Array.exactlyOne
+
+        Assert.Equal("projects/", item.Label)
+        Assert.Equal(Some "1 note", item.Detail)
+        Assert.Equal(Some "[x](/projects/", item.TextEdit |> Option.map (fun edit -> edit.NewText))
+
+module AssetTests =
+    let private doc = FakeDoc.Mk(path = "notes/doc.md", contentLines = [| "# Doc" |])
+    let private assets = [| "img/cat.png"; "notes/report.pdf" |]
+
+    [<Fact>]
+    let findAssetCandidates_relative () =
+        Assert.Equal<string * string>(
+            [| "../img/cat.png", "img/cat.png" |],
+            Candidates.findAssetCandidates doc "../cat" assets
+        )
+
+        Assert.Equal<string * string>(
+            [| "report.pdf", "notes/report.pdf" |],
+            Candidates.findAssetCandidates doc "rep" assets
+        )
+
+    [<Fact>]
+    let findAssetCandidates_rootRelative () =
+        Assert.Equal<string * string>(
+            [| "/img/cat.png", "img/cat.png" |],
+            Candidates.findAssetCandidates doc "/img" assets
+        )
+
+module DailyNoteTests =
+    let private today = System.DateTime(2024, 5, 1)
+
+    [<Fact>]
+    let dates_keywordsAndTypedDate () =
+        let dates = DailyNote.dates "yyyy-MM-dd"
+
+        Assert.Equal<string * System.DateTime>(
+            [ "yesterday", today.AddDays(-1.0) ],
+            dates today "y"
+        )
+
+        Assert.Equal<string * System.DateTime>(
+            [ "2024-02-29", System.DateTime(2024, 2, 29) ],
+            dates today "2024-02-29"
+        )
+
+        Assert.Empty(dates today "")
+
+    let private completeWith (config: Config.Config) (input: string) =
+        let doc1 = FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; $"[[{input}" |])
+        let daily = FakeDoc.Mk(path = "2024-05-01.md", contentLines = [| "# 2024-05-01" |])
+        let folder = FakeFolder.Mk([ doc1; daily ], config = config)
+        let pos = Position.Mk(1, 2 + input.Length)
+        let compl = findCompletableAtPos doc1 pos |> Option.get
+
+        Completions.dailyNotes folder today pos compl input
+
+    let private completeAt = completeWith Config.Config.Default
+
+    [<Fact>]
+    let dailyNotes_existingAndNew () =
+        let items = completeAt "to"
+
+        Assert.Equal<string>([| "today"; "tomorrow" |], items |> Array.map (fun item -> item.Label))
+
+        Assert.Equal<string>(
+            [| "[[2024-05-01]]"; "[[2024-05-02]]" |],
+            items |> Array.map (fun item -> item.TextEdit.Value.NewText)
+        )
+
+        Assert.Equal(Some "2024-05-02 (new note)", items[1].Detail)
+        Assert.Equal(None, items[1].Command)
+
+    [<Fact>]
+    let dailyNotes_createdOnAccept () =
+        let config = { Config.Config.Empty with complDailyNoteCreate = Some true }
+        let item = completeWith config "tom" |> Array.exactlyOne
+
+        match item.Command, item.Data with
+        | Some { Command = command; Arguments = Some [| path; _ |] }, None ->
+            Assert.Equal(NewNote.createCommandName, command)
+            Assert.EndsWith("2024-05-02.md", path.Value<string>())
+        | other -> failwith $"Unexpected command and data: {other}"
+
+    [<Fact>]
+    let dailyNotes_dateSnippet () =
+        let item = completeAt "da" |> Array.exactlyOne
+
+        let newText = item.TextEdit |> Option.map (fun edit -> edit.NewText)
+
+        Assert.Equal(Some InsertTextFormat.Snippet, item.InsertTextFormat)
+        Assert.Equal(Some "[[${1:2024-05-01}]]", newText)
+
+    [<Fact>]
+    let tryFindDoc_prefersDir () =
+        let elsewhere = FakeDoc.Mk(path = "2024-05-01.md", contentLines = [| "# Elsewhere" |])
+        let daily = FakeDoc.Mk(path = "journal/2024-05-01.md", contentLines = [| "# Daily" |])
+
+        let config = { Config.Config.Empty with complDailyNoteDir = Some "journal/" }
+        let folder = FakeFolder.Mk([ elsewhere; daily ], config = config)
+
+        Assert.Equal("journal/2024-05-01", DailyNote.pathFromRoot folder "2024-05-01")
+
+        Assert.Equal(
+            Some(Doc.path daily),
+            DailyNote.tryFindDoc folder "2024-05-01" |> Option.map Doc.path
+        )
+
+module PromptTests =
+    [<Fact>]
+    let ofCompletable_normalized () =
+        let doc = FakeDoc.Mk(path = "doc1.md", contentLines = [| "[[cafe\u0301" |])
+        let pos = Position.Mk(0, 7)
+        let prompt = findCompletableAtPos doc pos |> Option.bind (Prompt.ofCompletable pos)
+
+        Assert.Equal(Some(WikiDoc "caf\u00e9"), prompt)
+
+module TriggerTests =
+    [<Fact>]
+    let isTriggeredBy_kinds () =
+        Assert.True(Prompt.isTriggeredBy '(' (InlineDoc ""))
+        Assert.False(Prompt.isTriggeredBy '(' (WikiDoc ""))
+        Assert.True(Prompt.isTriggeredBy '#' (Tag ""))
+        Assert.False(Prompt.isTriggeredBy '@' (Reference ""))
+
+    [<Fact>]
+    let findTriggeredCandidates_gated () =
+        let doc1 = FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[[" |])
+        let doc2 = FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2" |])
+        let folder = FakeFolder.Mk([ doc1; doc2 ])
+
+        let find =
+            findTriggeredCandidates (Session.Store()) folder (Doc.path doc1) (Position.Mk(1, 2))
+
+        Assert.Empty(find (Some '('))
+        Assert.Equal(1, (find (Some '[')).Length)
+        Assert.Equal(1, (find None).Length)
+
+module PreviewTests =
+    [<Fact>]
+    let summarize_skipsFrontMatter () =
+        let content = "---\ntitle: T\n---\n\n# Doc\nText"
+        Assert.Equal("# Doc\nText", Preview.summarize 10 100 content)
+
+    [<Fact>]
+    let summarize_truncates () =
+        Assert.Equal("a\nb…", Preview.summarize 2 100 "a\nb\nc")
+        Assert.Equal("abc…", Preview.summarize 2 3 "abcdef")
+
+    [<Fact>]
+    let backlinkCount_otherLinkingDocs () =
+        let doc1 = FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[[doc-1]]" |])
+
+        let doc2 =
+            FakeDoc.Mk(path = "doc2.md", contentLines = [| "[[doc-1]]"; "[[doc-1]]"; "[[doc3]]" |])
+
+        let doc3 = FakeDoc.Mk(path = "doc3.md", contentLines = [| "[[doc-2]]" |])
+        let folder = FakeFolder.Mk([ doc1; doc2; doc3 ])
+
+        Assert.Equal(1, Preview.backlinkCount folder doc1)
+
+module LinkStyleTests =
+    let private complete (linkStyle: Config.ComplLinkStyle) =
+        let doc1 = FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[do" |])
+        let doc2 = FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2" |])
+        let config = { Config.Config.Empty with complLinkStyle = Some linkStyle }
+        let folder = FakeFolder.Mk([ doc1; doc2 ], config = config)
+
+        findCandidates folder (Doc.path doc1) (Position.Mk(1, 3))
+
+    let private newText (item: CompletionItem) =
+        item.TextEdit |> Option.map (fun edit -> edit.NewText)
+
+    [<Fact>]
+    let auto_noDocs () = Assert.Empty(complete Config.ComplLinkStyle.Auto)
+
+    [<Fact>]
+    let wiki () =
+        let item = complete Config.ComplLinkStyle.Wiki |> Array.exactlyOne
+        Assert.Equal(Some "[[doc-2]]", newText item)
+
+    [<Fact>]
+    let markdown () =
+        let item = complete Config.ComplLinkStyle.Markdown |> Array.exactlyOne
+        Assert.Equal(Some "[Doc 2](/doc2.md)", newText item)
+
+    [<Fact>]
+    let reference_addsLinkDef () =
+        let item = complete Config.ComplLinkStyle.Reference |> Array.exactlyOne
+        Assert.Equal(Some "[Doc 2]", newText item)
+
+        let linkDef = item.AdditionalTextEdits |> Option.map (Array.map (fun edit -> edit.NewText))
+
+        Assert.Equal(Some [| "\n\n[Doc 2]: /doc2.md\n" |], linkDef)
+
+module NewNoteTests =
+    [<Fact>]
+    let exists_byTitleOrPath () =
+        let doc = FakeDoc.Mk(path = "sub/my note.md", contentLines = [| "# Idea" |])
+        let folder = FakeFolder.Mk([ doc ])
+
+        Assert.True(NewNote.exists folder "idea")
+        Assert.True(NewNote.exists folder "My Note")
+        Assert.True(NewNote.exists folder "sub/my-note")
+        Assert.False(NewNote.exists folder "other")
+
+    let private completeAt (line: string) =
+        let doc1 = FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; line |])
+        let doc2 = FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Idea" |])
+        let config = { Config.Config.Empty with complCreateNoteEnable = Some true }
+        let folder = FakeFolder.Mk([ doc1; doc2 ], config = config)
+
+        findCandidates folder (Doc.path doc1) (Position.Mk(1, line.Length))
+        |> Array.filter (fun item -> item.Label.StartsWith("Create note"))
+
+    let private createArgs (item: CompletionItem) =
+        match item.Command with
+        | Some { Command = command; Arguments = Some [| path; content |] } ->
+            Assert.Equal(NewNote.createCommandName, command)
+            path.Value<string>(), content.Value<string>()
+        | other -> failwith $"Unexpected command: {other}"
+
+    [<Fact>]
+    let wiki_newName () =
+        let item = completeAt "[[new idea" |> Array.exactlyOne
+        let newText = item.TextEdit |> Option.map (fun edit -> edit.NewText)
+        let path, content = createArgs item
+
+        Assert.Equal("Create note 'new idea'", item.Label)
+        Assert.Equal(Some "[[new idea]]", newText)
+        Assert.EndsWith("new idea.md", path)
+        Assert.Equal("# new idea\n", content)
+
+    [<Fact>]
+    let resolve_createsNoFile () =
+        let item = completeAt "[[new idea" |> Array.exactlyOne
+        let path, _ = createArgs item
+        let folder = FakeFolder.Mk([ FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1" |]) ])
+
+        Preview.resolve folder item |> ignore
+        Assert.False(System.IO.File.Exists(path))
+
+    [<Fact>]
+    let wiki_existingName () = Assert.Empty(completeAt "[[idea")
+
+    [<Fact>]
+    let inline_newPath () =
+        let item = completeAt "[](notes/idea" |> Array.exactlyOne
+        let newText = item.TextEdit |> Option.map (fun edit -> edit.NewText)
+
+        Assert.Equal("Create note 'notes/idea'", item.Label)
+        Assert.Equal(Some "notes/idea.md (new note)", item.Detail)
+        Assert.Equal(Some "[${1:idea}](/notes/idea.md)", newText)
+
+module FootnoteAndBlockTests =
+    [<Fact>]
+    let footnote_partial () =
+        let doc =
+            FakeDoc.Mk(
+                path = "doc1.md",
+                contentLines = [| "Text [^f"; ""; "[^fn1]: First note"; "[link]: /url" |]
+            )
+
+        let folder = FakeFolder.Mk([ doc ])
+        let item = findCandidates folder (Doc.path doc) (Position.Mk(0, 8)) |> Array.exactlyOne
+        let newText = item.TextEdit |> Option.map (fun edit -> edit.NewText)
+
+        Assert.Equal("^fn1", item.Label)
+        Assert.Equal(Some "[^fn1]", newText)
+        Assert.Equal(Some "footnote", item.Detail)
+
+    [<Fact>]
+    let block_otherDoc () =
+        let doc1 = FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[[doc2#^cl" |])
+
+        let doc2 =
+            FakeDoc.Mk(
+                path = "doc2.md",
+                contentLines = [| "# Doc 2"; ""; "Some claim ^claim1"; ""; "Other ^other" |]
+            )
+
+        let folder = FakeFolder.Mk([ doc1; doc2 ])
+
+        let item =
+            findCandidates folder (Doc.path doc1) (Position.Mk(1, 10)) |> Array.exactlyOne
+
+        let newText = item.TextEdit |> Option.map (fun edit -> edit.NewText)
+
+        Assert.Equal("Doc 2 / ^claim1", item.Label)
+        Assert.Equal(Some "[[doc-2#^claim1]]", newText)
+        Assert.Equal(Some(Documentation.String "Some claim"), item.Documentation)
+
+    [<Fact>]
+    let isTriggeredBy_caret () =
+        Assert.True(Prompt.isTriggeredBy '^' (Footnote ""))
+        Assert.True(Prompt.isTriggeredBy '^' (WikiBlockInOtherDoc("doc", "")))
+        Assert.False(Prompt.isTriggeredBy '^' (Reference ""))
+
+module AbbreviationTests =
+    [<Fact>]
+    let wikiDoc_initialism () =
+        let doc1 = FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[[pkm" |])
+
+        let doc2 =
+            FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Personal Knowledge Management" |])
+
+        let folder = FakeFolder.Mk([ doc1; doc2 ])
+        let labels =
+            findCandidates folder (Doc.path doc1) (Position.Mk(1, 5))
+            |> Array.map (fun item -> item.Label)
+
+        Assert.Contains("Personal Knowledge Management", labels)
+
+// Counts cache lookups, which other tests turn off for the whole process
+[<Collection(GlobalState)>]
+module SessionTests =
+    [<Fact>]
+    let cache_narrowsWhileTyping () =
+        let docAt (input: string) =
+            FakeDoc.Mk(path = "doc1.md", contentLines = [| $"[[{input}" |])
+
+        let cache = Session.Cache<string>()
+        let lookups = ref 0
+
+        // Like the server, every keystroke puts the edited doc in a new folder
+        let find (folder: Folder) (input: string) =
+            let doc = docAt input
+            let folder = Folder.withDoc doc folder
+            let compl = findCompletableAtPos doc (Position.Mk(0, 2 + input.Length)) |> Option.get
+            let matches (cand: string) = cand.StartsWith(input)
+
+            let lookup () =
+                lookups.Value <- lookups.Value + 1
+                [| "doc"; "dog"; "cat" |] |> Array.filter matches
+
+            cache.Find(folder, doc, compl, input, lookup, matches)
+
+        let folder = FakeFolder.Mk([ docAt "d" ])
+
+        Assert.Equal<string>([| "doc"; "dog" |], find folder "d")
+        Assert.Equal<string>([| "doc" |], find folder "doc")
+        Assert.Equal(1, lookups.Value)
+
+        Assert.Equal<string>([| "cat" |], find folder "c")
+        Assert.Equal(2, lookups.Value)
+
+        // A new doc may be a candidate, so the candidates are looked up again
+        let withNewDoc = Folder.withDoc (FakeDoc.Mk(path = "doc2.md", contentLines = [||])) folder
+        Assert.Equal<string>([| "cat" |], find withNewDoc "ca")
+        Assert.Equal(3, lookups.Value)
+
+    [<Fact>]
+    let maxItems_keepsBestMatches () =
+        let doc1 = FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[[" |])
+
+        let others =
+            [ for i in 2..5 -> FakeDoc.Mk(path = $"doc{i}.md", contentLines = [| $"# Doc {i}" |]) ]
+
+        let config = { Config.Config.Empty with complMaxItems = Some 2 }
+        let folder = FakeFolder.Mk(doc1 :: others, config = config)
+
+        let labels =
+            findCandidates folder (Doc.path doc1) (Position.Mk(1, 2))
+            |> Array.map (fun item -> item.Label)
+
+        Assert.Equal<string>([| "Doc 2"; "Doc 3" |], labels)
+
+module CitationTests =
+    let private doc = FakeDoc.Mk(path = "doc1.md", contentLines = [| "[@do" |])
+
+    let private citationItem () =
+        let entry: Bibliography.Entry =
+            { key = "doe99"
+              kind = "article"
+              title = Some "Title"
+              authors = [ "Doe, John" ]
+              year = Some "1999"
+              container = None }
+
+        let pos = Position.Mk(0, 4)
+        let compl = findCompletableAtPos doc pos |> Option.get
+        Completions.citation doc pos compl entry |> Option.get
+
+    [<Fact>]
+    let citation_detailAndData () =
+        let item = citationItem ()
+
+        Assert.Equal(Some "Doe (1999) Title", item.Detail)
+        Assert.Equal(Some "doe99", ItemData.tryGet ItemData.citationKey item)
+
+    [<Fact>]
+    let resolve_formatsEntry () =
+        let bib = System.IO.Path.Join(System.IO.Path.GetTempPath(), $"{System.Guid.NewGuid()}.bib")
+        System.IO.File.WriteAllText(bib, "@article{doe99, title = {Title}, year = 1999}")
+
+        try
+            let config = { Config.Config.Empty with coreBibliography = Some [| bib |] }
+            let folder = FakeFolder.Mk([ doc ], config = config)
+
+            let documentation =
+                match (Preview.resolve folder (citationItem ())).Documentation with
+                | Some (Documentation.Markup markup) -> Some markup.Value
+                | _ -> None
+
+            Assert.Equal(Some "`@doe99` (article)\n\n(1999). *Title*.", documentation)
+        finally
+            System.IO.File.Delete(bib)
+
+module DocLabelTests =
+    let private labelsFor (label: Config.ComplDocLabel) =
+        let doc1 = FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[[" |])
+        let doc2 = FakeDoc.Mk(path = "sub/doc2.md", contentLines = [| "# Doc 2" |])
+        let config = { Config.Config.Empty with complDocLabel = Some label }
+        let folder = FakeFolder.Mk([ doc1; doc2 ], config = config)
+
+        findCandidates folder (Doc.path doc1) (Position.Mk(1, 2))
+        |> Array.map (fun item -> item.Label)
+
+    [<Fact>]
+    let labels () =
+        Assert.Equal<string>([| "Doc 2" |], labelsFor Config.ComplDocLabel.Title)
+        Assert.Equal<string>([| "doc2.md" |], labelsFor Config.ComplDocLabel.FileName)
+
+        Assert.Equal<string>(
+            [| "Doc 2 (doc2.md)" |],
+            labelsFor Config.ComplDocLabel.TitleAndFileName
+        )
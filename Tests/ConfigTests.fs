@@ -58,6 +58,35 @@ wiki.style = "file-stem"
 
     Assert.Equal(Some expected, actual)
 
+[<Fact>]
+let testParse_diagRules () =
+    let content =
+        """
+[diagnostics.rules]
+broken-link = "error"
+orphan = "hint"
+dup-heading = "nonsense"
+"""
+
+    let actual = Config.tryParse content
+
+    let rules = Map.ofList [ "broken-link", DiagSeverity.Error; "orphan", DiagSeverity.Hint ]
+    let expected = { Config.Empty with diagRules = Some rules }
+
+    Assert.Equal(Some expected, actual)
+
+[<Fact>]
+let testMerge_diagRules () =
+    let rules (pairs: list<string * DiagSeverity>) =
+        { Config.Empty with diagRules = Some(Map.ofList pairs) }
+
+    let hi = rules [ "broken-link", DiagSeverity.Off ]
+    let low = rules [ "broken-link", DiagSeverity.Error; "orphan", DiagSeverity.Hint ]
+
+    let merged = Config.merge hi low
+    Assert.Equal(Some DiagSeverity.Off, merged.DiagRuleSeverity("broken-link"))
+    Assert.Equal(Some DiagSeverity.Hint, merged.DiagRuleSeverity("orphan"))
+
 [<Fact>]
 let testParse_broken_0 () =
     let content =
@@ -111,3 +140,107 @@ let testDefault () =
     let content = using (new StreamReader(content)) (fun f -> f.ReadToEnd())
     let parsed = Config.tryParse content
     Assert.Equal(Some Config.Default, parsed)
+
+[<Fact>]
+let testParse_frontmatterSchema () =
+    let content =
+        """
+[diagnostics.frontmatter]
+required = ["date"]
+types = { date = "date", tags = "string[]", size = "huge" }
+
+[diagnostics.frontmatter.dirs."notes/journal/"]
+types = { mood = "string" }
+"""
+
+    let actual = Config.tryParse content |> Option.get
+
+    let journal = { required = None; types = Map.ofList [ "mood", FieldType.String ] }
+
+    Assert.Equal(Some [| "date" |], actual.diagFrontmatterRequired)
+    Assert.Equal(Some(Map.ofList [ "notes/journal", journal ]), actual.diagFrontmatterDirs)
+
+    let schema = actual.DiagFrontmatterSchema("notes/journal/today.md")
+    Assert.Equal(Some [| "date" |], schema.required)
+
+    Assert.Equal<Map<string, FieldType>>(
+        Map.ofList
+            [ "date", FieldType.Date
+              "mood", FieldType.String
+              "tags", FieldType.List FieldType.String ],
+        schema.types
+    )
+
+    Assert.Equal<Map<string, FieldType>>(
+        Map.ofList [ "date", FieldType.Date; "tags", FieldType.List FieldType.String ],
+        actual.DiagFrontmatterSchema("notes/other.md").types
+    )
+
+[<Fact>]
+let testParse_tagConvention () =
+    let parse content =
+        Config.tryParse content |> Option.map (fun config -> config.DiagTagConvention())
+
+    Assert.Equal(
+        Some TagConvention.SnakeCase,
+        parse "[diagnostics]\ntag_convention = \"snake_case\""
+    )
+
+    Assert.Equal(
+        Some(TagConvention.Pattern "[a-z]+"),
+        parse "[diagnostics]\ntag_convention = \"[a-z]+\""
+    )
+
+    // Malformed patterns are ignored like any other unknown value
+    Assert.Equal(Some TagConvention.Any, parse "[diagnostics]\ntag_convention = \"[a-z\"")
+
+[<Fact>]
+let testParse_outOfRange () =
+    let parse content =
+        Config.tryParse content |> Option.map (fun config -> config.CoreMaxFileSizeKb())
+
+    Assert.Equal(Some 10, parse "[core]\nmax_file_size_kb = 10")
+    // Out of range values are ignored like unknown ones
+    Assert.Equal(Some 1024, parse "[core]\nmax_file_size_kb = 0")
+    Assert.Equal(Some 1024, parse "[core]\nmax_file_size_kb = -1")
+
+    let memoryLimit content =
+        Config.tryParse content |> Option.map (fun config -> config.CoreMemoryLimitMb())
+
+    Assert.Equal(Some 512, memoryLimit "[core]\nmemory_limit_mb = 512")
+    Assert.Equal(Some 0, memoryLimit "[core]\nmemory_limit_mb = -1")
+    Assert.Equal(Some 0, memoryLimit "[core]\nmemory_limit_mb = 4294967296")
+
+    let debounce content =
+        Config.tryParse content |> Option.map (fun config -> config.DiagDebounceMs())
+
+    Assert.Equal(Some 0, debounce "[diagnostics]\ndebounce_ms = -1")
+    Assert.Equal(Some 10000, debounce "[diagnostics]\ndebounce_ms = 3600000")
+
+    let previewMaxChars content =
+        Config.tryParse content |> Option.map (fun config -> config.ComplPreviewMaxChars())
+
+    Assert.Equal(Some 80, previewMaxChars "[completion.preview]\nmax_chars = 80")
+    Assert.Equal(Some 500, previewMaxChars "[completion.preview]\nmax_chars = -1")
+
+    let tocMaxLevel content =
+        Config.tryParse content |> Option.map (fun config -> config.CaTocMaxLevel())
+
+    Assert.Equal(Some 6, tocMaxLevel "[code_action.toc]\nmax_level = 7")
+
+[<Fact>]
+let testOfJson () =
+    let settings =
+        Newtonsoft.Json.Linq.JObject.Parse(
+            """{ "completion": { "wiki": { "style": "file-stem" }, "max_items": 10 },
+                 "core": { "ignore": ["drafts/"] },
+                 "unknown": null }"""
+        )
+
+    let expected =
+        { Config.Empty with
+            complWikiStyle = Some FileStem
+            complMaxItems = Some 10
+            coreIgnore = Some [| "drafts/" |] }
+
+    Assert.Equal(Some expected, Config.ofJson settings)
@@ -1,6 +1,10 @@
 module Marksman.DiagTest
 
 open System.IO
+open System.Net
+open System.Net.Http
+open System.Threading
+open Ionide.LanguageServerProtocol.Types
 open Marksman.Index
 open Marksman.Misc
 open Xunit
@@ -53,6 +57,32 @@ let noDiagOnShortcutLinks () =
 
     Assert.Equal<string * string>([ "fake.md", "Link to non-existent heading 'h42'" ], diag)
 
+[<Fact>]
+let brokenAnchor_onlyFragmentHighlighted () =
+    let doc1 =
+        FakeDoc.Mk(path = "doc1.md", contentLines = [| "[[doc2#missing]]"; "[](doc2.md#nope)" |])
+
+    let doc2 = FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2" |])
+    let folder = FakeFolder.Mk([ doc1; doc2 ])
+
+    let diags = checkLinks folder doc1 |> Seq.map diagToLsp |> Array.ofSeq
+
+    Assert.Equal<string>(
+        [| "broken-anchor"; "broken-anchor" |],
+        diags |> Array.choose (fun diag -> diag.Code)
+    )
+    Assert.Equal(Range.Mk(0, 7, 0, 14), diags[0].Range)
+    Assert.Equal(Range.Mk(1, 11, 1, 15), diags[1].Range)
+
+[<Fact>]
+let brokenAnchor_missingDocIsBrokenLink () =
+    let doc = FakeDoc.Mk(path = "doc1.md", contentLines = [| "[[nodoc#heading]]" |])
+    let folder = FakeFolder.Mk([ doc ])
+
+    let codes = checkLinks folder doc |> Seq.map code |> List.ofSeq
+
+    Assert.Equal<string>([ "broken-link" ], codes)
+
 [<Fact>]
 let noDiagOnRealUrls () =
     let doc =
@@ -112,3 +142,558 @@ let noCrossFileDiagOnSingleFileFolders () =
         [ "fake.md", "Link to non-existent link definition with the label 'bad-ref'" ],
         diag
     )
+
+[<Fact>]
+let footnotes_undefinedAndUnused () =
+    let doc =
+        FakeDoc.Mk(
+            [| "# H1"
+               "Text[^1] and more[^2]."
+               ""
+               "[^1]: Used footnote"
+               "[^3]: Unused footnote" |]
+        )
+
+    let folder = FakeFolder.Mk([ doc ])
+    let diag = checkFolder folder |> diagToHuman
+
+    Assert.Equal<string * string>(
+        [ "fake.md", "Link to non-existent footnote '^2'"
+          "fake.md", "Footnote '^3' is never referenced" ],
+        diag
+    )
+
+[<Fact>]
+let linkDefs_unusedAndUndefinedWithFixes () =
+    let doc =
+        FakeDoc.Mk(
+            [| "# H1"
+               "Text[^1] and [link][nowhere]."
+               ""
+               "[unused]: https://example.com"
+               "[^2]: Unused footnote" |]
+        )
+
+    let folder = FakeFolder.Mk([ doc ])
+
+    let diags =
+        Seq.append (checkLinks folder doc) (checkLinkDefs doc)
+        |> Seq.map diagToLsp
+        |> Array.ofSeq
+
+    let context = { Diagnostics = diags; Only = None; TriggerKind = None }
+
+    let fixes =
+        CodeActions.linkDefFixes context doc
+        |> Array.map (fun (fix, _) -> fix.name, fix.edit.DebuggerDisplay, fix.newText)
+
+    let nl = System.Environment.NewLine
+
+    Assert.Equal<string * string * string>(
+        [| "Create footnote [^1]", "(5,0)-(5,0)", $"{nl}[^1]: {nl}"
+           "Create link definition [nowhere]", "(5,0)-(5,0)", $"{nl}[nowhere]: <>{nl}"
+           "Remove unused link definition [unused]", "(3,0)-(4,0)", ""
+           "Remove unused footnote [^2]", "(4,0)-(5,0)", "" |],
+        fixes
+    )
+
+[<Fact>]
+let missingImages () =
+    let doc =
+        FakeDoc.Mk(
+            [| "# H1"
+               "![](missing.png)"
+               "![remote](https://example.com/a.png)"
+               "[not an image](other.png)" |]
+        )
+
+    let folder = FakeFolder.Mk([ doc ])
+    let diag = checkFolder folder |> diagToHuman
+
+    Assert.Equal<string * string>([ "fake.md", "Image file 'missing.png' doesn't exist" ], diag)
+
+[<Fact>]
+let duplicateHeadings_pointToEachOther () =
+    let doc = FakeDoc.Mk([| "# Doc"; "## Setup"; "## Usage"; "## setup" |])
+    let folder = FakeFolder.Mk([ doc ])
+    let diag = checkFolder folder |> diagToHuman
+
+    Assert.Equal<string * string>(
+        [ "fake.md", "Duplicate heading 'Setup' makes links to it ambiguous"
+          "fake.md", "Duplicate heading 'setup' makes links to it ambiguous" ],
+        diag
+    )
+
+    let lsp = checkHeadings doc |> Seq.head |> diagToLsp
+    let related = lsp.RelatedInformation |> Option.defaultValue [||]
+    Assert.Equal(1, lsp.Range.Start.Line)
+    Assert.Equal<int>([| 3 |], related |> Array.map (fun info -> info.Location.Range.Start.Line))
+
+[<Fact>]
+let orphans_optIn () =
+    let doc1 = FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[[doc2]]" |])
+    let doc2 = FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2"; "[[#Doc 2]]" |])
+    let config = { Config.Config.Default with diagOrphanEnable = Some true }
+
+    let diag = checkFolder (FakeFolder.Mk([ doc1; doc2 ], config = config)) |> diagToHuman
+    Assert.Equal<string * string>([ "doc1.md", "No other note links to Doc 1" ], diag)
+
+    let diag = checkFolder (FakeFolder.Mk([ doc1; doc2 ])) |> diagToHuman
+    Assert.Empty(diag)
+
+[<Fact>]
+let singleFile_sameNoteChecksOnly () =
+    let doc = FakeDoc.Mk([| "# A"; "[[#nope]]"; "[[other]]"; "![](/img/missing.png)" |])
+    let config = { Config.Config.Default with diagOrphanEnable = Some true }
+    let folderDiag = FolderDiag.mk (Folder.singleFile doc (Some config))
+    let diags = folderDiag |> Array.collect snd
+
+    Assert.Equal<string>([| "broken-anchor" |], diags |> Array.choose (fun diag -> diag.Code))
+
+[<Fact>]
+let rules_severityAndOff () =
+    let doc = FakeDoc.Mk([| "# H1"; "[[#nope]]"; "![](missing.png)" |])
+
+    let rules =
+        Map.ofList
+            [ "broken-anchor", Config.DiagSeverity.Error
+              "missing-image", Config.DiagSeverity.Off ]
+
+    let config = { Config.Config.Default with diagRules = Some rules }
+    let folderDiag = FolderDiag.mk (FakeFolder.Mk([ doc ], config = config))
+    let diags = folderDiag |> Array.collect snd
+
+    Assert.Equal<string>([| "broken-anchor" |], diags |> Array.choose (fun diag -> diag.Code))
+    Assert.Equal(Some DiagnosticSeverity.Error, diags[0].Severity)
+
+[<Fact>]
+let malformedLinks () =
+    let doc =
+        FakeDoc.Mk(
+            [| "# H1"
+               "[[unclosed and [[]] here"
+               "[[doc#]] [empty]() [@] and [[ok]]"
+               "`[[code` \\[[escaped" |]
+        )
+
+    let ranges =
+        checkLinkSyntax doc
+        |> Seq.map (diagToLsp >> fun diag -> diag.Range.DebuggerDisplay, diag.Message)
+        |> List.ofSeq
+
+    Assert.Equal<string * string>(
+        [ "(1,0)-(1,14)", "Wiki link is never closed with ']]'"
+          "(1,15)-(1,19)", "Link has no target"
+          "(2,5)-(2,6)", "Nothing follows '#' in the link"
+          "(2,16)-(2,18)", "Link has no target"
+          "(2,20)-(2,21)", "Nothing follows '@' in the citation" ],
+        ranges
+    )
+
+[<Collection(GlobalState)>]
+module EncodingTests =
+    [<Fact>]
+    let malformedLinks_utf8 () =
+        let doc = FakeDoc.Mk([| "# H1"; "café [[doc#]] ünï [[x" |])
+
+        let ranges =
+            withPositionEncoding Text.Utf8 (fun () ->
+                checkLinkSyntax doc
+                |> Seq.map (diagToLsp >> fun diag -> diag.Range.DebuggerDisplay)
+                |> List.ofSeq)
+
+        Assert.Equal<string>([ "(1,11)-(1,12)"; "(1,21)-(1,24)" ], ranges)
+
+[<Fact>]
+let urlCheck_onlyExternalUrls () =
+    Assert.True(UrlCheck.isExternal "https://example.com")
+    Assert.True(UrlCheck.isExternal "HTTP://example.com")
+    Assert.False(UrlCheck.isExternal "mailto:doe@example.com")
+    Assert.False(UrlCheck.isExternal "doc.md")
+
+    let doc = FakeDoc.Mk([| "# H1"; "[a](doc.md) [b](mailto:doe@example.com)"; "[c]: ./c.md" |])
+    use checker = new UrlCheck.Checker()
+    Assert.Empty(checkUrls checker (System.TimeSpan.FromMinutes(1.0)) doc)
+
+// Answers every request with `status`, so that URLs are checked without the network
+type private StubHandler(status: HttpStatusCode) =
+    inherit HttpMessageHandler()
+
+    override _.SendAsync(_request, _cancel) =
+        Tasks.Task.FromResult(new HttpResponseMessage(status))
+
+[<Fact>]
+let urlCheck_deadUrlReported () =
+    use checker = new UrlCheck.Checker(new StubHandler(HttpStatusCode.NotFound))
+    use updated = new ManualResetEventSlim()
+    use _ = Observable.subscribe updated.Set checker.Updated
+    let ttl = System.TimeSpan.FromMinutes(1.0)
+
+    let doc = FakeDoc.Mk([| "# H1"; "[a](https://example.com/gone)" |])
+
+    // The first check only queues the URL
+    Assert.Empty(checkUrls checker ttl doc)
+    Assert.True(updated.Wait(System.TimeSpan.FromSeconds(10.0)))
+
+    match checkUrls checker ttl doc |> List.ofSeq with
+    | [ DeadUrl (url, reason) ] ->
+        Assert.Equal("https://example.com/gone", url.text)
+        Assert.StartsWith("HTTP 404", reason)
+    | other -> failwith $"Unexpected entries: {other}"
+
+[<Fact>]
+let brokenLinks_quickFixes () =
+    let doc1 =
+        FakeDoc.Mk(
+            path = "doc1.md",
+            contentLines = [| "# Doc 1"; "[[dok2]]"; "[](Doc2.md)"; "[[doc2#Intorduction]]" |]
+        )
+
+    let doc2 = FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2"; "## Introduction" |])
+    let folder = FakeFolder.Mk([ doc1; doc2 ])
+
+    let mkDiag (code: string) (line: int) (char: int) : Diagnostic =
+        { Range = Range.Mk(line, char, line, char)
+          Severity = None
+          Code = Some code
+          CodeDescription = None
+          Source = None
+          Message = ""
+          RelatedInformation = None
+          Tags = None
+          Data = None }
+
+    let context =
+        { Diagnostics =
+            [| mkDiag "broken-link" 1 2
+               mkDiag "broken-link" 2 3
+               mkDiag "broken-anchor" 3 7 |]
+          Only = None
+          TriggerKind = None }
+
+    let fixes =
+        CodeActions.brokenLinkFixes folder context doc1
+        |> Array.map (fun (fix, diag) ->
+            match fix with
+            | CodeActions.EditDoc action -> diag.Range.Start.Line, action.name
+            | CodeActions.CreateNote (name, _, _) -> diag.Range.Start.Line, name)
+
+    Assert.Equal<int * string>(
+        [| 1, "Replace with 'doc-2'"
+           1, "Create note 'dok2'"
+           2, "Fix casing to 'doc2.md'"
+           2, "Create note 'Doc2'"
+           3, "Replace with 'introduction'" |],
+        fixes
+    )
+
+[<Fact>]
+let frontmatter_schema () =
+    let journal =
+        Map.ofList
+            [ "journal",
+              { Config.required = Some [| "date"; "mood" |]
+                Config.types = Map.ofList [ "mood", Config.FieldType.String ] } ]
+
+    let config =
+        { Config.Config.Default with
+            diagFrontmatterRequired = Some [| "date"; "title" |]
+            diagFrontmatterTypes =
+                Some(
+                    Map.ofList
+                        [ "date", Config.FieldType.Date
+                          "tags", Config.FieldType.List Config.FieldType.String
+                          "draft", Config.FieldType.Bool ]
+                )
+            diagFrontmatterDirs = Some journal }
+
+    let content =
+        [| "---"
+           "date: 2023-13-01"
+           "draft: \"yes\""
+           "title:"
+           "tags:"
+           "  - ok"
+           // A string item rather than a nested list
+           "  - [nested]"
+           "---"
+           "# Note" |]
+
+    let check path =
+        checkFrontmatter config (FakeDoc.Mk(content, path = path))
+        |> Seq.map (diagToLsp >> fun diag -> diag.Range.DebuggerDisplay, diag.Message)
+        |> List.ofSeq
+
+    Assert.Equal<string * string>(
+        [ "(0,0)-(0,3)", "Missing required frontmatter field 'title'"
+          "(1,0)-(1,16)", "Frontmatter field 'date' should be date"
+          "(2,0)-(2,12)", "Frontmatter field 'draft' should be bool" ],
+        check "note.md"
+    )
+
+    let tagsContent = [| "---"; "date: 2023-01-01T10:00"; "title: T"; "tags: [a, 1]"; "---" |]
+    let diag = checkFrontmatter config (FakeDoc.Mk(tagsContent, path = "note.md"))
+    Assert.Empty(diag)
+
+    Assert.Equal<string * string>(
+        [ "(0,0)-(0,3)", "Missing required frontmatter field 'mood'"
+          "(1,0)-(1,16)", "Frontmatter field 'date' should be date"
+          "(2,0)-(2,12)", "Frontmatter field 'draft' should be bool" ],
+        check "journal/2023-01-01.md"
+    )
+
+    let noFrontmatter = FakeDoc.Mk([| "# Note" |], path = "note.md")
+
+    Assert.Equal<string>(
+        [ "Missing required frontmatter field 'date'"
+          "Missing required frontmatter field 'title'" ],
+        checkFrontmatter config noFrontmatter |> Seq.map entryToHuman
+    )
+
+[<Fact>]
+let duplicateTitles_relatedToOtherNotes () =
+    let doc1 = FakeDoc.Mk(path = "a/doc1.md", contentLines = [| "# Ideas" |])
+    let doc2 = FakeDoc.Mk(path = "b/doc2.md", contentLines = [| "Text"; "# ideas" |])
+    let doc3 = FakeDoc.Mk(path = "b/ideas.md", contentLines = [| "no title" |])
+    let doc4 = FakeDoc.Mk(path = "other.md", contentLines = [| "# Other" |])
+    let folder = FakeFolder.Mk([ doc1; doc2; doc3; doc4 ])
+
+    let dups = findDuplicateTitles folder
+    Assert.Equal<PathUri>([ Doc.path doc1; Doc.path doc2; Doc.path doc3 ] |> List.sort, dups.Keys)
+
+    let diag = diagToLsp (DuplicateTitle(doc2, dups[Doc.path doc2]))
+    Assert.Equal(Range.Mk(1, 2, 1, 7), diag.Range)
+    Assert.Equal("Duplicate note name 'ideas' makes links to it ambiguous", diag.Message)
+
+    let related =
+        diag.RelatedInformation
+        |> Option.defaultValue [||]
+        |> Array.map (fun rel -> rel.Location.Range.DebuggerDisplay, rel.Message)
+
+    Assert.Equal<string * string>(
+        [| "(0,2)-(0,7)", $"Another note '{Path.Join("a", "doc1.md")}'"
+           "(0,0)-(0,0)", $"Another note '{Path.Join("b", "ideas.md")}'" |],
+        related
+    )
+
+[<Fact>]
+let nonPortableLinks_withFixes () =
+    let doc1 =
+        FakeDoc.Mk(
+            path = "notes/index.md",
+            contentLines =
+                [| "# Index"
+                   "[](../Docs/Setup.md) [[docs\\setup]]"
+                   "[](../docs/setup.md) [](Missing.md)" |]
+        )
+
+    let doc2 = FakeDoc.Mk(path = "docs/setup.md", contentLines = [| "# Setup" |])
+    let folder = FakeFolder.Mk([ doc1; doc2 ])
+
+    let diags = checkLinks folder doc1 |> Seq.map diagToLsp |> Array.ofSeq
+
+    Assert.Equal<string * string>(
+        [| "non-portable-link", "(1,3)-(1,19)"
+           "non-portable-link", "(1,23)-(1,33)"
+           "broken-link", "(2,21)-(2,35)" |],
+        diags |> Array.map (fun diag -> Option.get diag.Code, diag.Range.DebuggerDisplay)
+    )
+
+    let context = { Diagnostics = diags; Only = None; TriggerKind = None }
+
+    let fixes =
+        CodeActions.nonPortableLinkFixes folder context doc1
+        |> Array.map (fun (fix, _) -> fix.name, fix.edit.DebuggerDisplay)
+
+    Assert.Equal<string * string>(
+        [| "Change link to '../docs/setup.md'", "(1,3)-(1,19)"
+           "Change link to 'docs/setup'", "(1,23)-(1,33)" |],
+        fixes
+    )
+
+[<Fact>]
+let incremental_matchesFullCheck () =
+    let doc1 = FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[[doc2#Setup]]" |])
+    let doc2 = FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2"; "## Setup" |])
+    let doc3 = FakeDoc.Mk(path = "doc3.md", contentLines = [| "# Doc 3"; "[[doc4]]" |])
+    let folder = FakeFolder.Mk([ doc1; doc2; doc3 ])
+
+    let check, _ = checkFolderIncremental None CancellationToken.None None folder
+
+    let steps =
+        [ // Only the body of doc1 changes; other docs are reused
+          FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[[doc2#Setup]] [[nope]]" |])
+          // Heading of doc2 is renamed, breaking the link of doc1
+          FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2"; "## Install" |])
+          // doc4 appears, fixing the link of doc3
+          FakeDoc.Mk(path = "doc4.md", contentLines = [| "# Doc 4" |]) ]
+
+    let mutable check = check
+    let mutable folder = folder
+
+    for doc in steps do
+        folder <- Folder.withDoc doc folder
+        let newCheck, entries =
+            checkFolderIncremental None CancellationToken.None (Some check) folder
+
+        check <- newCheck
+
+        Assert.Equal<string * string>(
+            checkFolder folder |> diagToHuman |> List.sort,
+            entries |> diagToHuman |> List.sort
+        )
+
+    Assert.Equal<string * string>(
+        [ "doc1.md", "Link to non-existent document 'nope'"
+          "doc1.md", "Link to non-existent heading 'Setup' in document 'doc2'" ],
+        checkFolderIncremental None CancellationToken.None (Some check) folder
+        |> snd
+        |> diagToHuman
+        |> List.sort
+    )
+
+[<Fact>]
+let incremental_explicitHeadingIdChanged () =
+    let doc1 = FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[[doc2#custom]]" |])
+    let doc2 = FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2"; "## Setup {#custom}" |])
+    let folder = FakeFolder.Mk([ doc1; doc2 ])
+    let check, _ = checkFolderIncremental None CancellationToken.None None folder
+
+    // Only the id changes, the title and its slug stay the same
+    let doc2 = FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2"; "## Setup {#other}" |])
+    let folder = Folder.withDoc doc2 folder
+    let _, entries = checkFolderIncremental None CancellationToken.None (Some check) folder
+
+    Assert.Equal<string * string>(
+        [ "doc1.md", "Link to non-existent heading 'custom' in document 'doc2'" ],
+        diagToHuman entries
+    )
+
+[<Fact>]
+let incremental_cancelled () =
+    let doc = FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[[nope]]" |])
+    let folder = FakeFolder.Mk([ doc ])
+    use cancel = new CancellationTokenSource()
+    cancel.Cancel()
+
+    let cancelled =
+        try
+            checkFolderIncremental None cancel.Token None folder |> ignore
+            false
+        with :? System.OperationCanceledException ->
+            true
+
+    Assert.True(cancelled)
+
+    // Nothing is left to check when every doc is reused
+    let check, _ = checkFolderIncremental None CancellationToken.None None folder
+    let _, entries = checkFolderIncremental None cancel.Token (Some check) folder
+    let expected = [ "doc1.md", "Link to non-existent document 'nope'" ]
+    Assert.Equal<string * string>(expected, diagToHuman entries)
+
+[<Fact>]
+let embedCycles_onlyWithinEmbeddedSections () =
+    let docA = FakeDoc.Mk(path = "a.md", contentLines = [| "# A"; "![[b]]" |])
+
+    let docB =
+        FakeDoc.Mk(path = "b.md", contentLines = [| "# B"; "## Part"; "![[a]]"; "## Other" |])
+
+    let docC = FakeDoc.Mk(path = "c.md", contentLines = [| "# C"; "![[b#Other]]"; "![[#C]]" |])
+    let folder = FakeFolder.Mk([ docA; docB; docC ])
+
+    let diags =
+        checkFolder folder
+        |> Seq.collect (fun (path, entries) ->
+            entries
+            |> List.map (fun entry ->
+                let lsp = diagToLsp entry
+                Path.GetFileName path.LocalPath, lsp.Range.DebuggerDisplay, lsp.Message))
+        |> List.ofSeq
+
+    Assert.Equal<string * string * string>(
+        [ "a.md", "(1,0)-(1,6)", "Circular embed: A → B → A"
+          "b.md", "(2,0)-(2,6)", "Circular embed: B → A → B"
+          "c.md", "(2,0)-(2,7)", "Circular embed: C → C" ],
+        diags
+    )
+
+[<Fact>]
+let headingStyle_optIn () =
+    let doc = FakeDoc.Mk(path = "notes.md", contentLines = [| "## Intro"; "#### Deep"; "### Ok" |])
+
+    Assert.Empty(checkHeadingStyle Config.Config.Default doc)
+
+    let config =
+        { Config.Config.Default with
+            diagMissingTitleEnable = Some true
+            diagHeadingJumpEnable = Some true }
+
+    Assert.Equal<string>(
+        [ "Note has no title, it's known by its file name 'notes'"
+          "Heading level jumps from H2 to H4" ],
+        checkHeadingStyle config doc |> Seq.map entryToHuman
+    )
+
+    // Configuring a severity turns a rule on too
+    let rules = Map.ofList [ "heading-jump", Config.DiagSeverity.Warning ]
+    let config = { Config.Config.Default with diagRules = Some rules }
+
+    Assert.Equal<string>(
+        [ "Heading level jumps from H2 to H4" ],
+        checkHeadingStyle config doc |> Seq.map entryToHuman
+    )
+
+[<Fact>]
+let tagConvention_withFixes () =
+    let doc =
+        FakeDoc.Mk(path = "doc.md", contentLines = [| "# Doc"; "#my-tag #MyTag #Sub_Area" |])
+
+    Assert.Empty(checkTags Config.Config.Default doc)
+
+    let config =
+        { Config.Config.Default with diagTagConvention = Some Config.TagConvention.KebabCase }
+    let diags = checkTags config doc |> Seq.map diagToLsp |> Array.ofSeq
+
+    Assert.Equal<string * string>(
+        [| "Tag '#MyTag' isn't kebab-case", "(1,8)-(1,14)"
+           "Tag '#Sub_Area' isn't kebab-case", "(1,15)-(1,24)" |],
+        diags |> Array.map (fun diag -> diag.Message, diag.Range.DebuggerDisplay)
+    )
+
+    let context = { Diagnostics = diags; Only = None; TriggerKind = None }
+
+    let fixes =
+        CodeActions.tagNameFixes config context doc
+        |> Array.map (fun (fix, _) -> fix.name, fix.newText)
+
+    Assert.Equal<string * string>(
+        [| "Rename to '#my-tag'", "my-tag"; "Rename to '#sub-area'", "sub-area" |],
+        fixes
+    )
+
+    // Patterns can only be reported
+    let config = { config with diagTagConvention = Some(Config.TagConvention.Pattern "[a-z]+") }
+
+    Assert.Equal<string>(
+        [ "Tag '#my-tag' doesn't match the pattern '[a-z]+'"
+          "Tag '#MyTag' doesn't match the pattern '[a-z]+'"
+          "Tag '#Sub_Area' doesn't match the pattern '[a-z]+'" ],
+        checkTags config doc |> Seq.map entryToHuman
+    )
+
+[<Fact>]
+let report_countsBySeverity () =
+    let doc1 = FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[[missing]] [[doc2]]" |])
+    let doc2 = FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2"; "## A"; "## A" |])
+    let doc3 = FakeDoc.Mk(path = "doc3.md", contentLines = [| "# Doc 3" |])
+    let folder = FakeFolder.Mk([ doc1; doc2; doc3 ])
+
+    let report = Map.ofList [ Folder.id folder, FolderDiag.mk folder ] |> Report.ofWorkspaceDiag
+
+    Assert.Equal<string * array<string>>(
+        [| Doc.uri doc1, [| "broken-link" |]; Doc.uri doc2, [| "dup-heading"; "dup-heading" |] |],
+        report.documents
+        |> Array.map (fun doc -> doc.uri, doc.diagnostics |> Array.choose (fun diag -> diag.Code))
+    )
+
+    Assert.Equal((1, 2, 0, 0), (report.errors, report.warnings, report.information, report.hints))
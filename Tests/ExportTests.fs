@@ -0,0 +1,83 @@
+module Marksman.ExportTests
+
+open System.IO
+
+open Xunit
+
+open Marksman.Export
+open Marksman.Helpers
+
+let private pagesOf () =
+    let docA =
+        FakeDoc.Mk(
+            path = "a.md",
+            contentLines = [| "# A"; "[[b#S]] and [[missing]]"; "[[b#^blk]]" |]
+        )
+
+    let docB =
+        FakeDoc.Mk(
+            path = "notes/b.md",
+            contentLines = [| "# B"; "## S"; "[A](../a.md)"; ""; "Block ^blk" |]
+        )
+
+    FakeFolder.Mk([ docA; docB ]) |> pages
+
+let private pageAt (path: string) =
+    pagesOf () |> Array.find (fun page -> page.path = path)
+
+[<Fact>]
+let relativeHref_paths () =
+    Assert.Equal("b.html", relativeHref "a.html" "b.html")
+    Assert.Equal("notes/b.html", relativeHref "a.html" "notes/b.html")
+    Assert.Equal("../a.html", relativeHref "notes/b.html" "a.html")
+    Assert.Equal("../y/c%20d.html", relativeHref "x/a.html" "y/c d.html")
+
+[<Fact>]
+let pages_resolvedLinks () =
+    let html = (pageAt "a.html").html
+    Assert.Contains("<a href=\"notes/b.html#s\">b#S</a>", html)
+    // Unresolved wiki links are kept as text
+    Assert.Contains("and missing", html)
+
+    Assert.Contains("<a href=\"../a.html\">A</a>", (pageAt "notes/b.html").html)
+
+[<Fact>]
+let pages_headingIds () =
+    Assert.Contains("<h2 id=\"s\">S</h2>", (pageAt "notes/b.html").html)
+
+[<Fact>]
+let pages_blockAnchors () =
+    Assert.Matches("<a href=\"notes/b.html#(\\^|%5E)blk\">", (pageAt "a.html").html)
+    // The marker is dropped, the block gets the anchor as its id
+    Assert.Contains("<p id=\"^blk\">Block</p>", (pageAt "notes/b.html").html)
+
+[<Fact>]
+let pages_backlinks () =
+    let html = (pageAt "notes/b.html").html
+    Assert.Contains("<h2>Backlinks</h2>", html)
+    Assert.Contains("<li><a href=\"../a.html\">A</a></li>", html)
+
+[<Fact>]
+let pages_index () =
+    let html = (pageAt indexPath).html
+    Assert.Contains("<li><a href=\"a.html\">A</a></li>", html)
+    Assert.Contains("<li><a href=\"notes/b.html\">B</a></li>", html)
+
+[<Fact>]
+let run_copiesAssets () =
+    let dir = Path.Join(Path.GetTempPath(), Path.GetRandomFileName())
+    let outDir = Path.Join(Path.GetTempPath(), Path.GetRandomFileName())
+    Directory.CreateDirectory(Path.Join(dir, "img")) |> ignore
+
+    try
+        File.WriteAllText(Path.Join(dir, "a.md"), "# A\n![](img/x.png)\n")
+        File.WriteAllText(Path.Join(dir, "img", "x.png"), "png")
+
+        Assert.Equal(0, run dir Format.Html outDir)
+        Assert.Contains("<img src=\"img/x.png\"", File.ReadAllText(Path.Join(outDir, "a.html")))
+        Assert.Equal("png", File.ReadAllText(Path.Join(outDir, "img", "x.png")))
+    finally
+        Directory.Delete(dir, true)
+
+        if Directory.Exists(outDir) then
+            Directory.Delete(outDir, true)
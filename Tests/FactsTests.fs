@@ -0,0 +1,41 @@
+module Marksman.FactsTests
+
+open Xunit
+
+open Marksman.Facts
+open Marksman.Helpers
+open Marksman.Workspace
+
+let private doc1 = FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "[[doc2#Setup]]" |])
+let private doc2 = FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2"; "## Setup" |])
+let private doc3 = FakeDoc.Mk(path = "doc3.md", contentLines = [| "# Doc 3" |])
+let private folder = FakeFolder.Mk([ doc1; doc2; doc3 ])
+
+let private invalidatedNames (folder': Folder) =
+    invalidated folder folder'
+    |> Option.map (Seq.map (fun path -> System.IO.Path.GetFileName(path.LocalPath)) >> List.ofSeq)
+
+[<Fact>]
+let invalidated_onlyEditedDocWhenTargetsStay () =
+    let edited = FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2"; "## Setup"; "Text" |])
+    Assert.Equal(Some [ "doc2.md" ], invalidatedNames (Folder.withDoc edited folder))
+
+[<Fact>]
+let invalidated_linkingDocsWhenTargetsChange () =
+    let renamed = FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2"; "## Install" |])
+    Assert.Equal(Some [ "doc1.md"; "doc2.md" ], invalidatedNames (Folder.withDoc renamed folder))
+
+[<Fact>]
+let memo_derivesOnlyInvalidated () =
+    let derived = ResizeArray()
+
+    let derive (doc: Doc) =
+        derived.Add(Doc.pathFromRoot doc)
+        Doc.name doc
+
+    let memo = Memo.ofFolder derive folder
+    let renamed = FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2"; "## Install" |])
+    let memo = Memo.update (fun _ -> true) derive (Some memo) (Folder.withDoc renamed folder)
+
+    Assert.Equal<string>([ "doc1.md"; "doc2.md"; "doc3.md"; "doc1.md"; "doc2.md" ], derived)
+    Assert.Equal(Some "Doc 3", Memo.tryFind doc3 memo)
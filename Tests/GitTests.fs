@@ -0,0 +1,75 @@
+module Marksman.GitTests
+
+open System
+open Xunit
+
+open Marksman.Git
+open Marksman.Misc
+open Marksman.Workspace
+
+let private hashA = String('a', 40)
+let private hashB = String('b', 40)
+
+let private porcelain =
+    [| $"{hashA} 1 1 2"
+       "author Alice"
+       "author-mail <alice@example.com>"
+       "author-time 1705062600"
+       "summary Add the note"
+       "filename note.md"
+       "\t# Note"
+       $"{hashA} 2 2"
+       "\ttext"
+       $"{hashB} 3 3 1"
+       "author Bob"
+       "author-time 1705149000"
+       "summary Fix a typo"
+       "filename note.md"
+       "\tmore text"
+       "" |]
+    |> String.concat "\n"
+
+[<Fact>]
+let parseBlame_commitsByLine () =
+    let commits = parseBlame porcelain
+
+    Assert.Equal<string * string>(
+        [| "Alice", "Add the note"; "Alice", "Add the note"; "Bob", "Fix a typo" |],
+        commits |> Array.map (fun commit -> commit.author, commit.summary)
+    )
+
+    Assert.Equal(DateTimeOffset.FromUnixTimeSeconds(1705062600L), commits[0].time)
+
+[<Fact>]
+let latestIn_lines () =
+    let commits = parseBlame porcelain
+    Assert.Equal(Some hashB, latestIn commits 0 5 |> Option.map (fun commit -> commit.hash))
+    Assert.Equal(Some hashA, latestIn commits 0 1 |> Option.map (fun commit -> commit.hash))
+    Assert.Equal(None, latestIn commits 3 5)
+
+[<Fact>]
+let fmt_uncommitted () =
+    let commit =
+        { hash = String('0', 40)
+          author = "Not Committed Yet"
+          time = DateTimeOffset.Now
+          summary = "Version of note.md from note.md" }
+
+    Assert.Equal("Not committed yet", Commit.fmt commit)
+
+[<Fact>]
+let isSaved_unsavedEdits () =
+    let path = IO.Path.Join(IO.Path.GetTempPath(), IO.Path.GetRandomFileName() + ".md")
+
+    try
+        IO.File.WriteAllText(path, "# A")
+        let root = RootPath.ofString (IO.Path.GetDirectoryName(path))
+        let docOf version content =
+            Doc.mk (PathUri.ofString path) root version (Text.mkText content)
+
+        Assert.True(isSaved (docOf None "# Loaded earlier"))
+        Assert.True(isSaved (docOf (Some 1) "# A"))
+        Assert.False(isSaved (docOf (Some 2) "# A, edited"))
+        Assert.Empty(headingLenses (docOf (Some 2) "# A, edited"))
+    finally
+        IO.File.Delete(path)
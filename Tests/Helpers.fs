@@ -2,12 +2,33 @@ module Marksman.Helpers
 
 open System.Runtime.InteropServices
 open Snapper
+open Xunit
 open Marksman.Misc
 open Marksman.Workspace
 open Marksman.CodeActions
 
 let pathToUri (path: string) = $"file://{path}"
 
+/// Tests changing settings of the whole process, e.g. the position encoding, go into this
+/// collection. It runs on its own, after the tests that can run in parallel.
+[<Literal>]
+let GlobalState = "Global state"
+
+[<CollectionDefinition(GlobalState, DisableParallelization = true)>]
+type GlobalStateCollection() =
+    class
+    end
+
+/// Runs `f` with positions counted in `encoding`, restoring the encoding in use afterwards.
+let withPositionEncoding (encoding: Text.PositionEncoding) (f: unit -> 'a) : 'a =
+    let prev = Text.PositionEncoding.current ()
+    Text.PositionEncoding.set encoding
+
+    try
+        f ()
+    finally
+        Text.PositionEncoding.set prev
+
 let dummyRoot =
     if RuntimeInformation.IsOSPlatform(OSPlatform.Windows) then
         "c:/"
@@ -0,0 +1,39 @@
+module Marksman.LoggingTests
+
+open Serilog.Events
+open Xunit
+
+open Marksman.Logging
+
+[<Fact>]
+let tryParse_levelAndOverrides () =
+    let filter = Filter.tryParse LogEventLevel.Information "debug, Server=trace,Diag=error"
+
+    Assert.Equal(
+        Some
+            { level = LogEventLevel.Debug
+              overrides = [ "Server", LogEventLevel.Verbose; "Diag", LogEventLevel.Error ] },
+        filter
+    )
+
+    Assert.Equal(Some LogEventLevel.Verbose, filter |> Option.map Filter.minimumLevel)
+
+[<Fact>]
+let tryParse_defaultLevel () =
+    let filter = Filter.tryParse LogEventLevel.Warning "State=debug"
+    Assert.Equal(Some LogEventLevel.Warning, filter |> Option.map (fun f -> f.level))
+
+[<Fact>]
+let tryParse_malformed () =
+    Assert.Equal(None, Filter.tryParse LogEventLevel.Information "verbose")
+    Assert.Equal(None, Filter.tryParse LogEventLevel.Information "Server=trace=debug")
+
+[<Fact>]
+let levelFor_longestPrefix () =
+    let filter =
+        { level = LogEventLevel.Information
+          overrides = [ "Server", LogEventLevel.Debug; "ServerUtil", LogEventLevel.Error ] }
+
+    Assert.Equal(LogEventLevel.Debug, Filter.levelFor "Server" filter)
+    Assert.Equal(LogEventLevel.Error, Filter.levelFor "ServerUtil" filter)
+    Assert.Equal(LogEventLevel.Information, Filter.levelFor "Diag" filter)
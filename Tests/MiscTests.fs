@@ -1,5 +1,7 @@
 module Marksman.MiscTests
 
+open System
+
 open Xunit
 
 open Misc
@@ -44,6 +46,25 @@ module StringExtensionsTests =
     [<Fact>]
     let slug_5 () = Assert.Equal("", "".Slug())
 
+    [<Fact>]
+    let slug_normalizationForm () =
+        // precomposed vs decomposed 'é'
+        Assert.Equal("caf\u00e9-crème".Slug(), "cafe\u0301-cre\u0300me".Slug())
+
+    [<Fact>]
+    let slug_caseFolding () = Assert.Equal("straße".Slug(), "STRAßE".Slug())
+
+    [<Fact>]
+    let isSubSequenceOf_normalizationForm () =
+        // Queries are normalized as they arrive
+        Assert.True("cafe\u0301".Normalize().IsSubSequenceOf("Le Caf\u00e9"))
+        Assert.True("CAF\u00c9".IsSubSequenceOf("le cafe\u0301"))
+
+    [<Fact>]
+    let isSubSequenceOf_cjkAndEmoji () =
+        Assert.True("日記".IsSubSequenceOf("日々の記録"))
+        Assert.True("🚀".IsSubSequenceOf("Launch 🚀 plan"))
+
     [<Fact>]
     let lines_1 () = Assert.Equal<string>([| "Line" |], "Line".Lines())
 
@@ -126,6 +147,16 @@ module PathUriTests =
         let puri = PathUri.ofString path
         Assert.Equal(uri, puri.DocumentUri)
 
+    [<Fact>]
+    let testUntitled () =
+        let uri = "untitled:Untitled-1"
+        let placed = PathUri.untitledIn "/notes" uri
+
+        Assert.True(placed.IsUntitled)
+        Assert.Equal(uri, placed.DocumentUri)
+        Assert.Equal(PathUri.ofString uri, placed)
+        Assert.NotEqual(PathUri.ofString "untitled:Untitled-2", placed)
+
 module LinkLabelTest =
     [<Fact>]
     let caseSensitivity () = Assert.Equal(LinkLabel.ofString "hello", LinkLabel.ofString "HELLO")
@@ -142,3 +173,88 @@ module WatchGlobTest =
     [<Fact>]
     let test1 () =
         Assert.Equal("**/*.{md,markdown,mdx}", mkWatchGlob [| "md"; "markdown"; "mdx" |])
+
+    [<Fact>]
+    let mixedCase () =
+        Assert.Equal("**/*.{md,Rmd,rmd}", mkWatchGlob [| "md"; ".Rmd" |])
+
+module MarkdownFileTests =
+    let exts = [| "md"; "qmd"; ".Rmd" |]
+
+    [<Fact>]
+    let configuredExtension () =
+        Assert.True(isMarkdownFile exts "/notes/doc.qmd")
+        Assert.True(isMarkdownFile exts "/notes/DOC.MD")
+
+    [<Fact>]
+    let extensionCaseIgnored () =
+        Assert.True(isMarkdownFile exts "/notes/analysis.Rmd")
+        Assert.True(isMarkdownFile exts "/notes/analysis.rmd")
+
+    [<Fact>]
+    let otherExtension () =
+        Assert.False(isMarkdownFile exts "/notes/doc.markdown")
+        Assert.False(isMarkdownFile exts "/notes/.#doc.md")
+
+module GitHubSlugTests =
+    [<Fact>]
+    let ofString_normalizationForm () =
+        let precomposed = GitHubSlug.ofString "R\u00e9sum\u00e9"
+        Assert.Equal(precomposed, GitHubSlug.ofString "Re\u0301sume\u0301")
+
+    [<Fact>]
+    let ofString_punctuation () =
+        Assert.Equal("whats-new-in-v20", GitHubSlug.ofString "What's new in v2.0?")
+
+    [<Fact>]
+    let ofString_keepsEverySpace () =
+        Assert.Equal("a---b_c", GitHubSlug.ofString "A - B_c")
+
+    [<Fact>]
+    let ofString_nonAscii () =
+        Assert.Equal("текст-на-языке", GitHubSlug.ofString "Текст на языке!")
+
+    [<Fact>]
+    let ofStrings_duplicates () =
+        Assert.Equal<string>(
+            [| "setup"; "setup-1"; "setup-2"; "other" |],
+            GitHubSlug.ofStrings [ "Setup"; "Setup"; "Setup"; "Other" ]
+        )
+
+module FuzzyTests =
+    let private scoreOf query candidate =
+        Fuzzy.score query candidate |> Option.defaultValue Int32.MinValue
+
+    [<Fact>]
+    let score_notSubsequence () = Assert.Equal(None, Fuzzy.score "xyz" "abc")
+
+    [<Fact>]
+    let score_emptyQuery () = Assert.Equal(Some 0, Fuzzy.score "" "abc")
+
+    [<Fact>]
+    let score_caseInsensitive () = Assert.True(Fuzzy.isMatch "ROOM" "Room")
+
+    [<Fact>]
+    let score_consecutiveBeatsScattered () =
+        Assert.True(scoreOf "doc" "document" > scoreOf "doc" "d_o_c")
+
+    [<Fact>]
+    let score_wordStartBeatsMidWord () =
+        Assert.True(scoreOf "b" "foo bar" > scoreOf "b" "foobar")
+
+    [<Fact>]
+    let score_initialismBeatsScattered () =
+        Assert.True(scoreOf "pkm" "Personal Knowledge Management" > scoreOf "pkm" "packmule")
+
+    [<Fact>]
+    let isAbbreviation_initialismsAndHumps () =
+        Assert.True(Fuzzy.isAbbreviation "pkm" "Personal Knowledge Management")
+        Assert.True(Fuzzy.isAbbreviation "NoteIdx" "Note Index")
+        Assert.True(Fuzzy.isAbbreviation "noteidx" "NoteIndex")
+        Assert.False(Fuzzy.isAbbreviation "ote" "Note Index")
+        Assert.False(Fuzzy.isAbbreviation "nIdx" "Nidx")
+        Assert.True(Fuzzy.isAbbreviation "kbase" "knowledge-base")
+
+    [<Fact>]
+    let sortText_betterFirst () =
+        Assert.True(Fuzzy.sortText 10 "b" < Fuzzy.sortText 5 "a")
@@ -0,0 +1,62 @@
+module Marksman.NoteGraphTests
+
+open Xunit
+
+open Marksman.Helpers
+open Marksman.NoteGraph
+
+let private graph () =
+    let docA = FakeDoc.Mk(path = "a.md", contentLines = [| "# A"; "#todo"; "[[b#S]]" |])
+    let docB = FakeDoc.Mk(path = "notes/b.md", contentLines = [| "# B"; "## S"; "[A](../a.md)" |])
+
+    FakeFolder.Mk([ docA; docB ]) |> ofFolder
+
+[<Fact>]
+let ofFolder_notes () =
+    let notes = (graph ()).notes
+
+    Assert.Equal<string>([| "a.md"; "notes/b.md" |], notes |> Array.map (fun note -> note.path))
+    Assert.Equal(Some "B", notes[1].title)
+    Assert.Equal<string>([| "todo" |], notes[0].tags)
+
+    Assert.Equal<int * string * int>(
+        [| 1, "B", 0; 2, "S", 1 |],
+        notes[1].headings |> Array.map (fun h -> h.level, h.title, h.line)
+    )
+
+[<Fact>]
+let ofFolder_links () =
+    let links = (graph ()).links
+
+    Assert.Equal<Edge>(
+        [| { source = "a.md"; target = "notes/b.md"; heading = Some "S"; line = 2 }
+           { source = "notes/b.md"; target = "a.md"; heading = None; line = 2 } |],
+        links
+    )
+
+[<Fact>]
+let ofFolder_backlinks () =
+    let notes = (graph ()).notes
+    Assert.Equal<int>([| 1; 1 |], notes |> Array.map (fun note -> note.backlinks))
+
+[<Fact>]
+let toDot_nodesAndEdges () =
+    let dot = graph () |> toDot
+
+    Assert.StartsWith("digraph notes {", dot)
+    Assert.Contains("  \"a.md\" [label=\"A\", tags=\"todo\", backlinks=1];", dot)
+    Assert.Contains("  \"a.md\" -> \"notes/b.md\" [label=\"S\"];", dot)
+    Assert.Contains("  \"notes/b.md\" -> \"a.md\";", dot)
+
+[<Fact>]
+let toGraphML_nodesAndEdges () =
+    let document = graph () |> toGraphML |> System.Xml.Linq.XDocument.Parse
+    let ns = System.Xml.Linq.XNamespace.Get("http://graphml.graphdrawing.org/xmlns")
+
+    let ids (name: string) (attr: string) =
+        document.Descendants(ns + name)
+        |> Seq.map (fun element -> element.Attribute(attr).Value)
+        |> Array.ofSeq
+
+    Assert.Equal<string>([| "a.md"; "notes/b.md" |], ids "node" "id")
+    Assert.Equal<string>([| "notes/b.md"; "a.md" |], ids "edge" "target")
@@ -0,0 +1,65 @@
+module Marksman.NumberingTests
+
+open Ionide.LanguageServerProtocol.Types
+open Xunit
+
+open Marksman.Cst
+open Marksman.Helpers
+open Marksman.Numbering
+
+let private doc =
+    FakeDoc.Mk(
+        path = "spec.md",
+        contentLines =
+            [| "# Spec"
+               "## Scope"
+               "3. one"
+               "3. two"
+               "7. three"
+               "### 9 Details"
+               "## 1.1 Terms"
+               "### Words" |]
+    )
+
+let private fmt (edit: TextEdit) =
+    edit.Range.Start.Line, edit.Range.Start.Character, edit.Range.End.Character, edit.NewText
+
+[<Fact>]
+let listEdits_fromFirstNumber () =
+    Assert.Equal<int * int * int * string>(
+        [| 3, 0, 1, "4"; 4, 0, 1, "5" |],
+        listEdits doc |> Array.map fmt
+    )
+
+[<Fact>]
+let headingNumbers_belowTitle () =
+    Assert.Equal<string * string>(
+        [| "Scope", "1"; "9 Details", "1.1"; "1.1 Terms", "2"; "Words", "2.1" |],
+        headingNumbers doc
+        |> Array.map (fun ({ data = heading }, number) -> heading.title.text, number)
+    )
+
+[<Fact>]
+let headingEdits_replaceExistingNumbers () =
+    Assert.Equal<int * int * int * string>(
+        [| 1, 3, 3, "1. "; 5, 4, 4, "1.1 "; 6, 3, 7, "2. "; 7, 4, 4, "2.1 " |],
+        headingEdits doc |> Array.map fmt
+    )
+
+[<Fact>]
+let headingEdits_titlesStartingWithNumbers () =
+    let doc =
+        FakeDoc.Mk(
+            path = "goals.md",
+            contentLines = [| "## 3 ways to go"; "## 2024 Goals"; "## 3 Plans"; "## 4. Done" |]
+        )
+
+    Assert.Equal<int * int * int * string>(
+        [| 0, 3, 3, "1. "; 1, 3, 3, "2. "; 2, 3, 5, "3. " |],
+        headingEdits doc |> Array.map fmt
+    )
+
+[<Fact>]
+let edits_headingsOptional () =
+    Assert.Equal(2, (edits false doc).Length)
+    Assert.Equal(6, (edits true doc).Length)
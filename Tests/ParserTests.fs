@@ -56,6 +56,35 @@ module HeadingTests =
         let document = scrapeString text
         checkSnapshot document
 
+    [<Fact>]
+    let parse_setext_headings () =
+        let text = "Title\n=====\nSub title\n---"
+        let document = scrapeString text
+
+        checkInlineSnapshot
+            document
+            [ "H1: range=(0,0)-(1,5); scope=(0,0)-(4,0)"
+              "  text=`Title"
+              "=====`"
+              "  title=`Title` @ (0,0)-(0,5)"
+              "  H2: range=(2,0)-(3,3); scope=(2,0)-(4,0)"
+              "    text=`Sub title"
+              "  ---`"
+              "    title=`Sub title` @ (2,0)-(2,9)" ]
+
+    [<Fact>]
+    let parse_heading_with_id () =
+        //          012345678901234567890
+        let text = "## Heading {#custom}"
+        let document = scrapeString text
+
+        checkInlineSnapshot
+            document
+            [ "H2: range=(0,0)-(0,20); scope=(0,0)-(1,0)"
+              "  text=`## Heading {#custom}`"
+              "  title=`Heading` @ (0,3)-(0,10)"
+              "  id=`custom` @ (0,13)-(0,19)" ]
+
 [<StoreSnapshotsPerClass>]
 module WikiLinkTests =
     [<Fact>]
@@ -65,6 +94,31 @@ module WikiLinkTests =
         let document = scrapeString text
         checkInlineSnapshot document [ "WL: [[note]]; (0,0)-(0,8)"; "  doc=note; (0,2)-(0,6)" ]
 
+    [<Fact>]
+    let parser_xref_note_alias () =
+        //          01234567890123456
+        let text = "[[note|Alias]]"
+        let document = scrapeString text
+
+        checkInlineSnapshot
+            document
+            [ "WL: [[note|Alias]]; (0,0)-(0,14)"
+              "  doc=note; (0,2)-(0,6)"
+              "  alias=Alias; (0,7)-(0,12)" ]
+
+    [<Fact>]
+    let parser_xref_note_heading_alias () =
+        //          012345678901234567890
+        let text = "[[note#head|C# tips]]"
+        let document = scrapeString text
+
+        checkInlineSnapshot
+            document
+            [ "WL: [[note#head|C# tips]]; (0,0)-(0,21)"
+              "  doc=note; (0,2)-(0,6)"
+              "  head=head; (0,7)-(0,11)"
+              "  alias=C# tips; (0,12)-(0,19)" ]
+
     [<Fact>]
     let parser_xref_note_heading () =
         //          01234567890123456
@@ -77,6 +131,29 @@ module WikiLinkTests =
               "  doc=note; (0,2)-(0,6)"
               "  head=heading; (0,7)-(0,14)" ]
 
+    [<Fact>]
+    let parser_embed_note_heading () =
+        //          01234567890123456
+        let text = "![[note#heading]]"
+        let document = scrapeString text
+
+        checkInlineSnapshot
+            document
+            [ "WL: ![[note#heading]]; (0,0)-(0,17)"
+              "  doc=note; (0,3)-(0,7)"
+              "  head=heading; (0,8)-(0,15)"
+              "  embed" ]
+
+    [<Fact>]
+    let parser_embed_text_before () =
+        //          0123456789012
+        let text = "Before ![[N]]"
+        let document = scrapeString text
+
+        checkInlineSnapshot
+            document
+            [ "WL: ![[N]]; (0,7)-(0,13)"; "  doc=N; (0,10)-(0,11)"; "  embed" ]
+
     [<Fact>]
     let parser_xref_text_before () =
         //          0123456789012
@@ -151,6 +228,19 @@ module WikiLinkTests =
         let document = scrapeString text
         checkSnapshot document
 
+[<Collection(GlobalState)>]
+module WikiLinkEncodingTests =
+    [<Fact>]
+    let parser_xref_note_alias_utf8 () =
+        let text = "ü [[café|x]]"
+        let document = withPositionEncoding Text.Utf8 (fun () -> scrapeString text)
+
+        checkInlineSnapshot
+            document
+            [ "WL: [[café|x]]; (0,3)-(0,14)"
+              "  doc=café; (0,5)-(0,10)"
+              "  alias=x; (0,11)-(0,12)" ]
+
 [<StoreSnapshotsPerClass>]
 module MdLinkTest =
     [<Fact>]
@@ -219,6 +309,17 @@ module MdLinkTest =
             [ "ML: [](url) @ (0,0)-(0,7)"
               "  IL: label= @ (0,0)-(0,0); url=url @ (0,3)-(0,6); title=∅" ]
 
+    [<Fact>]
+    let parser_link_angleBrackets () =
+        //          0123456789012345678
+        let text = "[](<my note.md#h>)"
+        let document = scrapeString text
+
+        checkInlineSnapshot
+            document
+            [ "ML: [](<my note.md#h>) @ (0,0)-(0,18)"
+              "  IL: label= @ (0,0)-(0,0); url=my note.md#h @ (0,4)-(0,16); title=∅" ]
+
     [<Fact>]
     let parser_link_8 () =
         let text = "[short_cut]"
@@ -294,9 +395,9 @@ module MdLinkTest =
               "  label=ref @ (2,1)-(2,4); url=https://some.url @ (2,7)-(2,23); title=∅" ]
 
 module FootnoteTests =
-    [<Fact(Skip = "Footnote parsing not implemented")>]
+    [<Fact>]
     let footnote_1 () =
-        let text = "[^1]\n\n[^1]: Single line footnote"
+        let text = "[^1]\n\n[^1]: Footnote"
         let document = scrapeString text
 
         checkInlineSnapshot
@@ -306,6 +407,258 @@ module FootnoteTests =
               "MLD: [^1]: Footnote @ (2,0)-(2,14)"
               "  label=^1 @ (2,1)-(2,3); url=Footnote @ (2,6)-(2,14); title=∅" ]
 
+    [<Fact>]
+    let footnote_continuationLines () =
+        let text = "[^1]: First\n    second\n\n    third\nlazy\n\nAfter\n[^2]: a\nb\n# H"
+
+        let defs =
+            scrapeString text
+            |> Array.choose (function
+                | MLD def -> Some(def.range.DebuggerDisplay, (MdLinkDef.url def.data).text)
+                | _ -> None)
+
+        Assert.Equal<string * string>(
+            [| "(0,0)-(4,4)", "First\n    second\n\n    third\nlazy"; "(7,0)-(8,1)", "a\nb" |],
+            defs
+        )
+
+    [<Fact>]
+    let footnote_notAtLineStart () =
+        let text = "Text [^1]: not a footnote"
+        let document = scrapeString text
+
+        checkInlineSnapshot document [ "ML: [^1] @ (0,5)-(0,9)"; "  RS: label=^1 @ (0,6)-(0,8)" ]
+
+module BlockAnchorTests =
+    [<Fact>]
+    let blockAnchor_endOfParagraph () =
+        //          0123456789012345678
+        let text = "Some text\nmore ^abc-1\n\nNo anchor^here"
+        let cst = scrapeString text
+
+        checkInlineSnapshot
+            cst
+            [ "BA: id=abc-1; range=(1,6)-(1,11); scope=(0,0)-(1,11) @ (1,5)-(1,11)" ]
+
+    [<Fact>]
+    let blockAnchor_notInTheMiddle () =
+        let text = "Some ^abc text"
+        let cst = scrapeString text
+
+        checkInlineSnapshot cst []
+
+module CodeTests =
+    [<Fact>]
+    let code_noLinksInFencedBlock () =
+        let text = "```md\n[not a link](x)\n#not-a-tag\n```"
+        let cst = scrapeString text
+
+        checkInlineSnapshot
+            cst
+            [ "C: block; info=md @ (0,0)-(3,3)" ]
+
+    [<Fact>]
+    let code_noLinksInInlineCode () =
+        //          0123456789012345678901
+        let text = "Text `[[not-a-link]]` [[link]]"
+        let cst = scrapeString text
+
+        checkInlineSnapshot
+            cst
+            [ "C: inline @ (0,5)-(0,21)"
+              "WL: [[link]]; (0,22)-(0,30)"
+              "  doc=link; (0,24)-(0,28)" ]
+
+    [<Fact>]
+    let code_noBlockAnchorInInlineCode () =
+        let text = "Text `code ^abc`"
+        let cst = scrapeString text
+
+        checkInlineSnapshot cst [ "C: inline @ (0,5)-(0,16)" ]
+
+    [<Fact>]
+    let math_noLinksInInlineMath () =
+        //          0123456789012345678
+        let text = "Set $[a]_{[[i]]}$ #tag"
+        let cst = scrapeString text
+
+        checkInlineSnapshot
+            cst
+            [ "C: math; inline @ (0,4)-(0,17)"
+              "T: name=tag; range=(0,19)-(0,22) @ (0,18)-(0,22)" ]
+
+    [<Fact>]
+    let math_displayBlock () =
+        let text = "$$\n\\sum_{[[i]]} x\n$$"
+        let cst = scrapeString text
+
+        checkInlineSnapshot cst [ "C: math; display @ (0,0)-(2,2)" ]
+
+module HtmlTests =
+    [<Fact>]
+    let html_commentExcluded () =
+        //          0123456789012345678901234567
+        let text = "See <!-- [[hidden]] --> #tag"
+        let cst = scrapeString text
+
+        checkInlineSnapshot
+            cst
+            [ "C: html @ (0,4)-(0,23)"
+              "T: name=tag; range=(0,25)-(0,28) @ (0,24)-(0,28)" ]
+
+    [<Fact>]
+    let html_blockExcluded () =
+        let text = "<div>\n[[hidden]] #tag\n</div>"
+        let cst = scrapeString text
+
+        checkInlineSnapshot cst [ "C: html @ (0,0)-(2,6)" ]
+
+    [<Fact>]
+    let html_disabledRegion () =
+        let text =
+            "[[a]]\n\n<!-- marksman:off -->\n# Ignored\n[[b]]\n<!-- marksman:on -->\n\n[[c]]"
+
+        let links =
+            scrapeString text
+            |> Array.choose (function
+                | WL link -> Some link.text
+                | _ -> None)
+
+        let hasHeading =
+            scrapeString text
+            |> Array.exists (function
+                | H _ -> true
+                | _ -> false)
+
+        Assert.Equal<string>([| "[[a]]"; "[[c]]" |], links)
+        Assert.False(hasHeading)
+
+    [<Fact>]
+    let html_disabledUntilEnd () =
+        let text = "[[a]]\n\n<!--marksman:off-->\n\n[[b]]"
+
+        let links =
+            scrapeString text
+            |> Array.filter (function
+                | WL _ -> true
+                | _ -> false)
+
+        Assert.Equal(1, links.Length)
+
+module CitationTests =
+    [<Fact>]
+    let citation_single () =
+        //          012345678901
+        let text = "As [@doe99]."
+        let cst = scrapeString text
+
+        checkInlineSnapshot cst [ "CT: key=doe99; range=(0,5)-(0,10) @ (0,4)-(0,10)" ]
+
+    [<Fact>]
+    let citation_multiple () =
+        //          0123456789012345678901234567
+        let text = "[see @doe:99, p. 3; @roe-x]"
+        let cst = scrapeString text
+
+        checkInlineSnapshot
+            cst
+            [ "CT: key=doe:99; range=(0,6)-(0,12) @ (0,5)-(0,12)"
+              "CT: key=roe-x; range=(0,21)-(0,26) @ (0,20)-(0,26)" ]
+
+    [<Fact>]
+    let citation_emailIsNotCitation () =
+        let text = "[mail me@example.com]"
+        let cst = scrapeString text
+
+        checkInlineSnapshot
+            cst
+            [ "ML: [mail me@example.com] @ (0,0)-(0,21)"
+              "  RS: label=mail me@example.com @ (0,1)-(0,20)" ]
+
+module CalloutTests =
+    [<Fact>]
+    let callout_withTitle () =
+        //          0123456789012345678
+        let text = "> [!NOTE] Read this\n> Body [[doc]]"
+        let cst = scrapeString text
+
+        checkInlineSnapshot
+            cst
+            [ "CO: kind=NOTE; title=Read this @ (0,10)-(0,19); scope=(0,0)-(1,14) @ (0,2)-(0,9)"
+              "WL: [[doc]]; (1,7)-(1,14)"
+              "  doc=doc; (1,9)-(1,12)" ]
+
+    [<Fact>]
+    let callout_foldableWithoutTitle () =
+        let text = "> [!tip]-\n> Hidden"
+        let cst = scrapeString text
+
+        checkInlineSnapshot cst [ "CO: kind=tip; title=∅; scope=(0,0)-(1,8) @ (0,2)-(0,8)" ]
+
+    [<Fact>]
+    let callout_onlyAtQuoteStart () =
+        let text = "> Quote\n> [!NOTE]\n\n[!NOTE] not quoted"
+
+        let callouts =
+            scrapeString text
+            |> Array.filter (function
+                | CO _ -> true
+                | _ -> false)
+
+        Assert.Empty(callouts)
+
+module TaskTests =
+    [<Fact>]
+    let tasks_openAndDone () =
+        let text = "- [ ] Buy milk\n- [x] Call [[mom]]\n- [X]\n- not a task"
+        let cst = scrapeString text
+
+        checkInlineSnapshot
+            cst
+            [ "TK: open; text=Buy milk @ (0,6)-(0,14) @ (0,2)-(0,5)"
+              "TK: done; text=Call [[mom]] @ (1,6)-(1,18) @ (1,2)-(1,5)"
+              "WL: [[mom]]; (1,11)-(1,18)"
+              "  doc=mom; (1,13)-(1,16)"
+              "TK: done; text= @ (2,5)-(2,5) @ (2,2)-(2,5)" ]
+
+    [<Fact>]
+    let tasks_onlyInListItems () =
+        let text = "[ ] not in a list\n\n- text\n\n  [x] not first"
+        let tasks =
+            scrapeString text
+            |> Array.filter (function
+                | TK _ -> true
+                | _ -> false)
+
+        Assert.Empty(tasks)
+
+module TableTests =
+    [<Fact>]
+    let table_cellsAndAlignment () =
+        let text = "| a | b |\n|:--|--:|\n| 1 | [[x]] |"
+        let cst = scrapeString text
+
+        checkInlineSnapshot
+            cst
+            [ "TB: (0,0)-(2,13)"
+              "  header=a @ (0,2)-(0,3); b @ (0,6)-(0,7)"
+              "  align=left; right"
+              "  row=1 @ (2,2)-(2,3); [[x]] @ (2,6)-(2,11)"
+              "WL: [[x]]; (2,6)-(2,11)"
+              "  doc=x; (2,8)-(2,9)" ]
+
+    [<Fact>]
+    let table_noOuterPipesAndEscapedPipe () =
+        let text = "a | b\n--- | :-:\nx \\| y |"
+        let cst = scrapeString text
+
+        checkInlineSnapshot
+            cst
+            [ "TB: (0,0)-(2,8)"
+              "  header=a @ (0,0)-(0,1); b @ (0,4)-(0,5)"
+              "  align=default; center"
+              "  row=x \\| y @ (2,0)-(2,6)" ]
+
 module TagsTests =
     [<Fact>]
     let tags_1 () =
@@ -369,3 +722,65 @@ module RegressionTests =
 
         let actual = scrapeString content
         checkInlineSnapshot actual []
+
+module ReparseTests =
+    let private fmtCst (cst: Cst) = cst |> Array.map Element.fmt |> concatLines
+
+    // Reparsing after the change has to give what parsing the changed text from scratch gives
+    let private checkReparse (prev: string) (next: string) =
+        let prevText = Text.mkText prev
+        let nextText = Text.mkText next
+        let reparsed = reparseText prevText (parseText prevText) nextText
+        Assert.Equal(fmtCst (parseText nextText), fmtCst reparsed)
+
+    let private note =
+        [| "---"
+           "tags: [a]"
+           "---"
+           "# Title"
+           "Intro [[other]]"
+           "## First"
+           "- [ ] task #tag"
+           "## Second"
+           "Para with [a link](a.md) ^anchor"
+           "### Nested"
+           "| a | b |"
+           "|---|---|"
+           "| [[x]] | y |"
+           "## Third"
+           "> [!NOTE] Callout"
+           "Last [[y#z]]" |]
+        |> concatLines
+
+    let private changeLines (f: array<string> -> array<string>) (text: string) =
+        text.Lines() |> f |> concatLines
+
+    [<Fact>]
+    let editWithinSection () =
+        let next = note.Replace("Para with", "Para [[new]] with\n\nmore lines\n")
+        checkReparse note next
+
+    [<Fact>]
+    let editBeforeFirstHeading () =
+        checkReparse note (note.Replace("tags: [a]", "tags: [a, b]"))
+
+    [<Fact>]
+    let headingLevelChanged () =
+        checkReparse note (note.Replace("### Nested", "# Nested"))
+
+    [<Fact>]
+    let headingRemoved () =
+        checkReparse note (changeLines (Array.filter (fun line -> line <> "## Second")) note)
+
+    [<Fact>]
+    let fenceOpened () =
+        // The fence swallows the headings that follow it
+        checkReparse note (note.Replace("Para with", "```\nPara with"))
+
+    [<Fact>]
+    let linkDefAdded () =
+        checkReparse note (note.Replace("Para with", "[a]: b.md\n\nPara with [a]"))
+
+    [<Fact>]
+    let unchanged () =
+        checkReparse note note
@@ -1,6 +1,7 @@
 module Marksman.RefactorTests
 
 open System.IO
+open System.Text.RegularExpressions
 open Ionide.LanguageServerProtocol.Types
 open Xunit
 open Misc
@@ -9,12 +10,18 @@ let editRanges =
     function
     | Refactor.Edit wsEdit ->
         match wsEdit.DocumentChanges with
-        | Some docEdits ->
-            docEdits
-            |> Array.map (fun docEdit ->
-                let doc = Path.GetFileName(docEdit.TextDocument.Uri)
-                let ranges = docEdit.Edits |> Array.map (fun x -> x.Range)
-                doc, ranges)
+        | Some docChanges ->
+            docChanges
+            |> Array.choose (function
+                | DocumentChange.Edit docEdit ->
+                    let doc = Path.GetFileName(docEdit.TextDocument.Uri)
+                    let ranges = docEdit.Edits |> Array.map (fun x -> x.Range)
+                    Some(doc, ranges)
+                | DocumentChange.AnnotatedEdit docEdit ->
+                    let doc = Path.GetFileName(docEdit.TextDocument.Uri)
+                    let ranges = docEdit.Edits |> Array.map (fun x -> x.Range)
+                    Some(doc, ranges)
+                | DocumentChange.Delete _ -> None)
             |> Map.ofArray
         | _ ->
             match wsEdit.Changes with
@@ -147,3 +154,242 @@ module RenameTests =
 
             Assert.Equal<Range>(Map.find "doc1.md" expectedRanges, Map.find "doc1.md" actualRanges)
             Assert.Equal<Range>(Map.find "doc2.md" expectedRanges, Map.find "doc2.md" actualRanges)
+
+module MergeTests =
+    let mkWorkspace () =
+        let doc1 = Helpers.FakeDoc.Mk([| "# Doc 1"; "See [[doc-2#section]]." |], path = "doc1.md")
+
+        let doc2 =
+            Helpers.FakeDoc.Mk(
+                [| "---"
+                   "tags: [a]"
+                   "---"
+                   "# Doc 2"
+                   "## Section"
+                   "Back to [[doc-2]], [here](doc2.md#section)." |],
+                path = "doc2.md"
+            )
+
+        let doc3 = Helpers.FakeDoc.Mk([| "# Doc 3"; "[[doc-2]]" |], path = "doc3.md")
+        doc1, doc2, doc3, Helpers.FakeFolder.Mk([ doc1; doc2; doc3 ])
+
+    [<Fact>]
+    let mergedContent_demotedAndRetargeted () =
+        let doc1, doc2, _, folder = mkWorkspace ()
+
+        Assert.Equal(
+            "## Doc 2\n### Section\nBack to [[doc-1]], [here](doc1.md#section).\n",
+            Refactor.mergedContent folder doc2 doc1
+        )
+
+    [<Fact>]
+    let mergedContent_rebasedOntoOtherDirectory () =
+        let dest = Helpers.FakeDoc.Mk([| "# Dest" |], path = "dest.md")
+
+        let src =
+            Helpers.FakeDoc.Mk(
+                [| "# Src"; "[Other](../other.md#intro), ![](img/a%20b.png), [up](/root.md)" |],
+                path = "notes/a/src.md"
+            )
+
+        let other = Helpers.FakeDoc.Mk([| "# Other"; "## Intro" |], path = "notes/other.md")
+        let folder = Helpers.FakeFolder.Mk([ dest; src; other ])
+
+        Assert.Equal(
+            "## Src\n[Other](notes/other.md#intro), ![](notes/a/img/a%20b.png), [up](/root.md)\n",
+            Refactor.mergedContent folder src dest
+        )
+
+    [<Fact>]
+    let mergeNotes_editsAndDelete () =
+        let doc1, doc2, doc3, folder = mkWorkspace ()
+
+        match Refactor.mergeNotes folder doc2 doc1 with
+        | Ok edit ->
+            let changes = edit.DocumentChanges |> Option.defaultValue [||]
+
+            let deleted =
+                changes
+                |> Array.choose (function
+                    | DocumentChange.Delete delete -> Some delete.Uri
+                    | DocumentChange.Edit _
+                    | DocumentChange.AnnotatedEdit _ -> None)
+
+            Assert.Equal<string>([| Workspace.Doc.uri doc2 |], deleted)
+
+            let ranges = editRanges (Refactor.Edit edit)
+            Assert.Equal<Range>([| Range.Mk(1, 2, 1, 7) |], Map.find "doc3.md" ranges)
+
+            // The appended note comes after the link edit
+            Assert.Equal<Range>(
+                [| Range.Mk(1, 22, 1, 22); Range.Mk(1, 6, 1, 11) |],
+                Map.find "doc1.md" ranges
+            )
+        | Error err -> failwith err
+
+    [<Fact>]
+    let mergeNotes_intoItself () =
+        let doc1, _, _, folder = mkWorkspace ()
+
+        match Refactor.mergeNotes folder doc1 doc1 with
+        | Error _ -> ()
+        | Ok edit -> failwith $"Unexpected edit: {edit}"
+
+module SectionLevelTests =
+    let doc = Helpers.FakeDoc.Mk([| "# T"; "## A"; "### A1"; "## B" |], path = "doc.md")
+
+    let actionsAt line =
+        Refactor.sectionLevelActions true doc (Range.Mk(line, 1, line, 1))
+        |> Array.map (fun (name, edit) -> name, editRanges (Refactor.Edit edit))
+
+    [<Fact>]
+    let subsection_promoteAndDemote () =
+        match actionsAt 1 with
+        | [| promote, promoteRanges; demote, demoteRanges |] ->
+            Assert.Equal("Promote the section", promote)
+            Assert.Equal("Demote the section", demote)
+
+            Assert.Equal<Range>(
+                [| Range.Mk(2, 0, 2, 1); Range.Mk(1, 0, 1, 1) |],
+                Map.find "doc.md" promoteRanges
+            )
+
+            Assert.Equal<Range>(
+                [| Range.Mk(2, 0, 2, 0); Range.Mk(1, 0, 1, 0) |],
+                Map.find "doc.md" demoteRanges
+            )
+        | other -> failwith $"Unexpected actions: {other}"
+
+    [<Fact>]
+    let title_onlyDemote () =
+        Assert.Equal<string>([| "Demote the section" |], actionsAt 0 |> Array.map fst)
+
+    [<Fact>]
+    let notHeading_none () =
+        let doc = Helpers.FakeDoc.Mk([| "# T"; "text" |], path = "doc.md")
+        Assert.Empty(Refactor.sectionLevelActions true doc (Range.Mk(1, 0, 1, 0)))
+
+module SectionOrderTests =
+    let doc =
+        Helpers.FakeDoc.Mk(
+            [| "# T"; "## B"; "text"; ""; "## A"; "### A1"; "## C" |],
+            path = "doc.md"
+        )
+
+    let actionsAt line =
+        Refactor.sectionOrderActions false doc (Range.Mk(line, 0, line, 0))
+
+    let applied line name =
+        let _, wsEdit = actionsAt line |> Array.find (fun (actionName, _) -> actionName = name)
+        let edit = Map.find (Doc.uri doc) wsEdit.Changes.Value |> Array.exactlyOne
+        let before, after = (Doc.text doc).Cutout(edit.Range)
+        before + edit.NewText + after
+
+    let reordered =
+        String.concat
+            System.Environment.NewLine
+            [ "# T"; "## A"; "### A1"; ""; "## B"; "text"; "## C" ]
+
+    [<Fact>]
+    let moveUp_keepsSpacing () =
+        Assert.Equal(reordered, applied 4 "Move the section up")
+
+    [<Fact>]
+    let sort_byTitle () =
+        Assert.Equal(reordered, applied 0 "Sort the subsections")
+
+    [<Fact>]
+    let actions_onlyWhereTheyApply () =
+        Assert.Equal<string>([| "Move the section up" |], actionsAt 6 |> Array.map fst)
+        Assert.Empty(actionsAt 5)
+        Assert.Empty(actionsAt 2)
+
+module ConvertLinksTests =
+    let doc1 =
+        Helpers.FakeDoc.Mk(
+            [| "# Doc 1"; "See [[doc-2#section|the section]] and [[doc-2]], not [[missing]]." |],
+            path = "doc1.md"
+        )
+
+    let doc2 =
+        Helpers.FakeDoc.Mk(
+            [| "# Doc 2"; "## Section"; "Back to [Doc 1](doc1.md) and [more](doc1.md)." |],
+            path = "doc2.md"
+        )
+
+    let folder = Helpers.FakeFolder.Mk([ doc1; doc2 ])
+
+    let newTexts doc style =
+        Refactor.convertLinks folder doc style
+        |> Array.sortBy (fun edit -> edit.Range.Start)
+        |> Array.map (fun edit -> edit.NewText)
+
+    [<Fact>]
+    let toMarkdown_keepsAliasAndHeading () =
+        Assert.Equal<string>(
+            [| "[the section](doc2.md#section)"; "[Doc 2](doc2.md)" |],
+            newTexts doc1 Refactor.LinkStyle.Markdown
+        )
+
+    [<Fact>]
+    let toWiki_aliasUnlessShown () =
+        Assert.Equal<string>(
+            [| "[[doc-1]]"; "[[doc-1|more]]" |],
+            newTexts doc2 Refactor.LinkStyle.Wiki
+        )
+
+    [<Fact>]
+    let sameStyle_noEdits () =
+        Assert.Empty(newTexts doc1 Refactor.LinkStyle.Wiki)
+        Assert.Equal(None, Refactor.convertedText folder doc2 Refactor.LinkStyle.Markdown)
+
+    [<Fact>]
+    let toMarkdown_encodesPath () =
+        let note = Helpers.FakeDoc.Mk([| "# My note" |], path = "my note.md")
+        let src = Helpers.FakeDoc.Mk([| "# Src"; "[[my note]]" |], path = "src.md")
+        let folder = Helpers.FakeFolder.Mk([ note; src ])
+
+        let converted =
+            Refactor.convertedText folder src Refactor.LinkStyle.Markdown |> Option.get
+
+        Assert.Equal<string>([| "[My note](my%20note.md)" |], converted.Split('\n')[1..])
+
+        // The converted link still points to the note
+        let src = Helpers.FakeDoc.Mk(converted.Split('\n'), path = "src.md")
+        let folder = Helpers.FakeFolder.Mk([ note; src ])
+        let link = Index.links (Workspace.Doc.index src) |> Seq.exactlyOne
+
+        Assert.Equal<string list>(
+            [ "my note.md" ],
+            Refs.Dest.tryResolveElement folder src link
+            |> Seq.map (Refs.Dest.doc >> Workspace.Doc.pathFromRoot)
+            |> List.ofSeq
+        )
+
+module FindAndReplaceTests =
+    let doc = Helpers.FakeDoc.Mk([| "# Notes"; "A #draft and #drafts."; "#draft" |])
+
+    let edits pattern replacement =
+        Refactor.replaceEdits pattern replacement doc
+        |> Array.map (fun edit -> edit.Range.Start.Line, edit.Range.Start.Character, edit.NewText)
+
+    [<Fact>]
+    let literal () =
+        Assert.Equal<int * int * string>(
+            [| 1, 2, "#final"; 1, 13, "#final"; 2, 0, "#final" |],
+            edits (Refactor.Pattern.Literal "#draft") "#final"
+        )
+
+    [<Fact>]
+    let regex_withGroups () =
+        let pattern = Refactor.Pattern.Regex(Regex(@"#draft(s?)\b"))
+
+        Assert.Equal<int * int * string>(
+            [| 1, 2, "#final"; 1, 13, "#finals"; 2, 0, "#final" |],
+            edits pattern "#final$1"
+        )
+
+    [<Fact>]
+    let emptyMatches_skipped () =
+        Assert.Empty(edits (Refactor.Pattern.Regex(Regex("x*"))) "y")
+        Assert.Empty(edits (Refactor.Pattern.Literal "") "y")
@@ -6,6 +6,7 @@ open Xunit
 
 open Marksman.Misc
 open Marksman.Helpers
+open Marksman.Config
 open Marksman.Cst
 open Marksman.Workspace
 open Marksman.Refs
@@ -165,6 +166,26 @@ module FileLinkTests =
 
         Assert.Equal<Doc>(actual, [| doc2 |])
 
+    [<Fact>]
+    let zettelId () =
+        let note = FakeDoc.Mk(path = "202401121230 Idea.md", contentLines = [| "# Idea" |])
+        let linking = FakeDoc.Mk(path = "linking.md", contentLines = [| "[[202401121230]]" |])
+
+        let linksOf config =
+            let folder = FakeFolder.Mk([ note; linking ], config)
+
+            FileLink.filterMatchingDocs folder linking (InternName "202401121230")
+            |> Seq.map (fun link -> link.dest, link.kind)
+            |> Array.ofSeq
+
+        Assert.Empty(linksOf Config.Config.Default)
+
+        let enabled = { Config.Config.Default with coreZettelIdEnable = Some true }
+        Assert.Equal<Doc * FileLinkKind>([| note, FileLinkKind.ZettelId |], linksOf enabled)
+
+        let folder = FakeFolder.Mk([ note; linking ], enabled)
+        Assert.Contains(linking, Folder.docsLinkingTo note folder)
+
 
 
 module BasicRefsTests =
@@ -273,7 +294,7 @@ module BasicRefsTests =
               "(doc2.md, (4,0)-(4,11))"
               "(doc2.md, (8,0)-(8,11))" ]
 
-    [<Fact(Skip = "Footnote parsing not implemented")>]
+    [<Fact>]
     let refToFootnote_atLink () =
         let fnLink =
             Cst.elementAtPos (Position.Mk(15, 2)) (Doc.cst doc2)
@@ -281,10 +302,13 @@ module BasicRefsTests =
 
         let refs = Dest.findElementRefs true folder doc2 fnLink |> stripRefs
 
+        // Like the one of a link definition, the range of a footnote is its whole definition,
+        // `[^fn1]: This is footnote`. The range expected while footnotes weren't parsed stopped
+        // short of its end
         checkInlineSnapshot
             (fun x -> x.ToString())
             refs
-            [ "(doc2.md, (19,0)-(19,16))"; "(doc2.md, (15,0)-(15,6))" ]
+            [ "(doc2.md, (19,0)-(19,24))"; "(doc2.md, (15,0)-(15,6))" ]
 
     [<Fact>]
     let refToDoc_atTitle () =
@@ -395,3 +419,268 @@ module LinkKindRefsTests =
             (fun x -> x.ToString())
             refs
             [ "(file1.md, (6,0)-(6,9))"; "(file2.md, (2,0)-(2,15))" ]
+
+module TagRefsTests =
+    let doc1 =
+        FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "#tag and #other"; "#tag" |])
+
+    let doc2 =
+        FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2"; "Text #tag" |])
+
+    let doc3 = FakeDoc.Mk(path = "doc3.md", contentLines = [| "# Doc 3"; "#other" |])
+
+    let folder = FakeFolder.Mk([ doc1; doc2; doc3 ])
+
+    let fmtRef (doc: Doc, el: Element) =
+        $"({Doc.pathFromRoot doc}, {(Element.range el).DebuggerDisplay})"
+
+    [<Fact>]
+    let tagRefs_acrossDocs () =
+        let tag =
+            match requireElementAtPos doc1 1 2 with
+            | T tag -> tag
+            | other -> failwith $"Expected a tag, got {other}"
+
+        let refs = Dest.findTagRefs folder tag |> Seq.sortBy fmtRef
+
+        checkInlineSnapshot
+            fmtRef
+            refs
+            [ "(doc1.md, (1,0)-(1,4))"; "(doc1.md, (2,0)-(2,4))"; "(doc2.md, (1,5)-(1,9))" ]
+
+    [<Fact>]
+    let tagIndex_updatedOnDocChange () =
+        let doc2Updated =
+            FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2"; "Text #other" |])
+
+        let folder = Folder.withDoc doc2Updated folder
+
+        let withTag tag =
+            Folder.docsWithTag tag folder
+            |> Seq.map Doc.pathFromRoot
+            |> Seq.sort
+            |> Array.ofSeq
+
+        Assert.Equal<string>([| "doc1.md" |], withTag "tag")
+        Assert.Equal<string>([| "doc1.md"; "doc2.md"; "doc3.md" |], withTag "other")
+
+        let folder = Folder.withoutDoc (Doc.path doc1) folder |> Option.get
+
+        Assert.Empty(Folder.docsWithTag "tag" folder)
+
+module BlockRefsTests =
+    let doc1 =
+        FakeDoc.Mk(
+            path = "doc1.md",
+            contentLines = [| "# Doc 1"; ""; "A paragraph"; "to link ^para1"; ""; "[[#^para1]]" |]
+        )
+
+    let doc2 =
+        FakeDoc.Mk(path = "doc2.md", contentLines = [| "# Doc 2"; "[[doc1#^para1]]"; "[[doc1#^nope]]" |])
+
+    let folder = FakeFolder.Mk([ doc1; doc2 ])
+
+    [<Fact>]
+    let resolveBlockRef_otherDoc () =
+        let link = requireElementAtPos doc2 1 3
+        let dest = Dest.tryResolveElement folder doc2 link |> Array.ofSeq
+
+        Assert.Equal(1, dest.Length)
+        Assert.Equal("doc1.md", Path.GetFileName(Dest.uri dest[0]))
+        Assert.Equal("(2,0)-(3,14)", (Dest.range dest[0]).DebuggerDisplay)
+
+    [<Fact>]
+    let resolveBlockRef_missing () =
+        let link = requireElementAtPos doc2 2 3
+        Assert.Empty(Dest.tryResolveElement folder doc2 link)
+
+    [<Fact>]
+    let refsToBlock_atAnchor () =
+        let anchor = requireElementAtPos doc1 3 10
+        let refs = Dest.findElementRefs false folder doc1 anchor |> stripRefs
+
+        checkInlineSnapshot
+            (fun x -> x.ToString())
+            refs
+            [ "(doc1.md, (5,0)-(5,11))"; "(doc2.md, (1,0)-(1,15))" ]
+
+module HeadingIdRefsTests =
+    let doc1 =
+        FakeDoc.Mk(path = "doc1.md", contentLines = [| "# Doc 1"; "## Long heading {#short}" |])
+
+    let doc2 =
+        FakeDoc.Mk(
+            path = "doc2.md",
+            contentLines =
+                [| "# Doc 2"
+                   "[[doc1#short]]"
+                   "[[doc1#long-heading]]"
+                   "[](doc1.md#short)" |]
+        )
+
+    let folder = FakeFolder.Mk([ doc1; doc2 ])
+
+    [<Fact>]
+    let resolveById () =
+        for line in [ 1; 2; 3 ] do
+            let link = requireElementAtPos doc2 line 3
+            let dest = Dest.tryResolveElement folder doc2 link |> Array.ofSeq
+
+            Assert.Equal(1, dest.Length)
+            Assert.Equal("(1,0)-(1,24)", (Dest.range dest[0]).DebuggerDisplay)
+
+module GitHubAnchorRefsTests =
+    let doc1 =
+        FakeDoc.Mk(
+            path = "doc1.md",
+            contentLines = [| "# Doc 1"; "## Setup"; "## Setup"; "## A - B" |]
+        )
+
+    let doc2 =
+        FakeDoc.Mk(
+            path = "doc2.md",
+            contentLines =
+                [| "# Doc 2"
+                   "[](doc1.md#setup)"
+                   "[](doc1.md#setup-1)"
+                   "[](doc1.md#a---b)" |]
+        )
+
+    let folder = FakeFolder.Mk([ doc1; doc2 ])
+
+    let resolveAt line =
+        let link = requireElementAtPos doc2 line 3
+
+        Dest.tryResolveElement folder doc2 link
+        |> Seq.map (fun dest -> (Dest.range dest).DebuggerDisplay)
+        |> List.ofSeq
+
+    [<Fact>]
+    let plainSlugMatchesAllDuplicates () =
+        Assert.Equal<string list>([ "(1,0)-(1,8)"; "(2,0)-(2,8)" ], resolveAt 1)
+
+    [<Fact>]
+    let numericSuffixPicksDuplicate () =
+        Assert.Equal<string list>([ "(2,0)-(2,8)" ], resolveAt 2)
+
+    [<Fact>]
+    let repeatedHyphens () = Assert.Equal<string list>([ "(3,0)-(3,8)" ], resolveAt 3)
+
+module EncodedUrlRefsTests =
+    let doc1 =
+        FakeDoc.Mk(path = "my note.md", contentLines = [| "# My note"; "## Some heading" |])
+
+    let doc2 =
+        FakeDoc.Mk(
+            path = "doc2.md",
+            contentLines =
+                [| "# Doc 2"
+                   "[](<my note.md#some-heading>)"
+                   "[](my%20note.md#some%20heading)"
+                   "[](<my note.md>)" |]
+        )
+
+    let folder = FakeFolder.Mk([ doc1; doc2 ])
+
+    let resolveAt line =
+        let link = requireElementAtPos doc2 line 5
+
+        Dest.tryResolveElement folder doc2 link
+        |> Seq.map (fun dest -> (Dest.range dest).DebuggerDisplay)
+        |> List.ofSeq
+
+    [<Fact>]
+    let angleBrackets () = Assert.Equal<string list>([ "(1,0)-(1,15)" ], resolveAt 1)
+
+    [<Fact>]
+    let percentEncoded () = Assert.Equal<string list>([ "(1,0)-(1,15)" ], resolveAt 2)
+
+    [<Fact>]
+    let angleBracketsDoc () = Assert.Equal<string list>([ "(0,0)-(0,9)" ], resolveAt 3)
+
+module LinkResolutionTests =
+    let docA = FakeDoc.Mk(path = "notes/deep/topic.md", contentLines = [| "# Topic" |])
+    let docB = FakeDoc.Mk(path = "journal/topic.md", contentLines = [| "# Topic" |])
+    let docC = FakeDoc.Mk(path = "topic.md", contentLines = [| "# Topic" |])
+
+    let src =
+        FakeDoc.Mk(path = "notes/deep/daily/today.md", contentLines = [| "# Today"; "[[topic]]" |])
+
+    let folder = FakeFolder.Mk([ docA; docB; docC; src ])
+
+    let pickPaths strategy =
+        let link = requireElementAtPos src 1 3
+
+        Dest.tryResolveElement folder src link
+        |> Dest.pick strategy src
+        |> Array.map (Dest.doc >> Doc.pathFromRoot)
+        |> List.ofArray
+
+    [<Fact>]
+    let all_sortedByPath () =
+        Assert.Equal<string list>(
+            [ "journal/topic.md"; "notes/deep/topic.md"; "topic.md" ],
+            pickPaths CoreLinkResolution.All
+        )
+
+    [<Fact>]
+    let nearest_sharesMostDirs () =
+        Assert.Equal<string list>([ "notes/deep/topic.md" ], pickPaths CoreLinkResolution.Nearest)
+
+    [<Fact>]
+    let shortestPath_closestToRoot () =
+        Assert.Equal<string list>([ "topic.md" ], pickPaths CoreLinkResolution.ShortestPath)
+
+    [<Fact>]
+    let toDestination_roundTrip () =
+        for name in [ "my note.md"; "100%.md"; "a (b).md"; "ü.md" ] do
+            let target = FakeDoc.Mk(path = name, contentLines = [| "# Target" |])
+
+            for angled in [ false; true ] do
+                let dest = Url.toDestination angled (name.AbsPathUrlEncode())
+                let link = if angled then $"[](<{dest}>)" else $"[]({dest})"
+                let src = FakeDoc.Mk(path = "src.md", contentLines = [| "# Src"; link |])
+                let folder = FakeFolder.Mk([ target; src ])
+                let resolved = Dest.tryResolveElement folder src (requireElementAtPos src 1 1)
+
+                Assert.Equal<string list>(
+                    [ name ],
+                    resolved |> Seq.map (Dest.doc >> Doc.pathFromRoot) |> List.ofSeq
+                )
+
+    [<Fact>]
+    let encodeAnchor_roundTrip () =
+        let target =
+            FakeDoc.Mk(
+                path = "target.md",
+                contentLines = [| "# Target"; "## Scope {#50%(of-it)}" |]
+            )
+
+        let anchor = Url.encodeAnchor "50%(of-it)"
+
+        let src =
+            FakeDoc.Mk(path = "src.md", contentLines = [| "# Src"; $"[](target.md#{anchor})" |])
+        let folder = FakeFolder.Mk([ target; src ])
+
+        let resolved =
+            Dest.tryResolveElement folder src (requireElementAtPos src 1 1)
+            |> Seq.map (fun dest -> (Dest.range dest).DebuggerDisplay)
+
+        Assert.Equal("50%25%28of-it%29", anchor)
+        Assert.Equal<string list>([ "(1,0)-(1,22)" ], List.ofSeq resolved)
+
+module EmbeddedTextTests =
+    [<Fact>]
+    let nestedEmbeds_shownUntilCycle () =
+        let docA =
+            FakeDoc.Mk(path = "a.md", content = "---\ntitle: A\n---\n# A\n![[b#Part]]\nEnd\n")
+
+        let docB = FakeDoc.Mk(path = "b.md", content = "# B\n## Part\nText ![[a]]\n## Other\n")
+        let docC = FakeDoc.Mk(path = "c.md", content = "# C\n![[a]]\n")
+        let folder = FakeFolder.Mk([ docA; docB; docC ])
+
+        let dest =
+            Dest.tryResolveElement folder docC (requireElementAtPos docC 1 2) |> Seq.exactlyOne
+
+        // The frontmatter of `a` is left out, and `b` embedding `a` back is left as it is
+        Assert.Equal("\n# A\n## Part\nText ![[a]]\n\nEnd\n", Dest.embeddedText folder dest)
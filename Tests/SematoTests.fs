@@ -30,3 +30,23 @@ End with [[wiki-link-no-eol]] and #tag."""
     Assert.Equal<uint32>([| 1u; 2u; 11u; 0u; 0u |], nthToken data 2)
     Assert.Equal<uint32>([| 2u; 11u; 16u; 0u; 0u |], nthToken data 3)
     Assert.Equal<uint32>([| 0u; 23u; 4u; 2u; 0u |], nthToken data 4)
+
+[<Fact>]
+let testMathToken () =
+    let docPath = dummyRootPath [ "folder"; "doc1.md" ] |> PathUri.ofString
+    let content = "Let $x^2$ be"
+
+    let doc = Doc.mk docPath folderPath None (Text.mkText content)
+    let data = Token.ofIndexEncoded (Doc.index doc)
+
+    Assert.Equal<uint32>([| 0u; 4u; 5u; 3u; 0u |], data)
+
+[<Fact>]
+let testCalloutToken () =
+    let docPath = dummyRootPath [ "folder"; "doc1.md" ] |> PathUri.ofString
+    let content = "> [!NOTE] Read this\n> carefully"
+
+    let doc = Doc.mk docPath folderPath None (Text.mkText content)
+    let data = Token.ofIndexEncoded (Doc.index doc)
+
+    Assert.Equal<uint32>([| 0u; 2u; 7u; 4u; 0u |], data)
@@ -0,0 +1,74 @@
+module Marksman.ServerTests
+
+open System.Threading
+
+open Ionide.LanguageServerProtocol.Types
+open Xunit
+
+open Marksman.Server
+
+let change (uri: string) (text: string) : DidChangeTextDocumentParams =
+    { TextDocument = { Uri = uri; Version = None }
+      ContentChanges = [| { Range = None; RangeLength = None; Text = text } |] }
+
+let texts (change: DidChangeTextDocumentParams) =
+    change.ContentChanges |> Array.map (fun x -> x.Text) |> List.ofArray
+
+module ChangeBufferTests =
+    [<Fact>]
+    let flush_coalescesChangesOfDoc () =
+        let batches = ResizeArray()
+        // Flushed only explicitly
+        use buffer = new ChangeBuffer(Timeout.Infinite, batches.Add)
+
+        buffer.Add(change "file:///a.md" "1")
+        buffer.Add(change "file:///b.md" "2")
+        buffer.Add(change "file:///a.md" "3")
+        buffer.Flush()
+        buffer.Flush()
+
+        Assert.Equal(1, batches.Count)
+        let batch = batches[0]
+
+        Assert.Equal<string>(
+            [ "file:///a.md"; "file:///b.md" ],
+            batch |> List.map (fun x -> x.TextDocument.Uri)
+        )
+
+        Assert.Equal<list<string>>([ [ "1"; "3" ]; [ "2" ] ], batch |> List.map texts)
+
+    [<Fact>]
+    let flush_laterChangesInNextBatch () =
+        let batches = ResizeArray()
+        use buffer = new ChangeBuffer(Timeout.Infinite, batches.Add)
+
+        buffer.Add(change "file:///a.md" "1")
+        buffer.Flush()
+        buffer.Add(change "file:///a.md" "2")
+        buffer.Flush()
+
+        Assert.Equal<list<list<string>>>(
+            [ [ [ "1" ] ]; [ [ "2" ] ] ],
+            batches |> Seq.map (List.map texts) |> List.ofSeq
+        )
+
+    [<Fact>]
+    let timer_flushesAfterWindow () =
+        let batches = ResizeArray()
+        use flushed = new ManualResetEventSlim()
+
+        use buffer =
+            new ChangeBuffer(
+                10,
+                fun changes ->
+                    batches.Add(changes)
+                    flushed.Set()
+            )
+
+        buffer.Add(change "file:///a.md" "1")
+
+        Assert.True(flushed.Wait(5000))
+        Assert.Equal<list<list<string>>>(
+            [ [ [ "1" ] ] ],
+            batches |> Seq.map (List.map texts) |> List.ofSeq
+        )
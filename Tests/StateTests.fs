@@ -0,0 +1,52 @@
+module Marksman.StateTests
+
+open Xunit
+
+open Marksman.Helpers
+open Marksman.State
+open Marksman.Workspace
+
+let private client: ClientDescription =
+    { info = None
+      caps =
+        { Workspace = None
+          TextDocument = None
+          Window = None
+          General = None
+          Experimental = None } }
+
+[<Fact>]
+let withIndexedFolder_takesOpenedDocs () =
+    let onDisk = FakeDoc.Mk(path = "b.md", contentLines = [| "# B" |])
+    let folder = FakeFolder.Mk([ FakeDoc.Mk(path = "a.md", contentLines = [| "# A" |]); onDisk ])
+    let root = Folder.rootPath folder
+
+    // Opened while the folder was read, with unsaved changes
+    let opened = Doc.mk (Doc.path onDisk) root (Some 1) (Text.mkText "# Edited")
+
+    let state =
+        State.mk client (Workspace.ofFolders None [ Folder.singleFile opened None ])
+        |> State.withFoldersToIndex (Map.ofList [ "dummy", root ])
+
+    let indexed = State.tryWithIndexedFolder folder state |> Option.get
+    let folders = Workspace.folders (State.workspace indexed) |> List.ofSeq
+
+    Assert.Equal<FolderId>([ Folder.id folder ], folders |> List.map Folder.id)
+    Assert.Equal(2, Folder.docCount folders[0])
+
+    let doc = State.tryFindDoc (Doc.path onDisk) indexed |> Option.get
+    Assert.Equal("# Edited", (Doc.text doc).content)
+    Assert.False(State.isToIndex root indexed)
+
+[<Fact>]
+let tryWithIndexedFolder_removedWhileRead () =
+    let folder = FakeFolder.Mk([ FakeDoc.Mk(path = "a.md", contentLines = [| "# A" |]) ])
+
+    let state =
+        State.mk client (Workspace.ofFolders None [])
+        |> State.withFoldersToIndex (Map.ofList [ "dummy", Folder.rootPath folder ])
+
+    Assert.True(State.tryWithIndexedFolder folder state |> Option.isSome)
+
+    let removed = State.updateFolders [] [ Folder.id folder ] state
+    Assert.True(State.tryWithIndexedFolder folder removed |> Option.isNone)
@@ -0,0 +1,31 @@
+module Marksman.StatsTests
+
+open Xunit
+
+open Marksman.Helpers
+open Marksman.Stats
+
+let private docA =
+    FakeDoc.Mk(
+        path = "a.md",
+        contentLines =
+            [| "---"; "tags: [x, y]"; "---"; "# Note A"; "- one two, [[b]]"; "## More"; "three" |]
+    )
+
+let private docB = FakeDoc.Mk(path = "b.md", contentLines = [| "# B"; "[A](a.md) and [[a#More]]" |])
+
+[<Fact>]
+let wordCount_skipsFrontMatterAndMarkup () = Assert.Equal(7, wordCount docA)
+
+[<Fact>]
+let ofDoc_counts () =
+    let folder = FakeFolder.Mk([ docA; docB ])
+
+    Assert.Equal(
+        { words = 7
+          headings = 2
+          inboundLinks = 2
+          outboundLinks = 1
+          readingMinutes = 1 },
+        ofDoc folder docA
+    )
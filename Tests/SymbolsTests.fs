@@ -5,6 +5,7 @@ open Xunit
 open Ionide.LanguageServerProtocol.Types
 
 open Marksman.Helpers
+open Marksman.Workspace
 
 module DocSymbols =
     let fakeDoc =
@@ -46,3 +47,65 @@ module DocSymbols =
         syms |> Array.iter collect
 
         Assert.Equal<string>([| "E"; "D"; "B"; "C"; "A" |], names)
+
+module CalloutSymbols =
+    let fakeDoc =
+        FakeDoc.Mk(
+            [| "> [!WARNING] Top" //
+               "# A"
+               "> [!NOTE]"
+               "> text" |]
+        )
+
+    [<Fact>]
+    let noHierarchy () =
+        let syms = Symbols.docSymbols false false fakeDoc
+
+        let symNames =
+            match syms with
+            | First x -> x
+            | _ -> failwith "Unexpected symbol type"
+            |> Array.map (fun x -> x.Name)
+
+        Assert.Equal<string>([| "WARNING: Top"; "H1: A"; "NOTE: NOTE" |], symNames)
+
+    [<Fact>]
+    let hierarchy () =
+        let syms =
+            match Symbols.docSymbols true false fakeDoc with
+            | Second x -> x
+            | _ -> failwith "Unexpected symbol type"
+
+        Assert.Equal<string>([| "Top"; "A" |], syms |> Array.map (fun x -> x.Name))
+
+        let children = syms[1].Children |> Option.defaultValue [||]
+        Assert.Equal<string>([| "NOTE" |], children |> Array.map (fun x -> x.Name))
+
+module WorkspaceSymbols =
+    let ws =
+        Workspace.ofFolders
+            None
+            [ FakeFolder.Mk(
+                  [ FakeDoc.Mk([| "# Note A"; "## Other"; "## Notes" |], path = "a.md")
+                    FakeDoc.Mk([| "# Note B" |], path = "b.md") ]
+              ) ]
+
+    let names (symbols: seq<SymbolInformation>) = symbols |> Seq.map (fun x -> x.Name) |> Seq.sort
+
+    [<Fact>]
+    let chunks_partialResults () =
+        let chunks = Symbols.workspaceSymbolChunks 2 "note" ws |> Array.ofSeq
+
+        Assert.Equal<int>([| 2; 1 |], chunks |> Array.map Array.length)
+
+        Assert.Equal<string>(
+            names (Symbols.workspaceSymbols "note" ws),
+            names (Array.concat chunks)
+        )
+
+    [<Fact>]
+    let chunks_documentOrder () =
+        let first = Symbols.workspaceSymbolChunks 2 "note" ws |> Seq.head
+
+        Assert.Equal<string>([ "H1: Note A"; "H2: Notes" ], names first)
+        Assert.All(first, (fun sym -> Assert.EndsWith("a.md", sym.Location.Uri)))
@@ -0,0 +1,96 @@
+module Marksman.TablesTests
+
+open Ionide.LanguageServerProtocol.Types
+open Xunit
+
+open Marksman.Cst
+open Marksman.Helpers
+open Marksman.Index
+open Marksman.Tables
+open Marksman.Workspace
+
+open type System.Environment
+
+let lines (xs: list<string>) = System.String.Join(NewLine, xs)
+
+let grid =
+    { header = [| "a"; "bb" |]
+      alignments = [| ColumnAlign.Default; ColumnAlign.Center |]
+      rows = [| [| "longer"; "x" |] |] }
+
+[<Fact>]
+let render_alignsAndPads () =
+    let expected =
+        lines [ "| a      | bb  |"; "| ------ | :-: |"; "| longer |  x  |" ]
+
+    Assert.Equal(expected, Grid.render grid)
+
+[<Fact>]
+let render_rightAlign () =
+    let grid =
+        { header = [| "n" |]
+          alignments = [| ColumnAlign.Right |]
+          rows = [| [| "10" |]; [| "1234" |] |] }
+
+    Assert.Equal(lines [ "|    n |"; "| ---: |"; "|   10 |"; "| 1234 |" ], Grid.render grid)
+
+[<Fact>]
+let insertRow_first () =
+    let grid = Grid.insertRow 0 grid
+    Assert.Equal<array<string>>([| [| ""; "" |]; [| "longer"; "x" |] |], grid.rows)
+
+[<Fact>]
+let insertColumn_middle () =
+    let grid = Grid.insertColumn 1 grid
+    Assert.Equal<string>([| "a"; ""; "bb" |], grid.header)
+
+    Assert.Equal<ColumnAlign>(
+        [| ColumnAlign.Default; ColumnAlign.Default; ColumnAlign.Center |],
+        grid.alignments
+    )
+
+    Assert.Equal<array<string>>([| [| "longer"; ""; "x" |] |], grid.rows)
+
+[<Fact>]
+let ofTable_padsShortRows () =
+    let doc = FakeDoc.Mk [| "| a | b |"; "| - | - |"; "| 1 |" |]
+    let table = Doc.index doc |> Index.tables |> Array.exactlyOne
+    let grid = Grid.ofTable table.data
+    Assert.Equal<array<string>>([| [| "1"; "" |] |], grid.rows)
+
+[<Fact>]
+let codeActions_atCursor () =
+    let doc =
+        FakeDoc.Mk [| "| a   | b   |"; "| --- | --- |"; "| 1   | 2   |" |]
+
+    let context = { Diagnostics = [||]; Only = None; TriggerKind = None }
+    let cursor = Range.Mk(2, 9, 2, 9)
+
+    let actions = CodeActions.tableActions cursor context doc
+    let names = actions |> Array.map (fun a -> a.name)
+
+    // The table is already formatted, so only insertions are offered
+    Assert.Equal<string>([| "Insert table row below"; "Insert table column after" |], names)
+
+    let expectedColumn =
+        lines [ "| a   | b   |     |"; "| --- | --- | --- |"; "| 1   | 2   |     |" ]
+
+    Assert.Equal(expectedColumn, actions[1].newText)
+
+[<Fact>]
+let codeActions_tableInBlockQuote () =
+    let doc = FakeDoc.Mk [| "> | a | b |"; "> | - | - |"; "> | 1 | 2 |" |]
+    let table = Doc.index doc |> Index.tables |> Array.exactlyOne
+    Assert.Equal<string>([| "1"; "2" |], table.data.rows[0] |> Array.map Node.text)
+
+    let context = { Diagnostics = [||]; Only = None; TriggerKind = None }
+    let actions = CodeActions.tableActions (Range.Mk(2, 4, 2, 4)) context doc
+    let format = actions |> Array.find (fun a -> a.name = "Format table")
+
+    // The edit starts after the quote marker of the first line
+    let expected = lines [ "| a   | b   |"; "> | --- | --- |"; "> | 1   | 2   |" ]
+    Assert.Equal(expected, format.newText)
+
+    let formatted = FakeDoc.Mk [| "> " + expected |]
+    let actions = CodeActions.tableActions (Range.Mk(2, 4, 2, 4)) context formatted
+    Assert.DoesNotContain("Format table", actions |> Array.map (fun a -> a.name))
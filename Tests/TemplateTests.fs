@@ -0,0 +1,47 @@
+module Marksman.TemplateTests
+
+open System
+open Xunit
+
+open Marksman.Helpers
+open Marksman.Compl
+
+[<Fact>]
+let render_knownAndUnknown () =
+    let vars = Map.ofList [ "title", "Note"; "date", "2024-05-01" ]
+
+    Assert.Equal(
+        "# Note\n2024-05-01, {{other}}",
+        Template.render vars "# {{title}}\n{{ date }}, {{other}}"
+    )
+
+[<Fact>]
+let contentOf_fromTemplateDir () =
+    let daily =
+        FakeDoc.Mk(path = "templates/daily.md", contentLines = [| "# {{title}}"; "{{author}}" |])
+
+    let other = FakeDoc.Mk(path = "templates/default.md", contentLines = [| "# {{title}}!" |])
+
+    let config = { Config.Config.Empty with complCreateNoteAuthor = Some "Ada" }
+
+    let folder = FakeFolder.Mk([ daily; other ], config = config)
+    let date = DateTime(2024, 5, 1)
+
+    Assert.Equal(
+        String.concat Environment.NewLine [ "# 2024-05-01"; "Ada" ],
+        NewNote.contentOf folder DailyNote.template "2024-05-01" date
+    )
+
+    Assert.Equal("# Note!", NewNote.contentOf folder "missing" "Note" date)
+
+[<Fact>]
+let contentOf_configTemplate () =
+    let config =
+        { Config.Config.Empty with complCreateNoteTemplate = Some "# {title}\n{{date}}\n" }
+
+    let folder = FakeFolder.Mk([], config = config)
+
+    Assert.Equal(
+        "# Note\n2024-05-01\n",
+        NewNote.contentOf folder NewNote.defaultTemplate "Note" (DateTime(2024, 5, 1))
+    )
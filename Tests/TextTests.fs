@@ -101,3 +101,62 @@ let applyTextChange_replace_single () =
 
     let expected = "Bye World!"
     Assert.Equal(expected, actual.content)
+
+[<Fact>]
+let applyTextChange_lineMapMatchesFullScan () =
+    let mutable text = Text.mkText "# A\r\n\r\nline\nmore\r\n"
+    let random = System.Random(42)
+    let pieces = [| ""; "x"; "\n"; "\r"; "\r\n"; "ab\ncd"; "\n\n" |]
+
+    let randomPos (text: Text.Text) =
+        let line = random.Next(text.lineMap.Map.Length)
+        let start, end_ = text.LineContentOffsets(line)
+        Text.mkPosition (line, random.Next(end_ - start + 1))
+
+    for _ in 1..500 do
+        let a = randomPos text
+        let b = randomPos text
+        let start, end_ = if (a.Line, a.Character) <= (b.Line, b.Character) then a, b else b, a
+
+        let change =
+            { Range = Some { Start = start; End = end_ }
+              RangeLength = None
+              Text = pieces[random.Next(pieces.Length)] }
+
+        text <- Text.applyTextChange [| change |] text
+        Assert.Equal<Text.LineRange>((Text.mkLineMap text.content).Map, text.lineMap.Map)
+
+[<Fact>]
+let positionEncoding_negotiate () =
+    Assert.Equal(Text.Utf8, Text.PositionEncoding.negotiate [| "utf-8"; "utf-16" |])
+    Assert.Equal(Text.Utf32, Text.PositionEncoding.negotiate [| "utf-7"; "utf-32" |])
+    Assert.Equal(Text.Utf16, Text.PositionEncoding.negotiate [||])
+
+[<Fact>]
+let positionEncoding_roundTrip () =
+    // 'é' takes 2 bytes in UTF-8, '€' 3 and the smiley, a surrogate pair in .NET, 4
+    let line = "é€😀x\n"
+    let charAt i = line[i]
+
+    // Offsets between the chars, the smiley is a single char
+    let offsets = [ 0; 1; 2; 4; 5; 6 ]
+
+    let units encoding =
+        offsets |> List.map (Text.PositionEncoding.units encoding charAt 0)
+
+    Assert.Equal<int list>([ 0; 2; 5; 9; 10; 11 ], units Text.Utf8)
+    Assert.Equal<int list>(offsets, units Text.Utf16)
+    Assert.Equal<int list>([ 0; 1; 2; 3; 4; 5 ], units Text.Utf32)
+
+    for encoding in [ Text.Utf8; Text.Utf16; Text.Utf32 ] do
+        for offset in offsets do
+            let units = Text.PositionEncoding.units encoding charAt 0 offset
+            let actual = Text.PositionEncoding.tryOffset encoding charAt 0 line.Length units
+            Assert.Equal(Some offset, actual)
+
+[<Fact>]
+let positionEncoding_pastLineEnd () =
+    let line = "é\n"
+    let charAt i = line[i]
+    Assert.Equal(None, Text.PositionEncoding.tryOffset Text.Utf8 charAt 0 line.Length 4)
+    Assert.Equal(Some 1, Text.PositionEncoding.tryOffset Text.Utf8 charAt 0 line.Length 1)
@@ -61,6 +61,25 @@ module CreateToc =
 
         Assert.Equal(expected, titles)
 
+    [<Fact>]
+    let createToc_explicitId () =
+        let doc = FakeDoc.Mk [| "# T1"; "## T2 {#custom}" |]
+
+        let toc = TableOfContents.mk (Doc.index doc) |> Option.get
+
+        Assert.Equal("custom", toc.entries[1].link |> Misc.Slug.toString)
+
+    [<Fact>]
+    let createToc_upTo () =
+        let doc = FakeDoc.Mk [| "# T1"; "## T2"; "### T3" |]
+
+        let toc = TableOfContents.mk (Doc.index doc) |> Option.get
+
+        let expected = { entries = [| Entry.Mk(1, "T1"); Entry.Mk(2, "T2") |] }
+
+        Assert.Equal(Some expected, TableOfContents.upTo 2 toc)
+        Assert.Equal(None, TableOfContents.upTo 0 toc)
+
     [<Fact>]
     let createToc_yamlFrontMatter () =
         let doc =
@@ -0,0 +1,49 @@
+module Marksman.TraceTests
+
+open Xunit
+
+open Marksman.Trace
+
+[<Fact>]
+let span_recorded () =
+    let result = span "test" "span_recorded" (fun () -> 42)
+    Assert.Equal(42, result)
+
+    // Other tests record spans concurrently, so only this one's is looked at
+    let recorded = recent () |> Array.filter (fun span -> span.name = "span_recorded")
+    Assert.Equal(1, recorded.Length)
+    Assert.Equal("test", recorded[0].category)
+    Assert.True(recorded[0].thread.IsSome)
+
+[<Fact>]
+let spanAsync_recordedWithoutThread () =
+    spanAsync "test" "spanAsync_recorded" (async { return () }) |> Async.RunSynchronously
+
+    let recorded = recent () |> Array.filter (fun span -> span.name = "spanAsync_recorded")
+    Assert.Equal(1, recorded.Length)
+    Assert.Equal<option<int>>(None, recorded[0].thread)
+
+[<Fact>]
+let toChromeTrace_events () =
+    let spans =
+        [| { name = "parse"
+             category = "parse"
+             detail = Some "/notes/a.md"
+             start = 10L
+             duration = 5L
+             thread = Some 3 }
+           { name = "textDocument/hover"
+             category = "request"
+             detail = None
+             start = 12L
+             duration = 20L
+             thread = None } |]
+
+    let events = (toChromeTrace spans)["traceEvents"] |> Seq.toArray
+    let field (name: string) (event: Newtonsoft.Json.Linq.JToken) = string (event[name])
+
+    Assert.Equal<string>([| "X"; "b"; "e" |], events |> Array.map (field "ph"))
+    Assert.Equal("5", field "dur" (events[0]))
+    Assert.Equal("/notes/a.md", string (events[0]["args"]["detail"]))
+    Assert.Equal<string>([| "12"; "32" |], events[1..] |> Array.map (field "ts"))
+    Assert.Equal(field "id" (events[1]), field "id" (events[2]))
@@ -1,5 +1,6 @@
 module Marksman.WorkspaceTest
 
+open System.IO
 open Ionide.LanguageServerProtocol.Types
 
 open Xunit
@@ -18,6 +19,273 @@ module FolderTest =
 
         Assert.Equal(dummyRootPath [ "a" ] |> RootPath.ofString, Folder.rootPath f1)
 
+    [<Fact>]
+    let tryReload_appliesNewConfig () =
+        let dir = Path.Join(Path.GetTempPath(), Path.GetRandomFileName())
+        Directory.CreateDirectory(Path.Join(dir, "drafts")) |> ignore
+
+        try
+            File.WriteAllText(Path.Join(dir, "a.md"), "# A")
+            File.WriteAllText(Path.Join(dir, "drafts", "b.md"), "# B")
+
+            let root = RootPath.ofString dir
+            let folder = Folder.tryLoad None "f" root |> Option.get
+            Assert.Equal(2, Folder.docCount folder)
+
+            // The open doc has unsaved changes
+            let aPath = PathUri.ofString (Path.Join(dir, "a.md"))
+            let edited = Doc.mk aPath root (Some 1) (Text.mkText "# Edited")
+            let folder = Folder.withDoc edited folder
+
+            File.WriteAllText(Path.Join(dir, ".marksman.toml"), "[core]\nignore = [\"drafts/\"]")
+
+            let reloaded = Folder.tryReload None folder |> Option.get
+            let docs = Folder.docs reloaded |> Seq.map (fun doc -> (Doc.text doc).content)
+
+            Assert.Equal<string>([ "# Edited" ], docs)
+            Assert.Equal(Some [| "drafts/" |], (Folder.configOrDefault reloaded).coreIgnore)
+        finally
+            Directory.Delete(dir, true)
+
+    [<Fact>]
+    let isIgnored_ignoreFilesAndConfig () =
+        let dir = Path.Join(Path.GetTempPath(), Path.GetRandomFileName())
+        Directory.CreateDirectory(Path.Join(dir, "notes")) |> ignore
+
+        try
+            File.WriteAllText(Path.Join(dir, ".gitignore"), "node_modules/")
+            File.WriteAllText(Path.Join(dir, "notes", ".ignore"), "*.draft.md")
+            File.WriteAllText(Path.Join(dir, ".marksman.toml"), "[core]\nignore = [\"/build\"]")
+
+            let folder = Folder.tryLoad None "f" (RootPath.ofString dir) |> Option.get
+            let isIgnored (path: string) =
+                Folder.isIgnored (PathUri.ofString (Path.Join(dir, path))) folder
+
+            Assert.False(isIgnored "a.md")
+            Assert.False(isIgnored "notes/a.md")
+            Assert.True(isIgnored "node_modules/pkg/README.md")
+            Assert.True(isIgnored "notes/a.draft.md")
+            Assert.False(isIgnored "a.draft.md")
+            Assert.True(isIgnored "build/a.md")
+            Assert.False(isIgnored "notes/build/a.md")
+        finally
+            Directory.Delete(dir, true)
+
+    [<Fact>]
+    let ignoredCount_notesSkippedByGlobs () =
+        let dir = Path.Join(Path.GetTempPath(), Path.GetRandomFileName())
+        Directory.CreateDirectory(Path.Join(dir, "drafts")) |> ignore
+
+        try
+            File.WriteAllText(Path.Join(dir, ".ignore"), "*.draft.md\ndrafts/")
+            File.WriteAllText(Path.Join(dir, "a.md"), "# A")
+            File.WriteAllText(Path.Join(dir, "b.draft.md"), "# B")
+            File.WriteAllText(Path.Join(dir, "c.txt"), "C")
+            File.WriteAllText(Path.Join(dir, "drafts", "d.md"), "# D")
+
+            let folder = Folder.tryLoad None "f" (RootPath.ofString dir) |> Option.get
+
+            Assert.Equal(1, Folder.docCount folder)
+            // Files in ignored directories aren't scanned, so they aren't counted
+            Assert.Equal(1, Folder.ignoredCount folder)
+        finally
+            Directory.Delete(dir, true)
+
+    [<Fact>]
+    let skippedCount_largeAndBinaryFiles () =
+        let dir = Path.Join(Path.GetTempPath(), Path.GetRandomFileName())
+        Directory.CreateDirectory(dir) |> ignore
+
+        try
+            File.WriteAllText(Path.Join(dir, ".marksman.toml"), "[core]\nmax_file_size_kb = 1")
+            File.WriteAllText(Path.Join(dir, "a.md"), "# A")
+            File.WriteAllText(Path.Join(dir, "large.md"), String.replicate 2048 "x")
+            File.WriteAllBytes(Path.Join(dir, "image.md"), [| 0x89uy; 0x50uy; 0x00uy; 0x0Auy |])
+            // UTF-16 text is full of NUL bytes, but starts with a byte order mark
+            File.WriteAllText(Path.Join(dir, "utf16.md"), "# U", System.Text.Encoding.Unicode)
+
+            let folder = Folder.tryLoad None "f" (RootPath.ofString dir) |> Option.get
+
+            Assert.Equal(2, Folder.docCount folder)
+            Assert.Equal(2, Folder.skippedCount folder)
+            Assert.Equal(0, Folder.ignoredCount folder)
+        finally
+            Directory.Delete(dir, true)
+
+    [<Fact>]
+    let tryLoadDoc_skipsLargeAndBinaryFiles () =
+        let dir = Path.Join(Path.GetTempPath(), Path.GetRandomFileName())
+        Directory.CreateDirectory(dir) |> ignore
+
+        try
+            File.WriteAllText(Path.Join(dir, ".marksman.toml"), "[core]\nmax_file_size_kb = 1")
+            let folder = Folder.tryLoad None "f" (RootPath.ofString dir) |> Option.get
+
+            // Files showing up after the folder was loaded
+            File.WriteAllText(Path.Join(dir, "a.md"), "# A")
+            File.WriteAllText(Path.Join(dir, "large.md"), String.replicate 2048 "x")
+            File.WriteAllBytes(Path.Join(dir, "image.md"), [| 0x89uy; 0x50uy; 0x00uy; 0x0Auy |])
+
+            let load (name: string) =
+                Folder.tryLoadDoc (PathUri.ofString (Path.Join(dir, name))) folder
+
+            Assert.True(Option.isSome (load "a.md"))
+            Assert.True(Option.isNone (load "large.md"))
+            Assert.True(Option.isNone (load "image.md"))
+        finally
+            Directory.Delete(dir, true)
+
+    [<Fact>]
+    let tryLoad_followSymlinks () =
+        let dir = Path.Join(Path.GetTempPath(), Path.GetRandomFileName())
+        let outside = Path.Join(Path.GetTempPath(), Path.GetRandomFileName())
+        Directory.CreateDirectory(Path.Join(dir, "notes")) |> ignore
+        Directory.CreateDirectory(outside) |> ignore
+
+        try
+            File.WriteAllText(Path.Join(dir, "notes", "a.md"), "# A")
+            File.WriteAllText(Path.Join(outside, "b.md"), "# B")
+            // Another path to the same directory, a cycle, and content from elsewhere
+            Directory.CreateSymbolicLink(Path.Join(dir, "alias"), Path.Join(dir, "notes")) |> ignore
+            Directory.CreateSymbolicLink(Path.Join(dir, "notes", "loop"), dir) |> ignore
+            Directory.CreateSymbolicLink(Path.Join(dir, "extern"), outside) |> ignore
+
+            let docCount () =
+                Folder.tryLoad None "f" (RootPath.ofString dir)
+                |> Option.get
+                |> Folder.docCount
+
+            Assert.Equal(1, docCount ())
+
+            File.WriteAllText(Path.Join(dir, ".marksman.toml"), "[core]\nfollow_symlinks = true")
+            Assert.Equal(2, docCount ())
+        finally
+            Directory.Delete(dir, true)
+            Directory.Delete(outside, true)
+
+    [<Fact>]
+    let configFor_nestedConfigsOverrideFolderConfig () =
+        let dir = Path.Join(Path.GetTempPath(), Path.GetRandomFileName())
+        Directory.CreateDirectory(Path.Join(dir, "archive", "old")) |> ignore
+
+        try
+            let config = "[completion]\nwiki.style = \"file-stem\""
+            File.WriteAllText(Path.Join(dir, ".marksman.toml"), config)
+
+            File.WriteAllText(
+                Path.Join(dir, "archive", ".marksman.toml"),
+                "[core]\nignore = [\"*.md\"]\n[diagnostics]\norphan.enable = true"
+            )
+
+            File.WriteAllText(Path.Join(dir, "a.md"), "# A")
+            File.WriteAllText(Path.Join(dir, "archive", "old", "b.md"), "# B")
+
+            let folder = Folder.tryLoad None "f" (RootPath.ofString dir) |> Option.get
+            let doc path = Folder.tryFindDocByPath (PathUri.ofString (Path.Join(dir, path))) folder
+            let configOf path = Folder.configFor (Option.get (doc path)) folder
+
+            // Only the folder config decides which notes belong to the folder
+            Assert.Equal(2, Folder.docCount folder)
+            Assert.Equal<string>([ "archive" ], Folder.dirConfigs folder |> Map.keys)
+
+            Assert.False((configOf "a.md").DiagOrphanEnable())
+            Assert.True((configOf "archive/old/b.md").DiagOrphanEnable())
+            Assert.Equal(ComplWikiStyle.FileStem, (configOf "archive/old/b.md").ComplWikiStyle())
+        finally
+            Directory.Delete(dir, true)
+
+    [<Fact>]
+    let configFor_dirWithoutNotesWhenLoaded () =
+        let dir = Path.Join(Path.GetTempPath(), Path.GetRandomFileName())
+        Directory.CreateDirectory(Path.Join(dir, "empty")) |> ignore
+        Directory.CreateDirectory(Path.Join(dir, "later")) |> ignore
+
+        try
+            let config = "[diagnostics]\norphan.enable = true"
+            File.WriteAllText(Path.Join(dir, "empty", ".marksman.toml"), config)
+            File.WriteAllText(Path.Join(dir, "a.md"), "# A")
+
+            let root = RootPath.ofString dir
+            let folder = Folder.tryLoad None "f" root |> Option.get
+            Assert.Equal<string>([ "empty" ], Folder.dirConfigs folder |> Map.keys)
+
+            // Configs of directories the folder didn't know about are read with their first note
+            File.WriteAllText(Path.Join(dir, "later", ".marksman.toml"), config)
+            File.WriteAllText(Path.Join(dir, "later", "b.md"), "# B")
+
+            let newDoc = PathUri.ofString (Path.Join(dir, "later", "b.md"))
+            let doc = Folder.tryLoadDoc newDoc folder |> Option.get
+            let folder = Folder.withDoc doc folder
+
+            Assert.Equal<string>([ "empty"; "later" ], Folder.dirConfigs folder |> Map.keys)
+            Assert.True((Folder.configFor doc folder).DiagOrphanEnable())
+        finally
+            Directory.Delete(dir, true)
+
+    [<Fact>]
+    let docsLinkingTo_followsEdits () =
+        let target = FakeDoc.Mk(path = "target.md", contentLines = [| "# Target Note" |])
+        let byTitle = FakeDoc.Mk(path = "a.md", contentLines = [| "# A"; "[[target note]]" |])
+        let byPath = FakeDoc.Mk(path = "b.md", contentLines = [| "# B"; "[t](./target.md#h)" |])
+        let other = FakeDoc.Mk(path = "c.md", contentLines = [| "# C"; "[[a]]" |])
+        let folder = FakeFolder.Mk([ target; byTitle; byPath; other ])
+
+        let linkingTo doc folder =
+            Folder.docsLinkingTo doc folder |> Seq.map Doc.pathFromRoot |> Seq.sort |> List.ofSeq
+
+        Assert.Equal<string>([ "a.md"; "b.md"; "target.md" ], linkingTo target folder)
+        Assert.Equal<string>([ "a.md"; "c.md" ], linkingTo byTitle folder)
+
+        let edited = Doc.mk (Doc.path byTitle) (Doc.rootPath byTitle) None (Text.mkText "# A")
+        let folder = Folder.withDoc edited folder
+        Assert.Equal<string>([ "b.md"; "target.md" ], linkingTo target folder)
+
+        let folder = Folder.withoutDoc (Doc.path byPath) folder |> Option.get
+        Assert.Equal<string>([ "target.md" ], linkingTo target folder)
+
+    [<Fact>]
+    let headingsMatching_followsEdits () =
+        let a = FakeDoc.Mk(path = "a.md", contentLines = [| "# Alpha"; "## Zeta" |])
+        let b = FakeDoc.Mk(path = "b.md", contentLines = [| "# Beta"; "## zeal" |])
+        let folder = FakeFolder.Mk([ a; b ])
+
+        let matching query folder =
+            Folder.headingsMatching query folder
+            |> Seq.map (fun (_, heading: Cst.Node<Cst.Heading>) -> Cst.Heading.name heading.data)
+            |> List.ofSeq
+
+        Assert.Equal<string>([ "Zeta"; "zeal" ], matching "ZE" folder)
+        Assert.Equal<string>([ "Alpha"; "Zeta"; "Beta"; "zeal" ], matching "" folder)
+        Assert.Empty(matching "q" folder)
+
+        let edited = Doc.mk (Doc.path a) (Doc.rootPath a) None (Text.mkText "# Quiz")
+        let folder = Folder.withDoc edited folder
+        Assert.Equal<string>([ "Quiz"; "zeal" ], matching "z" folder)
+
+    [<Fact>]
+    let templates_keptApartFromDocs () =
+        let note = FakeDoc.Mk(path = "a.md", contentLines = [| "# A" |])
+        let template = FakeDoc.Mk(path = "templates/default.md", contentLines = [| "# {{title}}" |])
+        let folder = FakeFolder.Mk([ note; template ])
+
+        let pathsOf docs =
+            docs |> Seq.map Doc.pathFromRoot |> Seq.sort |> List.ofSeq
+
+        Assert.Equal<string>([ "a.md" ], pathsOf (Folder.docs folder))
+        Assert.Equal<string>([ "templates/default.md" ], pathsOf (Folder.templates folder))
+        Assert.Empty(Folder.headingsMatching "title" folder)
+        Assert.Equal(Some template, Folder.tryFindDocByPath (Doc.path template) folder)
+
+        let edited = Doc.mk (Doc.path template) (Doc.rootPath template) (Some 1) (Text.mkText "# T")
+        let folder = Folder.withDoc edited folder
+        Assert.Equal<string>([ "a.md" ], pathsOf (Folder.docs folder))
+        Assert.Equal<Doc>([ edited ], Folder.templates folder)
+
+        let config = { Config.Empty with complCreateNoteTemplateDir = Some "" }
+        let folder = Folder.withConfig (Some config) folder
+        Assert.Equal<string>([ "a.md"; "templates/default.md" ], pathsOf (Folder.docs folder))
+        Assert.Empty(Folder.templates folder)
+
 module DocTest =
     [<Fact>]
     let applyLspChange () =
@@ -41,7 +309,48 @@ module DocTest =
         let doc = FakeDoc.Mk(path = "blah#blah.md", contentLines = [||])
         Assert.Equal("blah#blah.md", Doc.pathFromRoot doc)
 
+    [<Fact>]
+    let equality_byPathAndText () =
+        let doc = FakeDoc.Mk(path = "a.md", contentLines = [| "# A" |])
+
+        Assert.Equal(doc, FakeDoc.Mk(path = "a.md", contentLines = [| "# A" |]))
+        Assert.NotEqual(doc, FakeDoc.Mk(path = "a.md", contentLines = [| "# B" |]))
+        Assert.NotEqual(doc, FakeDoc.Mk(path = "b.md", contentLines = [| "# A" |]))
+
+// Caches are switched off for the whole process
+[<Collection(GlobalState)>]
+module DocCacheTest =
+    [<Fact>]
+    let parse_droppedOverMemoryLimit () =
+        try
+            // Every heap is over a limit of 1 MB
+            Caches.applyLimit 1 System.Int64.MaxValue |> ignore
+            let first = FakeDoc.Mk(path = "first.md", contentLines = [| "# First"; "## Sub" |])
+
+            for i in 1..300 do
+                Doc.index (FakeDoc.Mk(path = $"doc{i}.md", contentLines = [| $"# Doc {i}" |]))
+                |> ignore
+
+            Assert.Equal(2, Index.headings (Doc.index first) |> Array.length)
+        finally
+            Caches.applyLimit 0 0L |> ignore
+
 module WorkspaceTest =
+    [<Fact>]
+    let untitledDoc_foundByUri () =
+        let root = RootPath.ofString dummyRootUri
+        let path = PathUri.untitledIn dummyRoot "untitled:Untitled-1"
+        let doc = Doc.mk path root (Some 1) (Text.mkText "[[a]]")
+        let folder = FakeFolder.Mk([ FakeDoc.Mk(path = "a.md", contentLines = [| "# A" |]) ])
+        let ws = Workspace.ofFolders None [ Folder.withDoc doc folder ]
+
+        let untitled = PathUri.ofString "untitled:Untitled-1"
+        let found = Workspace.tryFindFolderEnclosing untitled ws
+        Assert.Equal(Some(Folder.id folder), found |> Option.map Folder.id)
+
+        let closed = found |> Option.bind (Folder.closeDoc untitled)
+        Assert.Equal(Some 1, closed |> Option.map Folder.docCount)
+
     [<Fact>]
     let folderAdded_evictSingleFile () =
         let d1 = FakeDoc.Mk(content = "", path = "a/b/d1.md", root = "a/b")
@@ -121,3 +430,13 @@ module WorkspaceTest =
         let f = (Workspace.folders ws) |> Seq.head
         let updatedConfig = Folder.config f
         Assert.Equal(wsConfig, updatedConfig)
+
+module RootPathTest =
+    [<Fact>]
+    let contains_onlyInsideDirectory () =
+        let root = dummyRootPath [ "notes" ] |> RootPath.ofString
+        let pathIn dirs = dummyRootPath dirs |> PathUri.ofString
+
+        Assert.True(RootPath.contains (pathIn [ "notes"; "a.md" ]) root)
+        Assert.True(RootPath.contains (pathIn [ "notes"; "sub"; "a.md" ]) root)
+        Assert.False(RootPath.contains (pathIn [ "notes2"; "a.md" ]) root)
@@ -0,0 +1,27 @@
+module Marksman.ZettelTests
+
+open System
+open Xunit
+
+open Marksman.Zettel
+
+[<Fact>]
+let idPart_leadingDigits () =
+    Assert.Equal(Some "202401121230", idPart "202401121230 Idea")
+    Assert.Equal(Some "20240112-1230", idPart "20240112-1230-idea")
+    Assert.Equal(Some "202401121230", idPart "202401121230")
+    Assert.Equal(None, idPart "2024abc")
+    Assert.Equal(None, idPart "Idea")
+
+[<Fact>]
+let tryId_format () =
+    Assert.Equal(Some "202401121230", tryId "yyyyMMddHHmm" "202401121230 Idea")
+    Assert.Equal(None, tryId "yyyyMMddHHmm" "2024 Idea")
+    Assert.Equal(Some "20240112-1230", tryId "yyyyMMdd-HHmm" "20240112-1230 Idea")
+
+[<Fact>]
+let newNote_name () =
+    let id = newId "yyyyMMddHHmm" (DateTime(2024, 1, 12, 12, 30, 0))
+    Assert.Equal("202401121230", id)
+    Assert.Equal("202401121230 Idea", noteName id " Idea")
+    Assert.Equal("202401121230", noteName id "")